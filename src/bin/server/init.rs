@@ -4,14 +4,14 @@ use aqua::schema::types::CharType::VarChar;
 use aqua::schema::types::{NumericType, Type};
 use aqua::storage::blkmgr::BlockManager;
 use aqua::storage::storagemgr::StorageManager;
-use aqua::{RcRefCell, AQUADIR, AQUA_HOME_VAR};
+use aqua::{ArcRwLock, AQUADIR, AQUA_HOME_VAR};
 use lazy_static::lazy_static;
 use std::cell::Cell;
 use std::env;
 use std::fs::{create_dir, create_dir_all};
 use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::rc::Rc;
+use std::sync::Arc;
 
 const AQUA_HOME_DIR: &str = "AQUA";
 lazy_static! {
@@ -22,14 +22,14 @@ lazy_static! {
 pub fn init_aqua() {
     init_homedir();
     CatalogManager::init_catalogs();
-    // let storage = RcRefCell!(StorageManager::new(&AQUADIR(),4096,100));
+    // let storage = ArcRwLock!(StorageManager::new(&AQUADIR(),4096,100));
     // let mut catalogmgr = CatalogManager::startup(storage.clone());
     //
     // catalogmgr.create_database("samir").unwrap();
     // let mut schema = Schema::new();
     // schema.set_name("omar");
     // schema.add_field_default_constraints("id",Type::Numeric(NumericType::Integer),None);
-    // schema.add_field_default_constraints("name",Type::Character(VarChar),None);
+    // schema.add_field_default_constraints("name",Type::Character(VarChar(None)),None);
     // schema.set_primary_keys(vec!["id".to_string()]);
     // catalogmgr.add_schema("samir",&schema).unwrap();
     // let omar = catalogmgr.get_schema("samir","omar");
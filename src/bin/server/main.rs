@@ -3,6 +3,7 @@ mod init;
 
 use aqua::common::boolean::{set_node_true, simplify};
 use aqua::database::server::DatabaseServer;
+use aqua::meta::upgrade;
 use aqua::sql::parser::{parse_query, Rule, SqlParser};
 use evalexpr::build_operator_tree;
 use pest::Parser;
@@ -17,6 +18,7 @@ use aqua::schema::types::Type::Numeric;
 use aqua::sql::query::query::SqlQuery;
 use aqua::sql::Sql;
 use aqua::storage::storagemgr::StorageManager;
+use aqua::AQUADIR;
 
 
 
@@ -29,6 +31,14 @@ fn main() {
     } else {
         init::init_homedir();
     }
+    if let Err(e) = upgrade::upgrade_data_dir() {
+        eprintln!("{}", e);
+        exit(2)
+    }
+    if let Err(e) = StorageManager::check_format_version(&AQUADIR()) {
+        eprintln!("{}", e);
+        exit(2)
+    }
     let server = DatabaseServer::new("hi", vec!["127.0.0.1:2710".to_string()]);
     server.run()
 }
@@ -0,0 +1,121 @@
+use evalexpr::{
+    ContextWithMutableFunctions, EvalexprError, Function, HashMapContext, IntType, Value,
+};
+use std::ops::Range;
+
+// Expressions see a binary value as its text, "\x" followed by two lowercase hex digits for each
+// byte, the way the client displays it. Comparing those texts orders the values byte by byte, and
+// the functions below read them back as bytes
+
+/// The text of a binary value
+pub fn format(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(2 + bytes.len() * 2);
+    text.push_str("\\x");
+    for byte in bytes {
+        text.push_str(&format!("{:02x}", byte));
+    }
+    text
+}
+
+/// Parses the digits of a hex literal, None unless there are two of them for each byte
+pub fn parse_hex(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) || !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Parses the text of a binary value back into its bytes, None for any other text
+pub fn parse(text: &str) -> Option<Vec<u8>> {
+    parse_hex(text.strip_prefix("\\x")?)
+}
+
+/// The part of a value of a length that a substring starting at a position reads, the positions
+/// start at 1 and the substring reads to the end without a count
+fn substring_range(length: usize, start: IntType, count: Option<IntType>) -> Range<usize> {
+    let end = count.map_or(IntType::MAX, |count| start.saturating_add(count));
+    let index = |position: IntType| position.saturating_sub(1).clamp(0, length as IntType) as usize;
+    index(start)..index(end)
+}
+
+/// Registers `length(value)` and `substring(value, start, count)` in the context an expression is
+/// evaluated with, they count the bytes of a binary value and the characters of a text
+pub fn add_functions(context: &mut HashMapContext) {
+    let length = Function::new(|argument: &Value| {
+        let text = argument.as_string()?;
+        let length = match parse(&text) {
+            Some(bytes) => bytes.len(),
+            None => text.chars().count(),
+        };
+        Ok(Value::Int(length as IntType))
+    });
+    let substring = Function::new(|argument: &Value| {
+        let arguments = argument.as_tuple()?;
+        let (text, start, count) = match arguments.as_slice() {
+            [text, start] => (text.as_string()?, start.as_int()?, None),
+            [text, start, count] => (text.as_string()?, start.as_int()?, Some(count.as_int()?)),
+            _ => {
+                return Err(EvalexprError::WrongFunctionArgumentAmount {
+                    expected: 3,
+                    actual: arguments.len(),
+                })
+            }
+        };
+        if count.is_some_and(|count| count < 0) {
+            return Err(EvalexprError::CustomMessage(
+                "The length of a substring cannot be negative".to_string(),
+            ));
+        }
+        match parse(&text) {
+            Some(bytes) => {
+                let range = substring_range(bytes.len(), start, count);
+                Ok(Value::String(format(&bytes[range])))
+            }
+            None => {
+                let chars = text.chars().collect::<Vec<_>>();
+                let range = substring_range(chars.len(), start, count);
+                Ok(Value::String(chars[range].iter().collect()))
+            }
+        }
+    });
+    context.set_function("length".to_string(), length).unwrap();
+    context
+        .set_function("substring".to_string(), substring)
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::binary::{add_functions, format, parse, parse_hex};
+    use evalexpr::{eval_with_context, HashMapContext, Value};
+
+    #[test]
+    fn test_hex_and_functions() {
+        assert_eq!(parse_hex("00ff7F"), Some(vec![0, 255, 127]));
+        assert_eq!(parse_hex("abc"), None);
+        assert_eq!(parse_hex("+f"), None);
+        assert_eq!(format(&[0, 255, 127]), "\\x00ff7f");
+        assert_eq!(parse(&format(&[1, 2])), Some(vec![1, 2]));
+        assert_eq!(parse("0102"), None);
+        let mut context = HashMapContext::new();
+        add_functions(&mut context);
+        let eval = |expression: &str| eval_with_context(expression, &context).unwrap();
+        assert_eq!(eval(r#"length("\\x00ff7f")"#), Value::Int(3));
+        assert_eq!(eval(r#"length("héllo")"#), Value::Int(5));
+        assert_eq!(
+            eval(r#"substring("\\x00ff7f", 2, 1)"#),
+            Value::String("\\xff".to_string())
+        );
+        assert_eq!(
+            eval(r#"substring("héllo", 0, 3)"#),
+            Value::String("hé".to_string())
+        );
+        assert_eq!(
+            eval(r#"substring("héllo", 4)"#),
+            Value::String("lo".to_string())
+        );
+    }
+}
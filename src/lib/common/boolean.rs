@@ -1,4 +1,5 @@
-use crate::schema::types::{NumericType, Type};
+use crate::common::{binary, decimal, temporal};
+use crate::schema::types::{CharType, NumericType, Type};
 use crate::FieldId;
 use evalexpr::Value::Boolean;
 use evalexpr::{FloatType, Node as ExprTree, Node, Operator, Value};
@@ -70,6 +71,87 @@ pub fn get_all_binary_clauses(root: &ExprTree) -> Vec<Node> {
     bin_clauses
 }
 
+/// Splits a condition on its top level ANDs, a row satisfies the condition when it satisfies
+/// every returned clause
+pub fn split_conjunction(root: &ExprTree) -> Vec<Node> {
+    fn add_to_vec(root: &ExprTree, data: &mut Vec<ExprTree>) {
+        match root.operator() {
+            Operator::RootNode | Operator::And if !root.children().is_empty() => {
+                for n in root.children() {
+                    add_to_vec(n, data);
+                }
+            }
+            _ => data.push(root.clone()),
+        }
+    }
+    let mut clauses = vec![];
+    add_to_vec(root, &mut clauses);
+    clauses
+}
+
+/// The two variables compared by a `var == var` clause
+pub fn variables_equality(clause: &ExprTree) -> Option<(&str, &str)> {
+    let children = clause.children();
+    if *clause.operator() != Operator::Eq || children.len() != 2 {
+        return None;
+    }
+    match (children[0].operator(), children[1].operator()) {
+        (
+            Operator::VariableIdentifierRead { identifier: first },
+            Operator::VariableIdentifierRead { identifier: second },
+        ) => Some((first.as_str(), second.as_str())),
+        _ => None,
+    }
+}
+
+/// The field, comparison and constant of a `field op constant` clause, the comparison is turned
+/// around for a `constant op field` one
+pub fn field_comparison(clause: &ExprTree) -> Option<(&str, Operator, &Value)> {
+    let children = clause.children();
+    if children.len() != 2 {
+        return None;
+    }
+    let (op, flipped) = match clause.operator() {
+        Operator::Eq => (Operator::Eq, Operator::Eq),
+        Operator::Lt => (Operator::Lt, Operator::Gt),
+        Operator::Gt => (Operator::Gt, Operator::Lt),
+        Operator::Leq => (Operator::Leq, Operator::Geq),
+        Operator::Geq => (Operator::Geq, Operator::Leq),
+        _ => return None,
+    };
+    match (children[0].operator(), children[1].operator()) {
+        (Operator::VariableIdentifierRead { identifier }, Operator::Const { value }) => {
+            Some((identifier.as_str(), op, value))
+        }
+        (Operator::Const { value }, Operator::VariableIdentifierRead { identifier }) => {
+            Some((identifier.as_str(), flipped, value))
+        }
+        _ => None,
+    }
+}
+
+/// Writes a condition back in infix notation, `Display` of evalexpr nodes is prefix
+pub fn infix(root: &ExprTree) -> String {
+    let children = root.children();
+    match (root.operator(), children.len()) {
+        (Operator::RootNode, 1) => infix(&children[0]),
+        (op @ (Operator::And | Operator::Or), 2) => {
+            let side = |child: &ExprTree| match child.operator() {
+                // nested conditions joined by the other operator keep their parentheses
+                child_op @ (Operator::And | Operator::Or) if child_op != op => {
+                    format!("({})", infix(child))
+                }
+                _ => infix(child),
+            };
+            format!("{} {} {}", side(&children[0]), op, side(&children[1]))
+        }
+        (op, 2) => format!("{} {} {}", infix(&children[0]), op, infix(&children[1])),
+        (Operator::Not, 1) => format!("!({})", infix(&children[0])),
+        (op, 0) => op.to_string(),
+        _ => root.to_string(),
+    }
+}
+
 pub fn get_single_binary_clause(root: &ExprTree) -> (Operator, Value) {
     let op = root.operator().clone();
     let val = root
@@ -172,8 +254,18 @@ fn is_false(node: &ExprTree) -> bool {
     )
 }
 
-pub fn value_as_bytes(val: &evalexpr::Value, key_type: Type) -> Vec<u8> {
-    match val {
+pub fn value_as_bytes(val: &evalexpr::Value, key_type: Type) -> Option<Vec<u8>> {
+    if let Type::Numeric(NumericType::Decimal(_, scale)) = key_type {
+        return decimal_bytes(val, scale);
+    }
+    if let (Value::String(a), Type::Character(CharType::Char(length))) = (val, key_type) {
+        return char_bytes(a, length);
+    }
+    // a binary value is compared as its text, other texts match none
+    if let (Value::String(a), Type::Binary) = (val, key_type) {
+        return binary::parse(a);
+    }
+    let bytes = match val {
         Value::String(a) => a.as_bytes().to_vec(),
         Value::Float(f) => match key_type {
             Type::Numeric(n) => match n {
@@ -191,8 +283,56 @@ pub fn value_as_bytes(val: &evalexpr::Value, key_type: Type) -> Vec<u8> {
                 NumericType::Serial => (*i as i32).to_ne_bytes().to_vec(),
                 _ => unreachable!(),
             },
+            // a date only holds the constants falling on a midnight
+            Type::Temporal(t) => return temporal::to_bytes(t, *i),
             _ => unreachable!(),
         },
         _ => unreachable!(),
+    };
+    Some(bytes)
+}
+
+/// The bytes a decimal field of a scale holds for a constant, None when it has digits past the
+/// scale
+fn decimal_bytes(val: &Value, scale: u8) -> Option<Vec<u8>> {
+    let digits = match val {
+        Value::Int(i) => decimal::parse_exact(&i.to_string(), scale),
+        Value::Float(f) => decimal::parse_exact(&f.to_string(), scale),
+        _ => None,
+    };
+    digits.map(|digits| digits.to_ne_bytes().to_vec())
+}
+
+/// The bytes a char field of a length holds for a constant. Expressions read a char without its
+/// padding blanks, None when the constant ends with one as no char equals it
+fn char_bytes(text: &str, length: u8) -> Option<Vec<u8>> {
+    if text.ends_with(' ') {
+        None
+    } else {
+        CharType::padded(text, length)
+    }
+}
+
+/// The bytes a field of a type holds for a constant, None when the field cannot hold it exactly
+pub fn value_as_field_bytes(val: &Value, field_type: Type) -> Option<Vec<u8>> {
+    match (val, field_type) {
+        (Value::String(a), Type::Character(CharType::Char(length))) => char_bytes(a, length),
+        (Value::String(a), Type::Character(_)) => Some(a.as_bytes().to_vec()),
+        (Value::String(a), Type::Binary) => binary::parse(a),
+        (Value::Float(f), Type::Numeric(NumericType::Double)) => Some(f.to_ne_bytes().to_vec()),
+        (Value::Int(_) | Value::Float(_), Type::Numeric(NumericType::Decimal(_, scale))) => {
+            decimal_bytes(val, scale)
+        }
+        (Value::Int(i), Type::Numeric(n)) => match n {
+            NumericType::SmallInt => i16::try_from(*i).ok().map(|i| i.to_ne_bytes().to_vec()),
+            NumericType::Integer | NumericType::Serial => {
+                i32::try_from(*i).ok().map(|i| i.to_ne_bytes().to_vec())
+            }
+            NumericType::BigInt => Some(i.to_ne_bytes().to_vec()),
+            NumericType::Single | NumericType::Double | NumericType::Decimal(_, _) => None,
+        },
+        (Value::Boolean(b), Type::Boolean) => Some(vec![*b as u8]),
+        (Value::Int(i), Type::Temporal(t)) => temporal::to_bytes(t, *i),
+        _ => None,
     }
 }
@@ -198,6 +198,34 @@ impl<T: Ord + ToOwned<Owned = T> + Encode + Decode, U: Encode + Decode> BTreeMul
         self.pop_first_key_match(|(k, v)| **k >= value)
     }
 
+    /// Removes the first occurrence of element U from whichever vector holds it, removes the entry
+    /// if the vector is empty after that and returns the key K the element was stored under
+    pub fn remove_element(&mut self, element: &U) -> Option<T>
+    where
+        U: PartialEq,
+    {
+        let entry = self
+            .btreemap
+            .iter_mut()
+            .find_map(|(k, v)| v.iter().position(|e| e == element).map(|idx| (k, v, idx)));
+        let entry = match entry {
+            None => None,
+            Some((k, v, idx)) => {
+                v.remove(idx);
+                Some((k.to_owned(), v.is_empty()))
+            }
+        };
+        match entry {
+            None => None,
+            Some((k, empty)) => {
+                if empty {
+                    self.btreemap.remove(&k);
+                }
+                Some(k)
+            }
+        }
+    }
+
     /// Helper function to print the tree
     pub fn print_all(&self)
     where
@@ -251,6 +279,18 @@ mod tests {
         // multimap.print_all()
     }
 
+    #[test]
+    fn remove_element() {
+        let mut multimap = BTreeMultimap::new();
+        multimap.insert_vec(100_u16, vec![1_u16, 2].as_slice());
+        multimap.insert_vec(150, vec![3].as_slice());
+        assert_eq!(multimap.remove_element(&2), Some(100));
+        assert_eq!(multimap.get(100).unwrap(), &vec![1]);
+        assert_eq!(multimap.remove_element(&3), Some(150));
+        assert!(multimap.get(150).is_none());
+        assert_eq!(multimap.remove_element(&3), None);
+    }
+
     #[test]
     fn range_mut() {
         let mut multimap = BTreeMultimap::new();
@@ -0,0 +1,122 @@
+use std::cmp::Ordering;
+
+// A decimal is kept as its unscaled value, the integer its digits make without the point, and
+// its scale, the number of those digits after the point: 12.50 is (1250, 2)
+
+/// The largest precision of a decimal, its unscaled value has to fit an i128
+pub const MAX_PRECISION: u8 = 38;
+/// The digits a quotient keeps past the scale of its dividend
+const QUOTIENT_EXTRA_SCALE: u8 = 4;
+
+fn power(exponent: u8) -> Option<i128> {
+    10_i128.checked_pow(exponent as u32)
+}
+
+/// The scale of the quotient of a decimal of a scale, AVG gives its results at it
+pub fn quotient_scale(scale: u8) -> u8 {
+    (scale + QUOTIENT_EXTRA_SCALE).min(MAX_PRECISION)
+}
+
+/// Whether an unscaled value has no more digits than a precision allows
+pub fn fits(value: i128, precision: u8) -> bool {
+    value.unsigned_abs() < 10_u128.pow(precision as u32)
+}
+
+/// Changes the scale of an unscaled value, the digits dropped round half away from zero
+pub fn rescale(value: i128, from: u8, to: u8) -> Option<i128> {
+    if to >= from {
+        return value.checked_mul(power(to - from)?);
+    }
+    let divisor = power(from - to)?;
+    let rounding = if (value % divisor).unsigned_abs() * 2 >= divisor as u128 {
+        value.signum()
+    } else {
+        0
+    };
+    Some(value / divisor + rounding)
+}
+
+/// The unscaled value and the scale of the text of a number, like `-12.50` or `.5`
+fn read(text: &str) -> Option<(i128, u8)> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    let all_digits = integer
+        .chars()
+        .chain(fraction.chars())
+        .all(|c| c.is_ascii_digit());
+    if !all_digits || integer.len() + fraction.len() == 0 {
+        return None;
+    }
+    let scale = u8::try_from(fraction.len()).ok()?;
+    let mut value: i128 = 0;
+    for digit in integer.bytes().chain(fraction.bytes()) {
+        value = value.checked_mul(10)?.checked_add((digit - b'0') as i128)?;
+    }
+    Some((if negative { -value } else { value }, scale))
+}
+
+/// Reads the text of a number into its unscaled value at a scale, the digits past the scale
+/// round half away from zero
+pub fn parse(text: &str, scale: u8) -> Option<i128> {
+    let (value, from) = read(text)?;
+    rescale(value, from, scale)
+}
+
+/// Reads the text of a number into its unscaled value at a scale, None when the number has
+/// digits past the scale
+pub fn parse_exact(text: &str, scale: u8) -> Option<i128> {
+    let (value, from) = read(text)?;
+    let rescaled = rescale(value, from, scale)?;
+    (rescale(rescaled, scale, from)? == value).then_some(rescaled)
+}
+
+/// Writes an unscaled value with its digits past the scale after the point
+pub fn format(value: i128, scale: u8) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let digits = value.unsigned_abs().to_string();
+    if scale == 0 {
+        return format!("{sign}{digits}");
+    }
+    let digits = format!("{:0>width$}", digits, width = scale as usize + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - scale as usize);
+    format!("{sign}{integer}.{fraction}")
+}
+
+/// The closest float to a decimal
+pub fn to_f64(value: i128, scale: u8) -> f64 {
+    format(value, scale).parse().unwrap()
+}
+
+/// Compares two decimals of any scales without rescaling them, which could overflow
+pub fn cmp(left: i128, left_scale: u8, right: i128, right_scale: u8) -> Ordering {
+    let (left_divisor, right_divisor) = (power(left_scale).unwrap(), power(right_scale).unwrap());
+    // truncating toward zero keeps the order, equal integer parts leave it to the fractions
+    match (left / left_divisor).cmp(&(right / right_divisor)) {
+        Ordering::Equal => {
+            let scale = left_scale.max(right_scale);
+            let left = rescale(left % left_divisor, left_scale, scale).unwrap();
+            let right = rescale(right % right_divisor, right_scale, scale).unwrap();
+            left.cmp(&right)
+        }
+        ordering => ordering,
+    }
+}
+
+/// Divides two decimals, the quotient has the scale `quotient_scale` gives for the dividend and
+/// rounds half away from zero. None when the divisor is zero or the quotient overflows
+pub fn divide(left: i128, left_scale: u8, right: i128, right_scale: u8) -> Option<(i128, u8)> {
+    if right == 0 {
+        return None;
+    }
+    let scale = quotient_scale(left_scale);
+    let dividend = left.checked_mul(power(scale - left_scale + right_scale)?)?;
+    let rounding = if (dividend % right).unsigned_abs() * 2 >= right.unsigned_abs() {
+        dividend.signum() * right.signum()
+    } else {
+        0
+    };
+    Some((dividend / right + rounding, scale))
+}
@@ -1,9 +1,9 @@
-/// Creates an Rc<RefCell<Value>>
+/// Creates an Arc<RwLock<Value>>
 ///
-/// Equivalent to Rc::new( RefCell::new( value ) )
+/// Equivalent to Arc::new( RwLock::new( value ) )
 #[macro_export]
-macro_rules! RcRefCell {
+macro_rules! ArcRwLock {
     ($a:expr) => {
-        std::rc::Rc::new(std::cell::RefCell::new($a))
+        std::sync::Arc::new(std::sync::RwLock::new($a))
     };
 }
@@ -1,6 +1,11 @@
+pub mod binary;
 pub mod boolean;
 pub mod btree_multimap;
+pub mod decimal;
 pub mod fileops;
 pub mod macros;
 pub mod net;
 pub mod numerical;
+pub mod temporal;
+#[cfg(test)]
+pub mod testing;
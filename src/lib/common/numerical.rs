@@ -31,6 +31,7 @@ pub trait ByteMagic {
     fn to_i16(self) -> i16;
     fn to_i32(self) -> i32;
     fn to_i64(self) -> i64;
+    fn to_i128(self) -> i128;
 }
 
 impl ByteMagic for &[u8] {
@@ -107,6 +108,10 @@ impl ByteMagic for &[u8] {
     fn to_i64(self) -> i64 {
         i64::from_ne_bytes(self.try_into().unwrap())
     }
+
+    fn to_i128(self) -> i128 {
+        i128::from_ne_bytes(self.try_into().unwrap())
+    }
 }
 
 #[cfg(test)]
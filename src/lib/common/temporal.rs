@@ -0,0 +1,223 @@
+use crate::common::numerical::ByteMagic;
+use crate::schema::types::TemporalType;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use evalexpr::{ContextWithMutableFunctions, EvalexprError, Function, HashMapContext, Value};
+
+// Expressions see every temporal value as a number of microseconds: since the epoch for dates and
+// timestamps, since midnight for times. Comparing them, or adding and subtracting intervals, is
+// then plain integer arithmetic
+
+pub const MICROS_PER_DAY: i64 = 86_400_000_000;
+const MICROS_PER_SECOND: i64 = 1_000_000;
+
+const TIME_FORMATS: [&str; 2] = ["%H:%M:%S%.f", "%H:%M"];
+const TIMESTAMP_FORMATS: [&str; 3] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+];
+
+type FieldReader = fn(NaiveDateTime) -> i64;
+
+/// The fields of a temporal value the functions of expressions read, by the name of the function
+const FIELDS: [(&str, FieldReader); 7] = [
+    ("year", |t| t.year() as i64),
+    ("month", |t| t.month() as i64),
+    ("day", |t| t.day() as i64),
+    ("hour", |t| t.hour() as i64),
+    ("minute", |t| t.minute() as i64),
+    ("second", |t| t.second() as i64),
+    ("epoch", |t| {
+        timestamp_micros(t).map_or(0, |micros| micros.div_euclid(MICROS_PER_SECOND))
+    }),
+];
+
+fn epoch() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).expect("the epoch is a valid date")
+}
+
+fn datetime(micros: i64) -> Option<NaiveDateTime> {
+    epoch()
+        .and_hms_opt(0, 0, 0)?
+        .checked_add_signed(Duration::microseconds(micros))
+}
+
+fn timestamp_micros(timestamp: NaiveDateTime) -> Option<i64> {
+    (timestamp - epoch().and_hms_opt(0, 0, 0)?).num_microseconds()
+}
+
+fn time_micros(time: NaiveTime) -> i64 {
+    time.num_seconds_from_midnight() as i64 * MICROS_PER_SECOND + time.nanosecond() as i64 / 1000
+}
+
+/// Parses the text of a value of a temporal type into its microseconds
+///
+/// A timestamp may leave out its time of day, it is then midnight
+pub fn parse(temporal: TemporalType, text: &str) -> Result<i64, String> {
+    let text = text.trim();
+    let micros = match temporal {
+        TemporalType::Date => NaiveDate::parse_from_str(text, "%Y-%m-%d")
+            .ok()
+            .map(|date| (date - epoch()).num_days() * MICROS_PER_DAY),
+        TemporalType::Time => TIME_FORMATS
+            .iter()
+            .find_map(|format| NaiveTime::parse_from_str(text, format).ok())
+            .map(time_micros),
+        TemporalType::Timestamp => TIMESTAMP_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+            .or_else(|| {
+                NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                    .ok()
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+            })
+            .and_then(timestamp_micros),
+        TemporalType::Interval => return parse_interval(text),
+    };
+    micros.ok_or_else(|| format!("\"{}\" is not a {}", text, temporal))
+}
+
+/// Parses an interval written as counts of units, like `1 day 12 hours`, and clock readings like
+/// `-01:30:00`.
+///
+/// Months and years are refused, their number of days varies
+fn parse_interval(text: &str) -> Result<i64, String> {
+    let invalid = || format!("\"{}\" is not an interval", text);
+    let mut micros: i64 = 0;
+    let mut words = text.split_whitespace();
+    while let Some(word) = words.next() {
+        let part = if word.contains(':') {
+            let (negative, clock) = match word.strip_prefix('-') {
+                Some(clock) => (true, clock),
+                None => (false, word),
+            };
+            let time = TIME_FORMATS
+                .iter()
+                .find_map(|format| NaiveTime::parse_from_str(clock, format).ok())
+                .ok_or_else(invalid)?;
+            if negative {
+                -time_micros(time)
+            } else {
+                time_micros(time)
+            }
+        } else {
+            let count = word.parse::<i64>().map_err(|_| invalid())?;
+            let unit = words.next().ok_or_else(invalid)?.to_lowercase();
+            let unit_micros = match unit.strip_suffix('s').unwrap_or(&unit) {
+                "microsecond" => 1,
+                "millisecond" => 1000,
+                "second" => MICROS_PER_SECOND,
+                "minute" => 60 * MICROS_PER_SECOND,
+                "hour" => 3600 * MICROS_PER_SECOND,
+                "day" => MICROS_PER_DAY,
+                "week" => 7 * MICROS_PER_DAY,
+                "month" | "year" => {
+                    return Err(String::from(
+                        "An interval cannot count months or years, their number of days varies",
+                    ))
+                }
+                _ => return Err(invalid()),
+            };
+            count.checked_mul(unit_micros).ok_or_else(invalid)?
+        };
+        micros = micros.checked_add(part).ok_or_else(invalid)?;
+    }
+    Ok(micros)
+}
+
+/// Writes the microseconds of a value of a temporal type the way its literals are written
+pub fn format(temporal: TemporalType, micros: i64) -> String {
+    match temporal {
+        TemporalType::Date => (epoch() + Duration::days(micros.div_euclid(MICROS_PER_DAY)))
+            .format("%Y-%m-%d")
+            .to_string(),
+        TemporalType::Time => match datetime(micros) {
+            Some(time) => time.format("%H:%M:%S%.f").to_string(),
+            None => micros.to_string(),
+        },
+        TemporalType::Timestamp => match datetime(micros) {
+            Some(timestamp) => timestamp.format("%Y-%m-%d %H:%M:%S%.f").to_string(),
+            None => micros.to_string(),
+        },
+        TemporalType::Interval => {
+            let sign = if micros < 0 { "-" } else { "" };
+            let micros = micros.unsigned_abs();
+            let days = micros / MICROS_PER_DAY as u64;
+            let clock = datetime((micros % MICROS_PER_DAY as u64) as i64)
+                .unwrap()
+                .format("%H:%M:%S%.f");
+            match days {
+                0 => format!("{sign}{clock}"),
+                1 => format!("{sign}1 day {sign}{clock}"),
+                _ => format!("{sign}{days} days {sign}{clock}"),
+            }
+        }
+    }
+}
+
+/// The bytes a field of a temporal type holds for a number of microseconds, None when a date is
+/// not a whole number of days
+pub fn to_bytes(temporal: TemporalType, micros: i64) -> Option<Vec<u8>> {
+    match temporal {
+        TemporalType::Date if micros % MICROS_PER_DAY == 0 => {
+            i32::try_from(micros / MICROS_PER_DAY)
+                .ok()
+                .map(|days| days.to_ne_bytes().to_vec())
+        }
+        TemporalType::Date => None,
+        _ => Some(micros.to_ne_bytes().to_vec()),
+    }
+}
+
+/// The number of microseconds a field of a temporal type holds
+pub fn from_bytes(temporal: TemporalType, bytes: &[u8]) -> i64 {
+    match temporal {
+        TemporalType::Date => bytes.to_i32() as i64 * MICROS_PER_DAY,
+        _ => bytes.to_i64(),
+    }
+}
+
+/// Registers the functions reading a field of a temporal value, like `year(orders.placed)`, in
+/// the context an expression is evaluated with
+pub fn add_functions(context: &mut HashMapContext) {
+    for (name, field) in FIELDS {
+        let function = Function::new(move |argument: &Value| {
+            let micros = argument.as_int()?;
+            match datetime(micros) {
+                Some(datetime) => Ok(Value::Int(field(datetime))),
+                None => Err(EvalexprError::CustomMessage(format!(
+                    "{} is out of the range of timestamps",
+                    micros
+                ))),
+            }
+        });
+        context.set_function(name.to_string(), function).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::temporal::{format, parse, MICROS_PER_DAY};
+    use crate::schema::types::TemporalType::{Date, Interval, Time, Timestamp};
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(parse(Date, "1970-01-02"), Ok(MICROS_PER_DAY));
+        assert_eq!(parse(Timestamp, "1970-01-02"), Ok(MICROS_PER_DAY));
+        assert_eq!(
+            parse(Interval, "1 day 02:00:00"),
+            parse(Interval, "26 hours")
+        );
+        assert!(parse(Interval, "1 month").is_err());
+        assert!(parse(Date, "2023-02-29").is_err());
+        for (temporal, text) in [
+            (Date, "1969-12-31"),
+            (Time, "23:59:59.500"),
+            (Timestamp, "2024-02-29 08:30:00"),
+            (Interval, "-3 days -00:00:01"),
+            (Interval, "1 day 00:00:00"),
+        ] {
+            assert_eq!(format(temporal, parse(temporal, text).unwrap()), text);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::database::db::DatabaseInstance;
+use crate::interface::message::{Message, Status};
+use crate::meta::catalogmgr::CatalogManager;
+use crate::query::concrete_types::ConcreteType;
+use crate::storage::storagemgr::StorageManager;
+use crate::table::tablemgr::TableManager;
+use crate::AQUA_HOME_VAR;
+use rand::random;
+use std::env;
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, Weak};
+use std::thread;
+
+const BLK_SIZE: usize = 4096;
+const HOME_BUFFERS: u32 = 400;
+
+/// A directory of a test under the system temp directory, removed with everything in it once the
+/// test drops it
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("aqua_{}_test{}", name, random::<u64>()));
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+    pub fn to_str(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+    /// A storage manager keeping its files in the directory, it has to be dropped before the
+    /// directory is
+    pub fn storage(&self, buffers: u32) -> Arc<RwLock<StorageManager>> {
+        Arc::new(RwLock::new(StorageManager::new(
+            self.to_str(),
+            BLK_SIZE,
+            buffers,
+        )))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.path).unwrap_or_default();
+    }
+}
+
+static HOME: Mutex<Weak<TestHome>> = Mutex::new(Weak::new());
+
+/// The data directory of the tests that need one, set as AQUADATA while any of them runs.
+///
+/// The tests share its storage and catalogs the way the sessions of a server do, each of them
+/// works in a database of its own. The directory is removed once the last test holding it ends
+pub struct TestHome {
+    pub catalog: Arc<RwLock<CatalogManager>>,
+    pub storage: Arc<RwLock<StorageManager>>,
+    // held by the tests that spill to the temporary files directory and check it is emptied
+    pub tmp: Mutex<()>,
+    dir: TempDir,
+}
+
+impl TestHome {
+    pub fn get() -> Arc<Self> {
+        let mut home = HOME.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(home) = home.upgrade() {
+            return home;
+        }
+        let dir = TempDir::new("home");
+        fs::create_dir_all(dir.join("base").join("tmp")).unwrap();
+        fs::create_dir_all(dir.join("global")).unwrap();
+        env::set_var(AQUA_HOME_VAR, dir.path());
+        CatalogManager::init_catalogs();
+        let storage = dir.storage(HOME_BUFFERS);
+        let catalog = Arc::new(RwLock::new(CatalogManager::startup(storage.clone())));
+        let created = Arc::new(Self {
+            catalog,
+            storage,
+            tmp: Mutex::new(()),
+            dir,
+        });
+        *home = Arc::downgrade(&created);
+        created
+    }
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+    pub fn tmp_dir(&self) -> PathBuf {
+        self.dir.join("base").join("tmp")
+    }
+}
+
+/// A session connected to a database of its own in the test home, the database is dropped with
+/// the session.
+///
+/// The session sends its messages through a socket like it would to a client, they are collected
+/// on the other end
+pub struct TestSession {
+    name: String,
+    instance: Option<DatabaseInstance>,
+    conn: TcpStream,
+    messages: Receiver<Message>,
+    home: Arc<TestHome>,
+}
+
+impl TestSession {
+    pub fn open() -> Self {
+        let home = TestHome::get();
+        let name = format!("test{}", random::<u32>());
+        home.catalog
+            .write()
+            .unwrap()
+            .create_database(&name)
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let conn = listener.accept().unwrap().0;
+        let (sender, messages) = channel();
+        thread::spawn(move || {
+            while let Ok(msg) = Message::receive_msg(&mut client) {
+                if sender.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+        let instance = DatabaseInstance::new(
+            &name,
+            home.storage.clone(),
+            home.catalog.clone(),
+            conn.try_clone().unwrap(),
+        );
+        Self {
+            name,
+            instance: Some(instance),
+            conn,
+            messages,
+            home,
+        }
+    }
+    /// Connects another session to the database of this one, it has to be dropped before this
+    /// one is.
+    ///
+    /// Nothing reads the messages of the other session, they wait in the returned client end of
+    /// its connection until the caller reads them, a session sending more than the socket holds
+    /// waits for it
+    pub fn connect(&self) -> (DatabaseInstance, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let conn = listener.accept().unwrap().0;
+        let instance = DatabaseInstance::new(
+            &self.name,
+            self.home.storage.clone(),
+            self.home.catalog.clone(),
+            conn,
+        );
+        (instance, client)
+    }
+    pub fn instance(&mut self) -> &mut DatabaseInstance {
+        self.instance.as_mut().unwrap()
+    }
+    /// Runs a query and returns the messages sent back for it
+    pub fn run(&mut self, query: &str) -> Vec<Message> {
+        self.instance().run_query(query);
+        // a session never sends a query, one marks the end of the messages of this one
+        Message::Query(String::new())
+            .send_msg_to(&mut self.conn)
+            .unwrap();
+        self.messages
+            .iter()
+            .take_while(|msg| !matches!(msg, Message::Query(_)))
+            .collect()
+    }
+    /// The status a query ended with
+    pub fn status(&mut self, query: &str) -> Status {
+        match self.run(query).pop() {
+            Some(Message::Status(status)) => status,
+            _ => panic!("{} sent no status", query),
+        }
+    }
+    /// The rows a query returned in the order they were sent, each with its values as text in the
+    /// order of the names of their fields, NULL for a missing value
+    pub fn rows(&mut self, query: &str) -> Vec<Vec<String>> {
+        let mut types = None;
+        let mut rows = vec![];
+        for msg in self.run(query) {
+            match msg {
+                Message::FieldTypes(fields) => {
+                    let mut fields = fields.into_iter().collect::<Vec<_>>();
+                    fields.sort_by_key(|(field, _)| field.to_string());
+                    types = Some(fields);
+                }
+                Message::Results(results) => {
+                    let fields = types.as_ref().unwrap();
+                    rows.extend(results.into_iter().map(|row| {
+                        fields
+                            .iter()
+                            .map(|(field, datatype)| match &row[field] {
+                                Some(bytes) => {
+                                    ConcreteType::from_bytes(*datatype, bytes).to_string()
+                                }
+                                None => "NULL".to_string(),
+                            })
+                            .collect()
+                    }));
+                }
+                Message::Status(Status::ResultsFinished) => {}
+                other => panic!("{} failed: {}", query, other.get_status().unwrap()),
+            }
+        }
+        rows
+    }
+    /// The rows of a query sorted, for queries with no order
+    pub fn sorted_rows(&mut self, query: &str) -> Vec<Vec<String>> {
+        let mut rows = self.rows(query);
+        rows.sort();
+        rows
+    }
+    /// Loads a table of the database from its files, apart from the one the session uses
+    pub fn load_table(&self, table: &str) -> TableManager {
+        let catalog = self.home.catalog.read().unwrap();
+        catalog.load_table(&self.name, table).unwrap()
+    }
+    /// The path of a file of the database
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.home.path().join("base").join(&self.name).join(name)
+    }
+}
+
+impl Drop for TestSession {
+    fn drop(&mut self) {
+        drop(self.instance.take());
+        // a failed test may have left the catalog poisoned
+        if let Ok(mut catalog) = self.home.catalog.write() {
+            catalog.drop_database(&self.name).unwrap_or_default();
+        }
+    }
+}
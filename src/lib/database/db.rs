@@ -1,27 +1,35 @@
 use crate::common::net::{receive_string, send_string};
-use crate::meta::catalogmgr::CatalogManager;
+use crate::database::lockmgr::{LockMode, Lockable};
+use crate::database::transaction::{Savepoint, Transaction};
+use crate::database::txnmgr::{Snapshot, TransactionManager};
+use crate::meta::catalogmgr::{CatalogManager, SharedTables};
 // use crate::query::plan::{create_plan, QueryPlan};
 use crate::interface::message::{Message, RowMap, Status};
 use crate::query::executor::Executor;
 use crate::query::physical::PhysicalNode;
 use crate::schema::schema::Schema;
+use crate::schema::types::{CharType::VarChar, NumericType::BigInt, Type};
+use crate::sql::alter_table::{AlterAction, AlterTable};
+use crate::sql::create_table::Index;
 use crate::sql::parser::{parse_query, SqlParser};
-use crate::sql::query::query::SqlQuery;
+use crate::sql::query::query::{SqlQuery, SqlValue};
+use crate::sql::transaction::TransactionControl;
 use crate::sql::Sql;
 use crate::{AQUA_TMP_DIR, storage};
 use crate::storage::storagemgr::StorageManager;
 use crate::table::tablemgr::TableManager;
-use std::cell::RefCell;
+use std::sync::RwLock;
 use std::collections::HashMap;
 use std::io::Read;
 use std::net::TcpStream;
 use std::ops::AddAssign;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Duration;
 use crate::index::Rid;
+use crate::FieldId;
 
-type Storage = Rc<RefCell<StorageManager>>;
-type Catalog = Rc<RefCell<CatalogManager>>;
+type Storage = Arc<RwLock<StorageManager>>;
+type Catalog = Arc<RwLock<CatalogManager>>;
 type Record = Result<Vec<(String, Option<Vec<u8>>)>, String>;
 type DbTables = HashMap<String, TableManager>;
 
@@ -31,8 +39,20 @@ type Row = HashMap<String, Option<Vec<u8>>>;
 
 pub enum QueryPlan {
     CreateTable(Schema),
+    AlterTable(AlterTable),
+    DropTable(String, bool),
+    Truncate(String),
+    CreateIndex(String, Index),
+    DropIndex(String, bool),
     Insert(Record, Schema),
     Select(PhysicalNode),
+    Update(PhysicalNode, Record, Schema),
+    Delete(PhysicalNode, Schema),
+    /// A planned query to explain, ran first when it is analyzed
+    Explain(PhysicalNode, bool),
+    /// The values given by the nextval and currval calls of a SELECT without a table, each as the
+    /// column of its sequence named after its function
+    SequenceValues(Vec<(FieldId, i64)>),
 }
 
 pub struct DatabaseInstance {
@@ -40,156 +60,461 @@ pub struct DatabaseInstance {
     storage: Storage,
     catalog: Catalog,
     conn: TcpStream,
-    tables: DbTables,
+    tables: SharedTables,
+    transactions: Arc<TransactionManager>,
+    // the transaction block the session is in, between BEGIN and COMMIT or ROLLBACK
+    transaction: Option<Transaction>,
+    // the last value nextval gave the session for each sequence, currval reads it
+    sequence_values: HashMap<String, i64>,
 }
 
 impl DatabaseInstance {
     pub fn new(name: &str, storage: Storage, catalog: Catalog, conn: TcpStream) -> Self {
         // .into_iter().map(|(k,v)| (k,Rc::new(v))).collect();
 
-        let tables = catalog.borrow().get_db_tables(name);
+        let tables = catalog.write().unwrap().get_db_tables(name);
+        let transactions = catalog.write().unwrap().get_db_transactions(name);
         Self {
             name: name.to_string(),
             storage,
             catalog,
             conn,
             tables,
+            transactions,
+            transaction: None,
+            sequence_values: HashMap::new(),
         }
     }
+    /// Why the database cannot be used anymore, None while it can.
+    ///
+    /// A session that panicked while holding the storage, the catalogs or the tables of the
+    /// database left them poisoned. What they guard may be half changed, the database is not used
+    /// again until the server restarts and recovers it from the log
+    pub fn failure(&self) -> Option<String> {
+        let poisoned =
+            self.storage.is_poisoned() || self.catalog.is_poisoned() || self.tables.is_poisoned();
+        poisoned.then(|| {
+            format!(
+                "Database {} is unavailable until the server restarts, a session failed while \
+                 changing it",
+                self.name
+            )
+        })
+    }
     pub fn flush_everything(&self) {
-        for tbl in self.tables.values() {
-            tbl.flush_all();
+        // a session that panicked while holding the tables or the storage poisons them, there is
+        // nothing safe to flush from it anymore
+        if self.storage.is_poisoned() {
+            return;
+        }
+        if let Ok(tables) = self.tables.lock() {
+            for tbl in tables.values() {
+                tbl.flush_all();
+            }
         }
     }
     pub fn handle_connection(&mut self) {
         self.conn.set_nonblocking(false);
-        'outer: loop {
-            let query = match Message::receive_msg(&mut self.conn) {
-                Ok(msg) => match msg.get_query() {
-                    Ok(s) => s,
-                    _ => return,
-                },
-                Err(_) => return,
+        while let Ok(msg) = Message::receive_msg(&mut self.conn) {
+            let query = match msg.get_query() {
+                Ok(s) => s,
+                _ => break,
             };
+            if let Some(failure) = self.failure() {
+                Message::Status(Status::Generic(failure))
+                    .send_msg_to(&mut self.conn)
+                    .unwrap_or_default();
+                break;
+            }
             if query.eq_ignore_ascii_case("exit db") {
                 Message::Status(Status::Generic(format!("Exit DB {}", self.name)))
                     .send_msg_to(&mut self.conn)
                     .unwrap_or_default();
-                return;
+                break;
             }
-            if query.eq("BATCH"){
+            if query.eq("BATCH") {
                 Message::Status(Status::Generic(String::from("BATCH INSERT MODE")))
-                    .send_msg_to(&mut self.conn).unwrap();
-
+                    .send_msg_to(&mut self.conn)
+                    .unwrap();
                 let inserts = match Message::receive_msg(&mut self.conn) {
                     Ok(msg) => match msg.get_query() {
                         Ok(s) => s,
-                        _ => return,
+                        _ => break,
                     },
-                    Err(_) => return,
+                    Err(_) => break,
                 };
-                let mut inserted_counter = 0;
-                let mut insert_plans = vec![];
-                for line in inserts.lines(){
-                    if line.is_empty() || line == "\n"{
-                        continue;
+                self.execute_batch(&inserts);
+                continue;
+            }
+            self.run_query(&query);
+        }
+        // the transaction of a client that left without ending it is rolled back. In a database
+        // that failed it is only aborted, its versions are removed once the server restarts
+        if let Some(transaction) = self.transaction.take() {
+            if self.failure().is_none() {
+                transaction.rollback(&mut self.tables.lock().unwrap());
+                self.storage.write().unwrap().flush_log();
+            }
+        }
+    }
+
+    /// Runs a query of the client, its results and status are sent back to the client
+    pub(crate) fn run_query(&mut self, query: &str) {
+        match parse_query(query) {
+            Ok(Sql::Transaction(control)) => self.control_transaction(control),
+            Ok(Sql::Vacuum(table)) => self.vacuum(table),
+            Ok(Sql::CreateSequence(name, start, increment)) => {
+                self.create_sequence(name, start, increment)
+            }
+            Ok(Sql::DropSequence(name, if_exists)) => self.drop_sequence(name, if_exists),
+            Ok(parsed) => self.execute_statement(query, parsed),
+            Err(e) => Message::Status(Status::Generic(e.to_string()))
+                .send_msg_to(&mut self.conn)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Inserts the records of a batch, all of them or none.
+    ///
+    /// The batch is planned before anything is inserted, then the inserts run as one unit, the
+    /// first failure rolls back the records inserted before it.
+    /// A batch sent inside a transaction becomes part of it
+    fn execute_batch(&mut self, inserts: &str) {
+        let explicit = self.transaction.is_some();
+        let mut transaction = self
+            .transaction
+            .take()
+            .unwrap_or_else(|| Transaction::new(self.transactions.clone()));
+        let shared = self.tables.clone();
+        let mut insert_plans = vec![];
+        let tables = shared.lock().unwrap();
+        for line in inserts.lines() {
+            if line.is_empty() || line == "\n" {
+                continue;
+            }
+            let planned = parse_query(line)
+                .map_err(|e| e.to_string())
+                .and_then(|parsed| self.create_plan(parsed, &tables, transaction.snapshot()));
+            match planned {
+                Ok(QueryPlan::Insert(Ok(record), schema)) => insert_plans.push((record, schema)),
+                Ok(QueryPlan::Insert(Err(e), _)) | Err(e) => {
+                    if explicit {
+                        self.transaction = Some(transaction);
                     }
-                    match parse_query(line){
-                        Ok(parsed) => {
-                            match self.create_plan(parsed){
-                                Ok(q) => {
-                                    if let QueryPlan::Insert(r,s) = &q{
-                                       match r{
-                                           Ok(_) => insert_plans.push(q),
-                                           Err(e) => { Message::Status(Status::Generic(format!("Batch Planning Failed: {}", e))).send_msg_to(&mut self.conn).unwrap();
-                                               continue 'outer
-                                           }
-                                       }
-                                    }
-                                }
-                                Err(e) => {
-                                    Message::Status(Status::Generic(format!("Batch Planning Failed: {}", e))).send_msg_to(&mut self.conn).unwrap();
-                                    continue 'outer
-                                }
-                            }
-                        }
-                        Err(e) => { Message::Status(Status::Generic(format!("Batch Planning Failed: {}", e))).send_msg_to(&mut self.conn).unwrap();
-                            continue 'outer
-                        }
+                    Message::Status(Status::Generic(format!("Batch Planning Failed: {}", e)))
+                        .send_msg_to(&mut self.conn)
+                        .unwrap();
+                    return;
+                }
+                Ok(_) => continue,
+            }
+        }
+        drop(tables);
+        let foreign_keys = self.catalog.read().unwrap().foreign_keys(&self.name);
+        let savepoint = transaction.savepoint();
+        let status = loop {
+            let mut tables = shared.lock().unwrap();
+            let mut executor = Executor::new(&mut tables, &mut transaction, foreign_keys.clone());
+            let mut inserted_counter = 0;
+            let mut failure = None;
+            for (record, schema) in insert_plans.iter().cloned() {
+                match executor.insert_record(record, schema) {
+                    Ok(_) => inserted_counter += 1,
+                    Err(e) => {
+                        failure = Some(e);
+                        break;
                     }
                 }
-                let mut executor = Executor::new(&mut self.tables);
-                let mut table_name = String::new();
-                for plan in insert_plans{
-                    if let QueryPlan::Insert(r,s) = plan{
-                        table_name = s.name().to_string();
-                        match executor.insert_record(r.unwrap(),s){
-                            Ok(_) => inserted_counter+=1,
-                            Err(e) => { Message::Status(Status::Generic(format!("Batch Insertion Failed at {} : {}", inserted_counter, e))).send_msg_to(&mut self.conn).unwrap();
-                                continue;
-                            }
-                        }
+            }
+            if let Some((target, mode)) = transaction.take_blocked() {
+                transaction.rollback_to(savepoint, &mut tables);
+                transaction.unlock_to(savepoint);
+                drop(tables);
+                if !self.wait_for_lock(&mut transaction, &target, mode) {
+                    return;
+                }
+                continue;
+            }
+            let status = match failure {
+                Some(e) => {
+                    transaction.rollback_to(savepoint, &mut tables);
+                    format!(
+                        "Batch Insertion Failed at {} : {}, no record was inserted",
+                        inserted_counter, e
+                    )
+                }
+                None => format!("Batch Success, Inserted: {}", inserted_counter),
+            };
+            if explicit {
+                self.transaction = Some(transaction);
+            } else {
+                // the batch commits before the tables are released
+                self.storage.write().unwrap().flush_log();
+                transaction.commit();
+            }
+            break status;
+        };
+        Message::Status(Status::Generic(status))
+            .send_msg_to(&mut self.conn)
+            .unwrap();
+    }
 
+    /// Runs a statement as part of the transaction of the session, or as a transaction of its own.
+    ///
+    /// The statement holds the tables of the database while it runs, the statements of the other
+    /// sessions connected to the database wait for it. When it stops at a lock held by another
+    /// transaction, its changes are undone and the tables released while it waits for the lock,
+    /// then it runs again from the start.
+    ///
+    /// A statement running as a transaction of its own reads with a snapshot taken once it holds the
+    /// tables and commits before releasing them, so it never meets a change committed while it runs.
+    /// A query is only planned while the tables are held, its rows are read and sent with the
+    /// tables released and its transaction commits once they are all sent
+    fn execute_statement(&mut self, text: &str, query: Sql) {
+        let explicit = self.transaction.is_some();
+        let mut transaction = self
+            .transaction
+            .take()
+            .unwrap_or_else(|| Transaction::new(self.transactions.clone()));
+        let shared = self.tables.clone();
+        let mut next = Some(query);
+        loop {
+            let query = next.take().unwrap_or_else(|| parse_query(text).unwrap());
+            let mut tables = shared.lock().unwrap();
+            if !explicit {
+                transaction.refresh_snapshot();
+            }
+            let savepoint = transaction.savepoint();
+            let reading = self.execute_cmd(query, &mut tables, &mut transaction, explicit);
+            let (target, mode) = match transaction.take_blocked() {
+                Some(blocked) => blocked,
+                None => {
+                    if let Some(plan) = reading {
+                        drop(tables);
+                        self.send_rows(plan);
+                    }
+                    if explicit {
+                        self.transaction = Some(transaction);
+                    } else {
+                        // a statement that commits on its own makes its changes durable in the log
+                        self.storage.write().unwrap().flush_log();
+                        transaction.commit();
                     }
+                    return;
                 }
-                Message::Status(Status::Generic(format!("Batch Success, Inserted: {}",inserted_counter))).send_msg_to(&mut self.conn).unwrap();
-                // let target = self.tables.get_mut(&table_name).unwrap();
-                // dbg!(&target.table_blocks);
-                // // let direct = target.direct_accessor();
-                // // dbg!(direct.get_tuple(Rid::new(9,0)));
-                // let iter = target.heapscan_iter();
-                // let v = iter.collect::<Vec<_>>();
-                // dbg!(v.len());
-                continue;
+            };
+            transaction.rollback_to(savepoint, &mut tables);
+            transaction.unlock_to(savepoint);
+            drop(tables);
+            if !self.wait_for_lock(&mut transaction, &target, mode) {
+                return;
             }
-            match parse_query(&query) {
-                Ok(parsed) => self.execute_cmd(parsed),
-                Err(e) => Message::Status(Status::Generic(e.to_string()))
+        }
+    }
+
+    /// Waits for a lock held by another transaction, with the tables of the database released.
+    ///
+    /// A transaction that is the victim of a deadlock is rolled back and the client is told so,
+    /// false is returned then
+    fn wait_for_lock(
+        &mut self,
+        transaction: &mut Transaction,
+        target: &Lockable,
+        mode: LockMode,
+    ) -> bool {
+        match transaction.wait_for(target, mode) {
+            Ok(_) => true,
+            Err(e) => {
+                transaction.rollback_to(Savepoint::default(), &mut self.tables.lock().unwrap());
+                self.storage.write().unwrap().flush_log();
+                Message::Status(Status::TransactionAborted(e))
                     .send_msg_to(&mut self.conn)
-                    .unwrap_or_default(),
+                    .unwrap_or_default();
+                false
             }
         }
     }
-    fn execute_cmd(&mut self, query: Sql) {
-        match self.create_plan(query) {
-            Ok(mut plan) => {
+
+    /// Runs a command with the tables of the database as part of a transaction.
+    ///
+    /// Queries read the tuples visible to the snapshot of the transaction, so they never wait for
+    /// the transactions changing the tables. A query is not run here, its plan is returned to be
+    /// run once the tables are released, the tables it reads are locked so they are not dropped
+    /// or altered meanwhile. A command that stops at a lock held by another transaction sends
+    /// nothing to the client
+    fn execute_cmd(
+        &mut self,
+        query: Sql,
+        tables: &mut DbTables,
+        transaction: &mut Transaction,
+        in_transaction: bool,
+    ) -> Option<PhysicalNode> {
+        let read_tables = match &query {
+            Sql::Query(SqlQuery::SELECT(select)) => select.from.tables(),
+            _ => vec![],
+        };
+        match self.create_plan(query, tables, transaction.snapshot()) {
+            Ok(plan) => {
                 if let QueryPlan::CreateTable(schema) = plan {
-                    self.add_schema(schema);
+                    if in_transaction {
+                        Message::Status(Status::TableNotCreated(
+                            schema.name().to_string(),
+                            "CREATE TABLE cannot run inside a transaction".to_string(),
+                        ))
+                        .send_msg_to(&mut self.conn)
+                        .unwrap_or_default();
+                    } else {
+                        self.add_schema(schema, tables);
+                    }
+                } else if let QueryPlan::AlterTable(alter) = plan {
+                    let name = alter.table_name().to_string();
+                    let status = if in_transaction {
+                        Status::TableNotAltered(
+                            name,
+                            "ALTER TABLE cannot run inside a transaction".to_string(),
+                        )
+                    } else {
+                        match self.alter_table(alter, tables, transaction) {
+                            Ok(_) => Status::TableAltered(name),
+                            Err(e) => Status::TableNotAltered(name, e),
+                        }
+                    };
+                    self.send_status(status, transaction);
+                } else if let QueryPlan::DropTable(name, if_exists) = plan {
+                    let status = if in_transaction {
+                        Status::TableNotDropped(
+                            name,
+                            "DROP TABLE cannot run inside a transaction".to_string(),
+                        )
+                    } else if !tables.contains_key(&name) && if_exists {
+                        Status::Generic(format!("Table {} does not exist, skipped", name))
+                    } else {
+                        match self.drop_table(&name, tables, transaction) {
+                            Ok(_) => Status::TableDropped(name),
+                            Err(e) => Status::TableNotDropped(name, e),
+                        }
+                    };
+                    self.send_status(status, transaction);
+                } else if let QueryPlan::Truncate(name) = plan {
+                    let status = if in_transaction {
+                        Status::TableNotTruncated(
+                            name,
+                            "TRUNCATE cannot run inside a transaction".to_string(),
+                        )
+                    } else {
+                        match self.truncate_table(&name, tables, transaction) {
+                            Ok(_) => Status::TableTruncated(name),
+                            Err(e) => Status::TableNotTruncated(name, e),
+                        }
+                    };
+                    self.send_status(status, transaction);
+                } else if let QueryPlan::CreateIndex(table, index) = plan {
+                    let name = index.name().to_string();
+                    let status = if in_transaction {
+                        Status::IndexNotCreated(
+                            name,
+                            "CREATE INDEX cannot run inside a transaction".to_string(),
+                        )
+                    } else {
+                        match self.create_index(&table, index, tables, transaction) {
+                            Ok(_) => Status::IndexCreated(name),
+                            Err(e) => Status::IndexNotCreated(name, e),
+                        }
+                    };
+                    self.send_status(status, transaction);
+                } else if let QueryPlan::DropIndex(name, if_exists) = plan {
+                    let table = self.catalog.read().unwrap().index_table(&self.name, &name);
+                    let status = match table {
+                        _ if in_transaction => Status::IndexNotDropped(
+                            name,
+                            "DROP INDEX cannot run inside a transaction".to_string(),
+                        ),
+                        None if if_exists => {
+                            Status::Generic(format!("Index {} does not exist, skipped", name))
+                        }
+                        None => Status::IndexNotDropped(
+                            name.clone(),
+                            format!("Index {} does not exist", name),
+                        ),
+                        Some(table) => match self.drop_index(&table, &name, tables, transaction) {
+                            Ok(_) => Status::IndexDropped(name),
+                            Err(e) => Status::IndexNotDropped(name, e),
+                        },
+                    };
+                    self.send_status(status, transaction);
+                } else if let QueryPlan::Select(plan) = plan {
+                    for table in read_tables {
+                        let target = Lockable::Table(table);
+                        transaction.lock(target, LockMode::SnapshotRead).ok()?;
+                    }
+                    return Some(plan);
+                } else if let QueryPlan::SequenceValues(values) = plan {
+                    let types = values
+                        .iter()
+                        .map(|(field, _)| (field.clone(), Type::Numeric(BigInt)))
+                        .collect();
+                    Message::FieldTypes(types)
+                        .send_msg_to(&mut self.conn)
+                        .unwrap_or_default();
+                    let row = values
+                        .into_iter()
+                        .map(|(field, number)| (field, Some(number.to_ne_bytes().to_vec())))
+                        .collect::<RowMap>();
+                    Message::Results(vec![row])
+                        .send_msg_to(&mut self.conn)
+                        .unwrap_or_default();
+                    Message::Status(Status::ResultsFinished)
+                        .send_msg_to(&mut self.conn)
+                        .unwrap_or_default();
                 } else {
-                    let mut executor = Executor::new(&mut self.tables);
+                    let foreign_keys = self.catalog.read().unwrap().foreign_keys(&self.name);
+                    let mut executor = Executor::new(tables, transaction, foreign_keys);
                     if let QueryPlan::Insert(record, schema) = plan {
-                        match record {
-                            Ok(r) => match executor.insert_record(r, schema) {
-                                Ok(_) => Message::Status(Status::RecordInserted)
+                        let status = match record.and_then(|r| executor.insert_record(r, schema)) {
+                            Ok(_) => Status::RecordInserted,
+                            Err(e) => Status::RecordNotInserted(e),
+                        };
+                        self.send_status(status, transaction);
+                    } else if let QueryPlan::Update(scan, changes, schema) = plan {
+                        let status = match changes
+                            .and_then(|changes| executor.update_records(scan, changes, schema))
+                        {
+                            Ok(n) => Status::RecordsUpdated(n),
+                            Err(e) => Status::RecordsNotUpdated(e),
+                        };
+                        self.send_status(status, transaction);
+                    } else if let QueryPlan::Delete(scan, schema) = plan {
+                        let status = match executor.delete_records(scan, schema) {
+                            Ok(n) => Status::RecordsDeleted(n),
+                            Err(e) => Status::Generic(e),
+                        };
+                        self.send_status(status, transaction);
+                    } else if let QueryPlan::Explain(s, analyze) = plan {
+                        let lines = match self.explain(s, analyze, tables) {
+                            Ok(lines) => lines,
+                            Err(e) => {
+                                Message::Status(Status::Generic(e))
                                     .send_msg_to(&mut self.conn)
-                                    .unwrap_or_default(),
-                                Err(e) => Message::Status(Status::RecordNotInserted(e))
-                                    .send_msg_to(&mut self.conn)
-                                    .unwrap_or_default(),
-                            },
-                            Err(e) => Message::Status(Status::RecordNotInserted(e))
-                                .send_msg_to(&mut self.conn)
-                                .unwrap_or_default(),
-                        }
-                    } else if let QueryPlan::Select(ref mut s) = plan {
-                        let types = s.get_type_map();
+                                    .unwrap_or_default();
+                                return None;
+                            }
+                        };
+                        let field = FieldId::new("explain", "plan");
+                        let types =
+                            HashMap::from([(field.clone(), Type::Character(VarChar(None)))]);
                         Message::FieldTypes(types)
                             .send_msg_to(&mut self.conn)
                             .unwrap_or_default();
-                        let mut total = 0;
-                        loop {
-                            let result: Vec<RowMap> = s.take(50).collect();
-                            total += result.len();
-                            if result.is_empty() {
-                                Message::Status(Status::ResultsFinished)
-                                    .send_msg_to(&mut self.conn)
-                                    .unwrap_or_default();
-                                break;
-                            } else {
-                                let msg = Message::Results(result);
-                                msg.send_msg_to(&mut self.conn);
-                            }
-                        }
+                        let lines = lines
+                            .into_iter()
+                            .map(|line| HashMap::from([(field.clone(), Some(line.into_bytes()))]))
+                            .collect::<Vec<RowMap>>();
+                        Message::Results(lines)
+                            .send_msg_to(&mut self.conn)
+                            .unwrap_or_default();
+                        Message::Status(Status::ResultsFinished)
+                            .send_msg_to(&mut self.conn)
+                            .unwrap_or_default();
                     }
                 }
             }
@@ -200,11 +525,185 @@ impl DatabaseInstance {
                     .unwrap_or_default();
             }
         }
+        None
+    }
+
+    /// Runs the plan of a query and sends its rows to the client, 50 at a time
+    fn send_rows(&mut self, mut plan: PhysicalNode) {
+        Message::FieldTypes(plan.get_type_map())
+            .send_msg_to(&mut self.conn)
+            .unwrap_or_default();
+        loop {
+            let result: Vec<RowMap> = plan.by_ref().take(50).collect();
+            if result.is_empty() {
+                let status = match plan.error() {
+                    Some(error) => Status::Generic(error),
+                    None => Status::ResultsFinished,
+                };
+                Message::Status(status)
+                    .send_msg_to(&mut self.conn)
+                    .unwrap_or_default();
+                break;
+            }
+            Message::Results(result)
+                .send_msg_to(&mut self.conn)
+                .unwrap_or_default();
+        }
+    }
+
+    /// Sends the status of a statement, unless it stopped at a lock and is going to run again
+    fn send_status(&mut self, status: Status, transaction: &Transaction) {
+        if !transaction.is_blocked() {
+            Message::Status(status)
+                .send_msg_to(&mut self.conn)
+                .unwrap_or_default();
+        }
+    }
+
+    /// Starts, commits or rolls back the transaction of the session.
+    ///
+    /// Changes are made durable in the log when the transaction commits, a rolled back transaction
+    /// reverts its changes, which are logged like any other change. The locks of the transaction
+    /// are released once it ended
+    fn control_transaction(&mut self, control: TransactionControl) {
+        let status = match (control, self.transaction.take()) {
+            (TransactionControl::Begin, None) => {
+                self.transaction = Some(Transaction::new(self.transactions.clone()));
+                Status::TransactionStarted
+            }
+            (TransactionControl::Begin, Some(transaction)) => {
+                self.transaction = Some(transaction);
+                Status::Generic("A transaction is already in progress".to_string())
+            }
+            (TransactionControl::Commit, Some(transaction)) => {
+                // the transaction ends before a statement can take a snapshot, as a statement running
+                // on its own would
+                let tables = self.tables.lock().unwrap();
+                self.storage.write().unwrap().flush_log();
+                transaction.commit();
+                drop(tables);
+                Status::TransactionCommitted
+            }
+            (TransactionControl::Rollback, Some(transaction)) => {
+                transaction.rollback(&mut self.tables.lock().unwrap());
+                self.storage.write().unwrap().flush_log();
+                Status::TransactionRolledBack
+            }
+            (_, None) => Status::Generic("There is no transaction in progress".to_string()),
+        };
+        Message::Status(status)
+            .send_msg_to(&mut self.conn)
+            .unwrap_or_default();
+    }
+    /// Removes the tuple versions no transaction can see anymore from a table, or from every table
+    /// of the database
+    fn vacuum(&mut self, table: Option<String>) {
+        let status = if self.transaction.is_some() {
+            Status::Generic("VACUUM cannot run inside a transaction".to_string())
+        } else {
+            let mut tables = self.tables.lock().unwrap();
+            let transactions = &self.transactions;
+            let status = match table {
+                Some(name) => match tables.get_mut(&name) {
+                    Some(table) => Status::Vacuumed(table.vacuum(transactions)),
+                    None => Status::Generic(format!("Table {} does not exist", name)),
+                },
+                None => Status::Vacuumed(
+                    tables
+                        .values_mut()
+                        .map(|table| table.vacuum(transactions))
+                        .sum(),
+                ),
+            };
+            self.storage.write().unwrap().flush_log();
+            status
+        };
+        Message::Status(status)
+            .send_msg_to(&mut self.conn)
+            .unwrap_or_default();
     }
-    fn add_schema(&mut self, schema: Schema) {
-        match self.catalog.borrow_mut().add_schema(&self.name, &schema) {
+    /// Records a new sequence of the database.
+    ///
+    /// A sequence hands out its values outside of any transaction, a value is never taken back, so
+    /// creating or dropping one is refused inside a transaction
+    fn create_sequence(&mut self, name: String, start: i64, increment: i64) {
+        let status = if self.transaction.is_some() {
+            Status::SequenceNotCreated(
+                name,
+                "CREATE SEQUENCE cannot run inside a transaction".to_string(),
+            )
+        } else {
+            let created = self
+                .catalog
+                .write()
+                .unwrap()
+                .create_sequence(&self.name, &name, start, increment);
+            match created {
+                Ok(_) => Status::SequenceCreated(name),
+                Err(e) => Status::SequenceNotCreated(name, e),
+            }
+        };
+        Message::Status(status)
+            .send_msg_to(&mut self.conn)
+            .unwrap_or_default();
+    }
+    /// Removes a sequence of the database, unless a field takes its default from it
+    fn drop_sequence(&mut self, name: String, if_exists: bool) {
+        let mut catalog = self.catalog.write().unwrap();
+        let status = if self.transaction.is_some() {
+            Status::SequenceNotDropped(
+                name,
+                "DROP SEQUENCE cannot run inside a transaction".to_string(),
+            )
+        } else if if_exists && !catalog.has_sequence(&self.name, &name) {
+            Status::Generic(format!("Sequence {} does not exist, skipped", name))
+        } else {
+            match catalog.drop_sequence(&self.name, &name) {
+                Ok(_) => {
+                    self.sequence_values.remove(&name);
+                    Status::SequenceDropped(name)
+                }
+                Err(e) => Status::SequenceNotDropped(name, e),
+            }
+        };
+        drop(catalog);
+        Message::Status(status)
+            .send_msg_to(&mut self.conn)
+            .unwrap_or_default();
+    }
+    /// Replaces the nextval and currval calls among the values of a statement with the values
+    /// they give, in the order they are written.
+    ///
+    /// currval gives the value nextval last gave the session, it fails if nextval was not called
+    /// for the sequence in the session yet
+    fn sequence_values<'a>(
+        &mut self,
+        values: impl Iterator<Item = &'a mut SqlValue>,
+    ) -> Result<(), String> {
+        for value in values {
+            let number = match value {
+                SqlValue::NextVal(sequence) => {
+                    let number = self
+                        .catalog
+                        .write()
+                        .unwrap()
+                        .next_value(&self.name, sequence)?;
+                    self.sequence_values.insert(sequence.clone(), number);
+                    number
+                }
+                SqlValue::CurrVal(sequence) => *self.sequence_values.get(sequence).ok_or(
+                    format!("currval of sequence {} is not yet defined in this session", sequence),
+                )?,
+                _ => continue,
+            };
+            *value = SqlValue::Numeric(number.to_string());
+        }
+        Ok(())
+    }
+    fn add_schema(&mut self, schema: Schema, tables: &mut DbTables) {
+        match self.catalog.write().unwrap().add_schema(&self.name, &schema) {
             Ok(table) => {
-                self.tables.insert(schema.name().to_string(), table);
+                tables.insert(schema.name().to_string(), table);
                 // send_string(
                 //     &mut self.conn,
                 //     &format!("Table: {} created successfully", schema.name()),
@@ -218,21 +717,372 @@ impl DatabaseInstance {
                 .unwrap_or_default(), // Err(s) => send_string(&mut self.conn, s.as_str()).unwrap(),
         }
     }
-    fn create_plan(&self, query_tree: Sql) -> Result<QueryPlan, String> {
+    /// Changes the definition of a table once every other transaction is kept off it.
+    ///
+    /// Adding or dropping a column rewrites the tuple versions of the table to the new layout and
+    /// drops the indexes on a dropped column, renaming only changes the catalog. The table is loaded
+    /// again as the catalog describes it afterwards
+    fn alter_table(
+        &mut self,
+        alter: AlterTable,
+        tables: &mut DbTables,
+        transaction: &mut Transaction,
+    ) -> Result<(), String> {
+        let name = alter.table_name();
+        if !tables.contains_key(name) {
+            return Err(format!("Table {} does not exist", name));
+        }
+        transaction.lock(Lockable::Table(name.to_string()), LockMode::Exclusive)?;
+        let mut catalog = self.catalog.write().unwrap();
+        let schema = catalog.get_schema(&self.name, name).unwrap();
+        let altered = alter.alter_schema(&schema)?;
+        let snapshot = transaction.dirty_snapshot();
+        let mut dropped_indexes = vec![];
+        let mut dropped_sequence = None;
+        match alter.action() {
+            AlterAction::AddColumn(field) => {
+                let default = alter.default_bytes()?;
+                let added = altered.fields_info()[field.name()].clone();
+                let rows = tables[name].heapscan_iter(Some(snapshot.clone())).count();
+                if !added.nullable() && default.is_none() && rows > 0 {
+                    return Err(format!(
+                        "{} is NOT NULL, the rows of the table need a default",
+                        field.name()
+                    ));
+                }
+                if added.unique() && default.is_some() && rows > 1 {
+                    return Err(format!("Duplicate Value : {}", field.name()));
+                }
+                if added.check().is_some() {
+                    for mut row in tables[name].heapscan_iter(Some(snapshot.clone())) {
+                        row.insert(field.name().to_string(), default.clone());
+                        altered.check_row(&row)?;
+                    }
+                }
+                if let (Some((ref_table, ref_col)), Some(value)) =
+                    (added.foreign_reference(), &default)
+                {
+                    let found = tables.get(ref_table).is_some_and(|table| {
+                        table
+                            .heapscan_iter(Some(snapshot.clone()))
+                            .any(|row| row.get(ref_col).is_some_and(|v| v.as_ref() == Some(value)))
+                    });
+                    if !found {
+                        return Err(format!(
+                            "Foreign Reference Not Found : ({}.{})",
+                            ref_table, ref_col
+                        ));
+                    }
+                }
+                let column = field.name().to_string();
+                tables.get_mut(name).unwrap().rewrite(
+                    Arc::new(altered.to_layout()),
+                    |fields| {
+                        let mut fields = fields.into_iter().collect::<Vec<_>>();
+                        fields.push((column.clone(), default.clone()));
+                        fields
+                    },
+                );
+            }
+            AlterAction::DropColumn(column) => {
+                let referencing = catalog
+                    .referencing_fields(&self.name, name, column)
+                    .into_iter()
+                    .find(|(table, field)| table != name || field != column);
+                if let Some((table, field)) = referencing {
+                    return Err(format!(
+                        "Column {} is referenced by {}.{}",
+                        column, table, field
+                    ));
+                }
+                // a SERIAL column takes the sequence it owns with it
+                let sequence = schema.fields_info()[column.as_str()].sequence();
+                dropped_sequence = schema
+                    .owned_sequences()
+                    .into_iter()
+                    .find(|owned| sequence == Some(owned))
+                    .map(String::from);
+                if let Some(sequence) = &dropped_sequence {
+                    let users = catalog.sequence_users(&self.name, sequence);
+                    if let Some((table, field)) =
+                        users.iter().find(|(table, field)| table != name || field != column)
+                    {
+                        return Err(format!(
+                            "Sequence {} of {} is used by {}.{}",
+                            sequence, column, table, field
+                        ));
+                    }
+                }
+                dropped_indexes = schema
+                    .indexes()
+                    .iter()
+                    .map(|idx| idx.to_index_info(&self.name))
+                    .filter(|idx| idx.columns.contains(column))
+                    .collect();
+                tables.get_mut(name).unwrap().rewrite(
+                    Arc::new(altered.to_layout()),
+                    |mut fields| {
+                        fields.remove(column);
+                        fields.into_iter().collect()
+                    },
+                );
+            }
+            AlterAction::RenameColumn(column, new_column) => {
+                catalog.rename_references(
+                    &self.name,
+                    name,
+                    name,
+                    Some((column.as_str(), new_column.as_str())),
+                );
+            }
+            AlterAction::RenameTo(new_name) => {
+                if tables.contains_key(new_name) {
+                    return Err(format!("Table '{}' already exists ", new_name));
+                }
+                catalog.rename_references(&self.name, name, new_name, None);
+            }
+        }
+        catalog.alter_schema(&self.name, name, &altered)?;
+        if let Some(sequence) = dropped_sequence {
+            catalog.drop_sequence(&self.name, &sequence)?;
+        }
+        let table = tables.remove(name).unwrap();
+        table.flush_all();
+        drop(table);
+        let mut storage = self.storage.write().unwrap();
+        for idx in dropped_indexes {
+            storage.remove_file(idx.index_file_path.to_str().unwrap());
+            if idx.directory_file_path.exists() {
+                std::fs::remove_file(idx.directory_file_path).unwrap();
+            }
+        }
+        storage.flush_log();
+        drop(storage);
+        let table = catalog.load_table(&self.name, alter.new_name()).unwrap();
+        tables.insert(alter.new_name().to_string(), table);
+        Ok(())
+    }
+    /// Removes a table and its files once every other transaction is kept off it, a table the
+    /// foreign keys of another table reference is kept, as is one owning a sequence another table
+    /// takes its defaults from
+    fn drop_table(
+        &mut self,
+        name: &str,
+        tables: &mut DbTables,
+        transaction: &mut Transaction,
+    ) -> Result<(), String> {
+        self.lock_unreferenced(name, tables, transaction)?;
+        let catalog = self.catalog.read().unwrap();
+        let schema = catalog.get_schema(&self.name, name).unwrap();
+        for sequence in schema.owned_sequences() {
+            let users = catalog.sequence_users(&self.name, sequence);
+            if let Some((table, field)) = users.iter().find(|(table, _)| table != name) {
+                return Err(format!(
+                    "Sequence {} of {} is used by {}.{}",
+                    sequence, name, table, field
+                ));
+            }
+        }
+        drop(catalog);
+        tables.remove(name);
+        self.catalog.write().unwrap().drop_table(&self.name, name)?;
+        self.storage.write().unwrap().flush_log();
+        Ok(())
+    }
+    /// Removes every row of a table at once by giving it new empty files, the tuple versions are
+    /// gone for every snapshot
+    fn truncate_table(
+        &mut self,
+        name: &str,
+        tables: &mut DbTables,
+        transaction: &mut Transaction,
+    ) -> Result<(), String> {
+        self.lock_unreferenced(name, tables, transaction)?;
+        tables.remove(name);
+        let table = self
+            .catalog
+            .write()
+            .unwrap()
+            .truncate_table(&self.name, name)?;
+        self.storage.write().unwrap().flush_log();
+        tables.insert(name.to_string(), table);
+        Ok(())
+    }
+    /// Builds an index on columns of a table from the tuple versions already in it.
+    ///
+    /// The table is locked shared, so the index is built once the transactions changing the table
+    /// ended and the ones about to change it wait for the index
+    fn create_index(
+        &mut self,
+        table_name: &str,
+        index: Index,
+        tables: &mut DbTables,
+        transaction: &mut Transaction,
+    ) -> Result<(), String> {
+        if !tables.contains_key(table_name) {
+            return Err(format!("Table {} does not exist", table_name));
+        }
+        transaction.lock(Lockable::Table(table_name.to_string()), LockMode::Shared)?;
+        let mut catalog = self.catalog.write().unwrap();
+        let schema = catalog.get_schema(&self.name, table_name).unwrap();
+        let fields = schema.fields_info();
+        if let Some(column) = index.fields().iter().find(|f| !fields.contains_key(f.as_str())) {
+            return Err(format!("Column {} does not exist", column));
+        }
+        if catalog.index_table(&self.name, index.name()).is_some() {
+            return Err(format!("Index {} already exists", index.name()));
+        }
+        if schema
+            .indexes()
+            .iter()
+            .any(|idx| {
+                idx.fieldnames() == index.fields() && idx.index_type() == index.index_type()
+            })
+        {
+            return Err(format!(
+                "Column {} already has a {} index",
+                index.fields().join(","),
+                index.index_type()
+            ));
+        }
+        let info = catalog.add_index(
+            &self.name,
+            table_name,
+            index.name(),
+            index.fields(),
+            index.index_type(),
+        )?;
+        let table = tables.get_mut(table_name).unwrap();
+        table.add_index(info);
+        table.flush_all();
+        self.storage.write().unwrap().flush_log();
+        Ok(())
+    }
+    /// Removes an index of a table once every other transaction is kept off the table
+    fn drop_index(
+        &mut self,
+        table_name: &str,
+        index_name: &str,
+        tables: &mut DbTables,
+        transaction: &mut Transaction,
+    ) -> Result<(), String> {
+        transaction.lock(Lockable::Table(table_name.to_string()), LockMode::Exclusive)?;
+        let info = self
+            .catalog
+            .write()
+            .unwrap()
+            .drop_index(&self.name, table_name, index_name)?;
+        if let Some(table) = tables.get_mut(table_name) {
+            table.drop_index(&info.key_columns(), info.index_type);
+        }
+        self.storage.write().unwrap().flush_log();
+        Ok(())
+    }
+    /// Takes the exclusive lock of a table no foreign key of another table references
+    fn lock_unreferenced(
+        &self,
+        name: &str,
+        tables: &DbTables,
+        transaction: &mut Transaction,
+    ) -> Result<(), String> {
+        if !tables.contains_key(name) {
+            return Err(format!("Table {} does not exist", name));
+        }
+        transaction.lock(Lockable::Table(name.to_string()), LockMode::Exclusive)?;
+        let catalog = self.catalog.read().unwrap();
+        let schema = catalog.get_schema(&self.name, name).unwrap();
+        let referencing = schema
+            .fields()
+            .iter()
+            .flat_map(|field| catalog.referencing_fields(&self.name, name, field.name()))
+            .find(|(table, _)| table != name);
+        match referencing {
+            Some((table, field)) => Err(format!(
+                "Table {} is referenced by {}.{}",
+                name, table, field
+            )),
+            None => Ok(()),
+        }
+    }
+    fn create_plan(
+        &mut self,
+        query_tree: Sql,
+        tables: &DbTables,
+        snapshot: Arc<Snapshot>,
+    ) -> Result<QueryPlan, String> {
         match query_tree {
-            Sql::CreateTable(ct) => Ok(QueryPlan::CreateTable(ct.to_schema())),
+            Sql::CreateTable(ct) => Ok(QueryPlan::CreateTable(ct.to_schema()?)),
+            Sql::AlterTable(at) => Ok(QueryPlan::AlterTable(at)),
+            Sql::DropTable(name, if_exists) => Ok(QueryPlan::DropTable(name, if_exists)),
+            Sql::Truncate(name) => Ok(QueryPlan::Truncate(name)),
+            Sql::CreateIndex(table, index) => Ok(QueryPlan::CreateIndex(table, index)),
+            Sql::DropIndex(name, if_exists) => Ok(QueryPlan::DropIndex(name, if_exists)),
+            Sql::Transaction(_) => Err("Transaction control is not a query".to_string()),
+            Sql::Vacuum(_) => Err("VACUUM is not a query".to_string()),
+            Sql::CreateSequence(..) | Sql::DropSequence(..) => {
+                Err("A sequence definition is not a query".to_string())
+            }
+            Sql::SequenceValues(mut values) => {
+                let fields = values
+                    .iter()
+                    .map(|value| match value {
+                        SqlValue::NextVal(sequence) => FieldId::new(sequence, "nextval"),
+                        SqlValue::CurrVal(sequence) => FieldId::new(sequence, "currval"),
+                        _ => unreachable!(),
+                    })
+                    .collect::<Vec<_>>();
+                self.sequence_values(values.iter_mut())?;
+                let numbers = values.into_iter().map(|value| match value {
+                    SqlValue::Numeric(number) => number.parse().unwrap(),
+                    _ => unreachable!(),
+                });
+                Ok(QueryPlan::SequenceValues(fields.into_iter().zip(numbers).collect()))
+            }
             Sql::Query(query) => match query {
-                SqlQuery::SELECT(s) => Ok(QueryPlan::Select(self.plan_query(s)?)),
-                SqlQuery::INSERT(i) => {
-                    let catalog = self.catalog.borrow();
-                    let schema = catalog
+                SqlQuery::SELECT(s) => Ok(QueryPlan::Select(self.plan_query(s, tables, snapshot)?)),
+                SqlQuery::EXPLAIN(e) => {
+                    let analyze = e.analyze();
+                    Ok(QueryPlan::Explain(
+                        self.plan_query(e.into_query(), tables, snapshot)?,
+                        analyze,
+                    ))
+                }
+                SqlQuery::INSERT(mut i) => {
+                    let schema = self
+                        .catalog
+                        .read()
+                        .unwrap()
                         .get_schema(&self.name, i.target_table())
                         .ok_or("Insert Error")?;
-                    let record = i.raw_bytes(&schema);
+                    i.add_sequence_defaults(&schema);
+                    let record = self
+                        .sequence_values(i.values_mut())
+                        .and_then(|_| i.raw_bytes(&schema));
                     Ok(QueryPlan::Insert(record, schema))
                 }
-                SqlQuery::DELETE(_) => todo!(),
-                SqlQuery::UPDATE(_) => todo!(),
+                SqlQuery::DELETE(d) => {
+                    let catalog = self.catalog.read().unwrap();
+                    let schema = catalog
+                        .get_schema(&self.name, d.table())
+                        .ok_or("Delete Error")?;
+                    let scan =
+                        self.plan_scan(d.table(), d.where_clause().cloned(), tables, snapshot)?;
+                    Ok(QueryPlan::Delete(scan, schema))
+                }
+                SqlQuery::UPDATE(mut u) => {
+                    let schema = self
+                        .catalog
+                        .read()
+                        .unwrap()
+                        .get_schema(&self.name, u.table())
+                        .ok_or("Update Error")?;
+                    let changes = self
+                        .sequence_values(u.values_mut())
+                        .and_then(|_| u.raw_bytes(&schema));
+                    let scan =
+                        self.plan_scan(u.table(), u.where_clause().cloned(), tables, snapshot)?;
+                    Ok(QueryPlan::Update(scan, changes, schema))
+                }
             },
         }
     }
@@ -246,17 +1096,321 @@ impl DatabaseInstance {
     pub fn catalog(&self) -> &Catalog {
         &self.catalog
     }
-    pub fn tables(&self) -> &DbTables {
+    pub fn tables(&self) -> &SharedTables {
         &self.tables
     }
 }
 
 impl Drop for DatabaseInstance {
     fn drop(&mut self) {
-        use std::fs;
         self.flush_everything();
-        //TODO if concurrency is implemented this would be BAD
-        let path = AQUA_TMP_DIR();
-        fs::remove_dir_all(&path).and_then(|_| fs::create_dir(&path)).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::testing::TestSession;
+    use crate::interface::message::{Message, Status};
+    use crate::FieldId;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(|value| value.to_string()).collect())
+            .collect()
+    }
+
+    // ROLLBACK reverts the inserts, updates and deletes of a transaction, and a statement failing
+    // inside it only reverts its own changes. The indexes on the table follow the rows every time
+    #[test]
+    fn test_rollback_restores_rows_and_indexes() {
+        let mut session = TestSession::open();
+        session.status(
+            "create table acc (id int primary key, owner varchar, bal int, \
+             create index hash oidx on (owner))",
+        );
+        session.status("create table loan (lid int, acc int references acc(id))");
+        for (id, owner) in [(1, "ann"), (2, "bob"), (3, "cy")] {
+            let insert = format!(
+                "insert into acc (id, owner, bal) values ({id}, \"{owner}\", {})",
+                id * 10
+            );
+            assert!(matches!(session.status(&insert), Status::RecordInserted));
+        }
+        session.status("insert into loan (lid, acc) values (1, 2)");
+        let all = "select acc.id, acc.owner, acc.bal from acc";
+        let by_owner =
+            |owner: &str| format!("select acc.id from acc where acc.owner == \"{owner}\"");
+        let initial = rows(&[&["10", "1", "ann"], &["20", "2", "bob"], &["30", "3", "cy"]]);
+        assert_eq!(session.sorted_rows(all), initial);
+
+        session.status("begin");
+        session.status("insert into acc (id, owner, bal) values (4, \"dan\", 40)");
+        session.status("update acc set bal = 999 where acc.id == 1");
+        session.status("update acc set owner = \"bo\" where acc.id == 2");
+        session.status("delete from acc where acc.id == 3");
+        let changed = rows(&[
+            &["20", "2", "bo"],
+            &["40", "4", "dan"],
+            &["999", "1", "ann"],
+        ]);
+        assert_eq!(session.sorted_rows(all), changed);
+        // both rows get their new version before the key of bo is found still referenced, the
+        // update is undone as a whole
+        assert!(matches!(
+            session.status("update acc set id = 8 where acc.bal < 100"),
+            Status::RecordsNotUpdated(_)
+        ));
+        assert_eq!(session.sorted_rows(all), changed);
+        assert_eq!(session.rows(&by_owner("dan")), rows(&[&["4"]]));
+        assert_eq!(
+            session.rows("select acc.owner from acc where acc.id == 8"),
+            rows(&[])
+        );
+
+        assert!(matches!(
+            session.status("rollback"),
+            Status::TransactionRolledBack
+        ));
+        assert_eq!(session.sorted_rows(all), initial);
+        assert_eq!(session.rows(&by_owner("bob")), rows(&[&["2"]]));
+        assert_eq!(session.rows(&by_owner("bo")), rows(&[]));
+        assert_eq!(session.rows(&by_owner("dan")), rows(&[]));
+        assert_eq!(
+            session.rows("select acc.owner from acc where acc.id == 3"),
+            rows(&[&["cy"]])
+        );
+        // the key of the rolled back row is free again
+        assert!(matches!(
+            session.status("insert into acc (id, owner, bal) values (4, \"dan\", 40)"),
+            Status::RecordInserted
+        ));
+    }
+
+    // TRUNCATE gives the table new empty files, the rows, their overflow values and their index
+    // entries are gone. DROP removes every file of the table and of its indexes
+    #[test]
+    fn test_truncate_and_drop_remove_table_files() {
+        let mut session = TestSession::open();
+        session.status(
+            "create table acc (id int, owner varchar, \
+             create index hash oidx on (owner), create index btree idx on (id))",
+        );
+        session.status("insert into acc (id, owner) values (1, \"ann\")");
+        let long = "x".repeat(9000);
+        session.status(&format!(
+            "insert into acc (id, owner) values (2, \"{long}\")"
+        ));
+        let overflow = ["acc_heap0_overflow", "acc_heap0_overflow_freemap"];
+        let files = [
+            ["acc_heap0", "acc_freemap0"].as_slice(),
+            &overflow,
+            &["oidx_idx_file", "oidx_idx_directory", "idx_idx_file"],
+        ]
+        .concat();
+        for file in files.iter() {
+            assert!(session.file(file).exists(), "{file} missing");
+        }
+
+        assert!(matches!(
+            session.status("truncate table acc"),
+            Status::TableTruncated(_)
+        ));
+        for file in overflow {
+            assert!(!session.file(file).exists(), "{file} kept");
+        }
+        let all = "select acc.id, acc.owner from acc";
+        assert_eq!(session.rows(all), rows(&[]));
+        let by_id = "select acc.owner from acc where acc.id == 1";
+        let by_owner = "select acc.id from acc where acc.owner == \"ann\"";
+        assert_eq!(session.rows(by_id), rows(&[]));
+        assert_eq!(session.rows(by_owner), rows(&[]));
+        session.status("insert into acc (id, owner) values (1, \"ann\")");
+        assert_eq!(session.rows(by_id), rows(&[&["ann"]]));
+        assert_eq!(session.rows(by_owner), rows(&[&["1"]]));
+
+        assert!(matches!(
+            session.status("drop table acc"),
+            Status::TableDropped(_)
+        ));
+        for file in files.iter() {
+            assert!(!session.file(file).exists(), "{file} kept");
+        }
+        assert!(matches!(
+            session.status("drop table if exists acc"),
+            Status::Generic(_)
+        ));
+    }
+
+    // CREATE INDEX fills the index from the rows already in the table, leaving out the deleted
+    // ones and the versions updates replaced, and queries use it from then on. DROP INDEX goes
+    // back to scanning the table and removes the files of the index
+    #[test]
+    fn test_create_index_backfills_existing_rows() {
+        let mut session = TestSession::open();
+        session.status("create table acc (id int, owner varchar)");
+        for id in 0..300 {
+            session.status(&format!(
+                "insert into acc (id, owner) values ({id}, \"o{}\")",
+                id % 30
+            ));
+        }
+        session.status("delete from acc where acc.id > 289");
+        session.status("update acc set owner = \"moved\" where acc.id == 5");
+        assert!(matches!(
+            session.status("create index idx using btree on acc(id)"),
+            Status::IndexCreated(_)
+        ));
+        assert!(matches!(
+            session.status("create index oidx using hash on acc(owner)"),
+            Status::IndexCreated(_)
+        ));
+        let plan = |session: &mut TestSession, query: &str| {
+            session
+                .rows(&format!("explain {query}"))
+                .concat()
+                .join("\n")
+        };
+        let by_id = |id: i32| format!("select acc.owner from acc where acc.id == {id}");
+        let by_owner =
+            |owner: &str| format!("select acc.id from acc where acc.owner == \"{owner}\"");
+        assert!(plan(&mut session, &by_id(1)).contains("Btree Index Scan on acc using id"));
+        assert!(plan(&mut session, &by_owner("o1")).contains("Hash Index Scan on acc using owner"));
+        for id in [0, 1, 150, 289] {
+            let owner = format!("o{}", id % 30);
+            assert_eq!(session.rows(&by_id(id)), rows(&[&[&owner]]));
+        }
+        assert_eq!(session.rows(&by_id(290)), rows(&[]));
+        assert_eq!(session.rows(&by_id(5)), rows(&[&["moved"]]));
+        let ids = |ids: &[i32]| {
+            let mut ids = ids
+                .iter()
+                .map(|id| vec![id.to_string()])
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        assert_eq!(
+            session.sorted_rows(&by_owner("o5")),
+            ids(&[35, 65, 95, 125, 155, 185, 215, 245, 275])
+        );
+        assert_eq!(session.sorted_rows(&by_owner("moved")), ids(&[5]));
+        // rows inserted afterwards are indexed as usual
+        session.status("insert into acc (id, owner) values (1000, \"o5\")");
+        assert_eq!(session.rows(&by_id(1000)), rows(&[&["o5"]]));
+        assert_eq!(session.sorted_rows(&by_owner("o5")).len(), 10);
+
+        let files = ["idx_idx_file", "oidx_idx_file", "oidx_idx_directory"];
+        for file in files {
+            assert!(session.file(file).exists(), "{file} missing");
+        }
+        for index in ["idx", "oidx"] {
+            assert!(matches!(
+                session.status(&format!("drop index {index}")),
+                Status::IndexDropped(_)
+            ));
+        }
+        for file in files {
+            assert!(!session.file(file).exists(), "{file} kept");
+        }
+        assert!(plan(&mut session, &by_id(1)).contains("Seq Scan on acc"));
+        assert_eq!(session.rows(&by_id(1000)), rows(&[&["o5"]]));
+    }
+
+    // A query sends its rows with the tables of the database released, an insert of another
+    // session commits while most of them wait for the client of the query to read them. The query
+    // goes on with its snapshot and never returns the inserted row
+    #[test]
+    fn test_insert_commits_while_select_streams() {
+        let mut session = TestSession::open();
+        session.status("create table doc (id int, grp int, body varchar)");
+        session.status("create table tag (id int, grp int)");
+        // the rows of the query are many times what the socket holds
+        let body = "x".repeat(8000);
+        for id in 0..40 {
+            session.status(&format!(
+                "insert into doc (id, grp, body) values ({id}, 1, \"{body}\")"
+            ));
+        }
+        for id in 0..100 {
+            session.status(&format!("insert into tag (id, grp) values ({id}, 1)"));
+        }
+        let (mut reader, mut client) = session.connect();
+        let query = thread::spawn(move || {
+            reader.run_query(
+                "select doc.id, doc.body, tag.id from doc join tag on doc.grp == tag.grp",
+            );
+            reader
+        });
+        assert!(matches!(
+            Message::receive_msg(&mut client).unwrap(),
+            Message::FieldTypes(_)
+        ));
+        let mut received = vec![];
+        match Message::receive_msg(&mut client).unwrap() {
+            Message::Results(rows) => received.extend(rows),
+            other => panic!("the query failed: {}", other.get_status().unwrap()),
+        }
+
+        let (sender, inserted) = channel();
+        let writer = thread::spawn(move || {
+            let status =
+                session.status("insert into doc (id, grp, body) values (40, 1, \"new\")");
+            sender.send(status).unwrap();
+            session
+        });
+        let status = inserted
+            .recv_timeout(Duration::from_secs(60))
+            .expect("the insert waited for the query");
+        assert!(matches!(status, Status::RecordInserted));
+        assert!(!query.is_finished());
+        let mut session = writer.join().unwrap();
+
+        loop {
+            match Message::receive_msg(&mut client).unwrap() {
+                Message::Results(rows) => received.extend(rows),
+                Message::Status(Status::ResultsFinished) => break,
+                other => panic!("the query failed: {}", other.get_status().unwrap()),
+            }
+        }
+        drop(query.join().unwrap());
+        assert_eq!(received.len(), 40 * 100);
+        let doc_id = FieldId::new("doc", "id");
+        assert!(received
+            .iter()
+            .all(|row| row[&doc_id] != Some(40_i32.to_ne_bytes().to_vec())));
+        assert_eq!(
+            session.rows("select doc.grp from doc where doc.id == 40"),
+            rows(&[&["1"]])
+        );
+    }
+
+    // A session that panics holding the tables of a database leaves them poisoned, the sessions of
+    // the database are refused from then on instead of working on tables that may be half changed
+    #[test]
+    fn test_poisoned_tables_fail_the_database() {
+        let mut session = TestSession::open();
+        session.status("create table acc (id int)");
+        assert!(session.instance().failure().is_none());
+        let tables = session.instance().tables.clone();
+        let failed = thread::spawn(move || {
+            let _tables = tables.lock().unwrap();
+            panic!("the session failed while changing the tables");
+        });
+        assert!(failed.join().is_err());
+        assert!(session.instance().failure().is_some());
+
+        let (mut other, mut client) = session.connect();
+        Message::Query("select acc.id from acc".to_string())
+            .send_msg_to(&mut client)
+            .unwrap();
+        other.handle_connection();
+        match Message::receive_msg(&mut client).unwrap() {
+            Message::Status(Status::Generic(failure)) => assert!(failure.contains("unavailable")),
+            other => panic!("the query was not refused: {}", other.get_status().unwrap()),
+        }
+        drop(other);
     }
 }
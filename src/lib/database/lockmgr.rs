@@ -0,0 +1,269 @@
+use crate::database::txnmgr::TransactionId;
+use crate::index::Rid;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::sync::{Condvar, Mutex};
+
+/// What a lock is taken on, a whole table or a single row of one
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Lockable {
+    Table(String),
+    Row(String, Rid),
+}
+
+impl Display for Lockable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lockable::Table(table) => write!(f, "table {}", table),
+            Lockable::Row(table, rid) => write!(
+                f,
+                "row ({}, {}) of table {}",
+                rid.block_num(),
+                rid.slot_num(),
+                table
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Reading a table with a snapshot, once the tables of the database are released. It only
+    /// keeps the table from being dropped, truncated or altered under the reader
+    SnapshotRead,
+    /// Reading a table
+    Shared,
+    /// Changing some rows of a table, each of them is locked Exclusive
+    IntentionExclusive,
+    /// Changing a row
+    Exclusive,
+}
+
+impl LockMode {
+    fn compatible(&self, other: &LockMode) -> bool {
+        match (self, other) {
+            (LockMode::SnapshotRead, mode) | (mode, LockMode::SnapshotRead) => {
+                *mode != LockMode::Exclusive
+            }
+            _ => matches!(
+                (self, other),
+                (LockMode::Shared, LockMode::Shared)
+                    | (LockMode::IntentionExclusive, LockMode::IntentionExclusive)
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LockTable {
+    granted: HashMap<Lockable, Vec<(TransactionId, LockMode)>>,
+    held: HashMap<TransactionId, HashSet<Lockable>>,
+    // the transactions each waiting transaction waits for
+    waits_for: HashMap<TransactionId, HashSet<TransactionId>>,
+}
+
+impl LockTable {
+    fn holds(&self, txn: TransactionId, target: &Lockable, mode: LockMode) -> bool {
+        self.granted.get(target).is_some_and(|granted| {
+            granted
+                .iter()
+                .any(|(t, m)| *t == txn && (*m == mode || *m == LockMode::Exclusive))
+        })
+    }
+    /// The other transactions holding locks on the target that conflict with the mode
+    fn blockers(
+        &self,
+        txn: TransactionId,
+        target: &Lockable,
+        mode: LockMode,
+    ) -> HashSet<TransactionId> {
+        self.granted
+            .get(target)
+            .map(|granted| {
+                granted
+                    .iter()
+                    .filter(|(t, m)| *t != txn && !mode.compatible(m))
+                    .map(|(t, _)| *t)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    fn grant(&mut self, txn: TransactionId, target: Lockable, mode: LockMode) {
+        self.granted
+            .entry(target.clone())
+            .or_default()
+            .push((txn, mode));
+        self.held.entry(txn).or_default().insert(target);
+    }
+    /// Whether a transaction waits, through the wait-for graph, for itself
+    fn in_cycle(&self, txn: TransactionId) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![txn];
+        while let Some(waiting) = stack.pop() {
+            for holder in self.waits_for.get(&waiting).into_iter().flatten() {
+                if *holder == txn {
+                    return true;
+                }
+                if visited.insert(*holder) {
+                    stack.push(*holder);
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Grants the table and row locks of the transactions running on a database.
+///
+/// Locks follow strict two phase locking, a transaction keeps every lock it was granted until it
+/// commits or rolls back.
+/// A transaction waiting for a lock waits for the transactions holding conflicting ones, these
+/// waits form the wait-for graph. When a request closes a cycle in it the transactions of the cycle
+/// would wait forever, the requesting transaction is the victim of the deadlock and its request fails
+#[derive(Debug, Default)]
+pub struct LockManager {
+    table: Mutex<LockTable>,
+    released: Condvar,
+}
+
+impl LockManager {
+    /// Whether a transaction holds a lock at least as strong as the mode on the target
+    pub fn holds(&self, txn: TransactionId, target: &Lockable, mode: LockMode) -> bool {
+        self.table.lock().unwrap().holds(txn, target, mode)
+    }
+
+    /// Grants a lock if no other transaction holds a conflicting one, without waiting
+    pub fn try_lock(&self, txn: TransactionId, target: &Lockable, mode: LockMode) -> bool {
+        let mut table = self.table.lock().unwrap();
+        if table.holds(txn, target, mode) {
+            return true;
+        }
+        if !table.blockers(txn, target, mode).is_empty() {
+            return false;
+        }
+        table.grant(txn, target.clone(), mode);
+        true
+    }
+
+    /// Grants a lock, waiting for the transactions holding conflicting ones to release them.
+    ///
+    /// Fails without granting the lock when waiting would deadlock
+    pub fn lock(
+        &self,
+        txn: TransactionId,
+        target: &Lockable,
+        mode: LockMode,
+    ) -> Result<(), String> {
+        let mut table = self.table.lock().unwrap();
+        if table.holds(txn, target, mode) {
+            return Ok(());
+        }
+        loop {
+            let blockers = table.blockers(txn, target, mode);
+            if blockers.is_empty() {
+                table.waits_for.remove(&txn);
+                table.grant(txn, target.clone(), mode);
+                return Ok(());
+            }
+            table.waits_for.insert(txn, blockers);
+            if table.in_cycle(txn) {
+                table.waits_for.remove(&txn);
+                return Err(format!(
+                    "deadlock detected while waiting for a lock on {}",
+                    target
+                ));
+            }
+            table = self.released.wait(table).unwrap();
+        }
+    }
+
+    /// Releases a single lock granted to a transaction
+    pub fn unlock(&self, txn: TransactionId, target: &Lockable, mode: LockMode) {
+        let mut table = self.table.lock().unwrap();
+        if let Some(granted) = table.granted.get_mut(target) {
+            if let Some(pos) = granted.iter().position(|grant| *grant == (txn, mode)) {
+                granted.remove(pos);
+            }
+            let still_held = granted.iter().any(|(t, _)| *t == txn);
+            if granted.is_empty() {
+                table.granted.remove(target);
+            }
+            if !still_held {
+                if let Some(held) = table.held.get_mut(&txn) {
+                    held.remove(target);
+                }
+            }
+        }
+        self.released.notify_all();
+    }
+
+    /// Releases every lock held by a transaction and wakes up the transactions waiting for locks
+    pub fn release_all(&self, txn: TransactionId) {
+        let mut table = self.table.lock().unwrap();
+        table.waits_for.remove(&txn);
+        for target in table.held.remove(&txn).unwrap_or_default() {
+            if let Some(granted) = table.granted.get_mut(&target) {
+                granted.retain(|(t, _)| *t != txn);
+                if granted.is_empty() {
+                    table.granted.remove(&target);
+                }
+            }
+        }
+        self.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_lock_compatibility() {
+        let locks = LockManager::default();
+        let table = Lockable::Table("t".to_string());
+        let row = Lockable::Row("t".to_string(), Rid::new(0, 1));
+        assert!(locks.try_lock(1, &table, LockMode::Shared));
+        assert!(locks.try_lock(2, &table, LockMode::Shared));
+        assert!(!locks.try_lock(3, &table, LockMode::IntentionExclusive));
+        locks.release_all(1);
+        locks.release_all(2);
+        assert!(locks.try_lock(3, &table, LockMode::IntentionExclusive));
+        assert!(locks.try_lock(4, &table, LockMode::IntentionExclusive));
+        assert!(locks.try_lock(3, &row, LockMode::Exclusive));
+        assert!(!locks.try_lock(4, &row, LockMode::Exclusive));
+        assert!(locks.try_lock(3, &row, LockMode::Exclusive));
+        locks.release_all(3);
+        assert!(locks.try_lock(4, &row, LockMode::Exclusive));
+        // a reader only keeps the table from being locked Exclusive
+        assert!(locks.try_lock(5, &table, LockMode::SnapshotRead));
+        assert!(!locks.try_lock(6, &table, LockMode::Exclusive));
+        locks.release_all(4);
+        assert!(locks.try_lock(6, &table, LockMode::Shared));
+        locks.release_all(5);
+        locks.release_all(6);
+        assert!(locks.try_lock(6, &table, LockMode::Exclusive));
+        assert!(!locks.try_lock(5, &table, LockMode::SnapshotRead));
+    }
+
+    #[test]
+    fn test_deadlock_victim() {
+        let locks = Arc::new(LockManager::default());
+        let a = Lockable::Table("a".to_string());
+        let b = Lockable::Table("b".to_string());
+        locks.lock(1, &a, LockMode::Exclusive).unwrap();
+        locks.lock(2, &b, LockMode::Exclusive).unwrap();
+        let waiter = {
+            let locks = locks.clone();
+            let b = b.clone();
+            thread::spawn(move || locks.lock(1, &b, LockMode::Exclusive))
+        };
+        thread::sleep(Duration::from_millis(100));
+        let victim = locks.lock(2, &a, LockMode::Exclusive);
+        assert!(victim.unwrap_err().contains("deadlock"));
+        locks.release_all(2);
+        assert!(waiter.join().unwrap().is_ok());
+    }
+}
@@ -1,3 +1,6 @@
 pub mod db;
+pub mod lockmgr;
 pub mod plan_query;
 pub mod server;
+pub mod transaction;
+pub mod txnmgr;
@@ -1,53 +1,122 @@
 use crate::database::db::DatabaseInstance;
+use crate::database::txnmgr::Snapshot;
 use crate::meta::catalogmgr::CatalogManager;
 use crate::query::physical::{realize::FromLogicalNode, PhysicalNode};
 use crate::schema::schema::Schema;
 use crate::schema::types::Type;
 use crate::sql::parser::Node;
 use crate::sql::parser::Rule::sql_value;
-use crate::sql::query::select::{ProjectionTarget, SqlSelect};
+use crate::sql::query::select::{FromClause, ProjectionTarget, SqlSelect};
 use crate::table::tablemgr::TableManager;
 use crate::{query, FieldId};
 use std::cell::Ref;
 use std::collections::{HashMap, HashSet};
 use std::process::exit;
+use std::sync::Arc;
+use std::time::Instant;
 
 type Row = HashMap<String, Option<Vec<u8>>>;
+type DbTables = HashMap<String, TableManager>;
 type TreeNode = Box<dyn Iterator<Item = Row>>;
 
 impl DatabaseInstance {
-    pub fn plan_query(&self, query: SqlSelect) -> Result<PhysicalNode, String> {
-        let planner_info = self.planner_info();
+    /// Plans a query whose scans read the tuples visible to a snapshot
+    pub fn plan_query(
+        &self,
+        query: SqlSelect,
+        tables: &DbTables,
+        snapshot: Arc<Snapshot>,
+    ) -> Result<PhysicalNode, String> {
+        let planner_info = Self::planner_info(tables, None);
         let logical_plan =
             query::algebra::LogicalNode::translate_sql(query, &planner_info, self.name())
                 .map_err(|_| "Broken Query".to_string())?;
-        // if cfg!(debug_assertions) {
-            println!("Logical Plan:");
-            dbg!(&logical_plan);
-            println!("--------------------------");
-        // }
-        let mut planner_info = self.planner_info();
-        let plan = PhysicalNode::from_logic(logical_plan, &mut planner_info, self.tables());
-        // if cfg!(debug_assertions) {
-            println!("Physical Plan:");
-            dbg!(&plan);
-            println!("--------------------------");
-        // }
+        let mut planner_info = Self::planner_info(tables, Some(snapshot));
+        let plan = PhysicalNode::from_logic(logical_plan, &mut planner_info, tables);
         Ok(plan)
     }
 
-    fn planner_info(&self) -> PlannerInfo {
-        let info = self
-            .tables()
+    /// The lines of EXPLAIN, the plan tree with the estimated rows of every node.
+    ///
+    /// EXPLAIN ANALYZE runs the plan to completion first, discarding its rows, so every node also
+    /// reports the rows it returned, the time spent in it and the blocks it pinned. It fails when
+    /// running the plan does
+    pub fn explain(
+        &self,
+        plan: PhysicalNode,
+        analyze: bool,
+        tables: &DbTables,
+    ) -> Result<Vec<String>, String> {
+        let planner_info = Self::planner_info(tables, None);
+        if !analyze {
+            return Ok(plan.explain(&planner_info));
+        }
+        let mut plan = plan.instrument();
+        let start = Instant::now();
+        plan.by_ref().for_each(drop);
+        let elapsed = start.elapsed();
+        if let Some(error) = plan.error() {
+            return Err(error);
+        }
+        let mut lines = plan.explain(&planner_info);
+        lines.push(format!(
+            "Execution Time: {:.3} ms",
+            elapsed.as_secs_f64() * 1e3
+        ));
+        Ok(lines)
+    }
+
+    /// Plans the scan locating the tuples a DML statement operates on.
+    ///
+    /// The scan is planned as a `SELECT * FROM table WHERE ..` without its projection so it uses the
+    /// same access paths as queries, and the Rid of every returned row is available through
+    /// `PhysicalNode::current_rid`
+    pub fn plan_scan(
+        &self,
+        table: &str,
+        where_clause: Option<String>,
+        tables: &DbTables,
+        snapshot: Arc<Snapshot>,
+    ) -> Result<PhysicalNode, String> {
+        if !tables.contains_key(table) {
+            return Err(format!("Table {} does not exist", table));
+        }
+        let query = SqlSelect::new(
+            false,
+            vec![ProjectionTarget::AllFields],
+            FromClause::Table(table.to_string()),
+            where_clause,
+            None,
+            None,
+        );
+        let planner_info = Self::planner_info(tables, None);
+        let logical_plan =
+            query::algebra::LogicalNode::translate_sql(query, &planner_info, self.name())
+                .map_err(|_| "Broken Query".to_string())?;
+        let scan = match logical_plan {
+            query::algebra::LogicalNode::Project(project) => *project.child,
+            other => other,
+        };
+        let mut planner_info = Self::planner_info(tables, Some(snapshot));
+        Ok(PhysicalNode::from_logic(scan, &mut planner_info, tables))
+    }
+
+    fn planner_info(tables: &DbTables, snapshot: Option<Arc<Snapshot>>) -> PlannerInfo {
+        let info = tables
             .iter()
             .map(|(name, table)| (name.clone(), table.planning_info()))
             .collect();
-        PlannerInfo { table_info: info }
+        PlannerInfo {
+            table_info: info,
+            snapshot,
+        }
     }
 }
 
 pub struct PlannerInfo {
     pub table_info: HashMap<String, TableInfo>,
+    /// The snapshot the planned scans read the tables with
+    pub snapshot: Option<Arc<Snapshot>>,
 }
 impl PlannerInfo {
     pub fn get_fields_map(&self, table: &str) -> Option<&HashMap<String, Type>> {
@@ -67,6 +136,8 @@ pub struct TableInfo {
     fields_desc: HashMap<String, Type>,
     btree_idx: HashSet<String>,
     hash_idx: HashSet<String>,
+    blocks: usize,
+    rows: usize,
 }
 
 impl TableInfo {
@@ -74,14 +145,26 @@ impl TableInfo {
         fields_desc: HashMap<String, Type>,
         btree_idx: HashSet<String>,
         hash_idx: HashSet<String>,
+        blocks: usize,
+        rows: usize,
     ) -> Self {
         Self {
             fields_desc,
             btree_idx,
             hash_idx,
+            blocks,
+            rows,
         }
     }
     pub fn has_index_for(&self, field: &str) -> bool {
         self.btree_idx.contains(field) || self.hash_idx.contains(field)
     }
+    /// Number of heap blocks of the table, the planner's estimate of its size
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+    /// Estimated number of tuples in the table
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
 }
@@ -3,13 +3,14 @@ use crate::database::db::DatabaseInstance;
 use crate::interface::message::{Message, Status};
 use crate::meta::catalogmgr::CatalogManager;
 use crate::storage::storagemgr::StorageManager;
-use crate::{RcRefCell, AQUADIR};
-use std::cell::RefCell;
+use crate::{ArcRwLock, AQUADIR, AQUA_TMP_DIR};
 use std::fmt::Display;
-use std::io::{Read, Write};
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
 use std::net::{Incoming, IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
-use std::rc::Rc;
-use std::thread::sleep;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread::{self, sleep};
 use std::time::Duration;
 
 const BLK_SIZE: usize = 4096;
@@ -19,8 +20,10 @@ pub struct DatabaseServer {
     home_dir: String,
     bind_addr: Vec<String>,
     sockets: Vec<TcpListener>,
-    catalog: Rc<RefCell<CatalogManager>>,
-    storage: Rc<RefCell<StorageManager>>,
+    catalog: Arc<RwLock<CatalogManager>>,
+    storage: Arc<RwLock<StorageManager>>,
+    // number of clients connected, the last one to leave empties the temporary files directory
+    sessions: Mutex<usize>,
 }
 
 impl DatabaseServer {
@@ -49,6 +52,15 @@ impl DatabaseServer {
             if cmd.is_empty() {
                 continue;
             }
+            if self.storage.is_poisoned() || self.catalog.is_poisoned() {
+                Message::Status(Status::Generic(String::from(
+                    "The server is unavailable until it restarts, a session failed while \
+                     changing its storage or catalogs",
+                )))
+                .send_msg_to(&mut conn)
+                .unwrap_or_default();
+                return;
+            }
             if cmd[0].eq_ignore_ascii_case("sync") {
                 self.sync();
                 Message::Status(Status::Generic(String::from(
@@ -62,7 +74,7 @@ impl DatabaseServer {
                 continue;
             }
             if cmd[0].eq_ignore_ascii_case("create") && cmd[1].eq_ignore_ascii_case("db") {
-                match self.catalog.borrow_mut().create_database(cmd[2]) {
+                match self.catalog.write().unwrap().create_database(cmd[2]) {
                     Ok(()) => Message::Status(Status::DatabaseCreated(cmd[2].to_string()))
                         .send_msg_to(&mut conn)
                         .unwrap_or_default(),
@@ -70,19 +82,36 @@ impl DatabaseServer {
                         .send_msg_to(&mut conn)
                         .unwrap_or_default(),
                 }
+            } else if cmd[0].eq_ignore_ascii_case("drop")
+                && (cmd[1].eq_ignore_ascii_case("db") || cmd[1].eq_ignore_ascii_case("database"))
+            {
+                let status = match self.catalog.write().unwrap().drop_database(cmd[2]) {
+                    Ok(()) => Status::DatabaseDropped(cmd[2].to_string()),
+                    Err(s) => Status::DatabaseNotDropped(cmd[2].to_string(), s),
+                };
+                Message::Status(status)
+                    .send_msg_to(&mut conn)
+                    .unwrap_or_default();
             } else if cmd[0].eq_ignore_ascii_case("connect") && cmd[1].eq_ignore_ascii_case("db") {
-                let has_db = self.catalog.borrow().has_db(cmd[2]);
+                let has_db = self.catalog.read().unwrap().has_db(cmd[2]);
                 if has_db {
-                    Message::Status(Status::DatabaseConnection(cmd[2].to_string()))
-                        .send_msg_to(&mut conn)
-                        .unwrap_or_default();
                     let mut db_instance = DatabaseInstance::new(
                         cmd[2],
                         self.storage.clone(),
                         self.catalog.clone(),
                         conn.try_clone().unwrap(),
                     );
-                    db_instance.handle_connection();
+                    let status = match db_instance.failure() {
+                        Some(failure) => Status::Generic(failure),
+                        None => Status::DatabaseConnection(cmd[2].to_string()),
+                    };
+                    let connected = matches!(status, Status::DatabaseConnection(_));
+                    Message::Status(status)
+                        .send_msg_to(&mut conn)
+                        .unwrap_or_default();
+                    if connected {
+                        db_instance.handle_connection();
+                    }
                     // db_instance.flush_everything();
                 } else {
                     Message::Status(Status::DatabaseNotFound(cmd[2].to_string()))
@@ -94,29 +123,62 @@ impl DatabaseServer {
             }
         }
     }
+    /// Serves a client on its own thread until it disconnects.
+    ///
+    /// A panic ends the session of the client alone, its transaction is aborted as the session
+    /// unwinds. The locks it poisoned stay poisoned, what they guard may be half changed: the
+    /// sessions of a database whose tables it poisoned are refused, every session is when it
+    /// poisoned the storage or the catalogs, until the server restarts and recovers them from the
+    /// log
+    fn serve(&self, conn: TcpStream) {
+        let _session = SessionCount::enter(&self.sessions);
+        // the panic was reported by the panic hook already
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| self.dispatch(conn)));
+    }
+    /// Empties the temporary files directory, a failure is reported and left for the next time
+    /// the last client leaves
+    fn clear_tmp_dir() {
+        let path = AQUA_TMP_DIR();
+        let cleared = match fs::remove_dir_all(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => fs::create_dir(&path),
+        };
+        if let Err(e) = cleared {
+            eprintln!(
+                "Could not empty the temporary files directory {} : {}",
+                path.display(),
+                e
+            );
+        }
+    }
     fn sync(&self) {
-        self.storage.borrow_mut().flush_all();
+        self.storage.write().unwrap().flush_all();
     }
 
     pub fn new(home_dir: &str, addr: Vec<String>) -> Self {
         let sockets = Self::create_server_sockets(&addr);
-        let storage = RcRefCell!(StorageManager::new(&AQUADIR(), BLK_SIZE, BUFFER_COUNT));
-        let catalog = Rc::new(RefCell::from(CatalogManager::startup(storage.clone())));
+        let storage = ArcRwLock!(StorageManager::new(&AQUADIR(), BLK_SIZE, BUFFER_COUNT));
+        let catalog = Arc::new(RwLock::new(CatalogManager::startup(storage.clone())));
         Self {
             home_dir: home_dir.to_string(),
             sockets,
             bind_addr: addr,
             storage,
             catalog,
+            sessions: Mutex::new(0),
         }
     }
+    /// Accepts clients on every listener, each client is served by a thread of its own
     pub fn run(&self) {
-        let mut socket = self.sockets[0].incoming();
-        for conn in socket {
-            if let Ok(net) = conn {
-                self.dispatch(net)
+        thread::scope(|scope| {
+            for socket in &self.sockets {
+                scope.spawn(move || {
+                    for conn in socket.incoming().flatten() {
+                        scope.spawn(move || self.serve(conn));
+                    }
+                });
             }
-        }
+        });
         // for socket in self.sockets.iter().cycle() {
         //     if let Ok(conn) = socket.accept() {
         //         println!("accepted");
@@ -128,6 +190,27 @@ impl DatabaseServer {
     }
 }
 
+/// Counts a client as connected while it is held, the last client to leave empties the temporary
+/// files directory
+struct SessionCount<'a>(&'a Mutex<usize>);
+
+impl<'a> SessionCount<'a> {
+    fn enter(sessions: &'a Mutex<usize>) -> Self {
+        *sessions.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        Self(sessions)
+    }
+}
+
+impl Drop for SessionCount<'_> {
+    fn drop(&mut self) {
+        let mut sessions = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        *sessions -= 1;
+        if *sessions == 0 {
+            DatabaseServer::clear_tmp_dir();
+        }
+    }
+}
+
 impl Drop for DatabaseServer {
     fn drop(&mut self) {
         self.sync();
@@ -0,0 +1,289 @@
+use crate::database::lockmgr::{LockMode, Lockable};
+use crate::database::txnmgr::{Snapshot, TransactionId, TransactionManager};
+use crate::index::Rid;
+use crate::table::tablemgr::TableManager;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A change made to a table, reverted by the opposite operation
+#[derive(Debug)]
+enum Change {
+    Insert {
+        table: String,
+        rid: Rid,
+    },
+    Delete {
+        table: String,
+        rid: Rid,
+    },
+    Update {
+        table: String,
+        old_rid: Rid,
+        new_rid: Rid,
+    },
+}
+
+/// A point of a transaction to roll back to
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Savepoint {
+    changes: usize,
+    locks: usize,
+}
+
+/// The undo log of a transaction, the changes it made to the tables of a database in order.
+///
+/// Changes are made through the TableManager like any other, so reverting them with the opposite
+/// operations keeps the heap, the FreeMap and the indexes of a table in agreement.
+/// A change never moves a tuple, deletes and updates only mark the versions they replace, so the
+/// Rids of the undo log stay valid until the transaction ends.
+///
+/// The transaction reads the tables through its snapshot. It ends when it is dropped and its locks
+/// are released then, a transaction dropped without committing is aborted
+#[derive(Debug)]
+pub struct Transaction {
+    id: TransactionId,
+    manager: Arc<TransactionManager>,
+    snapshot: Arc<Snapshot>,
+    changes: Vec<Change>,
+    // the locks granted through `lock`, in order
+    granted: Vec<(Lockable, LockMode)>,
+    // the lock a statement could not get without waiting for another transaction
+    blocked: Option<(Lockable, LockMode)>,
+}
+
+impl Transaction {
+    pub fn new(manager: Arc<TransactionManager>) -> Self {
+        let id = manager.begin();
+        Self {
+            id,
+            snapshot: Arc::new(manager.snapshot(id)),
+            manager,
+            changes: vec![],
+            granted: vec![],
+            blocked: None,
+        }
+    }
+
+    pub fn id(&self) -> TransactionId {
+        self.id
+    }
+    /// The snapshot the statements of the transaction read the tables with
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.clone()
+    }
+    /// Takes a new snapshot, used by statements running as a transaction of their own so every run
+    /// of the statement sees the changes committed before it
+    pub fn refresh_snapshot(&mut self) {
+        self.snapshot = Arc::new(self.manager.snapshot(self.id));
+    }
+    /// A snapshot of the latest versions, the ones of running transactions included, that
+    /// constraints are checked against
+    pub fn dirty_snapshot(&self) -> Arc<Snapshot> {
+        Arc::new(self.manager.dirty_snapshot(self.id))
+    }
+
+    /// Takes a lock for the rest of the transaction if it is free.
+    ///
+    /// When another transaction holds a conflicting lock the request is remembered and an error
+    /// returned, the statement is expected to stop there so it can be undone and wait for the lock
+    pub fn lock(&mut self, target: Lockable, mode: LockMode) -> Result<(), String> {
+        let locks = self.manager.locks();
+        if locks.holds(self.id, &target, mode) {
+            Ok(())
+        } else if locks.try_lock(self.id, &target, mode) {
+            self.granted.push((target, mode));
+            Ok(())
+        } else {
+            let error = format!("{} is locked by another transaction", target);
+            self.blocked = Some((target, mode));
+            Err(error)
+        }
+    }
+    /// Whether the running statement stopped at a lock
+    pub fn is_blocked(&self) -> bool {
+        self.blocked.is_some()
+    }
+    /// The lock the last statement stopped at
+    pub fn take_blocked(&mut self) -> Option<(Lockable, LockMode)> {
+        self.blocked.take()
+    }
+    /// Waits until a lock is granted, fails if the transaction is the victim of a deadlock
+    pub fn wait_for(&self, target: &Lockable, mode: LockMode) -> Result<(), String> {
+        self.manager.locks().lock(self.id, target, mode)
+    }
+
+    pub fn inserted(&mut self, table: &str, rid: Rid) {
+        self.changes.push(Change::Insert {
+            table: table.to_string(),
+            rid,
+        });
+    }
+    pub fn deleted(&mut self, table: &str, rid: Rid) {
+        self.changes.push(Change::Delete {
+            table: table.to_string(),
+            rid,
+        });
+    }
+    pub fn updated(&mut self, table: &str, old_rid: Rid, new_rid: Rid) {
+        self.changes.push(Change::Update {
+            table: table.to_string(),
+            old_rid,
+            new_rid,
+        });
+    }
+
+    /// Marks the current point of the transaction, the changes made after it can be rolled back
+    /// alone with `rollback_to`
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint {
+            changes: self.changes.len(),
+            locks: self.granted.len(),
+        }
+    }
+
+    /// Commits the transaction, its changes have to be in the log on disk already
+    pub fn commit(self) {
+        self.manager.commit(self.id);
+    }
+
+    /// Reverts every change of the transaction, newest first
+    pub fn rollback(mut self, tables: &mut HashMap<String, TableManager>) -> usize {
+        self.rollback_to(Savepoint::default(), tables)
+    }
+
+    /// Releases the locks granted after a savepoint.
+    ///
+    /// Only meant for a statement that stopped at a lock once its changes are rolled back, it runs
+    /// again from the start and takes its locks again, so it holds none of them while it waits
+    pub fn unlock_to(&mut self, savepoint: Savepoint) {
+        for (target, mode) in self.granted.drain(savepoint.locks..) {
+            self.manager.locks().unlock(self.id, &target, mode);
+        }
+    }
+
+    /// Reverts the changes made after a savepoint, newest first, and returns how many were reverted
+    ///
+    /// The versions inserted by the changes are removed and the versions they deleted are restored
+    pub fn rollback_to(
+        &mut self,
+        savepoint: Savepoint,
+        tables: &mut HashMap<String, TableManager>,
+    ) -> usize {
+        let reverted = self.changes.len().saturating_sub(savepoint.changes);
+        for change in self.changes.drain(savepoint.changes..).rev() {
+            match change {
+                Change::Insert { table, rid } => {
+                    tables.get_mut(&table).unwrap().delete_tuples(&[rid]);
+                }
+                Change::Delete { table, rid } => {
+                    tables.get_mut(&table).unwrap().restore_version(&rid);
+                }
+                Change::Update {
+                    table,
+                    old_rid,
+                    new_rid,
+                } => {
+                    let table = tables.get_mut(&table).unwrap();
+                    table.delete_tuples(&[new_rid]);
+                    table.restore_version(&old_rid);
+                }
+            }
+        }
+        reverted
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        self.manager.end(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::testing::TempDir;
+    use crate::schema::schema::Schema;
+    use crate::schema::types::NumericType::Integer;
+    use crate::schema::types::Type;
+    use crate::storage::free_space::FreeMap;
+    use crate::storage::heap::HeapPage;
+    use crate::storage::storagemgr::StorageManager;
+    use std::sync::RwLock;
+
+    fn open_table(dir: &TempDir, storage: &Arc<RwLock<StorageManager>>) -> TableManager {
+        let mut schema = Schema::new();
+        schema.add_field_default_constraints("k", Type::Numeric(Integer), None);
+        let (heap, freemap) = (dir.join("t"), dir.join("t_freemap"));
+        if !heap.exists() {
+            let blocks = storage
+                .write()
+                .unwrap()
+                .empty_heap_pages(heap.to_str().unwrap(), 1);
+            FreeMap::init(
+                freemap.clone(),
+                HeapPage::default_free_space(4096) as u16,
+                &blocks[0],
+            );
+        }
+        TableManager::from_file(
+            storage.clone(),
+            heap,
+            Arc::new(schema.to_layout()),
+            vec![],
+            freemap,
+        )
+    }
+
+    fn keys(table: &TableManager, snapshot: Option<Arc<Snapshot>>) -> Vec<i32> {
+        let mut keys = table
+            .heapscan_iter(snapshot)
+            .map(|row| i32::from_ne_bytes(row["k"].clone().unwrap().try_into().unwrap()))
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    fn row(k: i32) -> Vec<(String, Option<Vec<u8>>)> {
+        vec![("k".to_string(), Some(k.to_ne_bytes().to_vec()))]
+    }
+
+    // A transaction cut short by a crash after its pages reached the disk is aborted on restart:
+    // its rows are seen by no snapshot, vacuum removes them and takes back its deletions. The
+    // block the crash left unformatted is formatted when the table is loaded
+    #[test]
+    fn test_crash_aborts_running_transaction() {
+        let dir = TempDir::new("crash");
+        let kept = {
+            let storage = dir.storage(50);
+            let mut table = open_table(&dir, &storage);
+            let manager = Arc::new(TransactionManager::new(dir.path()));
+            let committed = Transaction::new(manager.clone());
+            let kept = table.insert_version(row(1), committed.id());
+            storage.write().unwrap().flush_log();
+            committed.commit();
+
+            let running = Transaction::new(manager.clone());
+            for k in 2..100 {
+                table.insert_version(row(k), running.id());
+            }
+            table.delete_version(&kept, running.id());
+            // the pages are written before the transaction commits, then the server dies
+            storage.write().unwrap().flush_all();
+            // but the last block the heap file was extended with never did
+            let heap = dir.join("t");
+            storage.write().unwrap().extend_file(heap.to_str().unwrap());
+            std::mem::forget(running);
+            kept
+        };
+        let storage = dir.storage(50);
+        let mut table = open_table(&dir, &storage);
+        let manager = Arc::new(TransactionManager::new(dir.path()));
+        let reader = Transaction::new(manager.clone());
+        assert_eq!(keys(&table, Some(reader.snapshot())), vec![1]);
+        drop(reader);
+        assert_eq!(table.vacuum(&manager), 98);
+        assert_eq!(keys(&table, None), vec![1]);
+        assert_eq!(table.get_xids(&kept).1, 0);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::database::lockmgr::LockManager;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+pub type TransactionId = u64;
+
+/// Transaction ids are reserved on disk this many at a time
+const XID_BATCH: TransactionId = 1024;
+
+const XID_FILE: &str = "aqua_xid";
+const COMMIT_FILE: &str = "aqua_commits";
+
+/// Whether each transaction committed, a bit for each transaction id kept in a file of the
+/// database.
+///
+/// The bit of a transaction is forced to the disk when it commits, after its changes are in the
+/// log. A transaction that ended without it, rolled back or cut short by a crash, is aborted and
+/// the versions it created are seen by no snapshot
+#[derive(Debug)]
+pub struct CommitLog {
+    file: File,
+    bits: RwLock<Vec<u8>>,
+}
+
+impl CommitLog {
+    fn new(path: PathBuf) -> Self {
+        let bits = fs::read(&path).unwrap_or_default();
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .unwrap();
+        Self {
+            file,
+            bits: RwLock::new(bits),
+        }
+    }
+
+    /// Whether a transaction committed, the frozen versions of transaction 0 always are
+    pub fn committed(&self, xid: TransactionId) -> bool {
+        let byte = self.bits.read().unwrap().get(xid as usize / 8).copied();
+        xid == 0 || byte.is_some_and(|byte| byte & (1 << (xid % 8)) != 0)
+    }
+
+    fn commit(&self, xid: TransactionId) {
+        // the byte is written while the bits are held, so a commit never overwrites the bit of
+        // another one with a stale byte
+        let mut bits = self.bits.write().unwrap();
+        let idx = xid as usize / 8;
+        if bits.len() <= idx {
+            bits.resize(idx + 1, 0);
+        }
+        bits[idx] |= 1 << (xid % 8);
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(idx as u64)).unwrap();
+        file.write_all(&bits[idx..=idx]).unwrap();
+        file.sync_data().unwrap();
+    }
+}
+
+/// The transactions a snapshot sees as committed, taken when a transaction starts reading.
+///
+/// A transaction sees the versions created by transactions that committed before its snapshot was
+/// taken and not deleted by any of them, plus the changes it made itself. A transaction id met in
+/// a tuple that is neither running nor newer than the snapshot belongs to a transaction that ended,
+/// the commit log tells whether it committed
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    xid: TransactionId,
+    // the first transaction id not handed out yet when the snapshot was taken
+    xmax: TransactionId,
+    // the other transactions running when the snapshot was taken
+    running: HashSet<TransactionId>,
+    // also sees the versions created by running transactions, constraints are checked against them
+    dirty: bool,
+    commits: Arc<CommitLog>,
+}
+
+impl Snapshot {
+    /// Whether the tuple version created by xmin and deleted by xmax, 0 if it is not, is visible
+    pub fn sees(&self, xmin: TransactionId, xmax: TransactionId) -> bool {
+        let created = xmin == self.xid || self.committed(xmin) || self.dirty && !self.aborted(xmin);
+        let deleted = xmax != 0 && (xmax == self.xid || self.committed(xmax));
+        created && !deleted
+    }
+    fn ended(&self, xid: TransactionId) -> bool {
+        xid < self.xmax && !self.running.contains(&xid)
+    }
+    fn committed(&self, xid: TransactionId) -> bool {
+        self.ended(xid) && self.commits.committed(xid)
+    }
+    fn aborted(&self, xid: TransactionId) -> bool {
+        self.ended(xid) && !self.commits.committed(xid)
+    }
+}
+
+#[derive(Debug)]
+struct Running {
+    next_xid: TransactionId,
+    // the ids below it may have been handed out before a restart
+    reserved: TransactionId,
+    // each running transaction with the oldest transaction its snapshots saw running
+    active: HashMap<TransactionId, TransactionId>,
+}
+
+/// The transactions running on a database, hands out their ids, takes their snapshots and grants
+/// their locks.
+///
+/// Transaction ids keep growing across restarts since the tuples on disk carry them, the next ids
+/// are reserved in a file of the database before they are handed out.
+/// The transactions that were running when the server stopped never committed, they are aborted
+/// once it starts again
+#[derive(Debug)]
+pub struct TransactionManager {
+    locks: LockManager,
+    running: Mutex<Running>,
+    xid_file: PathBuf,
+    commits: Arc<CommitLog>,
+}
+
+impl TransactionManager {
+    /// Loads the transaction ids and the commit log kept in the directory of a database
+    pub fn new(db_dir: &Path) -> Self {
+        let xid_file = db_dir.join(XID_FILE);
+        let next_xid = fs::read(&xid_file)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(TransactionId::from_ne_bytes)
+            .unwrap_or(1);
+        Self {
+            locks: LockManager::default(),
+            running: Mutex::new(Running {
+                next_xid,
+                reserved: next_xid,
+                active: HashMap::new(),
+            }),
+            xid_file,
+            commits: Arc::new(CommitLog::new(db_dir.join(COMMIT_FILE))),
+        }
+    }
+
+    pub fn locks(&self) -> &LockManager {
+        &self.locks
+    }
+
+    /// Starts a transaction and returns its id
+    pub fn begin(&self) -> TransactionId {
+        let mut running = self.running.lock().unwrap();
+        let xid = running.next_xid;
+        running.next_xid += 1;
+        if xid >= running.reserved {
+            running.reserved = xid + XID_BATCH;
+            fs::write(&self.xid_file, running.reserved.to_ne_bytes()).unwrap();
+        }
+        running.active.insert(xid, xid);
+        xid
+    }
+
+    /// Takes a snapshot of the running transactions for a transaction to read with
+    pub fn snapshot(&self, xid: TransactionId) -> Snapshot {
+        let mut running = self.running.lock().unwrap();
+        let xmax = running.next_xid;
+        let others = running
+            .active
+            .keys()
+            .copied()
+            .filter(|t| *t != xid)
+            .collect::<HashSet<_>>();
+        let oldest = others.iter().copied().fold(xid, TransactionId::min);
+        if let Some(seen) = running.active.get_mut(&xid) {
+            *seen = oldest.min(*seen);
+        }
+        Snapshot {
+            xid,
+            xmax,
+            running: others,
+            dirty: false,
+            commits: self.commits.clone(),
+        }
+    }
+
+    /// A snapshot seeing every version not deleted by a committed transaction, the versions
+    /// inserted by running transactions included
+    pub fn dirty_snapshot(&self, xid: TransactionId) -> Snapshot {
+        Snapshot {
+            dirty: true,
+            ..self.snapshot(xid)
+        }
+    }
+
+    /// Records a transaction as committed, its changes have to be in the log on disk already
+    pub fn commit(&self, xid: TransactionId) {
+        self.commits.commit(xid);
+    }
+
+    /// Whether a transaction that ended committed, see `CommitLog`
+    pub fn committed(&self, xid: TransactionId) -> bool {
+        self.commits.committed(xid)
+    }
+
+    /// Ends a transaction once it committed or reverted its changes, releasing its locks. A
+    /// transaction that ends without committing is aborted
+    pub fn end(&self, xid: TransactionId) {
+        self.running.lock().unwrap().active.remove(&xid);
+        self.locks.release_all(xid);
+    }
+
+    /// The oldest transaction a snapshot may still see running, the versions deleted by the
+    /// transactions before it are visible to no snapshot anymore
+    pub fn horizon(&self) -> TransactionId {
+        let running = self.running.lock().unwrap();
+        running
+            .active
+            .values()
+            .copied()
+            .fold(running.next_xid, TransactionId::min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::testing::TempDir;
+
+    #[test]
+    fn test_snapshot_visibility() {
+        let dir = TempDir::new("xid");
+        let manager = TransactionManager::new(dir.path());
+        let (t1, t2) = (manager.begin(), manager.begin());
+        let snapshot = manager.snapshot(t2);
+        // frozen, own and running versions
+        assert!(snapshot.sees(0, 0));
+        assert!(snapshot.sees(t2, 0));
+        assert!(!snapshot.sees(t2, t2));
+        assert!(!snapshot.sees(t1, 0));
+        assert!(manager.dirty_snapshot(t2).sees(t1, 0));
+        assert!(snapshot.sees(0, t1));
+        assert_eq!(manager.horizon(), t1);
+        manager.commit(t1);
+        manager.end(t1);
+        // t1 committed after the snapshot was taken
+        assert!(!snapshot.sees(t1, 0));
+        assert!(manager.snapshot(t2).sees(t1, 0));
+        let t3 = manager.begin();
+        assert!(!manager.snapshot(t2).sees(t3, 0));
+        manager.end(t2);
+        // t3 ends without committing, its versions are seen by no snapshot and its deletions are
+        // taken back
+        manager.end(t3);
+        let t4 = manager.begin();
+        assert!(!manager.snapshot(t4).sees(t3, 0));
+        assert!(!manager.dirty_snapshot(t4).sees(t3, 0));
+        assert!(manager.snapshot(t4).sees(t1, t3));
+        manager.end(t4);
+        assert_eq!(manager.horizon(), t4 + 1);
+        // ids keep growing and commits are kept after a restart
+        let restarted = TransactionManager::new(dir.path());
+        let t5 = restarted.begin();
+        assert!(t5 > t4);
+        assert!(restarted.snapshot(t5).sees(t1, 0));
+        assert!(!restarted.snapshot(t5).sees(t2, 0));
+    }
+}
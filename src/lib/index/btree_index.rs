@@ -10,14 +10,15 @@ use crate::storage::heap::HeapPage;
 use crate::storage::storagemgr::StorageManager;
 use crate::storage::tuple::Tuple;
 use pest::unicode::FORMAT;
-use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::env;
 use std::env::consts::OS;
 use std::fmt::Display;
 use std::io::Read;
-use std::rc::Rc;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::sync::RwLock;
 
 use super::Rid;
 
@@ -25,13 +26,14 @@ const BLOCK_NUM_SIZE: usize = 8;
 const SLOT_NUM_SIZE: usize = 2;
 const ORDER: usize = 4; // B+Tree order
 const INDEX_RECORD_SIZE: usize = 14;
+const LEAF_SPECIAL_AREA: u16 = 16;
 
 // Index record in the leaf node
 #[derive(Debug, Clone)]
 struct IndexRecord {
     key: Vec<u8>,
     value: Rid,
-    layout: Rc<Layout>,
+    layout: Arc<Layout>,
 }
 
 impl IndexRecord {
@@ -52,7 +54,7 @@ impl IndexRecord {
     }
 
     // Convert bytes to IndexRecord
-    fn from_bytes(mut bytes: HashMap<String, Option<Vec<u8>>>, layout: Rc<Layout>) -> IndexRecord {
+    fn from_bytes(mut bytes: HashMap<String, Option<Vec<u8>>>, layout: Arc<Layout>) -> IndexRecord {
         let key = bytes.remove("key").unwrap().unwrap();
         let block_num = bytes.remove("block_num").unwrap().unwrap();
         let slot_num = bytes.remove("slot_num").unwrap().unwrap();
@@ -65,15 +67,36 @@ impl IndexRecord {
     }
 }
 
+// Free space of an empty node page, the page header takes the first 6 bytes
+fn node_capacity(heap_page: &HeapPage) -> usize {
+    heap_page.header.special_area_offset - 6
+}
+
+// Space taken by a list of node entries including their tuple pointers
+fn entries_size(tuples: impl Iterator<Item = Tuple>) -> usize {
+    tuples.map(|tuple| tuple.tuple_size() + 4).sum()
+}
+
+// Zero out the page of a node emptied by a merge and hand back its block
+fn release_block(heap_page: &HeapPage, freed: &mut Vec<BlockId>) {
+    let page_size = heap_page.frame.read().unwrap().page.payload.len();
+    heap_page
+        .frame
+        .write()
+        .unwrap()
+        .write(vec![0; page_size].as_slice());
+    freed.push(heap_page.blk.clone());
+}
+
 // B+Tree structure
 #[derive(Clone)]
 pub struct BPTree {
     root: NodePage,
     root_frame: FrameRef,
     key_type: Type,
-    storage_manager: Rc<RefCell<StorageManager>>,
-    internal_layout: Rc<Layout>,
-    leaf_layout: Rc<Layout>,
+    storage_manager: Arc<RwLock<StorageManager>>,
+    internal_layout: Arc<Layout>,
+    leaf_layout: Arc<Layout>,
     index_file: String,
 }
 
@@ -81,27 +104,29 @@ pub struct BPTree {
 impl BPTree {
     pub fn init(
         key_type: Type,
-        storage_manager: Rc<RefCell<StorageManager>>,
+        storage_manager: Arc<RwLock<StorageManager>>,
         index_file: String,
     ) -> Self {
         let root_block = storage_manager
-            .borrow_mut()
+            .write()
+            .unwrap()
             .extend_file(index_file.as_str());
         let root_frame = storage_manager
-            .borrow_mut()
+            .write()
+            .unwrap()
             .pin(root_block.clone())
             .unwrap();
 
         let mut internal_schema = Schema::new();
         internal_schema.add_field_default_constraints("key", key_type, None);
         internal_schema.add_field_default_constraints("block_num", Type::Numeric(BigInt), None);
-        let internal_layout = Rc::new(internal_schema.to_layout());
+        let internal_layout = Arc::new(internal_schema.to_layout());
 
         let mut leaf_schema = Schema::new();
         leaf_schema.add_field_default_constraints("key", key_type, None);
         leaf_schema.add_field_default_constraints("block_num", Type::Numeric(BigInt), None);
         leaf_schema.add_field_default_constraints("slot_num", Type::Numeric(SmallInt), None);
-        let leaf_layout = Rc::new(leaf_schema.to_layout());
+        let leaf_layout = Arc::new(leaf_schema.to_layout());
 
         let root_heap = HeapPage::new_from_empty_special(
             root_frame.clone(),
@@ -132,24 +157,25 @@ impl BPTree {
     pub fn new(
         root_block: BlockId,
         key_type: Type,
-        storage_manager: Rc<RefCell<StorageManager>>,
+        storage_manager: Arc<RwLock<StorageManager>>,
         index_file: String,
     ) -> Self {
         let root_frame = storage_manager
-            .borrow_mut()
+            .write()
+            .unwrap()
             .pin(root_block.clone())
             .unwrap();
 
         let mut internal_schema = Schema::new();
         internal_schema.add_field_default_constraints("key", key_type, None);
         internal_schema.add_field_default_constraints("block_num", Type::Numeric(BigInt), None);
-        let internal_layout = Rc::new(internal_schema.to_layout());
+        let internal_layout = Arc::new(internal_schema.to_layout());
 
         let mut leaf_schema = Schema::new();
         leaf_schema.add_field_default_constraints("key", key_type, None);
         leaf_schema.add_field_default_constraints("block_num", Type::Numeric(BigInt), None);
         leaf_schema.add_field_default_constraints("slot_num", Type::Numeric(SmallInt), None);
-        let leaf_layout = Rc::new(leaf_schema.to_layout());
+        let leaf_layout = Arc::new(leaf_schema.to_layout());
 
         BPTree {
             root: NodePage::new(
@@ -170,201 +196,56 @@ impl BPTree {
     }
 
     // Insert a key-value pair into the B+Tree
+    //
+    // The root always lives in block 0, so when it splits its left half is moved to a new block
+    // and the root is rebuilt as an internal node over the two halves
     pub fn insert(&mut self, key: Vec<u8>, value: Rid) {
-        let mut free_space = 0_i32;
-        match self.root.clone() {
-            NodePage::Internal(root) => {
-                free_space = (root.heap_page.free_space()) as i32 - 4;
-            }
-            NodePage::Leaf(root) => {
-                free_space = (root.heap_page.free_space()) as i32 - 4;
+        let (split_key, split_block_num) = self.root.insert(key, value);
+        if let (Some(split_key), Some(split_block_num)) = (split_key, split_block_num) {
+            let root_payload = self.root_frame.read().unwrap().page.payload.clone();
+            let left_blockid = self
+                .storage_manager
+                .write()
+                .unwrap()
+                .extend_file(&self.index_file);
+            let left_frame = self
+                .storage_manager
+                .write()
+                .unwrap()
+                .pin(left_blockid.clone())
+                .unwrap();
+            left_frame.write().unwrap().write(root_payload.as_slice());
+            self.storage_manager.write().unwrap().unpin(left_frame);
+
+            if let NodePage::Leaf(root_leaf) = &self.root {
+                let mut right_leaf = root_leaf.sibling(split_block_num);
+                right_leaf.meta_data.prev_node_blockid = left_blockid.block_num;
+                right_leaf
+                    .heap_page
+                    .write_special_area(right_leaf.meta_data.to_bytes());
+                self.storage_manager
+                    .write()
+                    .unwrap()
+                    .unpin(right_leaf.heap_page.frame.clone());
             }
-        }
-        let (split_key, split_block_num) = self.root.insert(key.clone(), value.clone());
-        if let Some(split_key) = split_key {
-            let dummy_key: Vec<u8> = Vec::new();
-            match self.root.clone() {
-                NodePage::Internal(mut root_page) => {
-                    let right = (root_page.heap_page.tuple_pointers.len() - 1) as u16;
-                    let child_index = root_page.binary_search_child_node(1, right, key.clone());
-
-                    let new_child_tuple = ChildNode {
-                        key: split_key.clone(),
-                        block_num: split_block_num.unwrap(),
-                        layout: self.internal_layout.clone(),
-                    }
-                    .to_tuple();
-
-                    if free_space > (new_child_tuple.tuple_size() as i32) {
-                        if child_index == 0 {
-                            let first_child_block_num = root_page
-                                .heap_page
-                                .get_field("block_num", 0)
-                                .unwrap()
-                                .as_slice()
-                                .extract_u64(0);
-                            root_page.heap_page.mark_delete(0);
-                            root_page.heap_page.vacuum();
-
-                            root_page.insert_child(split_key, first_child_block_num);
-                            let mut last_inserted =
-                                root_page.heap_page.tuple_pointers.pop().unwrap();
-                            root_page
-                                .heap_page
-                                .tuple_pointers
-                                .insert(child_index, last_inserted);
-
-                            root_page.insert_child(dummy_key.clone(), split_block_num.unwrap());
-                            last_inserted = root_page.heap_page.tuple_pointers.pop().unwrap();
-                            root_page
-                                .heap_page
-                                .tuple_pointers
-                                .insert(child_index, last_inserted);
-
-                            root_page.heap_page.rewrite_tuple_pointers_to_frame();
-                        } else {
-                            root_page.heap_page.insert_tuple(new_child_tuple);
-                            let last_inserted = root_page.heap_page.tuple_pointers.pop().unwrap();
-                            root_page
-                                .heap_page
-                                .tuple_pointers
-                                .insert(child_index + 1, last_inserted);
-                            root_page.heap_page.rewrite_tuple_pointers_to_frame();
-                        }
-                    } else {
-                        let root_payload = self.root_frame.borrow().page.payload.clone();
-                        self.root_frame
-                            .borrow_mut()
-                            .page
-                            .write_bytes(vec![0; root_payload.len()].as_slice(), 0);
-                        let idx_file = self
-                            .root_frame
-                            .borrow()
-                            .blockid
-                            .as_ref()
-                            .unwrap()
-                            .filename
-                            .clone();
-                        let root_block = BlockId::new(idx_file.as_str(), 0);
-                        let new_root_heap = HeapPage::new_from_empty(
-                            self.root_frame.clone(),
-                            &root_block,
-                            self.internal_layout.clone(),
-                        );
-
-                        let mut new_root = InternalNodePage::new(
-                            new_root_heap,
-                            self.key_type,
-                            self.storage_manager.clone(),
-                            self.internal_layout.clone(),
-                            self.leaf_layout.clone(),
-                            self.index_file.clone(),
-                        );
-
-                        let left_blockid = self
-                            .storage_manager
-                            .borrow_mut()
-                            .extend_file(&self.index_file);
-                        let mut left_frame = self
-                            .storage_manager
-                            .borrow_mut()
-                            .pin(left_blockid.clone())
-                            .unwrap();
-                        left_frame.borrow_mut().page.payload = root_payload;
-                        let left_heap = HeapPage::new(
-                            left_frame.clone(),
-                            &left_blockid.clone(),
-                            self.internal_layout.clone(),
-                        );
-                        let split_blockid = BlockId {
-                            block_num: split_block_num.unwrap(),
-                            filename: self.index_file.to_string(),
-                        };
-                        let split_frame = self
-                            .storage_manager
-                            .borrow_mut()
-                            .pin(split_blockid.clone())
-                            .unwrap();
-                        let right_heap = HeapPage::new(
-                            split_frame.clone(),
-                            &split_blockid.clone(),
-                            self.internal_layout.clone(),
-                        );
-                        self.storage_manager.borrow_mut().unpin(left_frame);
-                        self.storage_manager.borrow_mut().unpin(split_frame);
-
-                        new_root.insert_child(dummy_key, left_blockid.block_num);
-                        new_root.insert_child(split_key.clone(), split_block_num.unwrap());
-                        self.root = NodePage::Internal(new_root);
-                    }
-                }
-                NodePage::Leaf(_) => {
-                    let root_payload = self.root_frame.borrow().page.payload.clone();
-                    self.root_frame
-                        .borrow_mut()
-                        .page
-                        .write_bytes(vec![0; root_payload.len()].as_slice(), 0);
-                    let idx_file = self
-                        .root_frame
-                        .borrow()
-                        .blockid
-                        .as_ref()
-                        .unwrap()
-                        .filename
-                        .clone();
-                    let root_block = BlockId::new(idx_file.as_str(), 0);
-                    let new_root_heap = HeapPage::new_from_empty(
-                        self.root_frame.clone(),
-                        &root_block,
-                        self.internal_layout.clone(),
-                    );
-
-                    let mut new_root = InternalNodePage::new(
-                        new_root_heap,
-                        self.key_type,
-                        self.storage_manager.clone(),
-                        self.internal_layout.clone(),
-                        self.leaf_layout.clone(),
-                        self.index_file.clone(),
-                    );
-
-                    let right_blockid = BlockId {
-                        block_num: split_block_num.unwrap(),
-                        filename: self.index_file.clone(),
-                    };
-                    let right_frame = self.storage_manager.borrow_mut().pin(right_blockid.clone());
-
-                    let left_blockid = self
-                        .storage_manager
-                        .borrow_mut()
-                        .extend_file(&self.index_file);
-                    let mut left_frame = self
-                        .storage_manager
-                        .borrow_mut()
-                        .pin(left_blockid.clone())
-                        .unwrap();
-                    left_frame.borrow_mut().page.payload = root_payload;
-
-                    let mut right_heap = HeapPage::new(
-                        right_frame.unwrap(),
-                        &right_blockid,
-                        self.leaf_layout.clone(),
-                    );
-                    let mut right_leaf = LeafNodePage::new(
-                        right_heap.clone(),
-                        self.key_type,
-                        self.storage_manager.clone(),
-                        self.leaf_layout.clone(),
-                        self.index_file.clone(),
-                    );
-                    right_leaf.meta_data.prev_node_blockid = left_blockid.block_num;
-                    right_heap.write_special_area(right_leaf.meta_data.to_bytes());
 
-                    new_root.insert_child(dummy_key, left_blockid.block_num);
-                    new_root.insert_child(split_key.clone(), split_block_num.unwrap());
-                    self.root = NodePage::Internal(new_root);
-                }
-            }
+            let root_block = BlockId::new(self.index_file.as_str(), 0);
+            let new_root_heap = HeapPage::new_from_empty(
+                self.root_frame.clone(),
+                &root_block,
+                self.internal_layout.clone(),
+            );
+            let mut new_root = InternalNodePage::new(
+                new_root_heap,
+                self.key_type,
+                self.storage_manager.clone(),
+                self.internal_layout.clone(),
+                self.leaf_layout.clone(),
+                self.index_file.clone(),
+            );
+            new_root.insert_child(Vec::new(), left_blockid.block_num);
+            new_root.insert_child(split_key, split_block_num);
+            self.root = NodePage::Internal(new_root);
         }
     }
 
@@ -373,6 +254,44 @@ impl BPTree {
         self.root.search(key)
     }
 
+    // Remove a key-value pair from the B+Tree, returns the blocks emptied by merging nodes or None
+    // if the pair was not found
+    //
+    // An internal root left with a single child is collapsed by moving that child into block 0
+    pub fn delete(&mut self, key: Vec<u8>, value: Rid) -> Option<Vec<BlockId>> {
+        let mut freed = Vec::new();
+        if !self.root.delete(key, value, &mut freed) {
+            return None;
+        }
+        let only_child = match &self.root {
+            NodePage::Internal(root) if root.heap_page.tuple_pointers.len() == 1 => {
+                Some(root.child(0))
+            }
+            _ => None,
+        };
+        if let Some(child) = only_child {
+            let child_payload = child.heap_page().frame.read().unwrap().page.payload.clone();
+            self.root_frame
+                .write()
+                .unwrap()
+                .write(child_payload.as_slice());
+            release_block(child.heap_page(), &mut freed);
+            self.storage_manager
+                .write()
+                .unwrap()
+                .unpin(child.heap_page().frame.clone());
+            self.root = NodePage::new(
+                self.root_frame.clone(),
+                self.key_type,
+                self.storage_manager.clone(),
+                self.internal_layout.clone(),
+                self.leaf_layout.clone(),
+                self.index_file.clone(),
+            );
+        }
+        Some(freed)
+    }
+
     pub fn get_greater_than(&self, key: Vec<u8>) -> Option<Vec<Rid>> {
         self.root.get_greater_than(key)
     }
@@ -389,6 +308,11 @@ impl BPTree {
         self.root.get_less_than_or_equal(key)
     }
 
+    // Get the values of the keys from low included to high excluded, the keys are compared as bytes
+    pub fn get_range(&self, low: Vec<u8>, high: Vec<u8>) -> Vec<Rid> {
+        self.root.get_range(&low, &high)
+    }
+
     pub fn print_root(&self) {
         self.root.print_node();
     }
@@ -406,13 +330,13 @@ impl NodePage {
     fn new(
         frame: FrameRef,
         key_type: Type,
-        storage_manager: Rc<RefCell<StorageManager>>,
-        internal_layout: Rc<Layout>,
-        leaf_layout: Rc<Layout>,
+        storage_manager: Arc<RwLock<StorageManager>>,
+        internal_layout: Arc<Layout>,
+        leaf_layout: Arc<Layout>,
         index_file: String,
     ) -> Self {
-        let block_id = frame.borrow().blockid.as_ref().unwrap().clone();
-        let mut heap_page = HeapPage::new(frame.clone(), &block_id, Rc::new(Layout::default()));
+        let block_id = frame.read().unwrap().blockid.as_ref().unwrap().clone();
+        let mut heap_page = HeapPage::new(frame.clone(), &block_id, Arc::new(Layout::default()));
         if heap_page.get_special_area().is_empty() {
             heap_page.layout = internal_layout.clone();
             NodePage::Internal(InternalNodePage::new(
@@ -438,12 +362,12 @@ impl NodePage {
     pub fn init(
         frame: FrameRef,
         key_type: Type,
-        storage_manager: Rc<RefCell<StorageManager>>,
-        internal_layout: Rc<Layout>,
-        leaf_layout: Rc<Layout>,
+        storage_manager: Arc<RwLock<StorageManager>>,
+        internal_layout: Arc<Layout>,
+        leaf_layout: Arc<Layout>,
         index_file: String,
     ) -> Self {
-        let block_id = frame.borrow().blockid.as_ref().unwrap().clone();
+        let block_id = frame.read().unwrap().blockid.as_ref().unwrap().clone();
         let mut heap_page =
             HeapPage::new_from_empty_special(frame.clone(), &block_id, leaf_layout.clone(), 16);
         NodePage::Leaf(LeafNodePage::new(
@@ -471,6 +395,28 @@ impl NodePage {
         }
     }
 
+    // Remove a key-value pair from the node, blocks emptied by merges are pushed to freed
+    fn delete(&mut self, key: Vec<u8>, value: Rid, freed: &mut Vec<BlockId>) -> bool {
+        match self {
+            NodePage::Internal(node) => node.delete(key, value, freed),
+            NodePage::Leaf(node) => node.remove_record(&key, &value),
+        }
+    }
+
+    fn heap_page(&self) -> &HeapPage {
+        match self {
+            NodePage::Internal(node) => &node.heap_page,
+            NodePage::Leaf(node) => &node.heap_page,
+        }
+    }
+
+    // A node underflows once less than half of its page is in use
+    fn underflows(&self) -> bool {
+        let heap_page = self.heap_page();
+        let capacity = node_capacity(heap_page);
+        capacity.saturating_sub(heap_page.free_space() as usize) < capacity / 2
+    }
+
     fn get_greater_than(&self, key: Vec<u8>) -> Option<Vec<Rid>> {
         match self {
             NodePage::Internal(node) => node.get_greater_than(key),
@@ -499,6 +445,13 @@ impl NodePage {
         }
     }
 
+    fn get_range(&self, low: &[u8], high: &[u8]) -> Vec<Rid> {
+        match self {
+            NodePage::Internal(node) => node.get_range(low, high),
+            NodePage::Leaf(node) => node.get_range(low, high),
+        }
+    }
+
     fn is_leaf(&self) -> bool {
         match self {
             NodePage::Internal(_) => false,
@@ -521,7 +474,7 @@ impl NodePage {
 struct ChildNode {
     key: Vec<u8>,
     block_num: u64,
-    layout: Rc<Layout>,
+    layout: Arc<Layout>,
 }
 
 impl ChildNode {
@@ -545,7 +498,7 @@ impl ChildNode {
 
     pub fn from_bytes(
         mut bytes: HashMap<String, Option<Vec<u8>>>,
-        layout: Rc<Layout>,
+        layout: Arc<Layout>,
     ) -> ChildNode {
         let key = bytes.remove("key").unwrap().unwrap();
         let block_num = bytes
@@ -568,15 +521,15 @@ impl ChildNode {
 pub struct InternalNodePage {
     heap_page: HeapPage,
     key_type: Type,
-    storage_manager: Rc<RefCell<StorageManager>>,
-    internal_layout: Rc<Layout>,
-    leaf_layout: Rc<Layout>,
+    storage_manager: Arc<RwLock<StorageManager>>,
+    internal_layout: Arc<Layout>,
+    leaf_layout: Arc<Layout>,
     index_file: String,
 }
 
 /*impl Drop for InternalNodePage {
     fn drop(&mut self) {
-        self.storage_manager.borrow_mut().unpin(self.heap_page.frame.clone());
+        self.storage_manager.write().unwrap().unpin(self.heap_page.frame.clone());
     }
 }*/
 
@@ -585,9 +538,9 @@ impl InternalNodePage {
     pub fn new(
         heap_page: HeapPage,
         key_type: Type,
-        storage_manager: Rc<RefCell<StorageManager>>,
-        internal_layout: Rc<Layout>,
-        leaf_layout: Rc<Layout>,
+        storage_manager: Arc<RwLock<StorageManager>>,
+        internal_layout: Arc<Layout>,
+        leaf_layout: Arc<Layout>,
         index_file: String,
     ) -> InternalNodePage {
         Self {
@@ -605,262 +558,309 @@ impl InternalNodePage {
         let right = (self.heap_page.tuple_pointers.len() - 1) as u16;
         let child_index = self.binary_search_child_node(1, right, key.clone());
 
-        let target_block_num = self
-            .heap_page
-            .get_field("block_num", child_index as u16)
+        let mut child = self.child(child_index);
+        let (split_key, split_block_num) = child.insert(key, value);
+        self.storage_manager
+            .write()
             .unwrap()
-            .as_slice()
-            .extract_u64(0);
-        let target_blockid = BlockId::new(&self.index_file, target_block_num);
+            .unpin(child.heap_page().frame.clone());
+        match (split_key, split_block_num) {
+            (Some(split_key), Some(split_block_num)) => {
+                self.insert_split_child(child_index + 1, split_key, split_block_num)
+            }
+            _ => (None, None),
+        }
+    }
 
-        let frame = self
+    // Add the entry of a newly split child at a given position. If it does not fit the node is
+    // split in two halves and the separator and block of the right half are returned
+    fn insert_split_child(
+        &mut self,
+        position: usize,
+        key: Vec<u8>,
+        block_num: u64,
+    ) -> (Option<Vec<u8>>, Option<u64>) {
+        let new_child = ChildNode {
+            key,
+            block_num,
+            layout: self.internal_layout.clone(),
+        };
+        let new_child_tuple = new_child.to_tuple();
+        let free_space = self.heap_page.free_space() as i32 - 4;
+        if free_space > new_child_tuple.tuple_size() as i32 {
+            self.heap_page.insert_tuple(new_child_tuple);
+            let last_inserted = self.heap_page.tuple_pointers.pop().unwrap();
+            self.heap_page
+                .tuple_pointers
+                .insert(position, last_inserted);
+            self.heap_page.rewrite_tuple_pointers_to_frame();
+            return (None, None);
+        }
+
+        let mut children = self.children();
+        children.insert(position, new_child);
+        let mut right_children = children.split_off(children.len() / 2);
+        let split_key = std::mem::take(&mut right_children[0].key);
+        self.rewrite(children);
+
+        let new_block = self
             .storage_manager
-            .borrow_mut()
-            .pin(target_blockid.clone())
+            .write()
+            .unwrap()
+            .extend_file(&self.index_file);
+        let right_frame = self
+            .storage_manager
+            .write()
+            .unwrap()
+            .pin(new_block.clone())
             .unwrap();
-        let target_heap_page = HeapPage::new(
-            frame.clone(),
-            &target_blockid,
-            self.heap_page.layout.clone(),
-        );
-
-        let mut node_page = NodePage::new(
-            frame.clone(),
-            self.key_type,
-            self.storage_manager.clone(),
+        let mut right_heap_page = HeapPage::new_from_empty(
+            right_frame.clone(),
+            &new_block,
             self.internal_layout.clone(),
-            self.leaf_layout.clone(),
-            self.index_file.clone(),
         );
+        for child_node in right_children {
+            right_heap_page.insert_tuple(child_node.to_tuple());
+        }
+        self.storage_manager.write().unwrap().unpin(right_frame);
+        (Some(split_key), Some(new_block.block_num))
+    }
 
-        match node_page {
-            NodePage::Internal(ref mut target_node) => {
-                let free_space = self.heap_page.free_space() as i32 - 4;
-                let (split_key, split_block_num) = target_node.insert(key.clone(), value);
-                self.storage_manager
-                    .borrow_mut()
-                    .unpin(target_node.heap_page.frame.clone());
-                if let Some(split_key) = split_key {
-                    let dummy_key: Vec<u8> = Vec::new();
-
-                    let right = (self.heap_page.tuple_pointers.len() - 1) as u16;
-                    let child_index = self.binary_search_child_node(1, right, key.clone());
-
-                    let new_child_tuple = ChildNode {
-                        key: split_key.clone(),
-                        block_num: split_block_num.unwrap(),
-                        layout: self.internal_layout.clone(),
-                    }
-                    .to_tuple();
-
-                    if free_space > new_child_tuple.tuple_size() as i32 {
-                        if child_index == 0 {
-                            let first_child_block_num = self
-                                .heap_page
-                                .get_field("block_num", 0)
-                                .unwrap()
-                                .as_slice()
-                                .extract_u64(0);
-                            self.heap_page.mark_delete(0);
-                            self.heap_page.vacuum();
-
-                            self.insert_child(split_key, first_child_block_num);
-                            let mut last_inserted = self.heap_page.tuple_pointers.pop().unwrap();
-                            self.heap_page
-                                .tuple_pointers
-                                .insert(child_index, last_inserted);
-
-                            self.insert_child(dummy_key.clone(), split_block_num.unwrap());
-                            last_inserted = self.heap_page.tuple_pointers.pop().unwrap();
-                            self.heap_page
-                                .tuple_pointers
-                                .insert(child_index, last_inserted);
-
-                            self.heap_page.rewrite_tuple_pointers_to_frame();
-                        } else {
-                            self.heap_page.insert_tuple(new_child_tuple);
-                            let last_inserted = self.heap_page.tuple_pointers.pop().unwrap();
-                            self.heap_page
-                                .tuple_pointers
-                                .insert(child_index + 1, last_inserted);
-                            self.heap_page.rewrite_tuple_pointers_to_frame();
-                        }
-                    } else {
-                        let pointers_num = self.heap_page.tuple_pointers.len();
-                        let mid = self.heap_page.tuple_pointers.len() as u16 / 2;
-                        let split_key = self.heap_page.get_field("key", mid);
-                        let mut right_child_nodes = Vec::new();
-                        for tuple_pointer_index in mid..pointers_num as u16 {
-                            let tuple_bytes = self.heap_page.get_multiple_fields(
-                                vec!["key".to_string(), "block_num".to_string()],
-                                tuple_pointer_index,
-                            );
-                            let child_node =
-                                ChildNode::from_bytes(tuple_bytes, self.heap_page.layout.clone());
-                            right_child_nodes.push(child_node);
-                            // vacuum the split records
-                            self.heap_page.mark_delete(tuple_pointer_index as usize);
-                        }
-                        let right_tuple_pointers =
-                            self.heap_page.tuple_pointers.split_off(mid as usize);
-                        self.heap_page.vacuum();
-
-                        let new_block = self
-                            .storage_manager
-                            .borrow_mut()
-                            .extend_file(&self.index_file);
-                        let right_frame = self
-                            .storage_manager
-                            .borrow_mut()
-                            .pin(new_block.clone())
-                            .unwrap();
-                        let mut right_heap_page = HeapPage::new_from_empty(
-                            right_frame,
-                            &new_block,
-                            self.heap_page.layout.clone(),
-                        );
-                        for child_node in right_child_nodes {
-                            right_heap_page.insert_tuple(child_node.to_tuple());
-                        }
-                        return (split_key, Some(new_block.block_num));
-                    }
-                }
+    // Search for a key in the internal node and return the associated values
+    fn search(&self, key: Vec<u8>) -> Option<Vec<Rid>> {
+        for child_index in self.candidate_children(&key) {
+            let child = self.child(child_index);
+            let results = match &child {
+                NodePage::Internal(target_node) => target_node.search(key.clone()),
+                NodePage::Leaf(target_node) => target_node.search(key.clone()),
+            };
+            self.storage_manager
+                .write()
+                .unwrap()
+                .unpin(child.heap_page().frame.clone());
+            if results.is_some() {
+                return results;
             }
-            NodePage::Leaf(ref mut target_node) => {
-                let (split_key, split_block_num) = target_node.insert(key, value);
-                self.storage_manager
-                    .borrow_mut()
-                    .unpin(target_node.heap_page.frame.clone());
-                if let Some(split_key) = split_key {
-                    let new_child_tuple = ChildNode {
-                        key: split_key.clone(),
-                        block_num: split_block_num.unwrap(),
-                        layout: target_heap_page.layout.clone(),
-                    }
-                    .to_tuple();
+        }
+        None
+    }
 
-                    let free_space = self.heap_page.free_space() as i32 - 4;
-                    if free_space > new_child_tuple.tuple_size() as i32 {
-                        self.heap_page.insert_tuple(new_child_tuple);
-                        let last_inserted = self.heap_page.tuple_pointers.pop().unwrap();
-                        self.heap_page
-                            .tuple_pointers
-                            .insert(child_index + 1, last_inserted);
-                        self.heap_page.rewrite_tuple_pointers_to_frame();
-                    } else {
-                        let pointers_num = self.heap_page.tuple_pointers.len();
-                        let mid = self.heap_page.tuple_pointers.len() as u16 / 2;
-                        let new_split_key = self.heap_page.get_field("key", mid);
-                        let mut right_child_nodes = Vec::new();
-                        for tuple_pointer_index in mid..pointers_num as u16 {
-                            let tuple_bytes = self.heap_page.get_multiple_fields(
-                                vec!["key".to_string(), "block_num".to_string()],
-                                tuple_pointer_index,
-                            );
-                            let child_node =
-                                ChildNode::from_bytes(tuple_bytes, self.heap_page.layout.clone());
-                            right_child_nodes.push(child_node);
-                            // vacuum the split records
-                            self.heap_page.mark_delete(tuple_pointer_index as usize);
-                        }
-                        let right_tuple_pointers =
-                            self.heap_page.tuple_pointers.split_off(mid as usize);
-                        self.heap_page.vacuum();
-
-                        let new_block = self
-                            .storage_manager
-                            .borrow_mut()
-                            .extend_file(&self.index_file);
-                        let right_frame = self
-                            .storage_manager
-                            .borrow_mut()
-                            .pin(new_block.clone())
-                            .unwrap();
-                        let mut right_heap_page = HeapPage::new_from_empty(
-                            right_frame.clone(),
-                            &new_block,
-                            self.heap_page.layout.clone(),
-                        );
-                        for child_node in right_child_nodes {
-                            right_heap_page.insert_tuple(child_node.to_tuple());
-                        }
-                        if child_index >= mid as usize {
-                            right_heap_page.insert_tuple(new_child_tuple);
-                            let last_inserted = right_heap_page.tuple_pointers.pop().unwrap();
-                            let index_to_put = child_index - self.heap_page.tuple_pointers.len();
-                            right_heap_page
-                                .tuple_pointers
-                                .insert(index_to_put, last_inserted);
-                            right_heap_page.rewrite_tuple_pointers_to_frame();
-                        } else {
-                            self.heap_page.insert_tuple(new_child_tuple);
-                            let last_inserted = self.heap_page.tuple_pointers.pop().unwrap();
-                            self.heap_page
-                                .tuple_pointers
-                                .insert(child_index, last_inserted);
-                            self.heap_page.rewrite_tuple_pointers_to_frame();
-                        }
-                        self.storage_manager.borrow_mut().unpin(right_frame);
-                        return (new_split_key, Some(new_block.block_num));
-                    }
-                }
-            }
+    // The children that may hold a key, a key equal to a separator may also sit at the end of the
+    // preceding child either because duplicates were split at that key or because the records
+    // the separator was taken from have been deleted since
+    fn candidate_children(&self, key: &[u8]) -> RangeInclusive<usize> {
+        let children_num = self.heap_page.tuple_pointers.len();
+        let right = (children_num - 1) as u16;
+        let mut first = self.binary_search_child_node(1, right, key.to_vec());
+        let mut last = first;
+        while first > 0 && self.heap_page.get_field("key", first as u16).unwrap() == key {
+            first -= 1;
+        }
+        while last + 1 < children_num
+            && self.heap_page.get_field("key", (last + 1) as u16).unwrap() == key
+        {
+            last += 1;
+        }
+        first..=last
+    }
+
+    // Descend to the first leaf that may hold the low end of a range, the leaves are then walked
+    // from there. An empty low end is below every key, like the key of the first child
+    fn get_range(&self, low: &[u8], high: &[u8]) -> Vec<Rid> {
+        let child_index = if low.is_empty() {
+            0
+        } else {
+            *self.candidate_children(low).start()
         };
-        return (None, None);
+        let child = self.child(child_index);
+        let results = child.get_range(low, high);
+        self.storage_manager
+            .write()
+            .unwrap()
+            .unpin(child.heap_page().frame.clone());
+        results
     }
 
-    // Search for a key in the internal node and return the associated values
-    fn search(&self, key: Vec<u8>) -> Option<Vec<Rid>> {
-        let right = (self.heap_page.tuple_pointers.len() - 1) as u16;
-        let child_index = self.binary_search_child_node(1, right, key.clone());
+    // Remove a key-value pair from the subtree rooted at the internal node, a child left
+    // underflowing is rebalanced with one of its siblings
+    fn delete(&mut self, key: Vec<u8>, value: Rid, freed: &mut Vec<BlockId>) -> bool {
+        let children_num = self.heap_page.tuple_pointers.len();
+        for child_index in self.candidate_children(&key) {
+            let mut child = self.child(child_index);
+            let deleted = child.delete(key.clone(), value.clone(), freed);
+            if deleted && child.underflows() && children_num > 1 {
+                self.rebalance(child_index, child, freed);
+            } else {
+                self.storage_manager
+                    .write()
+                    .unwrap()
+                    .unpin(child.heap_page().frame.clone());
+            }
+            if deleted {
+                return true;
+            }
+        }
+        false
+    }
 
-        let target_block_num = self
+    // Pin the child at a given position, the caller is responsible for unpinning it
+    fn child(&self, child_index: usize) -> NodePage {
+        let block_num = self
             .heap_page
             .get_field("block_num", child_index as u16)
             .unwrap()
             .as_slice()
             .extract_u64(0);
-        let target_blockid = BlockId::new(&self.index_file, target_block_num);
-
-        let target_frame = self
-            .storage_manager
-            .borrow_mut()
-            .pin(target_blockid.clone())
-            .unwrap();
-
-        let target_heap_page = HeapPage::new(
-            target_frame.clone(),
-            &target_blockid,
-            self.heap_page.layout.clone(),
-        );
-
-        let mut node_page = NodePage::new(
-            target_frame,
+        let block_id = BlockId::new(&self.index_file, block_num);
+        let frame = self.storage_manager.write().unwrap().pin(block_id).unwrap();
+        NodePage::new(
+            frame,
             self.key_type,
             self.storage_manager.clone(),
             self.internal_layout.clone(),
             self.leaf_layout.clone(),
             self.index_file.clone(),
-        );
+        )
+    }
 
-        match &node_page {
-            NodePage::Internal(target_node) => {
-                let results = target_node.search(key);
-                self.storage_manager
-                    .borrow_mut()
-                    .unpin(target_node.heap_page.frame.clone());
-                results
+    // Fix an underflowing child using its right sibling, or its left sibling for the last child.
+    // The two nodes are merged if their entries fit in one page, otherwise the entries are
+    // redistributed evenly between them
+    fn rebalance(&mut self, child_index: usize, child: NodePage, freed: &mut Vec<BlockId>) {
+        let (left_index, left, right) = if child_index + 1 < self.heap_page.tuple_pointers.len() {
+            let sibling = self.child(child_index + 1);
+            (child_index, child, sibling)
+        } else {
+            let sibling = self.child(child_index - 1);
+            (child_index - 1, sibling, child)
+        };
+        let left_frame = left.heap_page().frame.clone();
+        let right_frame = right.heap_page().frame.clone();
+        match (left, right) {
+            (NodePage::Leaf(mut left), NodePage::Leaf(mut right)) => {
+                self.rebalance_leaves(left_index, &mut left, &mut right, freed)
             }
-            NodePage::Leaf(target_node) => {
-                let results = target_node.search(key);
+            (NodePage::Internal(mut left), NodePage::Internal(mut right)) => {
+                self.rebalance_internals(left_index, &mut left, &mut right, freed)
+            }
+            _ => unreachable!(),
+        }
+        self.storage_manager.write().unwrap().unpin(left_frame);
+        self.storage_manager.write().unwrap().unpin(right_frame);
+    }
+
+    fn rebalance_leaves(
+        &mut self,
+        left_index: usize,
+        left: &mut LeafNodePage,
+        right: &mut LeafNodePage,
+        freed: &mut Vec<BlockId>,
+    ) {
+        let mut records = left.records();
+        records.append(&mut right.records());
+        let size = entries_size(records.iter().map(IndexRecord::to_tuple));
+        if size < node_capacity(&left.heap_page) {
+            let next_block_num = right.meta_data.next_node_blockid;
+            if next_block_num != 0 {
+                let mut next_leaf = right.sibling(next_block_num);
+                next_leaf.meta_data.prev_node_blockid = left.heap_page.blk.block_num;
+                next_leaf
+                    .heap_page
+                    .write_special_area(next_leaf.meta_data.to_bytes());
                 self.storage_manager
-                    .borrow_mut()
-                    .unpin(target_node.heap_page.frame.clone());
-                results
+                    .write()
+                    .unwrap()
+                    .unpin(next_leaf.heap_page.frame.clone());
+            }
+            left.meta_data.next_node_blockid = next_block_num;
+            left.rewrite(records);
+            release_block(&right.heap_page, freed);
+            self.remove_child(left_index + 1);
+        } else {
+            let right_records = records.split_off(records.len() / 2);
+            if self.replace_key(left_index + 1, right_records[0].key.clone()) {
+                left.rewrite(records);
+                right.rewrite(right_records);
             }
         }
     }
 
+    // The separator of the right node in the parent becomes the key of its first child, which
+    // is otherwise a dummy key
+    fn rebalance_internals(
+        &mut self,
+        left_index: usize,
+        left: &mut InternalNodePage,
+        right: &mut InternalNodePage,
+        freed: &mut Vec<BlockId>,
+    ) {
+        let mut children = left.children();
+        let mut right_children = right.children();
+        right_children[0].key = self
+            .heap_page
+            .get_field("key", (left_index + 1) as u16)
+            .unwrap();
+        children.append(&mut right_children);
+        let size = entries_size(children.iter().map(ChildNode::to_tuple));
+        if size < node_capacity(&left.heap_page) {
+            left.rewrite(children);
+            release_block(&right.heap_page, freed);
+            self.remove_child(left_index + 1);
+        } else {
+            let mut right_children = children.split_off(children.len() / 2);
+            let separator = std::mem::take(&mut right_children[0].key);
+            if self.replace_key(left_index + 1, separator) {
+                left.rewrite(children);
+                right.rewrite(right_children);
+            }
+        }
+    }
+
+    // Read every child entry of the node in key order
+    fn children(&self) -> Vec<ChildNode> {
+        (0..self.heap_page.tuple_pointers.len() as u16)
+            .map(|slot_num| {
+                let tuple_bytes = self.heap_page.get_multiple_fields(
+                    vec!["key".to_string(), "block_num".to_string()],
+                    slot_num,
+                );
+                ChildNode::from_bytes(tuple_bytes, self.internal_layout.clone())
+            })
+            .collect()
+    }
+
+    // Rebuild the node page from a sorted list of child entries
+    fn rewrite(&mut self, children: Vec<ChildNode>) {
+        let block_id = self.heap_page.blk.clone();
+        self.heap_page = HeapPage::new_from_empty(
+            self.heap_page.frame.clone(),
+            &block_id,
+            self.internal_layout.clone(),
+        );
+        for child in children {
+            self.heap_page.insert_tuple(child.to_tuple());
+        }
+    }
+
+    fn remove_child(&mut self, child_index: usize) {
+        let mut children = self.children();
+        children.remove(child_index);
+        self.rewrite(children);
+    }
+
+    // Replace the separator key of a child, returns false without touching the node if the new
+    // key does not fit in the page
+    fn replace_key(&mut self, child_index: usize, key: Vec<u8>) -> bool {
+        let mut children = self.children();
+        children[child_index].key = key;
+        if entries_size(children.iter().map(ChildNode::to_tuple)) >= node_capacity(&self.heap_page)
+        {
+            return false;
+        }
+        self.rewrite(children);
+        true
+    }
+
     fn get_less_than_or_equal(&self, key: Vec<u8>) -> Option<Vec<Rid>> {
         let right = (self.heap_page.tuple_pointers.len() - 1) as u16;
         let child_index = self.binary_search_child_node(1, right, key.clone());
@@ -875,7 +875,8 @@ impl InternalNodePage {
 
         let target_frame = self
             .storage_manager
-            .borrow_mut()
+            .write()
+            .unwrap()
             .pin(target_blockid.clone())
             .unwrap();
 
@@ -898,14 +899,16 @@ impl InternalNodePage {
             NodePage::Internal(target_node) => {
                 let results = target_node.get_less_than_or_equal(key);
                 self.storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .unpin(target_node.heap_page.frame.clone());
                 results
             }
             NodePage::Leaf(target_node) => {
                 let results = target_node.get_less_than_or_equal(key);
                 self.storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .unpin(target_node.heap_page.frame.clone());
                 results
             }
@@ -926,7 +929,8 @@ impl InternalNodePage {
 
         let target_frame = self
             .storage_manager
-            .borrow_mut()
+            .write()
+            .unwrap()
             .pin(target_blockid.clone())
             .unwrap();
 
@@ -949,14 +953,16 @@ impl InternalNodePage {
             NodePage::Internal(target_node) => {
                 let results = target_node.get_greater_than_or_equal(key);
                 self.storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .unpin(target_node.heap_page.frame.clone());
                 results
             }
             NodePage::Leaf(target_node) => {
                 let results = target_node.get_greater_than_or_equal(key);
                 self.storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .unpin(target_node.heap_page.frame.clone());
                 results
             }
@@ -977,7 +983,8 @@ impl InternalNodePage {
 
         let target_frame = self
             .storage_manager
-            .borrow_mut()
+            .write()
+            .unwrap()
             .pin(target_blockid.clone())
             .unwrap();
 
@@ -1000,14 +1007,16 @@ impl InternalNodePage {
             NodePage::Internal(target_node) => {
                 let results = target_node.get_greater_than(key);
                 self.storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .unpin(target_node.heap_page.frame.clone());
                 results
             }
             NodePage::Leaf(target_node) => {
                 let results = target_node.get_greater_than(key);
                 self.storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .unpin(target_node.heap_page.frame.clone());
                 results
             }
@@ -1028,7 +1037,8 @@ impl InternalNodePage {
 
         let target_frame = self
             .storage_manager
-            .borrow_mut()
+            .write()
+            .unwrap()
             .pin(target_blockid.clone())
             .unwrap();
 
@@ -1051,14 +1061,16 @@ impl InternalNodePage {
             NodePage::Internal(target_node) => {
                 let results = target_node.get_less_than(key);
                 self.storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .unpin(target_node.heap_page.frame.clone());
                 results
             }
             NodePage::Leaf(target_node) => {
                 let results = target_node.get_less_than(key);
                 self.storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .unpin(target_node.heap_page.frame.clone());
                 results
             }
@@ -1150,14 +1162,14 @@ pub struct LeafNodePage {
     heap_page: HeapPage,
     meta_data: LeafMetaData,
     key_type: Type,
-    storage_manager: Rc<RefCell<StorageManager>>,
-    leaf_layout: Rc<Layout>,
+    storage_manager: Arc<RwLock<StorageManager>>,
+    leaf_layout: Arc<Layout>,
     index_file: String,
 }
 
 /*impl Drop for LeafNodePage {
     fn drop(&mut self) {
-        self.storage_manager.borrow_mut().unpin(self.heap_page.frame.clone());
+        self.storage_manager.write().unwrap().unpin(self.heap_page.frame.clone());
     }
 }*/
 
@@ -1165,8 +1177,8 @@ impl LeafNodePage {
     pub fn new(
         heap_page: HeapPage,
         key_type: Type,
-        storage_manager: Rc<RefCell<StorageManager>>,
-        leaf_layout: Rc<Layout>,
+        storage_manager: Arc<RwLock<StorageManager>>,
+        leaf_layout: Arc<Layout>,
         index_file: String,
     ) -> LeafNodePage {
         let meta_data = LeafMetaData::from(heap_page.get_special_area());
@@ -1252,11 +1264,13 @@ impl LeafNodePage {
             self.heap_page.vacuum();
             let new_block = self
                 .storage_manager
-                .borrow_mut()
+                .write()
+                .unwrap()
                 .extend_file(&self.index_file);
             let right_frame = self
                 .storage_manager
-                .borrow_mut()
+                .write()
+                .unwrap()
                 .pin(new_block.clone())
                 .unwrap();
             let mut right_heap_page = HeapPage::new_from_empty_special(
@@ -1269,7 +1283,7 @@ impl LeafNodePage {
                 right_heap_page.insert_tuple(record.to_tuple());
             }
 
-            if index >= mid as usize {
+            if index > mid as usize {
                 right_heap_page.insert_tuple(new_index_record);
                 let last_inserted = right_heap_page.tuple_pointers.pop().unwrap();
                 let index_to_put = index - self.heap_page.tuple_pointers.len();
@@ -1289,7 +1303,8 @@ impl LeafNodePage {
                     BlockId::new(self.index_file.as_str(), self.meta_data.next_node_blockid);
                 let old_next_frame = self
                     .storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .pin(old_next.clone())
                     .unwrap();
                 let mut old_next_heap =
@@ -1304,7 +1319,8 @@ impl LeafNodePage {
                 old_next_node.meta_data.prev_node_blockid = new_block.block_num;
                 old_next_heap.write_special_area(old_next_node.meta_data.to_bytes());
                 self.storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .unpin(old_next_heap.frame.clone());
             }
 
@@ -1318,7 +1334,8 @@ impl LeafNodePage {
 
             right_heap_page.write_special_area(right_meta_data.to_bytes());
             self.storage_manager
-                .borrow_mut()
+                .write()
+                .unwrap()
                 .unpin(right_heap_page.frame.clone());
 
             return (split_key, Some(new_block.block_num));
@@ -1327,6 +1344,119 @@ impl LeafNodePage {
         return (None, None);
     }
 
+    // Remove the index record matching both the key and the Rid from the leaf, the page is
+    // compacted to keep the records contiguous and sorted
+    fn remove_record(&mut self, key: &[u8], value: &Rid) -> bool {
+        let position = (0..self.heap_page.tuple_pointers.len() as u16).position(|slot_num| {
+            let mut index_record = self.heap_page.get_multiple_fields(
+                vec![
+                    "key".to_string(),
+                    "block_num".to_string(),
+                    "slot_num".to_string(),
+                ],
+                slot_num,
+            );
+            index_record.remove("key").unwrap().unwrap() == key
+                && Rid::new(
+                    index_record.remove("block_num").unwrap().unwrap().to_u64(),
+                    index_record.remove("slot_num").unwrap().unwrap().to_u16(),
+                ) == *value
+        });
+        if let Some(position) = position {
+            self.heap_page.tuple_pointers.remove(position);
+            self.heap_page.vacuum();
+            // vacuuming rebuilds the page without its special area
+            self.heap_page.write_special_area(self.meta_data.to_bytes());
+            true
+        } else {
+            false
+        }
+    }
+
+    // Read every index record of the leaf in key order
+    fn records(&self) -> Vec<IndexRecord> {
+        (0..self.heap_page.tuple_pointers.len() as u16)
+            .map(|slot_num| {
+                let tuple_bytes = self.heap_page.get_multiple_fields(
+                    vec![
+                        "key".to_string(),
+                        "block_num".to_string(),
+                        "slot_num".to_string(),
+                    ],
+                    slot_num,
+                );
+                IndexRecord::from_bytes(tuple_bytes, self.leaf_layout.clone())
+            })
+            .collect()
+    }
+
+    // Rebuild the leaf page from a sorted list of index records, keeping its neighbour links
+    fn rewrite(&mut self, records: Vec<IndexRecord>) {
+        let block_id = self.heap_page.blk.clone();
+        self.heap_page = HeapPage::new_from_empty_special(
+            self.heap_page.frame.clone(),
+            &block_id,
+            self.leaf_layout.clone(),
+            LEAF_SPECIAL_AREA,
+        );
+        for record in records {
+            self.heap_page.insert_tuple(record.to_tuple());
+        }
+        self.heap_page.write_special_area(self.meta_data.to_bytes());
+    }
+
+    // Collect the values of the keys from low included to high excluded in this leaf and the
+    // following ones, the walk stops at the first key past the range
+    fn get_range(&self, low: &[u8], high: &[u8]) -> Vec<Rid> {
+        let (mut results, mut done) = self.records_in_range(low, high);
+        let mut next_block_num = self.meta_data.next_node_blockid;
+        while !done && next_block_num != 0 {
+            let next_leaf = self.sibling(next_block_num);
+            let (next_results, past_range) = next_leaf.records_in_range(low, high);
+            results.extend(next_results);
+            done = past_range;
+            next_block_num = next_leaf.meta_data.next_node_blockid;
+            self.storage_manager
+                .write()
+                .unwrap()
+                .unpin(next_leaf.heap_page.frame.clone());
+        }
+        results
+    }
+
+    // The values of the leaf's keys in a range and whether the leaf holds a key past it
+    fn records_in_range(&self, low: &[u8], high: &[u8]) -> (Vec<Rid>, bool) {
+        let mut results = Vec::new();
+        for record in self.records() {
+            if record.key.as_slice() >= high {
+                return (results, true);
+            }
+            if record.key.as_slice() >= low {
+                results.push(record.value);
+            }
+        }
+        (results, false)
+    }
+
+    // Pin a neighbouring leaf, the caller is responsible for unpinning it
+    fn sibling(&self, block_num: u64) -> LeafNodePage {
+        let block_id = BlockId::new(self.index_file.as_str(), block_num);
+        let frame = self
+            .storage_manager
+            .write()
+            .unwrap()
+            .pin(block_id.clone())
+            .unwrap();
+        let heap = HeapPage::new(frame, &block_id, self.leaf_layout.clone());
+        LeafNodePage::new(
+            heap,
+            self.key_type,
+            self.storage_manager.clone(),
+            self.leaf_layout.clone(),
+            self.index_file.clone(),
+        )
+    }
+
     fn get_matches(&self, key: Vec<u8>) -> Option<Vec<Rid>> {
         let mut results = Vec::new();
         let mut last_index = 0_u16;
@@ -1385,7 +1515,8 @@ impl LeafNodePage {
                     let block_id = BlockId::new(self.index_file.as_str(), next_block_num);
                     let next_frame = self
                         .storage_manager
-                        .borrow_mut()
+                        .write()
+                        .unwrap()
                         .pin(block_id.clone())
                         .unwrap();
                     let next_heap =
@@ -1411,7 +1542,8 @@ impl LeafNodePage {
                 let block_id = BlockId::new(self.index_file.as_str(), prev_block_num);
                 let prev_frame = self
                     .storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .pin(block_id.clone())
                     .unwrap();
                 let prev_heap =
@@ -1478,7 +1610,8 @@ impl LeafNodePage {
             let block_id = next_block.unwrap();
             let next_frame = self
                 .storage_manager
-                .borrow_mut()
+                .write()
+                .unwrap()
                 .pin(block_id.clone())
                 .unwrap();
             let next_heap = HeapPage::new(next_frame.clone(), &block_id, self.leaf_layout.clone());
@@ -1499,7 +1632,7 @@ impl LeafNodePage {
                     filename: self.index_file.clone(),
                 })
             }
-            self.storage_manager.borrow_mut().unpin(next_frame);
+            self.storage_manager.write().unwrap().unpin(next_frame);
             results.extend(new_results);
         }
 
@@ -1540,7 +1673,8 @@ impl LeafNodePage {
             let block_id = prev_block.unwrap();
             let prev_frame = self
                 .storage_manager
-                .borrow_mut()
+                .write()
+                .unwrap()
                 .pin(block_id.clone())
                 .unwrap();
             let prev_heap = HeapPage::new(prev_frame.clone(), &block_id, self.leaf_layout.clone());
@@ -1563,7 +1697,7 @@ impl LeafNodePage {
                     filename: self.index_file.clone(),
                 })
             }
-            self.storage_manager.borrow_mut().unpin(prev_frame);
+            self.storage_manager.write().unwrap().unpin(prev_frame);
             results.extend(new_results);
         }
 
@@ -1611,7 +1745,8 @@ impl LeafNodePage {
             let block_id = next_block.unwrap();
             let next_frame = self
                 .storage_manager
-                .borrow_mut()
+                .write()
+                .unwrap()
                 .pin(block_id.clone())
                 .unwrap();
             let next_heap = HeapPage::new(next_frame.clone(), &block_id, self.leaf_layout.clone());
@@ -1632,7 +1767,7 @@ impl LeafNodePage {
                     filename: self.index_file.clone(),
                 })
             }
-            self.storage_manager.borrow_mut().unpin(next_frame);
+            self.storage_manager.write().unwrap().unpin(next_frame);
             results.extend(new_results);
         }
 
@@ -1664,7 +1799,8 @@ impl LeafNodePage {
                     BlockId::new(self.index_file.as_str(), self.meta_data.prev_node_blockid);
                 let prev_frame = self
                     .storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .pin(prev_block.clone())
                     .unwrap();
                 let prev_heap = HeapPage::new(prev_frame, &prev_block, self.leaf_layout.clone());
@@ -1676,7 +1812,7 @@ impl LeafNodePage {
                     self.index_file.clone(),
                 );
                 let prev_results = (prev_node.get_less_than(key));
-                self.storage_manager.borrow_mut().unpin(prev_heap.frame);
+                self.storage_manager.write().unwrap().unpin(prev_heap.frame);
                 if prev_results.is_some() {
                     prev_results
                 } else {
@@ -1701,7 +1837,8 @@ impl LeafNodePage {
                 let block_id = prev_block.unwrap();
                 let prev_frame = self
                     .storage_manager
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .pin(block_id.clone())
                     .unwrap();
                 let prev_heap =
@@ -1725,7 +1862,7 @@ impl LeafNodePage {
                         filename: self.index_file.clone(),
                     })
                 }
-                self.storage_manager.borrow_mut().unpin(prev_frame);
+                self.storage_manager.write().unwrap().unpin(prev_frame);
                 results.extend(new_results);
             }
 
@@ -1780,3 +1917,121 @@ impl LeafNodePage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::testing::TempDir;
+    use crate::schema::types::CharType::VarChar;
+    use crate::schema::types::NumericType::Integer;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    fn test_tree(key_type: Type) -> (TempDir, BPTree) {
+        let dir = TempDir::new("btree");
+        let tree = BPTree::init(key_type, dir.storage(200), "btree_test".to_string());
+        (dir, tree)
+    }
+
+    fn sorted(mut rids: Vec<Rid>) -> Vec<Rid> {
+        rids.sort_by_key(|rid| (rid.block_num, rid.slot_num));
+        rids
+    }
+
+    // Apply random inserts and deletes to the tree and to a reference map, checking every key
+    // touched against the reference after each operation and draining the tree at the end
+    fn mixed_operations<F>(key_type: Type, seed: u64, ops: usize, key_range: u32, encode: F)
+    where
+        F: Fn(u32) -> Vec<u8>,
+    {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (_dir, mut tree) = test_tree(key_type);
+        let mut reference: BTreeMap<u32, Vec<Rid>> = BTreeMap::new();
+        for op in 0..ops {
+            let key = rng.gen_range(0..key_range);
+            let stored = reference.entry(key).or_default();
+            if stored.is_empty() || rng.gen_bool(0.6) {
+                let rid = Rid::new(op as u64, rng.gen());
+                tree.insert(encode(key), rid.clone());
+                stored.push(rid);
+            } else {
+                let rid = stored.swap_remove(rng.gen_range(0..stored.len()));
+                assert!(tree.delete(encode(key), rid).is_some());
+            }
+            let expected = sorted(stored.clone());
+            let found = sorted(tree.search(encode(key)).unwrap_or_default());
+            assert_eq!(found, expected, "key {key} after op {op}");
+        }
+        for (key, rids) in reference {
+            for rid in rids {
+                assert!(tree.delete(encode(key), rid.clone()).is_some());
+                assert!(tree.delete(encode(key), rid).is_none());
+            }
+            assert!(tree.search(encode(key)).is_none());
+        }
+        assert!(tree.root.is_leaf());
+    }
+
+    #[test]
+    fn insert_delete_integer_keys() {
+        for seed in 0..2 {
+            mixed_operations(Numeric(Integer), seed, 3000, 400, |key| {
+                (key as i32).to_ne_bytes().to_vec()
+            });
+        }
+    }
+
+    #[test]
+    fn insert_delete_duplicate_heavy_keys() {
+        mixed_operations(Numeric(Integer), 7, 3000, 5, |key| {
+            (key as i32).to_ne_bytes().to_vec()
+        });
+    }
+
+    #[test]
+    fn insert_delete_long_varchar_keys() {
+        mixed_operations(Type::Character(VarChar(None)), 11, 1500, 300, |key| {
+            format!("{:0>200}", key).into_bytes()
+        });
+    }
+
+    #[test]
+    fn range_over_key_prefix() {
+        let (_dir, mut tree) = test_tree(Type::Character(VarChar(None)));
+        let key = |tenant: u32, at: u32| format!("{tenant:04}{at:04}").into_bytes();
+        for tenant in 0..20 {
+            for at in 0..50 {
+                tree.insert(key(tenant, at), Rid::new(tenant as u64, at as u16));
+            }
+        }
+        let found = sorted(tree.get_range(key(7, 0), b"0007g".to_vec()));
+        let expected = (0..50).map(|at| Rid::new(7, at)).collect::<Vec<_>>();
+        assert_eq!(found, expected);
+        let found = sorted(tree.get_range(key(7, 45), key(8, 3)));
+        let mut expected = (45..50).map(|at| Rid::new(7, at)).collect::<Vec<_>>();
+        expected.extend((0..3).map(|at| Rid::new(8, at)));
+        assert_eq!(found, expected);
+        assert!(tree.get_range(key(30, 0), key(31, 0)).is_empty());
+        assert_eq!(tree.get_range(vec![], key(0, 10)).len(), 10);
+    }
+
+    #[test]
+    fn delete_frees_merged_blocks() {
+        let (_dir, mut tree) = test_tree(Numeric(Integer));
+        for key in 0..1000_i32 {
+            tree.insert(key.to_ne_bytes().to_vec(), Rid::new(key as u64, 0));
+        }
+        assert!(!tree.root.is_leaf());
+        let mut freed = Vec::new();
+        for key in 0..1000_i32 {
+            freed.extend(
+                tree.delete(key.to_ne_bytes().to_vec(), Rid::new(key as u64, 0))
+                    .unwrap(),
+            );
+        }
+        assert!(tree.root.is_leaf());
+        assert!(!freed.is_empty());
+        assert!(freed.iter().all(|blk| blk.block_num != 0));
+    }
+}
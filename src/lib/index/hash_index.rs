@@ -6,13 +6,13 @@ use crate::schema::schema::Layout;
 use crate::sql::create_table::IndexType;
 use crate::storage::blockid::BlockId;
 use crate::storage::buffermgr::FrameRef;
+use crate::storage::logmgr::LogRecord;
 use crate::storage::storagemgr::StorageManager;
 // use crate::table::tablemgr::{TableIter, TableManager};
-use crate::RcRefCell;
+use crate::ArcRwLock;
 use pest::pratt_parser::Op;
 use positioned_io2::WriteAt;
 use sdbm::sdbm_hash;
-use std::cell::{RefCell, RefMut};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fs;
@@ -23,7 +23,8 @@ use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
 use std::process::id;
 use std::ptr::addr_of_mut;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::{RwLock, RwLockWriteGuard};
 
 const IDX_RECORD_SIZE: usize = 15;
 const GLOBAL_DEPTH: u8 = 4;
@@ -76,6 +77,7 @@ pub struct BucketDirectory {
     global_depth: u8,
     buckets_num: u16,
     bucket_map: HashMap<u32, u64>,
+    free_blocks: Vec<u64>,
     size_in_bytes: usize,
 }
 
@@ -95,11 +97,18 @@ impl BucketDirectory {
             pos += 8;
             bucket_map.insert(bucket_id, block_num);
         }
+        // blocks released by merges are listed after the bucket map
+        let mut free_blocks = Vec::new();
+        while pos < size {
+            free_blocks.push(buffer.extract_u64(pos));
+            pos += 8;
+        }
         Self {
             index_dir_file: index_dir_file.to_owned(),
             global_depth,
             buckets_num,
             bucket_map,
+            free_blocks,
             size_in_bytes: size,
         }
     }
@@ -138,12 +147,11 @@ impl BucketDirectory {
             data.extend_from_slice(k.to_ne_bytes().as_slice());
             data.extend_from_slice(v.to_ne_bytes().as_slice());
         }
+        for blk_num in &self.free_blocks {
+            data.extend_from_slice(blk_num.to_ne_bytes().as_slice());
+        }
         data
     }
-
-    pub fn flush(&self) {
-        write_file(self.index_dir_file.as_path(), self.bucket_map_to_bytes());
-    }
 }
 
 /// The hash index which is most likely treated as a table.
@@ -252,7 +260,7 @@ impl HashIndex {
             global_depth -= 1;
             new_bucket_id = ((hash_val) % (2_u32.pow(global_depth as u32)));
             let new_block_num = self.bucket_dir.bucket_map.get(&new_bucket_id);
-            if let Some(..) = new_block_num {
+            if new_block_num.is_some() && !buckets.contains(&new_bucket_id) {
                 buckets.push(new_bucket_id);
             }
             if global_depth == GLOBAL_DEPTH {
@@ -285,7 +293,7 @@ impl HashIndex {
     fn create_bucket(
         &self,
         data_val: &[u8],
-        mut storage_mgr: &mut RefMut<StorageManager>,
+        mut storage_mgr: &mut RwLockWriteGuard<StorageManager>,
     ) -> BucketPage {
         let bucket_id = self.hash_code(data_val);
         let block_num = self.bucket_dir.bucket_map.get(&bucket_id);
@@ -298,7 +306,7 @@ impl HashIndex {
     fn create_bucket_from_hash_value(
         &self,
         hash_val: u32,
-        mut storage_mgr: &mut RefMut<StorageManager>,
+        mut storage_mgr: &mut RwLockWriteGuard<StorageManager>,
     ) -> BucketPage {
         let bucket_id = self.hash_val_to_bucket(hash_val);
         let block_num = self.bucket_dir.bucket_map.get(&bucket_id);
@@ -310,7 +318,7 @@ impl HashIndex {
     fn create_bucket_from_hashcode(
         &self,
         bucket_id: u32,
-        mut storage_mgr: &mut RefMut<StorageManager>,
+        mut storage_mgr: &mut RwLockWriteGuard<StorageManager>,
     ) -> BucketPage {
         let block_num = self.bucket_dir.bucket_map.get(&bucket_id);
         let block = BlockId::new(self.blocks[0].filename.as_str(), *block_num.unwrap());
@@ -326,7 +334,7 @@ impl HashIndex {
         data_val: &[u8],
         blk_num: u64,
         slot_num: u16,
-        mut storage_mgr: RefMut<StorageManager>,
+        mut storage_mgr: RwLockWriteGuard<StorageManager>,
     ) {
         let mut bucket_page = self.create_bucket(data_val, &mut storage_mgr);
         let rid = Rid::new(blk_num, slot_num);
@@ -346,7 +354,7 @@ impl HashIndex {
                 }
                 storage_mgr.unpin(frame);
             } else {
-                let block = storage_mgr.extend_file(self.blocks[0].filename.as_str());
+                let block = self.allocate_block(&mut storage_mgr);
                 let frame = storage_mgr.pin(block.clone()).unwrap();
                 let mut new_bucket_page =
                     BucketPage::new_from_empty(frame.clone(), bucket_page.depth, None);
@@ -363,7 +371,7 @@ impl HashIndex {
     fn reinsert_record(
         &mut self,
         idx_record: IdxRecord,
-        mut storage_mgr: &mut RefMut<StorageManager>,
+        mut storage_mgr: &mut RwLockWriteGuard<StorageManager>,
     ) {
         let mut bucket_page = self.create_bucket_from_hash_value(idx_record.hash_val, storage_mgr);
         if bucket_page.insert_record(&idx_record).is_err() {
@@ -378,7 +386,7 @@ impl HashIndex {
                 overflow_bucket.insert_record(&idx_record);
                 storage_mgr.unpin(frame);
             } else {
-                let block = storage_mgr.extend_file(self.blocks[0].filename.as_str());
+                let block = self.allocate_block(storage_mgr);
                 let frame = storage_mgr.pin(block.clone()).unwrap();
                 let mut new_bucket_page =
                     BucketPage::new_from_empty(frame.clone(), bucket_page.depth, None);
@@ -398,7 +406,7 @@ impl HashIndex {
         bucket_page: &BucketPage,
         idx_record: IdxRecord,
         data_val: &[u8],
-        mut storage_mgr: &mut RefMut<StorageManager>,
+        mut storage_mgr: &mut RwLockWriteGuard<StorageManager>,
     ) {
         if bucket_page.depth == self.global_depth {
             self.global_depth += 1;
@@ -415,7 +423,7 @@ impl HashIndex {
         self.num_buckets -= 1;
         self.bucket_dir.buckets_num -= 1;
 
-        let filename = self.blocks[0].filename.as_str();
+        let filename = self.blocks[0].filename.clone();
         let mut bucket_records = bucket_page.read_all_bucket_records();
         if bucket_page.overflow.is_some() {
             let block = self
@@ -428,21 +436,18 @@ impl HashIndex {
             bucket_records.append(overflow_bucket.read_all_bucket_records().as_mut());
             storage_mgr.unpin(frame);
         }
-        let mut block_one = BlockId::new(filename, block_num);
+        let mut block_one = BlockId::new(filename.as_str(), block_num);
         let bucket_one_id = bucket_page.bucket_num.unwrap();
         self.bucket_dir
             .insert_bucket(bucket_one_id, block_one.block_num);
         let frame_one = storage_mgr.pin(block_one.clone()).unwrap();
-        frame_one
-            .borrow_mut()
-            .page
-            .write_bytes(vec![0; 4096].as_slice(), 0);
+        frame_one.write().unwrap().write(vec![0; 4096].as_slice());
         let bucket_split_one = BucketPage::new_from_empty(
             frame_one.clone(),
             bucket_page.depth + 1,
             Some(bucket_one_id),
         );
-        let mut block_two = storage_mgr.extend_file(filename);
+        let mut block_two = self.allocate_block(storage_mgr);
         let bucket_two_id = bucket_one_id + 2_u32.pow(bucket_page.depth as u32);
         self.bucket_dir
             .insert_bucket(bucket_two_id, block_two.block_num);
@@ -463,11 +468,15 @@ impl HashIndex {
         self.reinsert_record(idx_record, &mut storage_mgr);
         storage_mgr.unpin(frame_one);
         storage_mgr.unpin(frame_two);
-        self.flush_directory();
+        self.flush_directory(storage_mgr);
     }
 
     /// Get all the rids of the matched index records with the search key.
-    pub fn get_rids(&self, search_key: &[u8], mut storage_mgr: RefMut<StorageManager>) -> Vec<Rid> {
+    pub fn get_rids(
+        &self,
+        search_key: &[u8],
+        mut storage_mgr: RwLockWriteGuard<StorageManager>,
+    ) -> Vec<Rid> {
         let hash_val = Self::hash_value(search_key);
         let bucket_ids = self.get_candidate_buckets(search_key);
         let mut rids = Vec::new();
@@ -490,17 +499,167 @@ impl HashIndex {
         rids
     }
 
-    pub fn flush_directory(&self) {
-        self.bucket_dir.flush();
+    /// Removes the index record pointing to a Rid, the search key is hashed to find the candidate
+    /// buckets and their overflow buckets.
+    /// An overflow bucket whose records fit back in its primary bucket is released, then the bucket
+    /// is merged with its buddy when possible.
+    /// Returns whether a matching record was found
+    pub fn delete_record(
+        &mut self,
+        data_val: &[u8],
+        rid: &Rid,
+        mut storage_mgr: RwLockWriteGuard<StorageManager>,
+    ) -> bool {
+        let hash_val = Self::hash_value(data_val);
+        for bucket_id in self.get_candidate_buckets(data_val) {
+            let mut bucket_page = self.create_bucket_from_hashcode(bucket_id, &mut storage_mgr);
+            let mut deleted = bucket_page.remove_record(hash_val, rid);
+            if let Some(overflow) = bucket_page.overflow {
+                let block = BlockId::new(self.blocks[0].filename.as_str(), overflow);
+                let frame = storage_mgr.pin(block).unwrap();
+                let mut overflow_bucket = BucketPage::new(frame.clone(), None);
+                if !deleted {
+                    deleted = overflow_bucket.remove_record(hash_val, rid);
+                }
+                if deleted
+                    && bucket_page.fits(bucket_page.num_records + overflow_bucket.num_records)
+                {
+                    for record in overflow_bucket.read_all_bucket_records() {
+                        bucket_page.insert_record(&record).unwrap();
+                    }
+                    bucket_page.clear_overflow();
+                    self.release_block(overflow, &mut storage_mgr);
+                }
+                storage_mgr.unpin(frame);
+            }
+            storage_mgr.unpin(bucket_page.frame);
+            if deleted {
+                self.merge_buckets(bucket_id, &mut storage_mgr);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Merges a bucket with its buddy, the bucket whose ID only differs in the highest bit of the
+    /// local depth, for as long as both have the same local depth and their records fit in one page.
+    /// The global depth is then lowered while no bucket uses its highest bit.
+    fn merge_buckets(
+        &mut self,
+        mut bucket_id: u32,
+        storage_mgr: &mut RwLockWriteGuard<StorageManager>,
+    ) {
+        loop {
+            let bucket_page = self.create_bucket_from_hashcode(bucket_id, storage_mgr);
+            let depth = bucket_page.depth;
+            let buddy_id = bucket_id ^ (1 << (depth - 1));
+            if depth <= GLOBAL_DEPTH || !self.bucket_dir.bucket_map.contains_key(&buddy_id) {
+                storage_mgr.unpin(bucket_page.frame);
+                break;
+            }
+            let buddy_page = self.create_bucket_from_hashcode(buddy_id, storage_mgr);
+            let mut records = self.bucket_records(&bucket_page, storage_mgr);
+            records.append(&mut self.bucket_records(&buddy_page, storage_mgr));
+            if buddy_page.depth != depth || !bucket_page.fits(records.len() as u16) {
+                storage_mgr.unpin(bucket_page.frame);
+                storage_mgr.unpin(buddy_page.frame);
+                break;
+            }
+
+            let (low_page, high_page) = if bucket_id < buddy_id {
+                (bucket_page, buddy_page)
+            } else {
+                (buddy_page, bucket_page)
+            };
+            let low_id = low_page.bucket_num.unwrap();
+            let high_id = high_page.bucket_num.unwrap();
+            for overflow in [low_page.overflow, high_page.overflow]
+                .into_iter()
+                .flatten()
+            {
+                self.release_block(overflow, storage_mgr);
+            }
+            let high_blk_num = self.bucket_dir.remove_bucket(high_id).unwrap();
+            self.release_block(high_blk_num, storage_mgr);
+            self.num_buckets -= 1;
+            self.bucket_dir.buckets_num -= 1;
+
+            let mut merged_page =
+                BucketPage::new_from_empty(low_page.frame.clone(), depth - 1, Some(low_id));
+            for record in records {
+                merged_page.insert_record(&record).unwrap();
+            }
+            storage_mgr.unpin(low_page.frame);
+            storage_mgr.unpin(high_page.frame);
+            bucket_id = low_id;
+        }
+        while self.global_depth > GLOBAL_DEPTH
+            && !self
+                .bucket_dir
+                .bucket_map
+                .keys()
+                .any(|bucket_id| *bucket_id >= 1 << (self.global_depth - 1))
+        {
+            self.global_depth -= 1;
+            self.bucket_dir.global_depth -= 1;
+        }
+        self.flush_directory(storage_mgr);
     }
 
-    pub fn flush_all(&self, mut storage_mgr: &mut RefMut<StorageManager>) {
+    /// Retrieve the index records of a bucket along with the ones in its overflow bucket.
+    fn bucket_records(
+        &self,
+        bucket_page: &BucketPage,
+        storage_mgr: &mut RwLockWriteGuard<StorageManager>,
+    ) -> Vec<IdxRecord> {
+        let mut records = bucket_page.read_all_bucket_records();
+        if let Some(overflow) = bucket_page.overflow {
+            let block = BlockId::new(self.blocks[0].filename.as_str(), overflow);
+            let frame = storage_mgr.pin(block).unwrap();
+            records.append(&mut BucketPage::new(frame.clone(), None).read_all_bucket_records());
+            storage_mgr.unpin(frame);
+        }
+        records
+    }
+
+    /// Takes a block from the blocks released by merges, or extends the index file if there are none.
+    fn allocate_block(&mut self, storage_mgr: &mut RwLockWriteGuard<StorageManager>) -> BlockId {
+        let filename = self.blocks[0].filename.clone();
+        match self.bucket_dir.free_blocks.pop() {
+            Some(blk_num) => {
+                self.flush_directory(storage_mgr);
+                BlockId::new(filename.as_str(), blk_num)
+            }
+            None => storage_mgr.extend_file(filename.as_str()),
+        }
+    }
+
+    /// Hands a block that no longer holds a bucket back to the directory for reuse.
+    fn release_block(&mut self, blk_num: u64, storage_mgr: &mut StorageManager) {
+        if let Some(idx) = self
+            .blocks
+            .iter()
+            .position(|block| block.block_num == blk_num)
+        {
+            self.blocks.remove(idx);
+        }
+        self.bucket_dir.free_blocks.push(blk_num);
+        self.flush_directory(storage_mgr);
+    }
+
+    /// Logs the whole directory after a change to it, the directory file is written from the log at
+    /// the next checkpoint so it always matches the bucket pages recovered with it
+    pub fn flush_directory(&self, storage_mgr: &mut StorageManager) {
+        let image = LogRecord::FileImage(self.bucket_dir.bucket_map_to_bytes());
+        storage_mgr.log_file(&self.bucket_dir.index_dir_file, image);
+    }
+
+    pub fn flush_all(&self, mut storage_mgr: &mut RwLockWriteGuard<StorageManager>) {
         for blk in &self.blocks {
             let mut frame = storage_mgr.pin(blk.clone()).unwrap();
             storage_mgr.flush_frame(frame.clone());
             storage_mgr.unpin(frame);
         }
-        self.flush_directory()
     }
 }
 
@@ -516,7 +675,7 @@ struct BucketPage {
 impl BucketPage {
     pub fn new(frame: FrameRef, bucket_num: Option<u32>) -> Self {
         let hash_frame = frame.clone();
-        let mut frame_ref = hash_frame.borrow_mut();
+        let mut frame_ref = hash_frame.write().unwrap();
         frame_ref.update_replace_stats();
         let depth = frame_ref.page.payload.as_slice()[0];
         let num_records = frame_ref.page.payload.as_slice().extract_u16(1);
@@ -532,7 +691,7 @@ impl BucketPage {
         }
     }
     fn init(frame: &FrameRef, depth: u8) {
-        let mut frame = frame.borrow_mut();
+        let mut frame = frame.write().unwrap();
         let metadata = [depth, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
         frame.update_replace_stats();
         frame.write(metadata.as_slice())
@@ -544,18 +703,33 @@ impl BucketPage {
         BucketPage::new(frame, bucket_num)
     }
 
+    /// Checks whether a number of index records fits in a single bucket page.
+    fn fits(&self, records_num: u16) -> bool {
+        let capacity = (self.frame.read().unwrap().page.payload.len() - 11) / IDX_RECORD_SIZE;
+        records_num as usize <= capacity
+    }
+
+    /// Unlinks the overflow bucket.
+    fn clear_overflow(&mut self) {
+        self.overflow = None;
+        self.frame
+            .write()
+            .unwrap()
+            .write_at(0_u64.to_ne_bytes().as_slice(), 3);
+    }
+
     /// Set the overflow bucket by writing the overflow bucket ID.
     pub fn set_overflow(&mut self, overflow: u64) {
         self.overflow = Some(overflow);
         self.frame
-            .borrow_mut()
-            .page
-            .write_bytes(overflow.to_ne_bytes().as_slice(), 3);
+            .write()
+            .unwrap()
+            .write_at(overflow.to_ne_bytes().as_slice(), 3);
     }
 
     /// Retrieving the Rids with hash value similar to the query's hash value.
     fn find_all(&self, hash_val: u32) -> Vec<Rid> {
-        let frame = self.frame.borrow();
+        let frame = self.frame.read().unwrap();
         let mut rids = Vec::new();
         let mut pos = 11_usize;
         for i in 0..self.num_records {
@@ -570,27 +744,134 @@ impl BucketPage {
 
     /// Writing the index record bytes inside the bucket if there is enough space, else returning error.
     fn insert_record(&mut self, record: &IdxRecord) -> Result<(), String> {
-        let mut frame = self.frame.borrow_mut();
+        let mut frame = self.frame.write().unwrap();
         let pos = 11_usize + self.num_records as usize * IDX_RECORD_SIZE;
         if frame.page.payload.len() - pos < IDX_RECORD_SIZE {
             return Err("Insufficient Space".to_string());
         }
         self.num_records += 1;
-        frame.write_at(self.num_records.to_ne_bytes().as_slice(), 1);
-        frame.write_at(record.to_bytes().as_slice(), pos as u64);
+        frame.apply(LogRecord::IndexInsert {
+            offset: pos as u16,
+            entry: record.to_bytes(),
+            num_records: self.num_records,
+        });
         Ok(())
     }
 
-    /// Retrieve all the index records inside a bucket.
+    /// Removes the live index record matching the hash value and the Rid, the last record of the
+    /// page is moved into its place to keep the records contiguous.
+    fn remove_record(&mut self, hash_val: u32, rid: &Rid) -> bool {
+        let mut frame = self.frame.write().unwrap();
+        let mut pos = 11_usize;
+        for i in 0..self.num_records {
+            let idx_record = IdxRecord::from_bytes(&frame.page.payload[pos..pos + IDX_RECORD_SIZE]);
+            if idx_record.hash_val == hash_val && idx_record.deleted == 0 && idx_record.rid == *rid
+            {
+                self.num_records -= 1;
+                let last_pos = 11_usize + self.num_records as usize * IDX_RECORD_SIZE;
+                let last_record = frame.page.payload[last_pos..last_pos + IDX_RECORD_SIZE].to_vec();
+                frame.apply(LogRecord::IndexInsert {
+                    offset: pos as u16,
+                    entry: last_record,
+                    num_records: self.num_records,
+                });
+                return true;
+            }
+            pos += IDX_RECORD_SIZE;
+        }
+        false
+    }
+
+    /// Retrieve all the live index records inside a bucket.
     fn read_all_bucket_records(&self) -> Vec<IdxRecord> {
-        let frame = self.frame.borrow();
+        let frame = self.frame.read().unwrap();
         let mut pos = 11_usize;
         let mut idx_records = Vec::new();
         for i in 0..self.num_records {
             let idx_record = IdxRecord::from_bytes(&frame.page.payload[pos..pos + IDX_RECORD_SIZE]);
-            idx_records.push(idx_record);
+            if idx_record.deleted == 0 {
+                idx_records.push(idx_record);
+            }
             pos += IDX_RECORD_SIZE;
         }
         idx_records
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::testing::TempDir;
+
+    fn test_index() -> (TempDir, HashIndex, Arc<RwLock<StorageManager>>) {
+        let dir = TempDir::new("hash");
+        let index_file = dir.join("hash_idx_file");
+        let dir_file = dir.join("hash_idx_directory");
+        HashIndex::init(&index_file, &dir_file, GLOBAL_DEPTH);
+        let storage_mgr = dir.storage(100);
+        let blocks = storage_mgr.read().unwrap().file_blks(index_file);
+        let index = HashIndex::new(&dir_file, "hash_idx".to_string(), blocks, "key".to_string());
+        (dir, index, storage_mgr)
+    }
+
+    #[test]
+    fn delete_merges_buckets_and_shrinks_directory() {
+        let (_dir, mut index, storage_mgr) = test_index();
+        let keys = (0..12000_u32).collect::<Vec<_>>();
+        for key in &keys {
+            index.insert_record(
+                &key.to_ne_bytes(),
+                *key as u64,
+                0,
+                storage_mgr.write().unwrap(),
+            );
+        }
+        let grown_depth = index.global_depth;
+        assert!(grown_depth > GLOBAL_DEPTH);
+
+        for key in keys.iter().filter(|key| *key % 2 == 0) {
+            let rid = Rid::new(*key as u64, 0);
+            assert!(index.delete_record(&key.to_ne_bytes(), &rid, storage_mgr.write().unwrap()));
+            assert!(!index.delete_record(&key.to_ne_bytes(), &rid, storage_mgr.write().unwrap()));
+        }
+        for key in &keys {
+            let rids = index.get_rids(&key.to_ne_bytes(), storage_mgr.write().unwrap());
+            if key % 2 == 0 {
+                assert!(rids.is_empty());
+            } else {
+                assert_eq!(rids, vec![Rid::new(*key as u64, 0)]);
+            }
+        }
+
+        for key in keys.iter().filter(|key| *key % 2 == 1) {
+            let rid = Rid::new(*key as u64, 0);
+            assert!(index.delete_record(&key.to_ne_bytes(), &rid, storage_mgr.write().unwrap()));
+        }
+        assert_eq!(index.global_depth, GLOBAL_DEPTH);
+        assert_eq!(index.bucket_dir.bucket_map.len(), 1 << GLOBAL_DEPTH);
+        assert!(!index.bucket_dir.free_blocks.is_empty());
+
+        storage_mgr.write().unwrap().flush_all();
+        let reopened = BucketDirectory::new(&index.bucket_dir.index_dir_file);
+        assert_eq!(reopened.global_depth, GLOBAL_DEPTH);
+        assert_eq!(reopened.bucket_map, index.bucket_dir.bucket_map);
+        assert_eq!(reopened.free_blocks, index.bucket_dir.free_blocks);
+
+        // growing again takes the released blocks before extending the file
+        let released = index.bucket_dir.free_blocks.len();
+        for key in &keys {
+            index.insert_record(
+                &key.to_ne_bytes(),
+                *key as u64,
+                0,
+                storage_mgr.write().unwrap(),
+            );
+        }
+        assert_eq!(index.global_depth, grown_depth);
+        assert!(index.bucket_dir.free_blocks.len() < released);
+        for key in &keys {
+            let rids = index.get_rids(&key.to_ne_bytes(), storage_mgr.write().unwrap());
+            assert_eq!(rids, vec![Rid::new(*key as u64, 0)]);
+        }
+    }
+}
@@ -2,15 +2,15 @@ use crate::common::numerical::ByteMagic;
 use crate::index::btree_index::BPTree;
 use crate::index::hash_index::HashIndex;
 use crate::schema::schema::FieldIndex;
-use crate::schema::types::Type;
+use crate::schema::types::{NumericType, Type};
 use crate::sql::create_table::IndexType;
 use crate::storage::blockid::BlockId;
 use crate::storage::storagemgr::StorageManager;
 use crate::AQUADIR;
-use std::cell::{RefCell, RefMut};
 use std::path::{Path, PathBuf};
 use std::process::id;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::{RwLock, RwLockWriteGuard};
 
 pub mod btree_index;
 pub mod hash_index;
@@ -18,7 +18,7 @@ pub mod hash_index;
 const GLOBAL_DEPTH: u8 = 4;
 
 // pub trait Index {
-//     fn get_rid(&self, search_key: String, storage_mgr: RefMut<StorageManager>) -> Vec<Rid>;
+//     fn get_rid(&self, search_key: String, storage_mgr: RwLockWriteGuard<StorageManager>) -> Vec<Rid>;
 //
 //     fn index_type(&self) -> IndexType;
 // }
@@ -55,7 +55,7 @@ pub enum Index {
 }
 
 impl Index {
-    pub fn init_index(index_info: IndexInfo, storage: Rc<RefCell<StorageManager>>) {
+    pub fn init_index(index_info: IndexInfo, storage: Arc<RwLock<StorageManager>>) {
         match index_info.index_type {
             IndexType::Hash => HashIndex::init(
                 index_info.index_file_path.as_path(),
@@ -75,14 +75,18 @@ impl Index {
         match index_info.index_type {
             IndexType::Hash => Self::Hash(HashIndex::new(
                 index_info.directory_file_path.as_path(),
-                index_info.index_name,
+                index_info.index_name.clone(),
                 blks,
-                index_info.column,
+                index_info.key_columns(),
             )),
             IndexType::Btree => todo!(),
         }
     }
-    pub fn get_rid(&self, search_key: &[u8], storage_mgr: RefMut<StorageManager>) -> Vec<Rid> {
+    pub fn get_rid(
+        &self,
+        search_key: &[u8],
+        storage_mgr: RwLockWriteGuard<StorageManager>,
+    ) -> Vec<Rid> {
         match self {
             Index::Hash(h) => h.get_rids(search_key, storage_mgr),
         }
@@ -92,14 +96,14 @@ impl Index {
         data_val: &[u8],
         blk: u64,
         slot: usize,
-        storage_mgr: RefMut<StorageManager>,
+        storage_mgr: RwLockWriteGuard<StorageManager>,
     ) {
         match self {
             Index::Hash(h) => h.insert_record(data_val, blk, slot as u16, storage_mgr),
         }
     }
 
-    // pub fn flush_all(&self, mut storage_mgr: &mut RefMut<StorageManager>) {
+    // pub fn flush_all(&self, mut storage_mgr: &mut RwLockWriteGuard<StorageManager>) {
     //     match self {
     //         Index::Hash(idx) => {
     //             idx.flush_all(storage_mgr);
@@ -108,10 +112,11 @@ impl Index {
     // }
 }
 
+#[derive(Clone)]
 pub struct IndexInfo {
     pub index_name: String,
     pub index_type: IndexType,
-    pub column: String,
+    pub columns: Vec<String>,
     pub index_file_path: PathBuf,
     pub directory_file_path: PathBuf,
     pub key_type: Type,
@@ -122,7 +127,7 @@ impl IndexInfo {
         db_name: &str,
         index_name: String,
         index_type: IndexType,
-        column: String,
+        columns: Vec<String>,
         index_file_path: PathBuf,
         directory_file_path: PathBuf,
         key_type: Type,
@@ -134,12 +139,119 @@ impl IndexInfo {
         Self {
             index_name,
             index_type,
-            column,
+            columns,
             index_file_path,
             directory_file_path,
             key_type,
         }
     }
+    /// The columns of the index joined by commas, the name the table knows the index by
+    pub fn key_columns(&self) -> String {
+        self.columns.join(",")
+    }
+}
+
+/// Sorts after every hex digit, appended to an encoded key prefix it bounds all the keys starting
+/// with that prefix
+const PREFIX_END: u8 = b'g';
+
+/// Encodes the values of the columns of a multi-column index into a single key.
+///
+/// Each value is encoded so that comparing the encodings byte by byte orders them like the values,
+/// and no encoding is the prefix of another. Keys therefore compare column after column, and the
+/// keys sharing the values of the first columns are contiguous in the B+Tree. The result is
+/// written in hex to be stored and compared as a varchar.
+pub fn composite_key(values: &[(Option<&[u8]>, Type)]) -> Vec<u8> {
+    let mut key = vec![];
+    for (value, value_type) in values {
+        encode_column(&mut key, *value, *value_type);
+    }
+    to_hex(&key)
+}
+
+/// The bounds of the keys of a multi-column index whose first columns hold some values, optionally
+/// followed by a column compared to a value. The lower bound is inclusive and the upper one is not
+pub fn composite_key_range(
+    prefix: &[(Option<&[u8]>, Type)],
+    range: Option<(evalexpr::Operator, &[u8], Type)>,
+) -> (Vec<u8>, Vec<u8>) {
+    use evalexpr::Operator::*;
+    let mut prefix_key = vec![];
+    for (value, value_type) in prefix {
+        encode_column(&mut prefix_key, *value, *value_type);
+    }
+    let start = to_hex(&prefix_key);
+    let mut end = start.clone();
+    end.push(PREFIX_END);
+    let (op, value, value_type) = match range {
+        Some(range) => range,
+        None => return (start, end),
+    };
+    encode_column(&mut prefix_key, Some(value), value_type);
+    let bound = to_hex(&prefix_key);
+    let mut bound_end = bound.clone();
+    bound_end.push(PREFIX_END);
+    // nulls sort before any value and stay in the ranges below one, whether they qualify is left
+    // to the condition
+    match op {
+        Lt => (start, bound),
+        Leq => (start, bound_end),
+        Gt => (bound_end, end),
+        Geq => (bound, end),
+        _ => unreachable!(),
+    }
+}
+
+fn encode_column(key: &mut Vec<u8>, value: Option<&[u8]>, value_type: Type) {
+    let value = match value {
+        Some(value) => value,
+        None => {
+            key.push(0);
+            return;
+        }
+    };
+    key.push(1);
+    match value_type {
+        Type::Numeric(NumericType::Single | NumericType::Double) => {
+            let float = if value.len() == 4 {
+                value.to_f32() as f64
+            } else {
+                value.to_f64()
+            };
+            // -0.0 and 0.0 are equal and get the same encoding
+            let bits = (float + 0.0).to_bits();
+            let bits = if bits >> 63 == 1 { !bits } else { bits ^ (1 << 63) };
+            key.extend(bits.to_be_bytes());
+        }
+        Type::Numeric(NumericType::Decimal(_, _)) => {
+            key.extend((value.to_i128() as u128 ^ (1 << 127)).to_be_bytes());
+        }
+        Type::Numeric(_) | Type::Temporal(_) => {
+            let int = match value.len() {
+                2 => value.to_i16() as i64,
+                4 => value.to_i32() as i64,
+                _ => value.to_i64(),
+            };
+            key.extend((int as u64 ^ (1 << 63)).to_be_bytes());
+        }
+        Type::Character(_) | Type::Binary => {
+            for byte in value {
+                key.push(*byte);
+                if *byte == 0 {
+                    key.push(0xFF);
+                }
+            }
+            key.extend([0, 1]);
+        }
+        Type::Boolean => key.push(value[0]),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .flat_map(|byte| format!("{:02x}", byte).into_bytes())
+        .collect()
 }
 
 // impl From<FieldIndex> for IndexInfo {
@@ -178,7 +290,7 @@ impl IndexInfo {
 //     }
 // }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct Rid {
     block_num: u64,
     slot_num: u16,
@@ -223,3 +335,73 @@ impl Rid {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::types::CharType::VarChar;
+    use crate::schema::types::NumericType::{BigInt, Double, Integer};
+
+    #[test]
+    fn composite_keys_order_column_by_column() {
+        let int = |i: i32| i.to_ne_bytes().to_vec();
+        let key = |tenant: Option<i32>, name: &str, score: f64| {
+            let tenant = tenant.map(int);
+            composite_key(&[
+                (tenant.as_deref(), Type::Numeric(Integer)),
+                (Some(name.as_bytes()), Type::Character(VarChar(None))),
+                (Some(&score.to_ne_bytes()), Type::Numeric(Double)),
+            ])
+        };
+        let ordered = [
+            key(None, "z", 9.0),
+            key(Some(-300), "a", 0.0),
+            key(Some(-1), "", 0.0),
+            key(Some(0), "a", 1.5),
+            key(Some(0), "a\0", -2.0),
+            key(Some(0), "ab", -2.0),
+            key(Some(0), "b", -1e300),
+            key(Some(0), "b", -0.5),
+            key(Some(0), "b", 0.0),
+            key(Some(0), "b", 0.25),
+            key(Some(7), "", -1.0),
+            key(Some(i32::MAX), "", 0.0),
+        ];
+        for pair in ordered.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+        assert_eq!(key(Some(0), "b", -0.0), key(Some(0), "b", 0.0));
+        assert!(ordered.iter().all(|k| String::from_utf8(k.clone()).is_ok()));
+    }
+
+    #[test]
+    fn composite_key_range_bounds_the_prefix() {
+        let big = |i: i64| i.to_ne_bytes();
+        let key = |tenant: i64, at: Option<i64>| {
+            let at = at.map(big);
+            composite_key(&[
+                (Some(&big(tenant)), Type::Numeric(BigInt)),
+                (at.as_ref().map(|a| a.as_slice()), Type::Numeric(BigInt)),
+            ])
+        };
+        let tenant = big(5);
+        let prefix = [(Some(tenant.as_slice()), Type::Numeric(BigInt))];
+        let within = |(low, high): &(Vec<u8>, Vec<u8>), k: &Vec<u8>| low <= k && k < high;
+        let all = composite_key_range(&prefix, None);
+        assert!(within(&all, &key(5, None)) && within(&all, &key(5, Some(i64::MAX))));
+        assert!(!within(&all, &key(4, Some(i64::MAX))) && !within(&all, &key(6, None)));
+        let ten = big(10);
+        let range = |op| composite_key_range(&prefix, Some((op, &ten, Type::Numeric(BigInt))));
+        let cases = [
+            (evalexpr::Operator::Lt, [true, true, false, false]),
+            (evalexpr::Operator::Leq, [true, true, true, false]),
+            (evalexpr::Operator::Gt, [false, false, false, true]),
+            (evalexpr::Operator::Geq, [false, false, true, true]),
+        ];
+        for (op, expected) in cases {
+            let bounds = range(op);
+            let found = [None, Some(-3), Some(10), Some(11)].map(|at| within(&bounds, &key(5, at)));
+            assert_eq!(found, expected);
+        }
+    }
+}
@@ -66,13 +66,37 @@ pub enum Status {
     DatabaseConnection(String),
     DatabaseCreated(String),
     DatabaseNotCreated(String, String),
+    DatabaseDropped(String),
+    DatabaseNotDropped(String, String),
     TableCreated(String),
     TableNotCreated(String, String),
+    TableAltered(String),
+    TableNotAltered(String, String),
+    TableDropped(String),
+    TableNotDropped(String, String),
+    TableTruncated(String),
+    TableNotTruncated(String, String),
+    IndexCreated(String),
+    IndexNotCreated(String, String),
+    IndexDropped(String),
+    IndexNotDropped(String, String),
+    SequenceCreated(String),
+    SequenceNotCreated(String, String),
+    SequenceDropped(String),
+    SequenceNotDropped(String, String),
     RecordInserted,
     RecordNotInserted(String),
+    RecordsUpdated(usize),
+    RecordsNotUpdated(String),
+    RecordsDeleted(usize),
     BadCommand,
     Generic(String),
     ResultsFinished,
+    TransactionStarted,
+    TransactionCommitted,
+    TransactionRolledBack,
+    TransactionAborted(String),
+    Vacuumed(usize),
 }
 
 impl Display for Status {
@@ -84,13 +108,45 @@ impl Display for Status {
             Status::DatabaseNotCreated(s1, s2) => {
                 write!(f, "Could not create Database {} : {}", s1, s2)
             }
+            Status::DatabaseDropped(db) => write!(f, "Database {} dropped successfully", db),
+            Status::DatabaseNotDropped(s1, s2) => {
+                write!(f, "Could not drop Database {} : {}", s1, s2)
+            }
             Status::TableCreated(s) => write!(f, "Table {} created successfully", s),
             Status::TableNotCreated(s1, s2) => write!(f, "Could not create table {} : {}", s1, s2),
+            Status::TableAltered(s) => write!(f, "Table {} altered successfully", s),
+            Status::TableNotAltered(s1, s2) => write!(f, "Could not alter table {} : {}", s1, s2),
+            Status::TableDropped(s) => write!(f, "Table {} dropped successfully", s),
+            Status::TableNotDropped(s1, s2) => write!(f, "Could not drop table {} : {}", s1, s2),
+            Status::TableTruncated(s) => write!(f, "Table {} truncated successfully", s),
+            Status::TableNotTruncated(s1, s2) => {
+                write!(f, "Could not truncate table {} : {}", s1, s2)
+            }
+            Status::IndexCreated(s) => write!(f, "Index {} created successfully", s),
+            Status::IndexNotCreated(s1, s2) => write!(f, "Could not create index {} : {}", s1, s2),
+            Status::IndexDropped(s) => write!(f, "Index {} dropped successfully", s),
+            Status::IndexNotDropped(s1, s2) => write!(f, "Could not drop index {} : {}", s1, s2),
+            Status::SequenceCreated(s) => write!(f, "Sequence {} created successfully", s),
+            Status::SequenceNotCreated(s1, s2) => {
+                write!(f, "Could not create sequence {} : {}", s1, s2)
+            }
+            Status::SequenceDropped(s) => write!(f, "Sequence {} dropped successfully", s),
+            Status::SequenceNotDropped(s1, s2) => {
+                write!(f, "Could not drop sequence {} : {}", s1, s2)
+            }
             Status::RecordInserted => write!(f, "Record Inserted Successfully"),
             Status::RecordNotInserted(s) => write!(f, "Record Insertion Failed: {}", s),
+            Status::RecordsUpdated(n) => write!(f, "Records Updated: {}", n),
+            Status::RecordsNotUpdated(s) => write!(f, "Record Update Failed: {}", s),
+            Status::RecordsDeleted(n) => write!(f, "Records Deleted: {}", n),
             Status::BadCommand => write!(f, "Command not found"),
             Status::Generic(s) => write!(f, "{}", s),
             Status::ResultsFinished => write!(f, ""),
+            Status::TransactionStarted => write!(f, "BEGIN"),
+            Status::TransactionCommitted => write!(f, "COMMIT"),
+            Status::TransactionRolledBack => write!(f, "ROLLBACK"),
+            Status::TransactionAborted(s) => write!(f, "Transaction aborted: {}", s),
+            Status::Vacuumed(n) => write!(f, "VACUUM removed {} dead tuple versions", n),
         }
     }
 }
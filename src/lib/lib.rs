@@ -5,7 +5,8 @@
 
 // extern crate core;
 
-use crate::schema::types::{NumericType, Type};
+use crate::common::decimal;
+use crate::schema::types::{NumericType, TemporalType, Type};
 use crate::sql::query::select::AggregateFunc;
 use bincode::{Decode, Encode};
 use std::fmt::{Display, Formatter};
@@ -88,7 +89,13 @@ impl AggregateField {
             AggregateFunc::Min => field_type,
             AggregateFunc::Max => field_type,
             AggregateFunc::Count => Type::Numeric(NumericType::BigInt),
-            AggregateFunc::Avg => Type::Numeric(NumericType::Double),
+            // the average of exact numbers is exact to a few more digits than they have
+            AggregateFunc::Avg => match field_type {
+                Type::Numeric(NumericType::Decimal(_, scale)) => Type::Numeric(
+                    NumericType::Decimal(decimal::MAX_PRECISION, decimal::quotient_scale(scale)),
+                ),
+                _ => Type::Numeric(NumericType::Double),
+            },
             AggregateFunc::Sum => match field_type {
                 Type::Numeric(n) => match n {
                     NumericType::SmallInt
@@ -96,7 +103,11 @@ impl AggregateField {
                     | NumericType::BigInt
                     | NumericType::Serial => Type::Numeric(NumericType::BigInt),
                     NumericType::Single | NumericType::Double => Type::Numeric(NumericType::Double),
+                    NumericType::Decimal(_, scale) => {
+                        Type::Numeric(NumericType::Decimal(decimal::MAX_PRECISION, scale))
+                    }
                 },
+                Type::Temporal(TemporalType::Interval) => field_type,
                 _ => unreachable!(),
             },
         }
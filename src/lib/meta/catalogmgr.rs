@@ -1,35 +1,54 @@
 use crate::common::btree_multimap::BTreeMultimap;
-use crate::index::Index;
-use crate::schema::schema::{Layout, Schema};
+use crate::common::numerical::ByteMagic;
+use crate::database::txnmgr::TransactionManager;
+use crate::index::{Index, IndexInfo, Rid};
+use crate::schema::schema::{ForeignKey, Layout, Schema};
 use crate::schema::types::CharType::VarChar;
 use crate::schema::types::{CharType, NumericType, Type};
+use crate::sql::create_table::IndexType;
 use crate::storage::blkmgr::BlockManager;
 use crate::storage::blockid::BlockId;
 use crate::storage::free_space::FreeMap;
 use crate::storage::heap::HeapPage;
+use crate::storage::overflow::{overflow_file, overflow_freemap_file};
 use crate::storage::storagemgr::StorageManager;
 use crate::table::tablemgr::TableManager;
-use crate::{RcRefCell, AQUADIR};
+use crate::{ArcRwLock, AQUADIR};
 use std::collections::{HashMap, HashSet};
-use std::fs::create_dir;
+use std::fs::{self, create_dir};
 use std::path::{Path, PathBuf};
-use std::{cell::RefCell, rc::Rc};
+use std::sync::{Arc, Mutex, RwLock};
+
+type CatalogRow = HashMap<String, Option<Vec<u8>>>;
+
+/// Sequence values are reserved in the catalog this many at a time
+const SEQUENCE_BATCH: i64 = 32;
+
+/// The values of a sequence handed out since the catalog was loaded
+struct SequenceCounter {
+    next: i64,
+    // the values from next up to it were reserved in the catalog, the ones after it were not
+    reserved: i64,
+    increment: i64,
+}
 
 struct InstanceCatalog {
     db_name: String,
     schemas: TableManager,
     tables_filepaths: TableManager,
     indexes: TableManager,
+    sequences: TableManager,
+    counters: HashMap<String, SequenceCounter>,
 }
 
 impl InstanceCatalog {
     fn get_db_tables(
         &self,
-        storage: &Rc<RefCell<StorageManager>>,
+        storage: &Arc<RwLock<StorageManager>>,
     ) -> HashMap<String, TableManager> {
         let tables = self
             .tables_filepaths
-            .heapscan_iter()
+            .heapscan_iter(None)
             .map(|mut r| {
                 (
                     String::from_utf8(r.remove("tablename").unwrap().unwrap()).unwrap(),
@@ -40,37 +59,70 @@ impl InstanceCatalog {
                 )
             })
             .collect::<HashMap<String, (String, String)>>();
-        let db_path = Path::new(AQUADIR().as_str())
-            .join("base")
-            .join(&self.db_name);
         tables
             .into_iter()
             .map(|(name, (heap_path, freemap_file))| {
-                let schema = self.get_schema(&name);
-                let indexes = schema
-                    .indexes()
-                    .iter()
-                    .map(|idx| idx.to_index_info(self.db_name.as_str()))
-                    .collect();
-                let table = TableManager::from_file(
-                    storage.clone(),
-                    db_path.join(heap_path),
-                    Rc::new(schema.to_layout()),
-                    indexes,
-                    db_path.join(freemap_file),
-                );
+                let table = self.open_table(&name, &heap_path, &freemap_file, storage);
                 (name, table)
             })
             .collect()
     }
+    /// Loads a table of the database from its files, with the layout and indexes of its schema
+    fn open_table(
+        &self,
+        name: &str,
+        heap_path: &str,
+        freemap_file: &str,
+        storage: &Arc<RwLock<StorageManager>>,
+    ) -> TableManager {
+        let db_path = Path::new(AQUADIR().as_str())
+            .join("base")
+            .join(&self.db_name);
+        let schema = self.get_schema(name);
+        let indexes = schema
+            .indexes()
+            .iter()
+            .map(|idx| idx.to_index_info(self.db_name.as_str()))
+            .collect();
+        TableManager::from_file(
+            storage.clone(),
+            db_path.join(heap_path),
+            Arc::new(schema.to_layout()),
+            indexes,
+            db_path.join(freemap_file),
+        )
+    }
+    fn load_table(
+        &self,
+        name: &str,
+        storage: &Arc<RwLock<StorageManager>>,
+    ) -> Option<TableManager> {
+        let files = self
+            .tables_filepaths
+            .heapscan_iter(None)
+            .find(|row| row.get("tablename").unwrap().as_deref() == Some(name.as_bytes()))?;
+        let file = |column: &str| String::from_utf8(files.get(column).unwrap().clone().unwrap());
+        Some(self.open_table(
+            name,
+            &file("filepath").unwrap(),
+            &file("freemap").unwrap(),
+            storage,
+        ))
+    }
+    fn table_names(&self) -> Vec<String> {
+        self.tables_filepaths
+            .heapscan_iter(None)
+            .map(|mut row| String::from_utf8(row.remove("tablename").unwrap().unwrap()).unwrap())
+            .collect()
+    }
     fn get_schema_indexes(&self, schema_name: &str) -> Vec<HashMap<String, Option<Vec<u8>>>> {
         self.indexes
-            .heapscan_iter()
+            .heapscan_iter(None)
             .filter(|row| row.get("tablename").unwrap().as_ref().unwrap() == schema_name.as_bytes())
             .collect()
     }
     fn get_schema(&self, schema_name: &str) -> Schema {
-        let schemas_catalog = self.schemas.heapscan_iter();
+        let schemas_catalog = self.schemas.heapscan_iter(None);
         let schema_vec = schemas_catalog
             .filter(|row| row.get("tablename").unwrap().as_ref().unwrap() == schema_name.as_bytes())
             .collect::<Vec<_>>();
@@ -79,9 +131,9 @@ impl InstanceCatalog {
     fn add_schema(
         &mut self,
         schema: &Schema,
-        storage: Rc<RefCell<StorageManager>>,
+        storage: Arc<RwLock<StorageManager>>,
     ) -> Result<TableManager, String> {
-        let mut catalog_iter = self.tables_filepaths.heapscan_iter();
+        let mut catalog_iter = self.tables_filepaths.heapscan_iter(None);
         if catalog_iter.any(|row| {
             row.get("tablename")
                 .unwrap()
@@ -99,6 +151,7 @@ impl InstanceCatalog {
         for idx in indexes {
             Index::init_index(idx, storage.clone());
         }
+        let (heap_file, freemap_file) = self.table_files(schema.name());
         let mut schema_catalog = &mut self.schemas;
         let (serde_schema, mut serde_indexes) = schema.serialize();
         for field in serde_schema {
@@ -111,13 +164,10 @@ impl InstanceCatalog {
                 "tablename".to_string(),
                 Some(schema.name().as_bytes().to_vec()),
             ),
-            (
-                "filepath".to_string(),
-                Some(format!("{}_heap0", &schema.name()).into_bytes()),
-            ),
+            ("filepath".to_string(), Some(heap_file.clone().into_bytes())),
             (
                 "freemap".to_string(),
-                Some(format!("{}_freemap", &schema.name()).into_bytes()),
+                Some(freemap_file.clone().into_bytes()),
             ),
         ]);
 
@@ -131,7 +181,7 @@ impl InstanceCatalog {
         let table_path = Path::new(AQUADIR().as_str())
             .join("base")
             .join(&self.db_name)
-            .join(format!("{}_heap0", &schema.name()));
+            .join(heap_file);
         let indexes = schema
             .indexes()
             .iter()
@@ -140,28 +190,236 @@ impl InstanceCatalog {
         let freemap_file = Path::new(AQUADIR().as_str())
             .join("base")
             .join(&self.db_name)
-            .join(format!("{}_freemap", &schema.name()));
+            .join(freemap_file);
         FreeMap::init(freemap_file.clone(), 0, &BlockId::new("", 0));
 
         let table = TableManager::from_file(
             storage,
             table_path,
-            Rc::new(schema.to_layout()),
+            Arc::new(schema.to_layout()),
             indexes,
             freemap_file,
         );
         Ok(table)
     }
+    /// The names of the heap and free space map files of a new table.
+    ///
+    /// A renamed table keeps its files, the first generation of the names no table uses is taken
+    fn table_files(&self, table_name: &str) -> (String, String) {
+        let db_path = Path::new(AQUADIR().as_str())
+            .join("base")
+            .join(&self.db_name);
+        (0..)
+            .map(|n| {
+                (
+                    format!("{}_heap{}", table_name, n),
+                    format!("{}_freemap{}", table_name, n),
+                )
+            })
+            .find(|(heap, freemap)| !db_path.join(heap).exists() && !db_path.join(freemap).exists())
+            .unwrap()
+    }
+    /// Replaces the catalog rows describing a table with the ones of its altered schema, the table
+    /// keeps its files when it is renamed
+    fn replace_schema(&mut self, table_name: &str, schema: &Schema) {
+        Self::remove_rows(&mut self.schemas, table_name);
+        Self::remove_rows(&mut self.indexes, table_name);
+        let (serde_schema, serde_indexes) = schema.serialize();
+        for field in serde_schema {
+            self.schemas.try_insert_tuple(field);
+        }
+        for idx in serde_indexes {
+            self.indexes.try_insert_tuple(idx);
+        }
+        if table_name != schema.name() {
+            for mut files in Self::remove_rows(&mut self.tables_filepaths, table_name) {
+                files.insert(
+                    "tablename".to_string(),
+                    Some(schema.name().as_bytes().to_vec()),
+                );
+                self.tables_filepaths
+                    .try_insert_tuple(files.into_iter().collect());
+            }
+        }
+        self.schemas.flush_all();
+        self.indexes.flush_all();
+        self.tables_filepaths.flush_all();
+    }
+    /// Deletes the rows of a catalog table describing a table and returns them
+    fn remove_rows(
+        catalog: &mut TableManager,
+        table_name: &str,
+    ) -> Vec<HashMap<String, Option<Vec<u8>>>> {
+        let mut rows = vec![];
+        let mut rids = vec![];
+        let mut catalog_iter = catalog.heapscan_iter(None);
+        while let Some(row) = catalog_iter.next() {
+            if row.get("tablename").unwrap().as_deref() == Some(table_name.as_bytes()) {
+                rids.push(catalog_iter.current_rid().unwrap());
+                rows.push(row);
+            }
+        }
+        catalog.delete_tuples(&rids);
+        rows
+    }
+    /// Removes a table from the catalog and deletes its heap, free space map, overflow and index
+    /// files, the schema it had is returned
+    fn drop_table(&mut self, table_name: &str, storage: &Arc<RwLock<StorageManager>>) -> Schema {
+        let schema = self.get_schema(table_name);
+        let files = Self::remove_rows(&mut self.tables_filepaths, table_name);
+        Self::remove_rows(&mut self.schemas, table_name);
+        Self::remove_rows(&mut self.indexes, table_name);
+        self.tables_filepaths.flush_all();
+        self.schemas.flush_all();
+        self.indexes.flush_all();
+        let db_path = Path::new(AQUADIR().as_str())
+            .join("base")
+            .join(&self.db_name);
+        let mut storage = storage.write().unwrap();
+        for row in files {
+            let file = |column: &str| {
+                db_path.join(String::from_utf8(row.get(column).unwrap().clone().unwrap()).unwrap())
+            };
+            let heap_file = file("filepath").to_str().unwrap().to_string();
+            storage.remove_file(&heap_file);
+            let freemap = file("freemap");
+            if freemap.exists() {
+                fs::remove_file(freemap).unwrap();
+            }
+            let overflow_freemap = PathBuf::from(overflow_freemap_file(&heap_file));
+            if overflow_freemap.exists() {
+                storage.remove_file(&overflow_file(&heap_file));
+                fs::remove_file(overflow_freemap).unwrap();
+            }
+        }
+        for idx in schema.indexes() {
+            Self::remove_index_files(&idx.to_index_info(&self.db_name), &mut storage);
+        }
+        schema
+    }
+    fn remove_index_files(idx: &IndexInfo, storage: &mut StorageManager) {
+        storage.remove_file(idx.index_file_path.to_str().unwrap());
+        if idx.directory_file_path.exists() {
+            fs::remove_file(&idx.directory_file_path).unwrap();
+        }
+    }
+    /// The table an index of the database is built on
+    fn index_table(&self, index_name: &str) -> Option<String> {
+        self.indexes
+            .heapscan_iter(None)
+            .find(|row| row.get("index_name").unwrap().as_deref() == Some(index_name.as_bytes()))
+            .map(|mut row| String::from_utf8(row.remove("tablename").unwrap().unwrap()).unwrap())
+    }
+    /// The catalog row of a sequence and its Rid
+    fn sequence_row(&self, name: &str) -> Option<(Rid, CatalogRow)> {
+        let mut catalog_iter = self.sequences.heapscan_iter(None);
+        while let Some(row) = catalog_iter.next() {
+            if row.get("sequence_name").unwrap().as_deref() == Some(name.as_bytes()) {
+                return Some((catalog_iter.current_rid().unwrap(), row));
+            }
+        }
+        None
+    }
+    /// Records a sequence whose first value is start, nothing of it is reserved yet
+    fn create_sequence(&mut self, name: &str, start: i64, increment: i64) -> Result<(), String> {
+        if self.sequence_row(name).is_some() {
+            return Err(format!("Sequence {} already exists", name));
+        }
+        self.store_sequence(name, start, increment);
+        Ok(())
+    }
+    fn store_sequence(&mut self, name: &str, reserved: i64, increment: i64) {
+        self.sequences.try_insert_tuple(vec![
+            ("sequence_name".to_string(), Some(name.as_bytes().to_vec())),
+            (
+                "increment".to_string(),
+                Some(increment.to_ne_bytes().to_vec()),
+            ),
+            (
+                "reserved".to_string(),
+                Some(reserved.to_ne_bytes().to_vec()),
+            ),
+        ]);
+        self.sequences.flush_all();
+    }
+    /// Removes a sequence from the catalog, false if there was none by that name
+    fn drop_sequence(&mut self, name: &str) -> bool {
+        self.counters.remove(name);
+        match self.sequence_row(name) {
+            Some((rid, _)) => {
+                self.sequences.delete_tuples(&[rid]);
+                self.sequences.flush_all();
+                true
+            }
+            None => false,
+        }
+    }
+    /// Hands out the next value of a sequence.
+    ///
+    /// The values are reserved in the catalog a batch at a time before any of them is handed out,
+    /// the catalog is loaded again after a restart so the sequence goes on from the end of its last
+    /// reserved batch and never hands out a value twice
+    fn next_value(&mut self, name: &str) -> Result<i64, String> {
+        if !self.counters.contains_key(name) {
+            let (_, row) = self
+                .sequence_row(name)
+                .ok_or(format!("Sequence {} does not exist", name))?;
+            let number = |column: &str| row.get(column).unwrap().as_deref().unwrap().to_i64();
+            let reserved = number("reserved");
+            self.counters.insert(
+                name.to_string(),
+                SequenceCounter {
+                    next: reserved,
+                    reserved,
+                    increment: number("increment"),
+                },
+            );
+        }
+        let counter = &self.counters[name];
+        let (next, increment) = (counter.next, counter.increment);
+        if next == counter.reserved {
+            let reserved = increment
+                .checked_mul(SEQUENCE_BATCH)
+                .and_then(|batch| next.checked_add(batch))
+                .ok_or(format!("Sequence {} reached its last value", name))?;
+            let (rid, _) = self.sequence_row(name).unwrap();
+            self.sequences.delete_tuples(&[rid]);
+            self.store_sequence(name, reserved, increment);
+            self.counters.get_mut(name).unwrap().reserved = reserved;
+        }
+        self.counters.get_mut(name).unwrap().next = next + increment;
+        Ok(next)
+    }
+    /// The fields of the tables of the database taking their default from a sequence, as
+    /// (table, field)
+    fn sequence_users(&self, sequence: &str) -> Vec<(String, String)> {
+        self.table_names()
+            .into_iter()
+            .flat_map(|name| {
+                self.get_schema(&name)
+                    .fields()
+                    .iter()
+                    .filter(|f| f.sequence() == Some(sequence))
+                    .map(|f| (name.clone(), f.name().to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
 }
+/// The tables of a database, shared by all the sessions connected to it
+pub type SharedTables = Arc<Mutex<HashMap<String, TableManager>>>;
+
 pub struct CatalogManager {
-    storage_mgr: Rc<RefCell<StorageManager>>,
+    storage_mgr: Arc<RwLock<StorageManager>>,
     databases_tbl: TableManager,
     databases_catalogs: HashMap<String, InstanceCatalog>,
+    open_databases: HashMap<String, SharedTables>,
+    transaction_managers: HashMap<String, Arc<TransactionManager>>,
 }
 
 impl CatalogManager {
     fn new(
-        storage_mgr: Rc<RefCell<StorageManager>>,
+        storage_mgr: Arc<RwLock<StorageManager>>,
         databases_tbl: TableManager,
         databases_catalogs: HashMap<String, InstanceCatalog>,
     ) -> Self {
@@ -169,11 +427,13 @@ impl CatalogManager {
             storage_mgr,
             databases_tbl,
             databases_catalogs,
+            open_databases: HashMap::new(),
+            transaction_managers: HashMap::new(),
         }
     }
-    pub fn startup(storagemgr: Rc<RefCell<StorageManager>>) -> Self {
+    pub fn startup(storagemgr: Arc<RwLock<StorageManager>>) -> Self {
         let databases_tbl = Self::load_dbs_table(&storagemgr);
-        let mut dbs_iter = databases_tbl.heapscan_iter();
+        let mut dbs_iter = databases_tbl.heapscan_iter(None);
         let mut db_names = vec![];
         while let Some(row) = dbs_iter.next() {
             db_names.push(
@@ -187,22 +447,51 @@ impl CatalogManager {
                 let schemas = Self::load_db_schema_table(&storagemgr, db.as_str());
                 let indexes = Self::load_db_indexes_table(&storagemgr, db.as_str());
                 let tables_filepaths = Self::load_db_tables_files_table(&storagemgr, db.as_str());
+                let sequences = Self::load_db_sequences_table(&storagemgr, db.as_str());
                 let instance = InstanceCatalog {
                     db_name: db.clone(),
                     schemas,
                     indexes,
                     tables_filepaths,
+                    sequences,
+                    counters: HashMap::new(),
                 };
                 (db, instance)
             })
             .collect();
         Self::new(storagemgr, databases_tbl, db_tbl_schema_catalogs)
     }
-    pub fn get_db_tables(&self, db_name: &str) -> HashMap<String, TableManager> {
-        self.databases_catalogs
-            .get(db_name)
-            .unwrap()
-            .get_db_tables(&self.storage_mgr)
+    /// The tables of a database, loaded by its first session and shared with the sessions after it
+    /// so they all see the same heap blocks and free space maps.
+    ///
+    /// The tables are vacuumed once loaded, the versions left by the transactions a crash cut short
+    /// are removed and their deletions taken back before any session reads them
+    pub fn get_db_tables(&mut self, db_name: &str) -> SharedTables {
+        if let Some(tables) = self.open_databases.get(db_name) {
+            return tables.clone();
+        }
+        let transactions = self.get_db_transactions(db_name);
+        let catalog = self.databases_catalogs.get(db_name).unwrap();
+        let mut tables = catalog.get_db_tables(&self.storage_mgr);
+        for table in tables.values_mut() {
+            table.vacuum(&transactions);
+        }
+        self.storage_mgr.write().unwrap().flush_log();
+        let tables = Arc::new(Mutex::new(tables));
+        self.open_databases
+            .insert(db_name.to_string(), tables.clone());
+        tables
+    }
+    /// The transaction manager of a database, shared by the transactions of all the sessions
+    /// connected to it
+    pub fn get_db_transactions(&mut self, db_name: &str) -> Arc<TransactionManager> {
+        self.transaction_managers
+            .entry(db_name.to_string())
+            .or_insert_with(|| {
+                let db_dir = Path::new(AQUADIR().as_str()).join("base").join(db_name);
+                Arc::new(TransactionManager::new(&db_dir))
+            })
+            .clone()
     }
     pub fn has_db(&self, db_name: &str) -> bool {
         // self.databases_tbl
@@ -211,22 +500,243 @@ impl CatalogManager {
         self.databases_catalogs.contains_key(db_name)
     }
     pub fn init_catalogs() {
-        let mut storagemgr = RcRefCell!(StorageManager::new(AQUADIR().as_str(), 4096, 10));
+        Self::create_catalogs();
+        StorageManager::write_format_version(AQUADIR().as_str());
+    }
+    /// Creates the catalog of the databases of a data directory without recording the version of
+    /// its format
+    pub(crate) fn create_catalogs() {
+        let mut storagemgr = ArcRwLock!(StorageManager::new(AQUADIR().as_str(), 4096, 10));
         let database_tbl = Self::init_dbs_table(storagemgr);
     }
     pub fn get_schema(&self, db_name: &str, table_name: &str) -> Option<Schema> {
         let db_catalog = self.databases_catalogs.get(db_name)?;
         Some(db_catalog.get_schema(table_name))
     }
+    /// Records a new table and creates its files, along with the sequences its SERIAL fields own.
+    ///
+    /// Fails if the table or one of those sequences exists already, or if a field takes its
+    /// default from a sequence that does not exist
     pub fn add_schema(&mut self, db_name: &str, schema: &Schema) -> Result<TableManager, String> {
         let mut db_catalog = self
             .databases_catalogs
             .get_mut(db_name)
             .ok_or("Database does not exist")?;
+        let owned = schema.owned_sequences();
+        for sequence in schema.fields().iter().filter_map(|f| f.sequence()) {
+            let exists = db_catalog.sequence_row(sequence).is_some();
+            if owned.contains(&sequence) && exists {
+                return Err(format!("Sequence {} already exists", sequence));
+            }
+            if !owned.contains(&sequence) && !exists {
+                return Err(format!("Sequence {} does not exist", sequence));
+            }
+        }
         // IF THIS IS INDEXABLE THEN BETTER
-        db_catalog.add_schema(schema, self.storage_mgr.clone())
+        let table = db_catalog.add_schema(schema, self.storage_mgr.clone())?;
+        for sequence in owned {
+            db_catalog.create_sequence(sequence, 1, 1)?;
+        }
+        Ok(table)
+    }
+    /// Stores the altered schema of a table in place of its current one
+    pub fn alter_schema(
+        &mut self,
+        db_name: &str,
+        table_name: &str,
+        schema: &Schema,
+    ) -> Result<(), String> {
+        let db_catalog = self
+            .databases_catalogs
+            .get_mut(db_name)
+            .ok_or("Database does not exist")?;
+        db_catalog.replace_schema(table_name, schema);
+        Ok(())
+    }
+    /// Removes a table and its files from a database, along with the sequences its SERIAL fields
+    /// own
+    pub fn drop_table(&mut self, db_name: &str, table_name: &str) -> Result<(), String> {
+        let db_catalog = self
+            .databases_catalogs
+            .get_mut(db_name)
+            .ok_or("Database does not exist")?;
+        let schema = db_catalog.drop_table(table_name, &self.storage_mgr);
+        for sequence in schema.owned_sequences() {
+            db_catalog.drop_sequence(sequence);
+        }
+        Ok(())
+    }
+    /// Records a new sequence of a database
+    pub fn create_sequence(
+        &mut self,
+        db_name: &str,
+        name: &str,
+        start: i64,
+        increment: i64,
+    ) -> Result<(), String> {
+        self.databases_catalogs
+            .get_mut(db_name)
+            .ok_or("Database does not exist")?
+            .create_sequence(name, start, increment)
+    }
+    /// Removes a sequence of a database, unless a field takes its default from it
+    pub fn drop_sequence(&mut self, db_name: &str, name: &str) -> Result<(), String> {
+        let db_catalog = self
+            .databases_catalogs
+            .get_mut(db_name)
+            .ok_or("Database does not exist")?;
+        if let Some((table, field)) = db_catalog.sequence_users(name).into_iter().next() {
+            return Err(format!("Sequence {} is used by {}.{}", name, table, field));
+        }
+        match db_catalog.drop_sequence(name) {
+            true => Ok(()),
+            false => Err(format!("Sequence {} does not exist", name)),
+        }
+    }
+    /// Whether a database has a sequence by that name
+    pub fn has_sequence(&self, db_name: &str, name: &str) -> bool {
+        self.databases_catalogs
+            .get(db_name)
+            .is_some_and(|db_catalog| db_catalog.sequence_row(name).is_some())
+    }
+    /// Hands out the next value of a sequence of a database, see `InstanceCatalog::next_value`
+    pub fn next_value(&mut self, db_name: &str, name: &str) -> Result<i64, String> {
+        self.databases_catalogs
+            .get_mut(db_name)
+            .ok_or("Database does not exist")?
+            .next_value(name)
+    }
+    /// The fields of the tables of a database taking their default from a sequence, as
+    /// (table, field)
+    pub fn sequence_users(&self, db_name: &str, sequence: &str) -> Vec<(String, String)> {
+        match self.databases_catalogs.get(db_name) {
+            Some(db_catalog) => db_catalog.sequence_users(sequence),
+            None => vec![],
+        }
+    }
+    /// Empties a table by replacing its files with new ones, the table is returned as the catalog
+    /// describes it afterwards
+    pub fn truncate_table(
+        &mut self,
+        db_name: &str,
+        table_name: &str,
+    ) -> Result<TableManager, String> {
+        let db_catalog = self
+            .databases_catalogs
+            .get_mut(db_name)
+            .ok_or("Database does not exist")?;
+        let schema = db_catalog.drop_table(table_name, &self.storage_mgr);
+        db_catalog.add_schema(&schema, self.storage_mgr.clone())
     }
-    fn load_dbs_table(storage: &Rc<RefCell<StorageManager>>) -> TableManager {
+    /// The table an index of a database is built on
+    pub fn index_table(&self, db_name: &str, index_name: &str) -> Option<String> {
+        self.databases_catalogs
+            .get(db_name)?
+            .index_table(index_name)
+    }
+    /// Records a new index on some fields of a table and sets up its empty structures, the info the table needs to
+    /// load and fill it is returned
+    pub fn add_index(
+        &mut self,
+        db_name: &str,
+        table_name: &str,
+        index_name: &str,
+        fieldnames: &[String],
+        index_type: IndexType,
+    ) -> Result<IndexInfo, String> {
+        let db_catalog = self
+            .databases_catalogs
+            .get_mut(db_name)
+            .ok_or("Database does not exist")?;
+        let mut schema = db_catalog.get_schema(table_name);
+        schema.add_index(index_name, fieldnames, index_type);
+        let index = schema.indexes().last().unwrap();
+        Index::init_index(index.to_index_info(db_name), self.storage_mgr.clone());
+        let info = index.to_index_info(db_name);
+        db_catalog.replace_schema(table_name, &schema);
+        Ok(info)
+    }
+    /// Removes an index of a table from the catalog and deletes its files, its info is returned
+    pub fn drop_index(
+        &mut self,
+        db_name: &str,
+        table_name: &str,
+        index_name: &str,
+    ) -> Result<IndexInfo, String> {
+        let db_catalog = self
+            .databases_catalogs
+            .get_mut(db_name)
+            .ok_or("Database does not exist")?;
+        let mut schema = db_catalog.get_schema(table_name);
+        let info = schema
+            .drop_index(index_name)
+            .ok_or(format!("Index {} does not exist", index_name))?
+            .to_index_info(db_name);
+        db_catalog.replace_schema(table_name, &schema);
+        InstanceCatalog::remove_index_files(&info, &mut self.storage_mgr.write().unwrap());
+        Ok(info)
+    }
+    /// Points the foreign references to a renamed table, or to a renamed field of it, at their new
+    /// names in every schema of a database
+    pub fn rename_references(
+        &mut self,
+        db_name: &str,
+        table_name: &str,
+        new_name: &str,
+        field: Option<(&str, &str)>,
+    ) {
+        if let Some(db_catalog) = self.databases_catalogs.get_mut(db_name) {
+            for name in db_catalog.table_names() {
+                let mut schema = db_catalog.get_schema(&name);
+                if schema.rename_references(table_name, new_name, field) {
+                    db_catalog.replace_schema(&name, &schema);
+                }
+            }
+        }
+    }
+    /// The fields of the tables of a database referencing a field of a table, as (table, field)
+    pub fn referencing_fields(
+        &self,
+        db_name: &str,
+        table_name: &str,
+        field: &str,
+    ) -> Vec<(String, String)> {
+        let db_catalog = match self.databases_catalogs.get(db_name) {
+            Some(db_catalog) => db_catalog,
+            None => return vec![],
+        };
+        db_catalog
+            .table_names()
+            .into_iter()
+            .flat_map(|name| {
+                db_catalog
+                    .get_schema(&name)
+                    .referencing_fields(table_name, field)
+                    .into_iter()
+                    .map(|f| (name.clone(), f.to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+    /// The foreign keys of every table of a database
+    pub fn foreign_keys(&self, db_name: &str) -> Vec<ForeignKey> {
+        let db_catalog = match self.databases_catalogs.get(db_name) {
+            Some(db_catalog) => db_catalog,
+            None => return vec![],
+        };
+        db_catalog
+            .table_names()
+            .into_iter()
+            .flat_map(|name| db_catalog.get_schema(&name).foreign_keys())
+            .collect()
+    }
+    /// Loads a table of a database from its files as the catalog describes it
+    pub fn load_table(&self, db_name: &str, table_name: &str) -> Option<TableManager> {
+        self.databases_catalogs
+            .get(db_name)?
+            .load_table(table_name, &self.storage_mgr)
+    }
+    fn load_dbs_table(storage: &Arc<RwLock<StorageManager>>) -> TableManager {
         let database_tbl_file = Path::new(AQUADIR().as_str())
             .join("global")
             .join("aqua_database");
@@ -242,7 +752,7 @@ impl CatalogManager {
         )
     }
     fn load_db_tables_files_table(
-        storage: &Rc<RefCell<StorageManager>>,
+        storage: &Arc<RwLock<StorageManager>>,
         db_name: &str,
     ) -> TableManager {
         let schema_name = format!("{}_{}", db_name, "tables_files");
@@ -262,7 +772,7 @@ impl CatalogManager {
             freemap_file,
         )
     }
-    fn load_db_schema_table(storage: &Rc<RefCell<StorageManager>>, db_name: &str) -> TableManager {
+    fn load_db_schema_table(storage: &Arc<RwLock<StorageManager>>, db_name: &str) -> TableManager {
         let schema_name = format!("{}_{}", db_name, "schemas");
         let db_schema_file = Path::new(AQUADIR().as_str())
             .join("base")
@@ -280,7 +790,7 @@ impl CatalogManager {
             freemap_file,
         )
     }
-    fn load_db_indexes_table(storage: &Rc<RefCell<StorageManager>>, db_name: &str) -> TableManager {
+    fn load_db_indexes_table(storage: &Arc<RwLock<StorageManager>>, db_name: &str) -> TableManager {
         let schema_name = format!("{}_{}", db_name, "indexes");
         let db_schema_file = Path::new(AQUADIR().as_str())
             .join("base")
@@ -298,20 +808,42 @@ impl CatalogManager {
             freemap_file,
         )
     }
-    fn init_dbs_table(storage: Rc<RefCell<StorageManager>>) -> TableManager {
+    fn load_db_sequences_table(
+        storage: &Arc<RwLock<StorageManager>>,
+        db_name: &str,
+    ) -> TableManager {
+        let schema_name = format!("{}_{}", db_name, "sequences");
+        let db_schema_file = Path::new(AQUADIR().as_str())
+            .join("base")
+            .join(db_name)
+            .join(schema_name.as_str());
+        let freemap_file = Path::new(AQUADIR().as_str())
+            .join("base")
+            .join(db_name)
+            .join(format!("{}_freemap", schema_name));
+        TableManager::from_file(
+            storage.clone(),
+            db_schema_file,
+            Self::db_sequences_layout(schema_name.as_str()),
+            vec![],
+            freemap_file,
+        )
+    }
+    fn init_dbs_table(storage: Arc<RwLock<StorageManager>>) -> TableManager {
         let layout = Self::dbs_table_layout();
         let path = Path::new(AQUADIR().as_str())
             .join("global")
             .join("aqua_database");
         let blks = storage
-            .borrow_mut()
+            .write()
+            .unwrap()
             .empty_heap_pages(path.to_str().unwrap(), 1);
         let freemap_file = Path::new(AQUADIR().as_str())
             .join("global")
             .join("aqua_freemap");
         let mut freemap = FreeMap::init(
             freemap_file,
-            HeapPage::default_free_space(storage.borrow().blk_size()) as u16,
+            HeapPage::default_free_space(storage.read().unwrap().blk_size()) as u16,
             &blks[0],
         );
         TableManager::new(blks, storage, freemap, layout, vec![])
@@ -331,6 +863,7 @@ impl CatalogManager {
         let schemas = self.create_db_schema_table(db_name);
         let indexes = self.create_db_indexes_table(db_name);
         let tables_filepaths = self.create_db_tables_files(db_name);
+        let sequences = self.create_db_sequences_table(db_name);
         self.databases_catalogs.insert(
             db_name.to_string(),
             InstanceCatalog {
@@ -338,10 +871,45 @@ impl CatalogManager {
                 schemas,
                 indexes,
                 tables_filepaths,
+                sequences,
+                counters: HashMap::new(),
             },
         );
         Ok(())
     }
+    /// Removes a database and every file under its directory, a database a session is connected to
+    /// cannot be dropped
+    pub fn drop_database(&mut self, db_name: &str) -> Result<(), String> {
+        if !self.databases_catalogs.contains_key(db_name) {
+            return Err(format!("Database {} does not exist", db_name));
+        }
+        if let Some(tables) = self.open_databases.get(db_name) {
+            // the catalog keeps the tables of a database once they are loaded, every session
+            // connected to it holds them as well
+            if Arc::strong_count(tables) > 1 {
+                return Err(format!("Database {} is in use", db_name));
+            }
+        }
+        self.open_databases.remove(db_name);
+        self.transaction_managers.remove(db_name);
+        self.databases_catalogs.remove(db_name);
+        let mut rids = vec![];
+        let mut dbs_iter = self.databases_tbl.heapscan_iter(None);
+        while let Some(row) = dbs_iter.next() {
+            if row.get("database_name").unwrap().as_deref() == Some(db_name.as_bytes()) {
+                rids.push(dbs_iter.current_rid().unwrap());
+            }
+        }
+        self.databases_tbl.delete_tuples(&rids);
+        self.databases_tbl.flush_all();
+        let path = Path::new(AQUADIR().as_str()).join("base").join(db_name);
+        let mut storage = self.storage_mgr.write().unwrap();
+        for entry in fs::read_dir(&path).unwrap().flatten() {
+            storage.remove_file(entry.path().to_str().unwrap());
+        }
+        fs::remove_dir_all(path).unwrap();
+        Ok(())
+    }
     fn create_db_indexes_table(&mut self, db_name: &str) -> TableManager {
         let layout = Self::db_indexes_layout(db_name);
         let schema_name = format!("{}_{}", db_name, "indexes");
@@ -351,7 +919,8 @@ impl CatalogManager {
             .join(schema_name.clone());
         let blks = self
             .storage_mgr
-            .borrow_mut()
+            .write()
+            .unwrap()
             .empty_heap_pages(path.to_str().unwrap(), 1);
         let freemap_file = Path::new(AQUADIR().as_str())
             .join("base")
@@ -359,10 +928,10 @@ impl CatalogManager {
             .join(format!("{}_freemap", schema_name));
         let mut freemap = FreeMap::init(
             freemap_file,
-            HeapPage::default_free_space(self.storage_mgr.borrow().blk_size()) as u16,
+            HeapPage::default_free_space(self.storage_mgr.read().unwrap().blk_size()) as u16,
             &blks[0],
         );
-        // freemap.add_blockspace(HeapPage::default_free_space(self.storage_mgr.borrow().blk_size()) as u16,&blks[0]);
+        // freemap.add_blockspace(HeapPage::default_free_space(self.storage_mgr.read().unwrap().blk_size()) as u16,&blks[0]);
         TableManager::new(blks, self.storage_mgr.clone(), freemap, layout, vec![])
         // TableManager::new(blks, self.storage_mgr.clone(), None, layout, vec![])
     }
@@ -375,7 +944,8 @@ impl CatalogManager {
             .join(schema_name.clone());
         let blks = self
             .storage_mgr
-            .borrow_mut()
+            .write()
+            .unwrap()
             .empty_heap_pages(path.to_str().unwrap(), 1);
         let freemap_file = Path::new(AQUADIR().as_str())
             .join("base")
@@ -383,10 +953,10 @@ impl CatalogManager {
             .join(format!("{}_freemap", schema_name));
         let mut freemap = FreeMap::init(
             freemap_file,
-            HeapPage::default_free_space(self.storage_mgr.borrow().blk_size()) as u16,
+            HeapPage::default_free_space(self.storage_mgr.read().unwrap().blk_size()) as u16,
             &blks[0],
         );
-        // freemap.add_blockspace(HeapPage::default_free_space(self.storage_mgr.borrow().blk_size()) as u16,&blks[0]);
+        // freemap.add_blockspace(HeapPage::default_free_space(self.storage_mgr.read().unwrap().blk_size()) as u16,&blks[0]);
         TableManager::new(blks, self.storage_mgr.clone(), freemap, layout, vec![])
     }
     fn create_db_tables_files(&mut self, db_name: &str) -> TableManager {
@@ -398,7 +968,8 @@ impl CatalogManager {
             .join(schema_name.clone());
         let blks = self
             .storage_mgr
-            .borrow_mut()
+            .write()
+            .unwrap()
             .empty_heap_pages(path.to_str().unwrap(), 1);
         let freemap_file = Path::new(AQUADIR().as_str())
             .join("base")
@@ -407,26 +978,49 @@ impl CatalogManager {
 
         let mut freemap = FreeMap::init(
             freemap_file,
-            HeapPage::default_free_space(self.storage_mgr.borrow().blk_size()) as u16,
+            HeapPage::default_free_space(self.storage_mgr.read().unwrap().blk_size()) as u16,
             &blks[0],
         );
-        // freemap.add_blockspace(HeapPage::default_free_space(self.storage_mgr.borrow().blk_size()) as u16,&blks[0]);
+        // freemap.add_blockspace(HeapPage::default_free_space(self.storage_mgr.read().unwrap().blk_size()) as u16,&blks[0]);
         TableManager::new(blks, self.storage_mgr.clone(), freemap, layout, vec![])
     }
-    fn dbs_table_layout() -> Rc<Layout> {
+    fn create_db_sequences_table(&mut self, db_name: &str) -> TableManager {
+        let layout = Self::db_sequences_layout(db_name);
+        let schema_name = format!("{}_{}", db_name, "sequences");
+        let path = Path::new(AQUADIR().as_str())
+            .join("base")
+            .join(db_name)
+            .join(schema_name.clone());
+        let blks = self
+            .storage_mgr
+            .write()
+            .unwrap()
+            .empty_heap_pages(path.to_str().unwrap(), 1);
+        let freemap_file = Path::new(AQUADIR().as_str())
+            .join("base")
+            .join(db_name)
+            .join(format!("{}_freemap", schema_name));
+        let freemap = FreeMap::init(
+            freemap_file,
+            HeapPage::default_free_space(self.storage_mgr.read().unwrap().blk_size()) as u16,
+            &blks[0],
+        );
+        TableManager::new(blks, self.storage_mgr.clone(), freemap, layout, vec![])
+    }
+    fn dbs_table_layout() -> Arc<Layout> {
         let mut schema = Schema::new();
         schema.add_field_default_constraints(
             "database_name",
-            Type::Character(CharType::VarChar),
+            Type::Character(CharType::VarChar(None)),
             None,
         );
-        Rc::new(schema.to_layout())
+        Arc::new(schema.to_layout())
     }
-    fn db_schema_layout(table_name: &str) -> Rc<Layout> {
+    fn db_schema_layout(table_name: &str) -> Arc<Layout> {
         let mut schema = Schema::new();
         schema.add_field(
             "tablename",
-            Type::Character(CharType::VarChar),
+            Type::Character(CharType::VarChar(None)),
             false,
             false,
             None,
@@ -434,7 +1028,15 @@ impl CatalogManager {
         );
         schema.add_field(
             "fieldname",
-            Type::Character(CharType::VarChar),
+            Type::Character(CharType::VarChar(None)),
+            false,
+            false,
+            None,
+            None,
+        );
+        schema.add_field(
+            "position",
+            Type::Numeric(NumericType::SmallInt),
             false,
             false,
             None,
@@ -442,7 +1044,7 @@ impl CatalogManager {
         );
         schema.add_field(
             "fieldtype",
-            Type::Character(CharType::VarChar),
+            Type::Character(CharType::VarChar(None)),
             false,
             false,
             None,
@@ -453,7 +1055,7 @@ impl CatalogManager {
         schema.add_field("unique", Type::Boolean, false, false, None, None);
         schema.add_field(
             "foreign_table",
-            Type::Character(VarChar),
+            Type::Character(VarChar(None)),
             true,
             false,
             Some((table_name.to_string(), "tablename".to_string())),
@@ -461,21 +1063,53 @@ impl CatalogManager {
         );
         schema.add_field(
             "foreign_field",
-            Type::Character(VarChar),
+            Type::Character(VarChar(None)),
             true,
             false,
             Some((table_name.to_string(), "fieldname".to_string())),
             None,
         );
+        schema.add_field(
+            "on_delete",
+            Type::Character(VarChar(None)),
+            true,
+            false,
+            None,
+            None,
+        );
+        schema.add_field(
+            "default_value",
+            Type::Character(VarChar(None)),
+            true,
+            false,
+            None,
+            None,
+        );
+        schema.add_field(
+            "check_expr",
+            Type::Character(VarChar(None)),
+            true,
+            false,
+            None,
+            None,
+        );
+        schema.add_field(
+            "default_sequence",
+            Type::Character(VarChar(None)),
+            true,
+            false,
+            None,
+            None,
+        );
         schema.set_name(table_name);
         schema.set_primary_keys(vec!["fieldname".to_string(), "tablename".to_string()]);
-        Rc::new(schema.to_layout())
+        Arc::new(schema.to_layout())
     }
-    fn db_indexes_layout(tablename: &str) -> Rc<Layout> {
+    fn db_indexes_layout(tablename: &str) -> Arc<Layout> {
         let mut schema = Schema::new();
         schema.add_field(
             "tablename",
-            Type::Character(VarChar),
+            Type::Character(VarChar(None)),
             false,
             false,
             None,
@@ -483,7 +1117,7 @@ impl CatalogManager {
         );
         schema.add_field(
             "index_name",
-            Type::Character(VarChar),
+            Type::Character(VarChar(None)),
             false,
             false,
             None,
@@ -491,7 +1125,7 @@ impl CatalogManager {
         );
         schema.add_field(
             "fieldname",
-            Type::Character(VarChar),
+            Type::Character(VarChar(None)),
             false,
             false,
             None,
@@ -499,7 +1133,7 @@ impl CatalogManager {
         );
         schema.add_field(
             "directory_file",
-            Type::Character(VarChar),
+            Type::Character(VarChar(None)),
             false,
             false,
             None,
@@ -507,7 +1141,7 @@ impl CatalogManager {
         );
         schema.add_field(
             "index_file",
-            Type::Character(VarChar),
+            Type::Character(VarChar(None)),
             false,
             false,
             None,
@@ -515,7 +1149,7 @@ impl CatalogManager {
         );
         schema.add_field(
             "index_type",
-            Type::Character(VarChar),
+            Type::Character(VarChar(None)),
             false,
             false,
             None,
@@ -527,13 +1161,13 @@ impl CatalogManager {
             "index_name".to_string(),
             "fieldname".to_string(),
         ]);
-        Rc::new(schema.to_layout())
+        Arc::new(schema.to_layout())
     }
-    fn db_tables_file_layout(tablename: &str) -> Rc<Layout> {
+    fn db_tables_file_layout(tablename: &str) -> Arc<Layout> {
         let mut schema = Schema::new();
         schema.add_field(
             "tablename",
-            Type::Character(VarChar),
+            Type::Character(VarChar(None)),
             false,
             true,
             None,
@@ -541,13 +1175,50 @@ impl CatalogManager {
         );
         schema.add_field(
             "filepath",
-            Type::Character(VarChar),
+            Type::Character(VarChar(None)),
             false,
             true,
             None,
             None,
         );
-        schema.add_field("freemap", Type::Character(VarChar), false, true, None, None);
-        Rc::new(schema.to_layout())
+        schema.add_field(
+            "freemap",
+            Type::Character(VarChar(None)),
+            false,
+            true,
+            None,
+            None,
+        );
+        Arc::new(schema.to_layout())
+    }
+    fn db_sequences_layout(tablename: &str) -> Arc<Layout> {
+        let mut schema = Schema::new();
+        schema.add_field(
+            "sequence_name",
+            Type::Character(VarChar(None)),
+            false,
+            false,
+            None,
+            None,
+        );
+        schema.add_field(
+            "increment",
+            Type::Numeric(NumericType::BigInt),
+            false,
+            false,
+            None,
+            None,
+        );
+        schema.add_field(
+            "reserved",
+            Type::Numeric(NumericType::BigInt),
+            false,
+            false,
+            None,
+            None,
+        );
+        schema.set_name(tablename);
+        schema.set_primary_keys(vec!["sequence_name".to_string()]);
+        Arc::new(schema.to_layout())
     }
 }
@@ -1 +1,2 @@
 pub mod catalogmgr;
+pub mod upgrade;
@@ -0,0 +1,230 @@
+use crate::meta::catalogmgr::CatalogManager;
+use crate::schema::schema::{Layout, Schema};
+use crate::schema::types::CharType::VarChar;
+use crate::schema::types::Type;
+use crate::storage::heap::HeapPage;
+use crate::storage::storagemgr::StorageManager;
+use crate::{ArcRwLock, AQUADIR};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// The directory of a data directory the files of format version 1 are moved to while they are
+/// rewritten, it is removed once the upgrade is done
+const STAGING_DIR: &str = "upgrade_v1";
+/// The write-ahead log of the data directory, format version 1 had none
+const LOG_FILE: &str = "aqua_wal";
+
+type Row = Vec<(String, Option<Vec<u8>>)>;
+
+/// Rewrites the data directory in the current format if its files are in format version 1, the
+/// version of the directories written before the files recorded their version.
+///
+/// The files of version 1 are moved aside then the databases, their tables and their rows are
+/// created again from them, the indexes are built again as the rows are inserted. An upgrade cut
+/// short starts over from the moved files the next time it runs
+pub fn upgrade_data_dir() -> Result<(), String> {
+    let home = PathBuf::from(AQUADIR());
+    let staging = home.join(STAGING_DIR);
+    if StorageManager::format_version(&AQUADIR())? != 1 {
+        // the upgrade was done but the files it moved aside were not removed yet
+        if staging.exists() {
+            fs::remove_dir_all(&staging).map_err(|e| e.to_string())?;
+        }
+        return Ok(());
+    }
+    if !staging.exists() && !home.join("global").join("aqua_database").exists() {
+        return Err(format!(
+            "The data directory {} holds no databases, it has to be initialized with `init`",
+            AQUADIR()
+        ));
+    }
+    fs::create_dir_all(&staging).map_err(|e| e.to_string())?;
+    for dir in ["base", "global"] {
+        if staging.join(dir).exists() {
+            // moved aside by an upgrade cut short, what it wrote in its place is dropped
+            if home.join(dir).exists() {
+                fs::remove_dir_all(home.join(dir)).map_err(|e| e.to_string())?;
+            }
+        } else {
+            fs::rename(home.join(dir), staging.join(dir)).map_err(|e| e.to_string())?;
+        }
+    }
+    if home.join(LOG_FILE).exists() {
+        fs::remove_file(home.join(LOG_FILE)).map_err(|e| e.to_string())?;
+    }
+    fs::create_dir_all(home.join("base").join("tmp")).map_err(|e| e.to_string())?;
+    fs::create_dir_all(home.join("global")).map_err(|e| e.to_string())?;
+
+    CatalogManager::create_catalogs();
+    let storage = ArcRwLock!(StorageManager::new(&AQUADIR(), 4096, 100));
+    let mut catalog = CatalogManager::startup(storage.clone());
+    let mut databases = vec![];
+    scan_v1(
+        &storage,
+        staging.join("global").join("aqua_database"),
+        &v1_databases_layout(),
+        |row| databases.push(text(&row, "database_name")),
+    );
+    for db in databases {
+        upgrade_database(&storage, &mut catalog, &staging, &db)?;
+    }
+    storage.write().unwrap().flush_all();
+    drop(catalog);
+    drop(storage);
+    StorageManager::write_format_version(&AQUADIR());
+    fs::remove_dir_all(&staging).map_err(|e| e.to_string())
+}
+
+/// Creates a database of a data directory in format version 1 again, along with its tables and
+/// their rows
+fn upgrade_database(
+    storage: &Arc<RwLock<StorageManager>>,
+    catalog: &mut CatalogManager,
+    staging: &Path,
+    db: &str,
+) -> Result<(), String> {
+    catalog.create_database(db)?;
+    let db_dir = staging.join("base").join(db);
+    let mut fields = vec![];
+    scan_v1(
+        storage,
+        db_dir.join(format!("{}_schemas", db)),
+        &v1_schemas_layout(),
+        |row| fields.push(row),
+    );
+    let mut indexes = vec![];
+    scan_v1(
+        storage,
+        db_dir.join(format!("{}_indexes", db)),
+        &v1_indexes_layout(),
+        |row| indexes.push(row),
+    );
+    let mut tables = vec![];
+    scan_v1(
+        storage,
+        db_dir.join(format!("{}_tables_files", db)),
+        &v1_tables_files_layout(),
+        |row| tables.push((text(&row, "tablename"), text(&row, "filepath"))),
+    );
+    for (table_name, heap_file) in tables {
+        // the fields of a table were recorded in the order of its schema
+        let table_fields = fields
+            .iter()
+            .filter(|row| text(row, "tablename") == table_name)
+            .enumerate()
+            .map(|(position, row)| v1_field(row, position as i16))
+            .collect::<Vec<_>>();
+        let table_indexes = indexes
+            .iter()
+            .filter(|row| text(row, "tablename") == table_name)
+            .map(|row| row.iter().cloned().collect())
+            .collect();
+        let schema = Schema::deserialize(table_fields, table_indexes);
+        // the rows of the table are laid out as the current format lays out the types version 1 had
+        let v1_layout = Arc::new(schema.to_layout());
+        let mut table = catalog.add_schema(db, &schema)?;
+        scan_v1(storage, db_dir.join(heap_file), &v1_layout, |row| {
+            table.try_insert_tuple(row);
+        });
+        table.flush_all();
+    }
+    Ok(())
+}
+
+/// The catalog row of a field of format version 1 as the current catalog records it, the fields
+/// added to the catalog since are not set
+fn v1_field(row: &Row, position: i16) -> HashMap<String, Option<Vec<u8>>> {
+    let mut field = row.iter().cloned().collect::<HashMap<_, _>>();
+    // CHAR had no length and was stored as VARCHAR is
+    if text(row, "fieldtype") == "char" {
+        field.insert("fieldtype".to_string(), Some(b"varchar".to_vec()));
+    }
+    field.insert(
+        "position".to_string(),
+        Some(position.to_ne_bytes().to_vec()),
+    );
+    for added in [
+        "on_delete",
+        "default_value",
+        "check_expr",
+        "default_sequence",
+    ] {
+        field.insert(added.to_string(), None);
+    }
+    field
+}
+
+/// Calls a function with the rows of a heap file of format version 1 in the order they are stored
+fn scan_v1(
+    storage: &Arc<RwLock<StorageManager>>,
+    path: PathBuf,
+    layout: &Arc<Layout>,
+    mut f: impl FnMut(Row),
+) {
+    let blks = storage.read().unwrap().file_blks(path);
+    for blk in blks {
+        let frame = storage.write().unwrap().pin(blk.clone()).unwrap();
+        let page = HeapPage::new(frame.clone(), &blk, layout.clone());
+        for slot in 0..page.pointer_count() {
+            if page.pointer_and_tuple_exist(slot) == (true, true) {
+                f(page.v1_tuple(slot));
+            }
+        }
+        storage.write().unwrap().unpin(frame);
+    }
+}
+
+/// The text of a field of a catalog row
+fn text(row: &Row, field: &str) -> String {
+    row.iter()
+        .find(|(name, _)| name == field)
+        .and_then(|(_, data)| data.as_ref())
+        .map(|data| String::from_utf8_lossy(data).to_string())
+        .unwrap_or_default()
+}
+
+fn v1_layout(fields: &[(&str, Type)]) -> Arc<Layout> {
+    let mut schema = Schema::new();
+    for (name, field_type) in fields {
+        schema.add_field_default_constraints(name, *field_type, None);
+    }
+    Arc::new(schema.to_layout())
+}
+
+fn v1_databases_layout() -> Arc<Layout> {
+    v1_layout(&[("database_name", Type::Character(VarChar(None)))])
+}
+
+fn v1_schemas_layout() -> Arc<Layout> {
+    v1_layout(&[
+        ("tablename", Type::Character(VarChar(None))),
+        ("fieldname", Type::Character(VarChar(None))),
+        ("fieldtype", Type::Character(VarChar(None))),
+        ("pkey_piece", Type::Boolean),
+        ("nullable", Type::Boolean),
+        ("unique", Type::Boolean),
+        ("foreign_table", Type::Character(VarChar(None))),
+        ("foreign_field", Type::Character(VarChar(None))),
+    ])
+}
+
+fn v1_indexes_layout() -> Arc<Layout> {
+    v1_layout(&[
+        ("tablename", Type::Character(VarChar(None))),
+        ("index_name", Type::Character(VarChar(None))),
+        ("fieldname", Type::Character(VarChar(None))),
+        ("directory_file", Type::Character(VarChar(None))),
+        ("index_file", Type::Character(VarChar(None))),
+        ("index_type", Type::Character(VarChar(None))),
+    ])
+}
+
+fn v1_tables_files_layout() -> Arc<Layout> {
+    v1_layout(&[
+        ("tablename", Type::Character(VarChar(None))),
+        ("filepath", Type::Character(VarChar(None))),
+        ("freemap", Type::Character(VarChar(None))),
+    ])
+}
@@ -57,6 +57,28 @@ impl LogicalNode {
         matches!(self, Self::Empty | Self::Relation(_))
     }
 
+    /// Rough size of the node output in blocks, the blocks of all the tables below it
+    pub fn estimated_blocks(&self, planner_info: &PlannerInfo) -> usize {
+        match self {
+            LogicalNode::Project(a) => a.child.estimated_blocks(planner_info),
+            LogicalNode::Select(a) => a.child.estimated_blocks(planner_info),
+            LogicalNode::Cross(a) => {
+                a.left.estimated_blocks(planner_info) + a.right.estimated_blocks(planner_info)
+            }
+            LogicalNode::Join(a) => {
+                a.left.estimated_blocks(planner_info) + a.right.estimated_blocks(planner_info)
+            }
+            LogicalNode::Relation(a) => planner_info
+                .table_info
+                .get(&a.name)
+                .map_or(0, |info| info.blocks()),
+            LogicalNode::Sort(a) => a.child.estimated_blocks(planner_info),
+            LogicalNode::DeDup(a) => a.child.estimated_blocks(planner_info),
+            LogicalNode::GroupBy(a) => a.child.estimated_blocks(planner_info),
+            LogicalNode::Empty => 0,
+        }
+    }
+
     pub fn get_fields_map(&self) -> HashMap<FieldId, Type> {
         match self {
             LogicalNode::Project(a) => a.fields_map.clone(),
@@ -362,44 +384,34 @@ impl LogicalNode {
         // dbg!(&queue);
         Ok(Self::link_nodes(&mut queue))
     }
+    /// Builds a left deep join tree, `a JOIN b .. JOIN c ..` becomes `(a JOIN b) JOIN c`
+    ///
+    /// The left input of every join is the tree of the tables before it and the right input is
+    /// the joined table, which keeps the sides of LEFT and RIGHT joins as written.
+    /// Nodes are pushed so that `Join::chain` pops the left input first.
     fn preprocess_joins(
-        mut joins: JoinClause,
+        joins: JoinClause,
         joined: &Option<HashMap<String, &HashMap<String, Type>>>,
     ) -> Vec<LogicalNode> {
+        let relation = |table: &str| {
+            LogicalNode::Relation(BaseRelation::new(
+                table,
+                PlannerInfo::qualify_table_map(table, joined.as_ref().unwrap().get(table).unwrap()),
+            ))
+        };
         let mut result = vec![];
-        let first =
-            sql::query::select::Join::new(joins.first.clone(), joins.joins[0].join_type, None);
-        joins.joins.insert(0, first);
-        for i in 0..joins.joins.len() - 1 {
-            joins.joins[i].join_condition = joins.joins[i + 1].join_condition.take();
-            joins.joins[i].join_type = joins.joins[i + 1].join_type;
-        }
-        let last_join = joins.joins.pop().unwrap();
-        'pushing_order: for j in joins.joins {
-            let mut condition = j
-                .join_condition
-                .map(|jc| build_operator_tree(jc.as_str()).unwrap());
-            if let Some(condition) = condition.as_mut() {
-                Self::qualify_attributes(condition, &None, joined);
-            }
-            let node = Join::with_condition(j.join_type, condition.unwrap());
+        for j in joins.joins.into_iter().rev() {
+            // a join without ON pairs every row of both inputs
+            let mut condition = match j.join_condition {
+                Some(jc) => build_operator_tree(jc.as_str()).unwrap(),
+                None => build_operator_tree("true").unwrap(),
+            };
+            Self::qualify_attributes(&mut condition, &None, joined);
+            let node = Join::with_condition(j.join_type, condition);
             result.push(LogicalNode::Join(node));
-            result.push(LogicalNode::Relation(BaseRelation::new(
-                &j.table,
-                PlannerInfo::qualify_table_map(
-                    &j.table,
-                    joined.as_ref().unwrap().get(&j.table).unwrap(),
-                ),
-            )));
+            result.push(relation(&j.table));
         }
-        let last = LogicalNode::Relation(BaseRelation::new(
-            &last_join.table,
-            PlannerInfo::qualify_table_map(
-                &last_join.table,
-                joined.as_ref().unwrap().get(&last_join.table).unwrap(),
-            ),
-        ));
-        result.push(last);
+        result.push(relation(&joins.first));
         result
     }
 
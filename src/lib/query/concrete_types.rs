@@ -1,15 +1,18 @@
 use crate::common::numerical::{ByteMagic, MultiFieldCmp};
+use crate::common::temporal::{self, MICROS_PER_DAY};
+use crate::common::{binary, decimal};
 use crate::query::concrete_types::ConcreteType::{
-    BigInt, Boolean, Char, Double, Integer, Serial, Single, SmallInt, VarChar,
+    BigInt, Boolean, Bytes, Char, Date, Decimal, Double, Integer, Interval, Serial, Single,
+    SmallInt, Time, Timestamp, VarChar,
 };
-use crate::schema::types::{CharType, NumericType, Type as SchemaType};
+use crate::schema::types::{CharType, NumericType, TemporalType, Type as SchemaType};
 use bincode::{Decode, Encode};
 use num_order::NumOrd;
 use std::cmp::Ordering;
 use std::cmp::Ordering::{Equal, Greater, Less};
 use std::fmt::{write, Display, Formatter};
-use std::io::{Bytes, Read};
-use std::ops::{AddAssign, Div};
+use std::io::Read;
+use std::ops::Div;
 
 #[derive(Debug, Clone, Encode, Decode)]
 pub enum ConcreteType {
@@ -22,13 +25,30 @@ pub enum ConcreteType {
     VarChar(String),
     Char(String),
     Boolean(bool),
+    /// The digits of an exact number without its point, and the count of them after it
+    Decimal(i128, u8),
+    /// Days since 1970-01-01
+    Date(i32),
+    /// Microseconds since midnight
+    Time(i64),
+    /// Microseconds since the epoch
+    Timestamp(i64),
+    /// Microseconds
+    Interval(i64),
+    Bytes(Vec<u8>),
     NULL,
 }
 impl ConcreteType {
     pub fn is_numeric(&self) -> bool {
         matches!(
             self,
-            SmallInt(_) | Integer(_) | BigInt(_) | Single(_) | Double(_) | Serial(_)
+            SmallInt(_)
+                | Integer(_)
+                | BigInt(_)
+                | Single(_)
+                | Double(_)
+                | Serial(_)
+                | Decimal(_, _)
         )
     }
     pub fn is_text(&self) -> bool {
@@ -39,6 +59,57 @@ impl ConcreteType {
         matches!(self, Self::NULL)
     }
 
+    /// The digits and the scale of an exact number, integers have a scale of 0
+    fn as_decimal(&self) -> Option<(i128, u8)> {
+        match self {
+            SmallInt(x) => Some((*x as i128, 0)),
+            Integer(x) | Serial(x) => Some((*x as i128, 0)),
+            BigInt(x) => Some((*x as i128, 0)),
+            Decimal(x, scale) => Some((*x, *scale)),
+            _ => None,
+        }
+    }
+
+    /// The closest float to a number
+    fn as_f64(&self) -> f64 {
+        match self {
+            Single(x) => *x as f64,
+            Double(x) => *x,
+            _ => {
+                let (digits, scale) = self.as_decimal().unwrap();
+                decimal::to_f64(digits, scale)
+            }
+        }
+    }
+
+    /// Compares a decimal with another number, exactly against integers and decimals and as
+    /// floats when either side is a float. None when a float is NaN
+    fn mixed_decimal_cmp(&self, other: &ConcreteType) -> Option<Ordering> {
+        match (self.as_decimal(), other.as_decimal()) {
+            (Some((l, l_scale)), Some((r, r_scale))) => Some(decimal::cmp(l, l_scale, r, r_scale)),
+            _ => self.as_f64().partial_cmp(&other.as_f64()),
+        }
+    }
+
+    /// The microseconds of a temporal value, dates count from midnight
+    fn micros(&self) -> i64 {
+        match self {
+            Date(days) => *days as i64 * MICROS_PER_DAY,
+            Time(micros) | Timestamp(micros) | Interval(micros) => *micros,
+            _ => unreachable!(),
+        }
+    }
+
+    fn temporal_type(&self) -> TemporalType {
+        match self {
+            Date(_) => TemporalType::Date,
+            Time(_) => TemporalType::Time,
+            Timestamp(_) => TemporalType::Timestamp,
+            Interval(_) => TemporalType::Interval,
+            _ => unreachable!(),
+        }
+    }
+
     pub fn from_bytes(datatype: SchemaType, bytes: &[u8]) -> Self {
         if bytes.is_empty() {
             return Self::NULL;
@@ -51,14 +122,22 @@ impl ConcreteType {
                 NumericType::Single => Single(bytes.to_f32()),
                 NumericType::Double => Double(bytes.to_f64()),
                 NumericType::Serial => Serial(bytes.to_i32()),
+                NumericType::Decimal(_, scale) => Decimal(bytes.to_i128(), scale),
             },
             SchemaType::Character(char) => match char {
-                CharType::Char => ConcreteType::Char(String::from_utf8_lossy(bytes).to_string()),
-                CharType::VarChar => {
+                CharType::Char(_) => ConcreteType::Char(String::from_utf8_lossy(bytes).to_string()),
+                CharType::VarChar(_) => {
                     ConcreteType::VarChar(String::from_utf8_lossy(bytes).to_string())
                 }
             },
             SchemaType::Boolean => Boolean(bytes[0] == 1),
+            SchemaType::Temporal(temporal) => match temporal {
+                TemporalType::Date => Date(bytes.to_i32()),
+                TemporalType::Time => Time(bytes.to_i64()),
+                TemporalType::Timestamp => Timestamp(bytes.to_i64()),
+                TemporalType::Interval => Interval(bytes.to_i64()),
+            },
+            SchemaType::Binary => Bytes(bytes.to_vec()),
         }
     }
     pub fn to_bytes(self) -> Option<Vec<u8>> {
@@ -80,6 +159,7 @@ impl From<ConcreteType> for Vec<u8> {
             Single(x) => x.to_ne_bytes().to_vec(),
             Double(x) => x.to_ne_bytes().to_vec(),
             Serial(x) => x.to_ne_bytes().to_vec(),
+            Decimal(x, _) => x.to_ne_bytes().to_vec(),
             VarChar(x) => x.as_bytes().to_vec(),
             Char(x) => x.as_bytes().to_vec(),
             Boolean(x) => {
@@ -89,6 +169,9 @@ impl From<ConcreteType> for Vec<u8> {
                     vec![0]
                 }
             }
+            Date(x) => x.to_ne_bytes().to_vec(),
+            Time(x) | Timestamp(x) | Interval(x) => x.to_ne_bytes().to_vec(),
+            Bytes(x) => x,
             ConcreteType::NULL => vec![],
         }
     }
@@ -103,9 +186,14 @@ impl From<ConcreteType> for String {
             Single(x) => format!("{x}"),
             Double(x) => format!("{x}"),
             Serial(x) => format!("{x}"),
+            Decimal(x, scale) => decimal::format(x, scale),
             ConcreteType::VarChar(sth) => format!("{sth}"),
             ConcreteType::Char(sth) => format!("{sth}"),
             Boolean(b) => format!("{b}"),
+            Date(_) | Time(_) | Timestamp(_) | Interval(_) => {
+                temporal::format(value.temporal_type(), value.micros())
+            }
+            Bytes(x) => binary::format(&x),
             ConcreteType::NULL => format!(""),
         }
     }
@@ -120,9 +208,18 @@ impl Display for ConcreteType {
             Single(x) => write!(f, "{x}"),
             Double(x) => write!(f, "{x}"),
             Serial(x) => write!(f, "{x}"),
+            Decimal(x, scale) => write!(f, "{}", decimal::format(*x, *scale)),
             ConcreteType::VarChar(sth) => write!(f, "{sth}"),
             ConcreteType::Char(sth) => write!(f, "{sth}"),
             Boolean(b) => write!(f, "{b}"),
+            Date(_) | Time(_) | Timestamp(_) | Interval(_) => {
+                write!(
+                    f,
+                    "{}",
+                    temporal::format(self.temporal_type(), self.micros())
+                )
+            }
+            Bytes(x) => write!(f, "{}", binary::format(x)),
             ConcreteType::NULL => write!(f, ""),
         }
     }
@@ -144,8 +241,9 @@ impl PartialEq<Self> for ConcreteType {
                 Single(num2) => NumOrd::num_eq(num1, num2),
                 Double(num2) => NumOrd::num_eq(num1, num2),
                 Serial(num2) => NumOrd::num_eq(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other) == Some(Equal),
                 ConcreteType::NULL => false,
+                _ => unreachable!(),
             },
             Integer(num1) => match other {
                 SmallInt(num2) => NumOrd::num_eq(num1, num2),
@@ -154,8 +252,9 @@ impl PartialEq<Self> for ConcreteType {
                 Single(num2) => NumOrd::num_eq(num1, num2),
                 Double(num2) => NumOrd::num_eq(num1, num2),
                 Serial(num2) => NumOrd::num_eq(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other) == Some(Equal),
                 ConcreteType::NULL => false,
+                _ => unreachable!(),
             },
             BigInt(num1) => match other {
                 SmallInt(num2) => NumOrd::num_eq(num1, num2),
@@ -164,8 +263,9 @@ impl PartialEq<Self> for ConcreteType {
                 Single(num2) => NumOrd::num_eq(num1, num2),
                 Double(num2) => NumOrd::num_eq(num1, num2),
                 Serial(num2) => NumOrd::num_eq(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other) == Some(Equal),
                 ConcreteType::NULL => false,
+                _ => unreachable!(),
             },
             Single(num1) => match other {
                 SmallInt(num2) => NumOrd::num_eq(num1, num2),
@@ -174,8 +274,9 @@ impl PartialEq<Self> for ConcreteType {
                 Single(num2) => NumOrd::num_eq(num1, num2),
                 Double(num2) => NumOrd::num_eq(num1, num2),
                 Serial(num2) => NumOrd::num_eq(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other) == Some(Equal),
                 ConcreteType::NULL => false,
+                _ => unreachable!(),
             },
             Double(num1) => match other {
                 SmallInt(num2) => NumOrd::num_eq(num1, num2),
@@ -184,8 +285,9 @@ impl PartialEq<Self> for ConcreteType {
                 Single(num2) => NumOrd::num_eq(num1, num2),
                 Double(num2) => NumOrd::num_eq(num1, num2),
                 Serial(num2) => NumOrd::num_eq(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other) == Some(Equal),
                 ConcreteType::NULL => false,
+                _ => unreachable!(),
             },
             Serial(num1) => match other {
                 SmallInt(num2) => NumOrd::num_eq(num1, num2),
@@ -194,8 +296,15 @@ impl PartialEq<Self> for ConcreteType {
                 Single(num2) => NumOrd::num_eq(num1, num2),
                 Double(num2) => NumOrd::num_eq(num1, num2),
                 Serial(num2) => NumOrd::num_eq(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other) == Some(Equal),
                 ConcreteType::NULL => false,
+                _ => unreachable!(),
+            },
+            Decimal(num1, scale1) => match other {
+                Decimal(num2, scale2) => decimal::cmp(*num1, *scale1, *num2, *scale2) == Equal,
+                ConcreteType::NULL => false,
+                _ if other.is_numeric() => self.mixed_decimal_cmp(other) == Some(Equal),
+                _ => unreachable!(),
             },
             VarChar(char1) | Char(char1) => match other {
                 VarChar(char2) | Char(char2) => char1.eq(char2),
@@ -208,6 +317,15 @@ impl PartialEq<Self> for ConcreteType {
                 ConcreteType::NULL => false,
                 _ => unreachable!(),
             },
+            Date(_) | Time(_) | Timestamp(_) | Interval(_) => match other {
+                ConcreteType::NULL => false,
+                _ => self.micros() == other.micros(),
+            },
+            Bytes(b1) => match other {
+                Bytes(b2) => b1 == b2,
+                ConcreteType::NULL => false,
+                _ => unreachable!(),
+            },
         }
     }
 }
@@ -224,8 +342,9 @@ impl PartialOrd for ConcreteType {
                 Single(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Double(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_partial_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other),
                 ConcreteType::NULL => Some(Less),
+                _ => unreachable!(),
             },
             Integer(num1) => match other {
                 SmallInt(num2) => NumOrd::num_partial_cmp(num1, num2),
@@ -234,8 +353,9 @@ impl PartialOrd for ConcreteType {
                 Single(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Double(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_partial_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other),
                 ConcreteType::NULL => Some(Less),
+                _ => unreachable!(),
             },
             BigInt(num1) => match other {
                 SmallInt(num2) => NumOrd::num_partial_cmp(num1, num2),
@@ -244,8 +364,9 @@ impl PartialOrd for ConcreteType {
                 Single(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Double(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_partial_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other),
                 ConcreteType::NULL => Some(Less),
+                _ => unreachable!(),
             },
             Single(num1) => match other {
                 SmallInt(num2) => NumOrd::num_partial_cmp(num1, num2),
@@ -254,8 +375,9 @@ impl PartialOrd for ConcreteType {
                 Single(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Double(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_partial_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other),
                 ConcreteType::NULL => Some(Less),
+                _ => unreachable!(),
             },
             Double(num1) => match other {
                 SmallInt(num2) => NumOrd::num_partial_cmp(num1, num2),
@@ -264,8 +386,9 @@ impl PartialOrd for ConcreteType {
                 Single(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Double(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_partial_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other),
                 ConcreteType::NULL => Some(Less),
+                _ => unreachable!(),
             },
             Serial(num1) => match other {
                 SmallInt(num2) => NumOrd::num_partial_cmp(num1, num2),
@@ -274,8 +397,15 @@ impl PartialOrd for ConcreteType {
                 Single(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Double(num2) => NumOrd::num_partial_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_partial_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other),
+                ConcreteType::NULL => Some(Less),
+                _ => unreachable!(),
+            },
+            Decimal(num1, scale1) => match other {
+                Decimal(num2, scale2) => Some(decimal::cmp(*num1, *scale1, *num2, *scale2)),
                 ConcreteType::NULL => Some(Less),
+                _ if other.is_numeric() => self.mixed_decimal_cmp(other),
+                _ => unreachable!(),
             },
             VarChar(char1) | Char(char1) => match other {
                 VarChar(char2) | Char(char2) => char1.partial_cmp(char2),
@@ -294,6 +424,15 @@ impl PartialOrd for ConcreteType {
                 ConcreteType::NULL => Some(Less),
                 _ => unreachable!(),
             },
+            Date(_) | Time(_) | Timestamp(_) | Interval(_) => match other {
+                ConcreteType::NULL => Some(Less),
+                _ => self.micros().partial_cmp(&other.micros()),
+            },
+            Bytes(b1) => match other {
+                Bytes(b2) => b1.partial_cmp(b2),
+                ConcreteType::NULL => Some(Less),
+                _ => unreachable!(),
+            },
         }
     }
 }
@@ -308,8 +447,9 @@ impl Ord for ConcreteType {
                 Single(num2) => NumOrd::num_cmp(num1, num2),
                 Double(num2) => NumOrd::num_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other).unwrap_or(Equal),
                 ConcreteType::NULL => Less,
+                _ => unreachable!(),
             },
             Integer(num1) => match other {
                 SmallInt(num2) => NumOrd::num_cmp(num1, num2),
@@ -318,8 +458,9 @@ impl Ord for ConcreteType {
                 Single(num2) => NumOrd::num_cmp(num1, num2),
                 Double(num2) => NumOrd::num_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other).unwrap_or(Equal),
                 ConcreteType::NULL => Less,
+                _ => unreachable!(),
             },
             BigInt(num1) => match other {
                 SmallInt(num2) => NumOrd::num_cmp(num1, num2),
@@ -328,8 +469,9 @@ impl Ord for ConcreteType {
                 Single(num2) => NumOrd::num_cmp(num1, num2),
                 Double(num2) => NumOrd::num_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other).unwrap_or(Equal),
                 ConcreteType::NULL => Less,
+                _ => unreachable!(),
             },
             Single(num1) => match other {
                 SmallInt(num2) => NumOrd::num_cmp(num1, num2),
@@ -338,8 +480,9 @@ impl Ord for ConcreteType {
                 Single(num2) => NumOrd::num_cmp(num1, num2),
                 Double(num2) => NumOrd::num_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other).unwrap_or(Equal),
                 ConcreteType::NULL => Less,
+                _ => unreachable!(),
             },
             Double(num1) => match other {
                 SmallInt(num2) => NumOrd::num_cmp(num1, num2),
@@ -348,8 +491,9 @@ impl Ord for ConcreteType {
                 Single(num2) => NumOrd::num_cmp(num1, num2),
                 Double(num2) => NumOrd::num_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other).unwrap_or(Equal),
                 ConcreteType::NULL => Less,
+                _ => unreachable!(),
             },
             Serial(num1) => match other {
                 SmallInt(num2) => NumOrd::num_cmp(num1, num2),
@@ -358,8 +502,15 @@ impl Ord for ConcreteType {
                 Single(num2) => NumOrd::num_cmp(num1, num2),
                 Double(num2) => NumOrd::num_cmp(num1, num2),
                 Serial(num2) => NumOrd::num_cmp(num1, num2),
-                ConcreteType::VarChar(_) | ConcreteType::Char(_) | Boolean(_) => unreachable!(),
+                Decimal(_, _) => self.mixed_decimal_cmp(other).unwrap_or(Equal),
                 ConcreteType::NULL => Less,
+                _ => unreachable!(),
+            },
+            Decimal(num1, scale1) => match other {
+                Decimal(num2, scale2) => decimal::cmp(*num1, *scale1, *num2, *scale2),
+                ConcreteType::NULL => Less,
+                _ if other.is_numeric() => self.mixed_decimal_cmp(other).unwrap_or(Equal),
+                _ => unreachable!(),
             },
             VarChar(char1) | Char(char1) => match other {
                 VarChar(char2) | Char(char2) => char1.cmp(char2),
@@ -378,6 +529,15 @@ impl Ord for ConcreteType {
                 ConcreteType::NULL => Less,
                 _ => unreachable!(),
             },
+            Date(_) | Time(_) | Timestamp(_) | Interval(_) => match other {
+                ConcreteType::NULL => Less,
+                _ => self.micros().cmp(&other.micros()),
+            },
+            Bytes(b1) => match other {
+                Bytes(b2) => b1.cmp(b2),
+                ConcreteType::NULL => Less,
+                _ => unreachable!(),
+            },
         }
     }
 }
@@ -398,31 +558,40 @@ impl MultiFieldCmp for &[ConcreteType] {
     }
 }
 
-impl std::ops::Add for ConcreteType {
-    type Output = ConcreteType;
-
-    fn add(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (SmallInt(l), SmallInt(r)) => BigInt(l as i64 + r as i64),
-            (Integer(l), Integer(r)) => BigInt(l as i64 + r as i64),
-            (BigInt(l), BigInt(r)) => BigInt(l + r),
-            (BigInt(l), SmallInt(r)) => BigInt(l + r as i64),
-            (BigInt(l), Integer(r)) => BigInt(l + r as i64),
-            (SmallInt(l), BigInt(r)) => BigInt(l as i64 + r),
-            (Integer(l), BigInt(r)) => BigInt(l as i64 + r),
-            (Single(l), Single(r)) => Double(l as f64 + r as f64),
-            (Double(l), Double(r)) => Double(l + r),
-            (Double(l), Single(r)) => Double(l + r as f64),
-            (Single(l), Double(r)) => Double(l as f64 + r),
-            _ => unreachable!(),
-        }
+impl ConcreteType {
+    /// The sum of two values, SUM and AVG add up their values with it. Integers add up to a
+    /// BigInt, a float makes the sum a Double and a decimal keeps the larger scale.
+    ///
+    /// Fails when the sum is too large for its type
+    pub fn checked_add(self, rhs: Self) -> Result<ConcreteType, String> {
+        let sum = match (&self, &rhs) {
+            (Interval(l), Interval(r)) => l.checked_add(*r).map(Interval),
+            (Single(_) | Double(_), _) | (_, Single(_) | Double(_)) => {
+                Some(Double(self.as_f64() + rhs.as_f64()))
+            }
+            (Decimal(_, _), _) | (_, Decimal(_, _)) => {
+                let ((l, l_scale), (r, r_scale)) = decimal_operands(&self, &rhs);
+                let scale = l_scale.max(r_scale);
+                decimal::rescale(l, l_scale, scale)
+                    .zip(decimal::rescale(r, r_scale, scale))
+                    .and_then(|(l, r)| l.checked_add(r))
+                    .filter(|sum| decimal::fits(*sum, decimal::MAX_PRECISION))
+                    .map(|sum| Decimal(sum, scale))
+            }
+            _ => {
+                let ((l, _), (r, _)) = decimal_operands(&self, &rhs);
+                i64::try_from(l + r).ok().map(BigInt)
+            }
+        };
+        sum.ok_or_else(|| format!("{} + {} is out of range", self, rhs))
     }
 }
 
-impl AddAssign for ConcreteType {
-    fn add_assign(&mut self, rhs: Self) {
-        let value = self.clone() + rhs;
-        *self = value
+/// The digits and scales of the operands of an arithmetic operation on exact numbers
+fn decimal_operands(l: &ConcreteType, r: &ConcreteType) -> ((i128, u8), (i128, u8)) {
+    match (l.as_decimal(), r.as_decimal()) {
+        (Some(l), Some(r)) => (l, r),
+        _ => unreachable!(),
     }
 }
 
@@ -456,7 +625,13 @@ impl Div for ConcreteType {
             (Double(l), BigInt(r)) => Double(l as f64 / r as f64),
             (Double(l), Single(r)) => Double(l as f64 / r as f64),
             (Double(l), Double(r)) => Double(l as f64 / r as f64),
-            _ => unreachable!(),
+            (l, r) => {
+                let ((l, l_scale), (r, r_scale)) = decimal_operands(&l, &r);
+                match decimal::divide(l, l_scale, r, r_scale) {
+                    Some((quotient, scale)) => Decimal(quotient, scale),
+                    None => ConcreteType::NULL,
+                }
+            }
         }
     }
 }
@@ -485,4 +660,46 @@ mod tests {
         rows.sort_by(|a, b| a.as_slice().multi_cmp(b, &desc));
         assert_eq!(rows, vec![second, first])
     }
+
+    #[test]
+    fn test_decimal_arithmetic() {
+        use ConcreteType::{BigInt, Decimal, SmallInt};
+        let mut sum = Decimal(0, 2);
+        for _ in 0..10 {
+            sum = sum.checked_add(Decimal(10, 2)).unwrap();
+        }
+        assert_eq!(sum.to_string(), "1.00");
+        assert_eq!((sum.clone() / BigInt(3)).to_string(), "0.333333");
+        assert_eq!((Decimal(-5, 1) / BigInt(3)).to_string(), "-0.16667");
+        let sum = Decimal(5, 0).checked_add(Decimal(-1999, 3)).unwrap();
+        assert_eq!(sum.to_string(), "3.001");
+        assert_eq!(
+            BigInt(2).checked_add(Decimal(5, 1)).unwrap().to_string(),
+            "2.5"
+        );
+        let largest = Decimal(10_i128.pow(38) - 1, 2);
+        assert!(largest.clone().checked_add(Decimal(1, 2)).is_err());
+        assert!(largest.checked_add(Decimal(1, 3)).is_err());
+        assert!(BigInt(i64::MAX).checked_add(SmallInt(1)).is_err());
+        assert_eq!(Decimal(100, 2), Decimal(1, 0));
+        assert!(Decimal(-5, 1) < Decimal(3, 2));
+        assert!(Decimal(i128::MAX, 0) > Decimal(99, 38));
+        assert_eq!(sum / BigInt(0), ConcreteType::NULL);
+    }
+
+    #[test]
+    fn test_decimal_compares_with_other_numbers() {
+        use std::cmp::Ordering::{Equal, Greater, Less};
+        use ConcreteType::{BigInt, Decimal, Double, Integer, Single, SmallInt};
+        assert_eq!(Decimal(700, 2), Integer(7));
+        assert_eq!(SmallInt(-3), Decimal(-30, 1));
+        assert_ne!(Decimal(701, 2), BigInt(7));
+        assert!(Decimal(i128::MAX, 0) > BigInt(i64::MAX));
+        assert!(Integer(2) < Decimal(201, 2));
+        assert_eq!(Decimal(25, 1), Double(2.5));
+        assert_eq!(Single(0.5).partial_cmp(&Decimal(5, 1)), Some(Equal));
+        assert_eq!(Decimal(1, 2).cmp(&Double(0.02)), Less);
+        assert_eq!(Double(-1.0).cmp(&Decimal(-15, 1)), Greater);
+        assert_eq!(Decimal(1, 0).partial_cmp(&Double(f64::NAN)), None);
+    }
 }
@@ -1,27 +1,56 @@
 use super::seq_scan::SeqScan;
 use crate::common::numerical::ByteMagic;
+use crate::database::lockmgr::{LockMode, Lockable};
+use crate::database::transaction::Transaction;
+use crate::database::txnmgr::Snapshot;
+use crate::index::Rid;
+use crate::query::physical::utils::data_to_value;
+use crate::query::physical::PhysicalNode;
 use crate::query::tuple_table::TupleTable;
-use crate::schema::schema::Schema;
+use crate::schema::schema::{ForeignKey, Schema};
 use crate::schema::types::{NumericType, Type};
+use crate::sql::create_table::OnDelete;
 use crate::table::tablemgr::TableManager;
 use evalexpr::{ContextWithMutableVariables, FloatType, HashMapContext, IntType, Node, Value};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::File;
+use std::sync::Arc;
 
 type TupleField = Option<Vec<u8>>;
 type Record = Vec<(String, Option<Vec<u8>>)>;
+type Row = HashMap<String, Option<Vec<u8>>>;
 
+/// Executes the statements changing the tables of a database, every change is recorded in the
+/// transaction it is part of.
+///
+/// A statement locks the table it changes for changes before reading it, and every row it changes
+/// before changing it, it stops at the first lock held by another transaction.
+///
+/// The foreign keys of every table of the database are given to it, deleting or updating a row
+/// reaches the rows referencing it through them
 pub struct Executor<'db> {
     db_tables: &'db mut HashMap<String, TableManager>,
+    transaction: &'db mut Transaction,
+    foreign_keys: Vec<ForeignKey>,
 }
 
 impl<'db> Executor<'db> {
-    pub fn new(db_tables: &'db mut HashMap<String, TableManager>) -> Self {
-        Self { db_tables }
+    pub fn new(
+        db_tables: &'db mut HashMap<String, TableManager>,
+        transaction: &'db mut Transaction,
+        foreign_keys: Vec<ForeignKey>,
+    ) -> Self {
+        Self {
+            db_tables,
+            transaction,
+            foreign_keys,
+        }
     }
     pub fn insert_record(&mut self, record: Record, schema: Schema) -> Result<(), String> {
+        self.lock_table(schema.name())?;
         let target_table = self.db_tables.get(schema.name()).ok_or(String::default())?;
+        let snapshot = self.transaction.dirty_snapshot();
         // let available_indexes = target_table.indexes();
         let fields = schema.fields_info();
 
@@ -41,7 +70,7 @@ impl<'db> Executor<'db> {
         unique_record_fields.retain(|k, _| unique_field_names.contains(&k.as_str()));
 
         if need_heapscan {
-            let mut target_tbl_iter = target_table.heapscan_iter();
+            let mut target_tbl_iter = target_table.heapscan_iter(Some(snapshot.clone()));
             for rec in target_tbl_iter {
                 for (name, data) in &unique_record_fields {
                     if rec.get(name).unwrap() == data {
@@ -53,7 +82,11 @@ impl<'db> Executor<'db> {
             let mut dup_val = String::new();
             let duplicate_by_hash = unique_fields
                 .clone()
-                .filter_map(|(f, _)| target_table.hashscan_iter(f).map(|idx| (f, idx)))
+                .filter_map(|(f, _)| {
+                    target_table
+                        .hashscan_iter(f, Some(snapshot.clone()))
+                        .map(|idx| (f, idx))
+                })
                 .any(|(name, mut hash)| {
                     dup_val = name.to_string();
                     hash.load_key(unique_record_fields.get(*name).unwrap().as_ref().unwrap());
@@ -65,7 +98,7 @@ impl<'db> Executor<'db> {
             let duplicate_by_tree = unique_fields
                 .filter_map(|(f, _)| {
                     target_table
-                        .btree_iter(f, evalexpr::Operator::Eq)
+                        .btree_iter(f, evalexpr::Operator::Eq, Some(snapshot.clone()))
                         .map(|idx| (f, idx))
                 })
                 .any(|(name, mut tree)| {
@@ -78,65 +111,12 @@ impl<'db> Executor<'db> {
             }
         }
 
-        let foreign_referring = fields
-            .iter()
-            .filter(|(_, v)| v.foreign_reference().is_some())
-            .collect::<HashMap<_, _>>();
-
-        let mut foreign_record_fields = record
-            .clone()
-            .into_iter()
-            .map(|(name, data)| (name, data))
-            .collect::<HashMap<_, _>>();
-        foreign_record_fields.retain(|k, _| foreign_referring.contains_key(&k.as_str()));
-        let mut ok_insert = true;
-        let mut foreign_not_found = (String::new(), String::new());
-        for (name, field) in foreign_referring {
-            if !ok_insert {
-                break;
-            }
-            let (ref_table, ref_col) = field.foreign_reference().as_ref().unwrap();
-            let ref_table_mgr = self.db_tables.get(ref_table).unwrap();
-            if let Some(mut hash) = ref_table_mgr.hashscan_iter(ref_col) {
-                hash.load_key(foreign_record_fields.get(*name).unwrap().as_ref().unwrap());
-                ok_insert = hash.next().is_some();
-                if ok_insert {
-                    continue;
-                } else {
-                    foreign_not_found = (ref_table.clone(), ref_col.clone());
-                }
-            } else if let Some(mut btree) =
-                ref_table_mgr.btree_iter(ref_col, evalexpr::Operator::Eq)
-            {
-                btree.load_key(foreign_record_fields.get(*name).unwrap().as_ref().unwrap());
-                ok_insert = btree.next().is_some();
-                if ok_insert {
-                    continue;
-                } else {
-                    foreign_not_found = (ref_table.clone(), ref_col.clone());
-                }
-            } else {
-                let mut heap_iter = ref_table_mgr.heapscan_iter();
-                for row in heap_iter {
-                    if row.get(*name).unwrap() == foreign_record_fields.get(*name).unwrap() {
-                        ok_insert = true;
-                        continue;
-                    }
-                }
-                ok_insert = false;
-                foreign_not_found = (ref_table.clone(), ref_col.clone());
-            }
-        }
-        if ok_insert {
-            let target_table = self.db_tables.get_mut(schema.name()).unwrap();
-            target_table.try_insert_tuple(record);
-            Ok(())
-        } else {
-            Err(format!(
-                "Foreign Reference Not Found : ({}.{})",
-                foreign_not_found.0, foreign_not_found.1
-            ))
-        }
+        schema.check_row(&record.iter().cloned().collect())?;
+        self.check_references(&schema, record.iter())?;
+        let target_table = self.db_tables.get_mut(schema.name()).unwrap();
+        let rid = target_table.insert_version(record, self.transaction.id());
+        self.transaction.inserted(schema.name(), rid.clone());
+        self.lock_row(schema.name(), rid)
 
         // let mut need_fullscan = false;
         // for (k, v) in &fields {
@@ -212,6 +192,333 @@ impl<'db> Executor<'db> {
         // }
     }
 
+    /// Applies the SET values of an update to every tuple returned by the scan and returns the
+    /// number of updated tuples.
+    ///
+    /// All matches are collected before any tuple is touched, so a new version created by the update
+    /// is never visited. Since every matched tuple gets the same values, a unique field can only be
+    /// set if a single tuple matches and no other tuple holds the new value. A key referenced by a
+    /// foreign key cannot be changed while rows reference it
+    pub fn update_records(
+        &mut self,
+        mut scan: PhysicalNode,
+        changes: Record,
+        schema: Schema,
+    ) -> Result<usize, String> {
+        self.lock_table(schema.name())?;
+        let mut matches: Vec<(Rid, Row)> = vec![];
+        while let Some(row) = scan.next() {
+            let rid = scan.current_rid().ok_or("Update Error")?;
+            let row = row.into_iter().map(|(f, v)| (f.field, v)).collect();
+            matches.push((rid, row));
+        }
+        if let Some(error) = scan.error() {
+            return Err(error);
+        }
+        drop(scan);
+        self.lock_rows(schema.name(), &matches)?;
+
+        let target_table = self.db_tables.get(schema.name()).ok_or(String::default())?;
+        let snapshot = self.transaction.dirty_snapshot();
+        let fields = schema.fields_info();
+        for (name, data) in &changes {
+            if !fields.get(name.as_str()).unwrap().unique() {
+                continue;
+            }
+            if let Some(data) = data {
+                let duplicate = match matches.as_slice() {
+                    [] => false,
+                    [(rid, _)] => Self::value_taken(target_table, name, data, rid, &snapshot),
+                    _ => true,
+                };
+                if duplicate {
+                    return Err(format!("Duplicate Value : {}", name));
+                }
+            }
+        }
+        for (_, row) in &matches {
+            let mut row = row.clone();
+            row.extend(changes.iter().cloned());
+            schema.check_row(&row)?;
+        }
+        if !matches.is_empty() {
+            self.check_references(&schema, changes.iter())?;
+        }
+
+        let updated = matches.len();
+        self.atomically(|executor| executor.update_rows(schema.name(), matches, &changes))?;
+        Ok(updated)
+    }
+
+    /// Deletes every tuple returned by the scan and returns the number of deleted tuples
+    ///
+    /// The tuples are only marked deleted by the transaction, the snapshots of the other
+    /// transactions keep seeing them until it commits. The rows referencing a deleted tuple through
+    /// a foreign key are handled as its ON DELETE action says
+    pub fn delete_records(
+        &mut self,
+        mut scan: PhysicalNode,
+        schema: Schema,
+    ) -> Result<usize, String> {
+        self.lock_table(schema.name())?;
+        let mut matches: Vec<(Rid, Row)> = vec![];
+        while let Some(row) = scan.next() {
+            let rid = scan.current_rid().ok_or("Delete Error")?;
+            let row = row.into_iter().map(|(f, v)| (f.field, v)).collect();
+            matches.push((rid, row));
+        }
+        if let Some(error) = scan.error() {
+            return Err(error);
+        }
+        drop(scan);
+        let deleted = matches.len();
+        self.atomically(|executor| executor.delete_rows(schema.name(), matches))?;
+        Ok(deleted)
+    }
+
+    /// Runs the changes of a statement, they are all reverted when one of them fails so the
+    /// statement is never left half done
+    fn atomically(
+        &mut self,
+        changes: impl FnOnce(&mut Self) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let savepoint = self.transaction.savepoint();
+        let result = changes(self);
+        if result.is_err() {
+            self.transaction.rollback_to(savepoint, self.db_tables);
+        }
+        result
+    }
+
+    /// Writes a new version of every row with some of its values changed, then applies the foreign
+    /// keys referencing the keys the old versions held
+    ///
+    /// The rows are expected to be locked already
+    fn update_rows(
+        &mut self,
+        table: &str,
+        rows: Vec<(Rid, Row)>,
+        changes: &Record,
+    ) -> Result<(), String> {
+        let xid = self.transaction.id();
+        let mut old_rows = Vec::with_capacity(rows.len());
+        for (rid, row) in rows {
+            let mut new_row = row.clone();
+            for (name, data) in changes {
+                new_row.insert(name.clone(), data.clone());
+            }
+            let target_table = self.db_tables.get_mut(table).unwrap();
+            let new_rid = target_table.update_version(&rid, new_row.into_iter().collect(), xid);
+            self.transaction.updated(table, rid, new_rid.clone());
+            self.lock_row(table, new_rid)?;
+            old_rows.push(row);
+        }
+        self.release_keys(table, &old_rows, Some(changes))
+    }
+
+    /// Locks rows and deletes them, then applies the foreign keys referencing the keys they held
+    fn delete_rows(&mut self, table: &str, rows: Vec<(Rid, Row)>) -> Result<(), String> {
+        self.lock_rows(table, &rows)?;
+        let xid = self.transaction.id();
+        let target_table = self.db_tables.get_mut(table).ok_or(String::default())?;
+        for (rid, _) in &rows {
+            target_table.delete_version(rid, xid);
+        }
+        let mut old_rows = Vec::with_capacity(rows.len());
+        for (rid, row) in rows {
+            self.transaction.deleted(table, rid);
+            old_rows.push(row);
+        }
+        self.release_keys(table, &old_rows, None)
+    }
+
+    /// Applies the foreign keys referencing a table to the keys some of its rows held before they
+    /// were deleted, or before the changes of an update were applied to them.
+    ///
+    /// A key still held by another row of the table stays referenced. The rows referencing a key
+    /// that is gone make an update fail, a delete fails too or deletes them or sets their foreign
+    /// key to NULL as the action of the foreign key says
+    fn release_keys(
+        &mut self,
+        table: &str,
+        rows: &[Row],
+        changes: Option<&Record>,
+    ) -> Result<(), String> {
+        let referencing = self
+            .foreign_keys
+            .iter()
+            .filter(|fk| fk.ref_table == table)
+            .filter(|fk| changes.is_none_or(|c| c.iter().any(|(f, _)| *f == fk.ref_field)))
+            .cloned()
+            .collect::<Vec<_>>();
+        let snapshot = self.transaction.dirty_snapshot();
+        for fk in referencing {
+            let keys = rows
+                .iter()
+                .filter_map(|row| row.get(&fk.ref_field).cloned().flatten())
+                .collect::<HashSet<_>>();
+            for key in keys {
+                let held = Self::find_rows(&self.db_tables[table], &fk.ref_field, &key, &snapshot);
+                if !held.is_empty() {
+                    continue;
+                }
+                self.lock_table(&fk.table)?;
+                let children =
+                    Self::find_rows(&self.db_tables[&fk.table], &fk.field, &key, &snapshot);
+                if children.is_empty() {
+                    continue;
+                }
+                match (changes, fk.on_delete) {
+                    (None, OnDelete::Cascade) => self.delete_rows(&fk.table, children)?,
+                    (None, OnDelete::SetNull) => {
+                        self.lock_rows(&fk.table, &children)?;
+                        let changes = vec![(fk.field.clone(), None)];
+                        self.update_rows(&fk.table, children, &changes)?
+                    }
+                    _ => {
+                        return Err(format!(
+                            "{}.{} is still referenced by {}.{}",
+                            table, fk.ref_field, fk.table, fk.field
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails unless a row of the referenced table holds each foreign key of some values, NULL
+    /// references nothing.
+    ///
+    /// The referenced rows are locked shared until the transaction ends, so no other transaction
+    /// deletes them or changes their key while they are referenced
+    fn check_references<'r>(
+        &mut self,
+        schema: &Schema,
+        values: impl Iterator<Item = &'r (String, Option<Vec<u8>>)>,
+    ) -> Result<(), String> {
+        let fields = schema.fields_info();
+        let snapshot = self.transaction.dirty_snapshot();
+        for (name, value) in values {
+            let (ref_table, ref_col) = match fields.get(name.as_str()) {
+                Some(field) => match field.foreign_reference() {
+                    Some(reference) => reference,
+                    None => continue,
+                },
+                None => continue,
+            };
+            let key = match value {
+                Some(key) => key,
+                None => continue,
+            };
+            let parents = self
+                .db_tables
+                .get(ref_table)
+                .map(|table| Self::find_rows(table, ref_col, key, &snapshot))
+                .unwrap_or_default();
+            if parents.is_empty() {
+                return Err(format!(
+                    "Foreign Reference Not Found : ({}.{})",
+                    ref_table, ref_col
+                ));
+            }
+            for (rid, _) in &parents {
+                self.transaction.lock(
+                    Lockable::Row(ref_table.to_string(), rid.clone()),
+                    LockMode::Shared,
+                )?;
+            }
+            self.check_concurrent_changes(ref_table, parents.iter().map(|(rid, _)| rid))?;
+        }
+        Ok(())
+    }
+
+    /// Fails if a tuple the statement is about to change was deleted or updated by another
+    /// transaction, which committed since the row locks of the statement are granted.
+    ///
+    /// The change is not in the snapshot of the transaction, changing the tuple would overwrite it
+    /// unseen
+    fn check_concurrent_changes<'r>(
+        &self,
+        table: &str,
+        mut rids: impl Iterator<Item = &'r Rid>,
+    ) -> Result<(), String> {
+        let target_table = self.db_tables.get(table).ok_or(String::default())?;
+        let xid = self.transaction.id();
+        let changed = rids.any(|rid| {
+            let (_, xmax) = target_table.get_xids(rid);
+            xmax != 0 && xmax != xid
+        });
+        if changed {
+            Err("could not serialize access due to a concurrent update".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn lock_table(&mut self, table: &str) -> Result<(), String> {
+        self.transaction.lock(
+            Lockable::Table(table.to_string()),
+            LockMode::IntentionExclusive,
+        )
+    }
+    fn lock_row(&mut self, table: &str, rid: Rid) -> Result<(), String> {
+        self.transaction
+            .lock(Lockable::Row(table.to_string(), rid), LockMode::Exclusive)
+    }
+    /// Locks the rows a statement changes, then checks no other transaction changed them unseen
+    fn lock_rows(&mut self, table: &str, rows: &[(Rid, Row)]) -> Result<(), String> {
+        for (rid, _) in rows {
+            self.lock_row(table, rid.clone())?;
+        }
+        self.check_concurrent_changes(table, rows.iter().map(|(rid, _)| rid))
+    }
+
+    /// Checks whether a tuple other than `except` holds a value for a field
+    fn value_taken(
+        table: &TableManager,
+        field: &str,
+        value: &[u8],
+        except: &Rid,
+        snapshot: &Arc<Snapshot>,
+    ) -> bool {
+        Self::find_rows(table, field, value, snapshot)
+            .iter()
+            .any(|(rid, _)| rid != except)
+    }
+
+    /// The tuples holding a value for a field, looked up through an index on the field when one
+    /// exists
+    fn find_rows(
+        table: &TableManager,
+        field: &str,
+        value: &[u8],
+        snapshot: &Arc<Snapshot>,
+    ) -> Vec<(Rid, Row)> {
+        let mut found = vec![];
+        if let Some(mut hash) = table.hashscan_iter(field, Some(snapshot.clone())) {
+            hash.load_key(value);
+            while let Some(row) = hash.next() {
+                found.extend(hash.current_rid().map(|rid| (rid, row)));
+            }
+        } else if let Some(mut tree) =
+            table.btree_iter(field, evalexpr::Operator::Eq, Some(snapshot.clone()))
+        {
+            tree.load_key(value);
+            while let Some(row) = tree.next() {
+                found.extend(tree.current_rid().map(|rid| (rid, row)));
+            }
+        } else {
+            let mut heap = table.heapscan_iter(Some(snapshot.clone()));
+            while let Some(row) = heap.next() {
+                if row.get(field).unwrap().as_deref() == Some(value) {
+                    found.extend(heap.current_rid().map(|rid| (rid, row)));
+                }
+            }
+        }
+        found
+    }
+
     // pub fn execute_seqscan(&mut self, node: SeqScan) {
     //     let (table, fields) = (node.table, node.fields);
     //     let tblmgr = self.db_tables.get(&table).unwrap();
@@ -264,47 +571,15 @@ impl<'db> Executor<'db> {
         type_map: &HashMap<String, Type>,
         context: &mut HashMapContext,
         expr: &Node,
-    ) -> bool {
+    ) -> Result<bool, String> {
         for (field_name, value) in tuple {
-            if let Some(value) = value {
-                match type_map.get(field_name).unwrap() {
-                    Type::Numeric(num) => match num {
-                        NumericType::SmallInt => context.set_value(
-                            field_name.to_string(),
-                            Value::Int(value.to_i16() as IntType),
-                        ),
-                        NumericType::Integer => context.set_value(
-                            field_name.to_string(),
-                            Value::Int(value.to_i32() as IntType),
-                        ),
-                        NumericType::BigInt => context.set_value(
-                            field_name.to_string(),
-                            Value::Int(value.to_i64() as IntType),
-                        ),
-                        NumericType::Single => context.set_value(
-                            field_name.to_string(),
-                            Value::Float(value.to_f32() as FloatType),
-                        ),
-                        NumericType::Double => context.set_value(
-                            field_name.to_string(),
-                            Value::Float(value.to_f32() as FloatType),
-                        ),
-                        NumericType::Serial => context.set_value(
-                            field_name.to_string(),
-                            Value::Int(value.to_i32() as IntType),
-                        ),
-                    },
-                    Type::Character(_) => context.set_value(
-                        field_name.to_string(),
-                        Value::String(String::from_utf8(value.to_vec()).unwrap()),
-                    ),
-                    Type::Boolean => {
-                        context.set_value(field_name.to_string(), Value::Boolean(value[0] == 1))
-                    }
-                };
+            if value.is_some() {
+                let value = data_to_value(value.as_ref(), *type_map.get(field_name).unwrap())?;
+                context.set_value(field_name.to_string(), value).unwrap();
             }
         }
-        expr.eval_boolean_with_context(context).unwrap()
+        expr.eval_boolean_with_context(context)
+            .map_err(|e| e.to_string())
     }
 
     // pub fn simulate_join(&self, table1: String, table2: String, join_field: String) {
@@ -335,3 +610,129 @@ impl<'db> Executor<'db> {
     //     let joined_file = fs::write("/home/ahmed/join", result);
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::common::testing::TestSession;
+    use crate::interface::message::Status;
+
+    fn values(values: &[&str]) -> Vec<Vec<String>> {
+        values.iter().map(|value| vec![value.to_string()]).collect()
+    }
+
+    /// Numbers as the sorted rows of a single field
+    fn numbers(numbers: impl Iterator<Item = i32>) -> Vec<Vec<String>> {
+        let mut rows = numbers.map(|n| vec![n.to_string()]).collect::<Vec<_>>();
+        rows.sort();
+        rows
+    }
+
+    // UPDATE writes a new version of the rows it changes, one too large to be rewritten in place
+    // moves. Both indexes of the table find the rows by their new keys only
+    #[test]
+    fn test_update_keeps_indexes_in_sync() {
+        let mut session = TestSession::open();
+        session.status(
+            "create table acc (id int, owner varchar, bal int, \
+             create index hash oidx on (owner), create index btree idx on (id))",
+        );
+        for id in 1..=50 {
+            let insert = format!(
+                "insert into acc (id, owner, bal) values ({id}, \"o{}\", {id})",
+                id % 5
+            );
+            session.status(&insert);
+        }
+        let owner = "an owner name much longer than any name the rows had before";
+        assert!(matches!(
+            session.status(&format!(
+                "update acc set owner = \"{owner}\" where acc.id <= 10"
+            )),
+            Status::RecordsUpdated(10)
+        ));
+        assert!(matches!(
+            session.status("update acc set id = 100 where acc.id == 50"),
+            Status::RecordsUpdated(1)
+        ));
+        let by_owner =
+            |owner: &str| format!("select acc.id from acc where acc.owner == \"{owner}\"");
+        assert_eq!(session.sorted_rows(&by_owner(owner)), numbers(1..=10));
+        assert_eq!(
+            session.sorted_rows(&by_owner("o1")),
+            numbers((11..50).filter(|id| id % 5 == 1))
+        );
+        assert_eq!(
+            session.rows("select acc.owner from acc where acc.id == 3"),
+            values(&[owner])
+        );
+        assert_eq!(
+            session.rows("select acc.bal from acc where acc.id == 50"),
+            values(&[])
+        );
+        assert_eq!(
+            session.rows("select acc.bal from acc where acc.id == 100"),
+            values(&["50"])
+        );
+        assert_eq!(
+            session.sorted_rows("select acc.id from acc"),
+            numbers((1..50).chain([100]))
+        );
+    }
+
+    // A row cannot reference a key no row holds. Deleting the row holding a key deletes the rows
+    // referencing it, sets their reference to NULL or fails, as their foreign key says
+    #[test]
+    fn test_foreign_key_enforcement() {
+        let mut session = TestSession::open();
+        session.status("create table cust (cid int, name varchar)");
+        session.status(
+            "create table orders (oid int, cid int references cust(cid) on delete cascade)",
+        );
+        session.status(
+            "create table ships (sid int, cid int references cust(cid) on delete set null)",
+        );
+        session.status("create table audits (aid int, cid int references cust(cid))");
+        for cid in 1..=3 {
+            session.status(&format!(
+                "insert into cust (cid, name) values ({cid}, \"c{cid}\")"
+            ));
+        }
+        for (oid, cid) in [(10, 1), (11, 1), (20, 2)] {
+            session.status(&format!(
+                "insert into orders (oid, cid) values ({oid}, {cid})"
+            ));
+        }
+        session.status("insert into ships (sid, cid) values (1, 1)");
+        session.status("insert into audits (aid, cid) values (1, 3)");
+        assert!(matches!(
+            session.status("insert into orders (oid, cid) values (99, 9)"),
+            Status::RecordNotInserted(_)
+        ));
+
+        assert!(matches!(
+            session.status("delete from cust where cust.cid == 1"),
+            Status::RecordsDeleted(1)
+        ));
+        assert_eq!(
+            session.sorted_rows("select orders.oid from orders"),
+            values(&["20"])
+        );
+        assert_eq!(
+            session.rows("select ships.sid, ships.cid from ships"),
+            vec![vec!["NULL".to_string(), "1".to_string()]]
+        );
+
+        assert!(matches!(
+            session.status("delete from cust where cust.cid == 3"),
+            Status::Generic(_)
+        ));
+        assert_eq!(
+            session.sorted_rows("select cust.cid from cust"),
+            values(&["2", "3"])
+        );
+        assert_eq!(
+            session.rows("select audits.cid from audits"),
+            values(&["3"])
+        );
+    }
+}
@@ -0,0 +1,483 @@
+use super::{AccessMethod, PhysicalNode, TypeMap};
+use crate::common::boolean::{infix, split_conjunction};
+use crate::database::plan_query::PlannerInfo;
+use crate::index::Rid;
+use crate::query::MergedRow;
+use crate::sql::query::select::JoinType;
+use crate::storage::storagemgr::StorageManager;
+use crate::FieldId;
+use evalexpr::Operator;
+use std::fmt::{Debug, Formatter};
+use std::time::{Duration, Instant};
+
+/// Wraps a node of a plan run by EXPLAIN ANALYZE to record the rows it returned, the time spent
+/// in it and the blocks it pinned.
+///
+/// The time and the pins include the ones of the node's inputs
+pub struct Instrumented {
+    node: Box<PhysicalNode>,
+    rows: usize,
+    elapsed: Duration,
+    pins: u64,
+}
+
+impl Debug for Instrumented {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+impl Instrumented {
+    pub fn get_type_map(&self) -> TypeMap {
+        self.node.get_type_map()
+    }
+    pub(super) fn load_key(&mut self, key: &[u8]) {
+        self.node.load_key(key)
+    }
+    pub(super) fn current_rid(&self) -> Option<Rid> {
+        self.node.current_rid()
+    }
+    pub(super) fn error(&self) -> Option<String> {
+        self.node.error()
+    }
+}
+
+impl Iterator for Instrumented {
+    type Item = MergedRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pins = StorageManager::thread_pins();
+        let start = Instant::now();
+        let next = self.node.next();
+        self.elapsed += start.elapsed();
+        self.pins += StorageManager::thread_pins() - pins;
+        if next.is_some() {
+            self.rows += 1;
+        }
+        next
+    }
+}
+
+impl PhysicalNode {
+    /// Wraps every node of the plan to record what it does while the plan runs
+    pub fn instrument(self) -> Self {
+        let node = match self {
+            PhysicalNode::Project(mut a) => {
+                a.child = Box::new(a.child.instrument());
+                PhysicalNode::Project(a)
+            }
+            PhysicalNode::Select(mut a) => {
+                a.child = Box::new(a.child.instrument());
+                PhysicalNode::Select(a)
+            }
+            PhysicalNode::MergeJoin(mut a) => {
+                a.left = Box::new(a.left.instrument());
+                a.right = Box::new(a.right.instrument());
+                PhysicalNode::MergeJoin(a)
+            }
+            PhysicalNode::HashJoin(mut a) => {
+                a.left = Box::new(a.left.instrument());
+                a.right = Box::new(a.right.instrument());
+                PhysicalNode::HashJoin(a)
+            }
+            PhysicalNode::IndexedLoopJoin(mut a) => {
+                a.left = Box::new(a.left.instrument());
+                a.right = Box::new(a.right.instrument());
+                PhysicalNode::IndexedLoopJoin(a)
+            }
+            PhysicalNode::NestedLoopJoin(mut a) => {
+                a.left = Box::new(a.left.instrument());
+                a.right = Box::new(a.right.instrument());
+                PhysicalNode::NestedLoopJoin(a)
+            }
+            PhysicalNode::RemoveDuplicates(mut a) => {
+                a.child = Box::new(a.child.instrument());
+                PhysicalNode::RemoveDuplicates(a)
+            }
+            PhysicalNode::Sort(mut a) => {
+                a.child = Box::new(a.child.instrument());
+                PhysicalNode::Sort(a)
+            }
+            PhysicalNode::GroupBy(mut a) => {
+                a.child = Box::new(a.child.instrument());
+                PhysicalNode::GroupBy(a)
+            }
+            node @ (PhysicalNode::AccessPath(_) | PhysicalNode::Instrumented(_)) => node,
+        };
+        PhysicalNode::Instrumented(Instrumented {
+            node: Box::new(node),
+            rows: 0,
+            elapsed: Duration::ZERO,
+            pins: 0,
+        })
+    }
+
+    /// The plan as an indented tree, every node is described on its own line followed by the
+    /// details of what it does and by its inputs.
+    ///
+    /// Nodes of an instrumented plan that ran also report their actual rows, time and pins
+    pub fn explain(&self, planner_info: &PlannerInfo) -> Vec<String> {
+        self.explain_node(planner_info).0
+    }
+
+    /// The lines explaining a node and its inputs, with the number of rows it is estimated to
+    /// return
+    fn explain_node(&self, planner_info: &PlannerInfo) -> (Vec<String>, usize) {
+        if let PhysicalNode::Instrumented(a) = self {
+            let (mut lines, rows) = a.node.explain_node(planner_info);
+            lines[0].push_str(&format!(
+                " (actual rows={} time={:.3} ms pins={})",
+                a.rows,
+                a.elapsed.as_secs_f64() * 1e3,
+                a.pins
+            ));
+            return (lines, rows);
+        }
+        let inputs = self
+            .inputs()
+            .into_iter()
+            .map(|input| input.explain_node(planner_info))
+            .collect::<Vec<_>>();
+        let input_rows = inputs.iter().map(|(_, rows)| *rows).collect::<Vec<_>>();
+        let (name, details, rows) = self.describe(planner_info, &input_rows);
+        let mut lines = vec![format!("{name} (rows={rows})")];
+        lines.extend(details.into_iter().map(|detail| format!("  {detail}")));
+        for (input_lines, _) in inputs {
+            for (idx, line) in input_lines.into_iter().enumerate() {
+                let prefix = if idx == 0 { "  -> " } else { "     " };
+                lines.push(format!("{prefix}{line}"));
+            }
+        }
+        (lines, rows)
+    }
+
+    fn inputs(&self) -> Vec<&PhysicalNode> {
+        match self {
+            PhysicalNode::Project(a) => vec![&a.child],
+            PhysicalNode::Select(a) => vec![&a.child],
+            PhysicalNode::MergeJoin(a) => vec![&a.left, &a.right],
+            PhysicalNode::HashJoin(a) => vec![&a.left, &a.right],
+            PhysicalNode::IndexedLoopJoin(a) => vec![&a.left, &a.right],
+            PhysicalNode::NestedLoopJoin(a) => vec![&a.left, &a.right],
+            PhysicalNode::RemoveDuplicates(a) => vec![&a.child],
+            PhysicalNode::Sort(a) => vec![&a.child],
+            PhysicalNode::GroupBy(a) => vec![&a.child],
+            PhysicalNode::Instrumented(a) => vec![&a.node],
+            PhysicalNode::AccessPath(_) => vec![],
+        }
+    }
+
+    /// The name of a node, the lines detailing it and its estimated rows given the ones of its
+    /// inputs
+    fn describe(
+        &self,
+        planner_info: &PlannerInfo,
+        input_rows: &[usize],
+    ) -> (String, Vec<String>, usize) {
+        let input = input_rows.first().copied().unwrap_or_default();
+        match self {
+            PhysicalNode::Project(a) => (
+                format!("Project {}", fields_list(a.fields_map.keys()))
+                    .trim_end()
+                    .to_string(),
+                vec![],
+                input,
+            ),
+            PhysicalNode::Select(a) if a.bridged.1.is_some() => (
+                "Select".to_string(),
+                vec![format!("Index Cond: {}", infix(&a.condition))],
+                input,
+            ),
+            PhysicalNode::Select(a) => (
+                "Select".to_string(),
+                vec![format!("Filter: {}", infix(&a.condition))],
+                scale(input, selectivity(&split_conjunction(&a.condition))),
+            ),
+            PhysicalNode::MergeJoin(a) => {
+                let mut details = vec![format!("Join Key: {}", keys_list(&a.eq_fields))];
+                details.extend(filter_line(a.filter.clauses()));
+                let rows = join_rows(a.join_type, input_rows, true, a.filter.clauses());
+                (
+                    format!("Merge Join {}", join_name(a.join_type)),
+                    details,
+                    rows,
+                )
+            }
+            PhysicalNode::HashJoin(a) => {
+                let build = if a.build_left { "left" } else { "right" };
+                let mut details = vec![
+                    format!("Join Key: {}", keys_list(&a.eq_fields)),
+                    format!("Build: {build}"),
+                ];
+                details.extend(filter_line(a.filter.clauses()));
+                let rows = join_rows(a.join_type, input_rows, true, a.filter.clauses());
+                (
+                    format!("Hash Join {}", join_name(a.join_type)),
+                    details,
+                    rows,
+                )
+            }
+            PhysicalNode::IndexedLoopJoin(a) => {
+                let mut details = vec![format!(
+                    "Join Key: {}",
+                    keys_list(std::slice::from_ref(&a.eq_fields))
+                )];
+                details.extend(filter_line(a.filter.clauses()));
+                // the index scan estimates the rows of a single probe, the join is estimated
+                // from the whole right table
+                let right_rows = table_rows(&a.right, planner_info);
+                let rows = join_rows(a.join_type, &[input, right_rows], true, a.filter.clauses());
+                (
+                    format!("Indexed Nested Loop Join {}", join_name(a.join_type)),
+                    details,
+                    rows,
+                )
+            }
+            PhysicalNode::NestedLoopJoin(a) => {
+                let rows = join_rows(a.join_type, input_rows, false, a.filter.clauses());
+                (
+                    format!("Nested Loop Join {}", join_name(a.join_type)),
+                    filter_line(a.filter.clauses()).into_iter().collect(),
+                    rows,
+                )
+            }
+            PhysicalNode::RemoveDuplicates(_) => ("Remove Duplicates".to_string(), vec![], input),
+            PhysicalNode::Sort(a) => {
+                let keys = a
+                    .fields
+                    .iter()
+                    .zip(a.desc.iter())
+                    .map(|(field, desc)| {
+                        if *desc {
+                            format!("{field} DESC")
+                        } else {
+                            field.to_string()
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                (
+                    "Sort".to_string(),
+                    vec![format!("Sort Key: {}", keys.join(", "))],
+                    input,
+                )
+            }
+            PhysicalNode::GroupBy(a) => {
+                let mut aggregates = a
+                    .agg_ops
+                    .values()
+                    .map(|agg| format!("{}({})", agg.op.to_string(), agg.field))
+                    .collect::<Vec<_>>();
+                aggregates.sort();
+                let mut details = vec![];
+                if !a.group_on.is_empty() {
+                    details.push(format!("Group Key: {}", fields_list(a.group_on.iter())));
+                }
+                if !aggregates.is_empty() {
+                    details.push(format!("Aggregates: {}", aggregates.join(", ")));
+                }
+                // without statistics on the distinct values assume groups of ten rows
+                let rows = if a.group_on.is_empty() {
+                    1
+                } else {
+                    (input / 10).max(1)
+                };
+                ("Group By".to_string(), details, rows)
+            }
+            PhysicalNode::AccessPath(a) => describe_access(a, planner_info),
+            PhysicalNode::Instrumented(a) => a.node.describe(planner_info, input_rows),
+        }
+    }
+}
+
+fn describe_access(
+    access: &AccessMethod,
+    planner_info: &PlannerInfo,
+) -> (String, Vec<String>, usize) {
+    let table = scanned_table(access);
+    let rows = planner_info
+        .table_info
+        .get(table)
+        .map(|info| info.rows())
+        .unwrap_or_default();
+    match access {
+        AccessMethod::HeapIter(..) => (format!("Seq Scan on {table}"), vec![], rows),
+        AccessMethod::HashIter(_, iter) => (
+            format!("Hash Index Scan on {table} using {}", iter.index_field()),
+            vec![],
+            scale(rows, EQ_SELECTIVITY),
+        ),
+        AccessMethod::BtreeIter(_, iter) => {
+            let op = iter.operator();
+            let selectivity = if *op == Operator::Eq {
+                EQ_SELECTIVITY
+            } else {
+                RANGE_SELECTIVITY
+            };
+            (
+                format!(
+                    "Btree Index Scan on {table} using {} ({op})",
+                    iter.index_field()
+                ),
+                vec![],
+                scale(rows, selectivity),
+            )
+        }
+    }
+}
+
+/// Selectivity assumed for an equality, no statistics are kept on the values of a field
+const EQ_SELECTIVITY: f64 = 0.1;
+/// Selectivity assumed for a range comparison
+const RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+/// Selectivity assumed for any other condition
+const DEFAULT_SELECTIVITY: f64 = 0.5;
+
+/// Estimated fraction of the rows satisfying all the clauses
+fn selectivity(clauses: &[evalexpr::Node]) -> f64 {
+    clauses.iter().map(clause_selectivity).product()
+}
+
+fn clause_selectivity(clause: &evalexpr::Node) -> f64 {
+    let children = clause.children();
+    match clause.operator() {
+        Operator::RootNode | Operator::And => selectivity(children),
+        Operator::Or => children
+            .iter()
+            .map(clause_selectivity)
+            .sum::<f64>()
+            .min(1.0),
+        Operator::Not if children.len() == 1 => 1.0 - clause_selectivity(&children[0]),
+        Operator::Eq => EQ_SELECTIVITY,
+        Operator::Neq => 1.0 - EQ_SELECTIVITY,
+        Operator::Lt | Operator::Gt | Operator::Leq | Operator::Geq => RANGE_SELECTIVITY,
+        Operator::Const { value } if value.as_boolean() == Ok(true) => 1.0,
+        _ => DEFAULT_SELECTIVITY,
+    }
+}
+
+fn scale(rows: usize, selectivity: f64) -> usize {
+    match rows {
+        0 => 0,
+        rows => ((rows as f64 * selectivity).round() as usize).max(1),
+    }
+}
+
+/// Estimated rows of a join given the rows of its inputs.
+///
+/// Joins on keys are assumed to match every row of the smaller input with one of the larger
+/// input, like a foreign key does. Outer joins return at least the rows of the inputs they keep
+fn join_rows(
+    join_type: JoinType,
+    input_rows: &[usize],
+    keyed: bool,
+    filter: &[evalexpr::Node],
+) -> usize {
+    let (left, right) = (input_rows[0], input_rows[1]);
+    let matched = if keyed {
+        left.max(right)
+    } else {
+        left.saturating_mul(right)
+    };
+    let mut rows = scale(matched, selectivity(filter));
+    if join_type.keeps_left() {
+        rows = rows.max(left);
+    }
+    if join_type.keeps_right() {
+        rows = rows.max(right);
+    }
+    rows
+}
+
+/// Estimated rows of the table read by an access path
+fn table_rows(node: &PhysicalNode, planner_info: &PlannerInfo) -> usize {
+    match node {
+        PhysicalNode::Instrumented(a) => table_rows(&a.node, planner_info),
+        PhysicalNode::AccessPath(a) => planner_info
+            .table_info
+            .get(scanned_table(a))
+            .map(|info| info.rows())
+            .unwrap_or_default(),
+        _ => 0,
+    }
+}
+
+fn scanned_table(access: &AccessMethod) -> &str {
+    match access {
+        AccessMethod::HeapIter(table, _)
+        | AccessMethod::HashIter(table, _)
+        | AccessMethod::BtreeIter(table, _) => table,
+    }
+}
+
+fn join_name(join_type: JoinType) -> String {
+    format!("{join_type:?}").to_uppercase()
+}
+
+fn keys_list(eq_fields: &[(FieldId, FieldId)]) -> String {
+    eq_fields
+        .iter()
+        .map(|(left, right)| format!("{left} == {right}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn filter_line(clauses: &[evalexpr::Node]) -> Option<String> {
+    if clauses.is_empty() {
+        return None;
+    }
+    let clauses = clauses.iter().map(infix).collect::<Vec<_>>();
+    Some(format!("Filter: {}", clauses.join(" && ")))
+}
+
+/// Fields sorted by name, the maps of a plan have no order of their own
+fn fields_list<'a>(fields: impl Iterator<Item = &'a FieldId>) -> String {
+    let mut fields = fields.map(|field| field.to_string()).collect::<Vec<_>>();
+    fields.sort();
+    fields.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::testing::TestSession;
+
+    /// The lines of the plan of a query
+    fn plan(session: &mut TestSession, query: &str) -> Vec<String> {
+        session.rows(query).concat()
+    }
+
+    // EXPLAIN returns the plan tree with the estimated rows of every node, EXPLAIN ANALYZE runs it
+    // and adds the actual rows and pins of every node and the time the whole query took
+    #[test]
+    fn test_explain_and_explain_analyze() {
+        let mut session = TestSession::open();
+        session.status("create table t (id int, name varchar, create index btree tid on (id))");
+        for id in 1..=20 {
+            session.status(&format!(
+                "insert into t (id, name) values ({id}, \"n{id}\")"
+            ));
+        }
+        let scan = "select t.name from t where t.name == \"n3\"";
+        let lines = plan(&mut session, &format!("explain {scan}"));
+        assert_eq!(lines.len(), 4, "{lines:?}");
+        assert!(lines[0].starts_with("Project t.name (rows="));
+        assert!(lines[1].starts_with("  -> Select (rows="));
+        assert_eq!(lines[2], "       Filter: t.name == \"n3\"");
+        assert!(lines[3].starts_with("       -> Seq Scan on t (rows="));
+        assert!(lines.iter().all(|line| !line.contains("actual")));
+
+        let lines = plan(&mut session, &format!("explain analyze {scan}"));
+        assert_eq!(lines.len(), 5, "{lines:?}");
+        assert!(lines[0].contains(") (actual rows=1 time="));
+        assert!(lines[3].contains(") (actual rows=20 time="));
+        assert!(lines[4].starts_with("Execution Time: ") && lines[4].ends_with(" ms"));
+
+        let lookup = "select t.name from t where t.id == 3";
+        let lines = plan(&mut session, &format!("explain analyze {lookup}"));
+        assert_eq!(lines[2], "       Index Cond: t.id == 3");
+        assert!(lines[3].starts_with("       -> Btree Index Scan on t using id (==) (rows=1)"));
+        let actual = lines[3].split(" (actual ").nth(1).unwrap();
+        assert!(actual.starts_with("rows=1 time="));
+        let pins = actual.split("pins=").nth(1).unwrap();
+        assert!(pins.trim_end_matches(')').parse::<u64>().unwrap() > 0);
+    }
+}
@@ -0,0 +1,566 @@
+use super::utils::{merge, null_row, JoinFilter};
+use super::{PhysicalNode, TypeMap, MAX_WORKING_MEM};
+use crate::query::MergedRow;
+use crate::sql::query::select::JoinType;
+use crate::{FieldId, AQUA_TMP_DIR};
+use rand::{thread_rng, Rng};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::fs::{remove_file, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// Number of partitions a spilled input is split into
+const FAN_OUT: usize = 8;
+/// Partitions still too big for memory are split again until this depth, past it the partition
+/// is mostly one key and is joined in memory whatever its size
+const MAX_PARTITION_DEPTH: usize = 3;
+
+/// Rows written to a temporary file in `AQUA_TMP_DIR`, the file is removed once dropped
+pub(super) struct SpillFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl SpillFile {
+    pub(super) fn new(name: &str) -> Self {
+        let path = AQUA_TMP_DIR().join(format!("tmp{}{}", name, thread_rng().gen::<u64>()));
+        let file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        // the partition buffers share the working memory
+        let writer = BufWriter::with_capacity(MAX_WORKING_MEM / FAN_OUT, file);
+        Self { path, writer }
+    }
+    pub(super) fn push(&mut self, row: &MergedRow) {
+        bincode::encode_into_std_write(row, &mut self.writer, bincode::config::standard()).unwrap();
+    }
+    pub(super) fn into_reader(mut self) -> SpillReader {
+        self.writer.flush().unwrap();
+        let path = std::mem::take(&mut self.path);
+        let file = File::open(&path).unwrap();
+        SpillReader {
+            path,
+            reader: BufReader::new(file),
+        }
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            remove_file(&self.path).unwrap_or_default();
+        }
+    }
+}
+
+pub(super) struct SpillReader {
+    path: PathBuf,
+    reader: BufReader<File>,
+}
+
+impl Iterator for SpillReader {
+    type Item = MergedRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        bincode::decode_from_std_read(&mut self.reader, bincode::config::standard()).ok()
+    }
+}
+
+impl SpillReader {
+    /// Starts reading the rows again from the first one
+    pub(super) fn rewind(&mut self) {
+        self.reader.seek(SeekFrom::Start(0)).unwrap();
+    }
+}
+
+impl Drop for SpillReader {
+    fn drop(&mut self) {
+        remove_file(&self.path).unwrap_or_default();
+    }
+}
+
+/// A pair of matching partitions of the build and probe inputs
+struct Partition {
+    build: SpillReader,
+    probe: SpillReader,
+    depth: usize,
+}
+
+enum ProbeInput {
+    Child,
+    Spilled(SpillReader),
+}
+
+/// Equi-join building a hash table on the smaller input and probing it with the other one.
+///
+/// Rows are hashed on all the key fields at once, the remaining join conditions are checked by
+/// the filter on every pair of rows with equal keys.
+///
+/// When the build input outgrows `MAX_WORKING_MEM` both inputs are hash partitioned into files
+/// (grace hash join) and every pair of partitions is joined on its own.
+/// The rows of the build input are flagged once matched so outer joins can emit the ones that
+/// never did, padded with NULLs
+pub struct HashJoin {
+    fields_map: TypeMap,
+    pub(super) join_type: JoinType,
+    pub(super) left: Box<PhysicalNode>,
+    pub(super) right: Box<PhysicalNode>,
+    pub(super) eq_fields: Vec<(FieldId, FieldId)>,
+    pub(super) filter: JoinFilter,
+    pub(super) build_left: bool,
+    left_nulls: MergedRow,
+    right_nulls: MergedRow,
+    loaded: bool,
+    build_rows: Vec<(MergedRow, bool)>,
+    table: HashMap<Vec<u8>, Vec<usize>>,
+    probe: Option<ProbeInput>,
+    partitions: VecDeque<Partition>,
+    pending: VecDeque<MergedRow>,
+}
+
+impl Debug for HashJoin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HashJoin")
+            .field("join_type", &self.join_type)
+            .field("eq_fields", &self.eq_fields)
+            .field("build_left", &self.build_left)
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .finish()
+    }
+}
+
+impl HashJoin {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        fields_map: TypeMap,
+        join_type: JoinType,
+        left: Box<PhysicalNode>,
+        right: Box<PhysicalNode>,
+        eq_fields: Vec<(FieldId, FieldId)>,
+        filter: JoinFilter,
+        build_left: bool,
+        left_headers: TypeMap,
+        right_headers: TypeMap,
+    ) -> Self {
+        Self {
+            fields_map,
+            join_type,
+            left,
+            right,
+            eq_fields,
+            filter,
+            build_left,
+            left_nulls: null_row(&left_headers),
+            right_nulls: null_row(&right_headers),
+            loaded: false,
+            build_rows: vec![],
+            table: HashMap::new(),
+            probe: None,
+            partitions: VecDeque::new(),
+            pending: VecDeque::new(),
+        }
+    }
+    pub fn get_type_map(&self) -> TypeMap {
+        self.fields_map.clone()
+    }
+    fn build_fields(&self) -> Vec<FieldId> {
+        self.eq_fields
+            .iter()
+            .map(|(left, right)| if self.build_left { left } else { right })
+            .cloned()
+            .collect()
+    }
+    fn probe_fields(&self) -> Vec<FieldId> {
+        self.eq_fields
+            .iter()
+            .map(|(left, right)| if self.build_left { right } else { left })
+            .cloned()
+            .collect()
+    }
+    fn keeps_build(&self) -> bool {
+        if self.build_left {
+            self.join_type.keeps_left()
+        } else {
+            self.join_type.keeps_right()
+        }
+    }
+    fn keeps_probe(&self) -> bool {
+        if self.build_left {
+            self.join_type.keeps_right()
+        } else {
+            self.join_type.keeps_left()
+        }
+    }
+    fn probe_nulls(&self) -> &MergedRow {
+        if self.build_left {
+            &self.right_nulls
+        } else {
+            &self.left_nulls
+        }
+    }
+    fn build_nulls(&self) -> &MergedRow {
+        if self.build_left {
+            &self.left_nulls
+        } else {
+            &self.right_nulls
+        }
+    }
+
+    /// Reads the build input into memory, partitioning both inputs if it does not fit
+    fn load(&mut self) {
+        let build_fields = self.build_fields();
+        let probe_fields = self.probe_fields();
+        let (build, probe) = if self.build_left {
+            (&mut self.left, &mut self.right)
+        } else {
+            (&mut self.right, &mut self.left)
+        };
+        match read_build_input(build.as_mut()) {
+            Ok(rows) => {
+                self.fill_table(rows, &build_fields);
+                self.probe = Some(ProbeInput::Child);
+            }
+            Err(rows) => {
+                let build = partition(rows.into_iter().chain(build.as_mut()), &build_fields, 0);
+                let probe = partition(probe.as_mut(), &probe_fields, 0);
+                self.partitions
+                    .extend(
+                        build
+                            .into_iter()
+                            .zip(probe)
+                            .map(|(build, probe)| Partition {
+                                build,
+                                probe,
+                                depth: 0,
+                            }),
+                    );
+                self.next_partition();
+            }
+        }
+        self.loaded = true;
+    }
+
+    /// Loads the build side of the next spilled partition, splitting it further while it does
+    /// not fit in memory
+    fn next_partition(&mut self) {
+        while let Some(Partition {
+            mut build,
+            probe,
+            depth,
+        }) = self.partitions.pop_front()
+        {
+            let rows = if depth < MAX_PARTITION_DEPTH {
+                read_build_input(&mut build)
+            } else {
+                Ok(build.by_ref().collect())
+            };
+            match rows {
+                Ok(rows) => {
+                    let build_fields = self.build_fields();
+                    self.fill_table(rows, &build_fields);
+                    self.probe = Some(ProbeInput::Spilled(probe));
+                    return;
+                }
+                Err(rows) => {
+                    let build_fields = self.build_fields();
+                    let probe_fields = self.probe_fields();
+                    let build = partition(rows.into_iter().chain(build), &build_fields, depth + 1);
+                    let probe = partition(probe, &probe_fields, depth + 1);
+                    for (build, probe) in build.into_iter().zip(probe).rev() {
+                        self.partitions.push_front(Partition {
+                            build,
+                            probe,
+                            depth: depth + 1,
+                        });
+                    }
+                }
+            }
+        }
+        self.probe = None;
+    }
+
+    fn fill_table(&mut self, rows: Vec<MergedRow>, build_fields: &[FieldId]) {
+        self.table.clear();
+        self.build_rows.clear();
+        for (idx, row) in rows.into_iter().enumerate() {
+            // NULL keys never match but the row is kept for outer joins
+            if let Some(key) = hash_key(&row, build_fields) {
+                self.table.entry(key).or_default().push(idx);
+            }
+            self.build_rows.push((row, false));
+        }
+    }
+
+    fn next_probe_row(&mut self) -> Option<MergedRow> {
+        match self.probe.as_mut()? {
+            ProbeInput::Child => {
+                if self.build_left {
+                    self.right.next()
+                } else {
+                    self.left.next()
+                }
+            }
+            ProbeInput::Spilled(reader) => reader.next(),
+        }
+    }
+
+    /// Queues the build rows that never matched once their partition was fully probed
+    fn finish_partition(&mut self) {
+        let rows = std::mem::take(&mut self.build_rows);
+        self.table.clear();
+        if self.keeps_build() {
+            for (row, _) in rows.into_iter().filter(|(_, matched)| !matched) {
+                let padded = merge(&row, self.probe_nulls());
+                self.pending.push_back(padded);
+            }
+        }
+    }
+}
+
+impl Iterator for HashJoin {
+    type Item = MergedRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.loaded {
+            self.load();
+        }
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Some(row);
+            }
+            self.probe.as_ref()?;
+            let probe = match self.next_probe_row() {
+                Some(row) => row,
+                None => {
+                    self.finish_partition();
+                    self.next_partition();
+                    continue;
+                }
+            };
+            let mut matched = false;
+            let matches =
+                hash_key(&probe, &self.probe_fields()).and_then(|key| self.table.get(&key));
+            for idx in matches.into_iter().flatten() {
+                let (build, build_matched) = &mut self.build_rows[*idx];
+                let row = merge(build, &probe);
+                if self.filter.accepts(&row) {
+                    *build_matched = true;
+                    matched = true;
+                    self.pending.push_back(row);
+                }
+            }
+            if !matched && self.keeps_probe() {
+                return Some(merge(&probe, self.build_nulls()));
+            }
+        }
+    }
+}
+
+/// Reads a whole input if it fits in `MAX_WORKING_MEM`, otherwise returns the rows read so far
+pub(super) fn read_build_input(
+    input: &mut dyn Iterator<Item = MergedRow>,
+) -> Result<Vec<MergedRow>, Vec<MergedRow>> {
+    let mut rows = vec![];
+    let mut memory_use = 0;
+    for row in input {
+        memory_use += row.values().flatten().map(|data| data.len()).sum::<usize>();
+        rows.push(row);
+        if memory_use > MAX_WORKING_MEM {
+            return Err(rows);
+        }
+    }
+    Ok(rows)
+}
+
+/// The values of the key fields of a row, each prefixed with its length so different keys never
+/// concatenate to the same bytes. None if any of them is NULL
+fn hash_key(row: &MergedRow, fields: &[FieldId]) -> Option<Vec<u8>> {
+    let mut key = vec![];
+    for field in fields {
+        let data = row.get(field).unwrap().as_ref()?;
+        key.extend_from_slice(&(data.len() as u32).to_ne_bytes());
+        key.extend_from_slice(data);
+    }
+    Some(key)
+}
+
+/// Hash partitions rows on the join key into `FAN_OUT` spill files.
+///
+/// The depth salts the hash so a partition split again spreads over new partitions
+fn partition(
+    rows: impl Iterator<Item = MergedRow>,
+    key: &[FieldId],
+    depth: usize,
+) -> Vec<SpillReader> {
+    let mut files = (0..FAN_OUT)
+        .map(|_| SpillFile::new(&key[0].table))
+        .collect::<Vec<_>>();
+    for row in rows {
+        let idx = match hash_key(&row, key) {
+            Some(data) => {
+                let mut hasher = DefaultHasher::new();
+                depth.hash(&mut hasher);
+                data.hash(&mut hasher);
+                hasher.finish() as usize % FAN_OUT
+            }
+            None => 0,
+        };
+        files[idx].push(&row);
+    }
+    files.into_iter().map(SpillFile::into_reader).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::testing::{TempDir, TestHome};
+    use crate::query::physical::AccessMethod;
+    use crate::schema::schema::Schema;
+    use crate::schema::types::CharType::VarChar;
+    use crate::schema::types::NumericType::Integer;
+    use crate::schema::types::Type;
+    use crate::storage::free_space::FreeMap;
+    use crate::storage::heap::HeapPage;
+    use crate::storage::storagemgr::StorageManager;
+    use crate::table::tablemgr::TableManager;
+    use std::collections::HashSet;
+    use std::fs;
+    use std::sync::Arc;
+    use std::sync::RwLock;
+
+    fn test_table(
+        storage: &Arc<RwLock<StorageManager>>,
+        dir: &std::path::Path,
+        name: &str,
+        keys: impl Iterator<Item = Option<i32>>,
+    ) -> TableManager {
+        let mut schema = Schema::new();
+        schema.add_field_default_constraints("k", Type::Numeric(Integer), None);
+        schema.add_field_default_constraints("v", Type::Character(VarChar(None)), None);
+        let heap = dir.join(name);
+        let blocks = storage
+            .write()
+            .unwrap()
+            .empty_heap_pages(heap.to_str().unwrap(), 1);
+        let free_map = FreeMap::init(
+            dir.join(format!("{name}_fsm")),
+            HeapPage::default_free_space(4096) as u16,
+            &blocks[0],
+        );
+        let mut table = TableManager::new(
+            blocks,
+            storage.clone(),
+            free_map,
+            Arc::new(schema.to_layout()),
+            vec![],
+        );
+        for (idx, key) in keys.enumerate() {
+            table.try_insert_tuple(vec![
+                ("k".to_string(), key.map(|k| k.to_ne_bytes().to_vec())),
+                (
+                    "v".to_string(),
+                    Some(format!("{name} row {idx}").into_bytes()),
+                ),
+            ]);
+        }
+        table
+    }
+
+    fn scan(table: &TableManager, name: &str) -> Box<PhysicalNode> {
+        let access = AccessMethod::HeapIter(name.to_string(), table.heapscan_iter(None));
+        Box::new(PhysicalNode::AccessPath(Box::new(access)))
+    }
+
+    fn headers(name: &str) -> TypeMap {
+        HashMap::from([
+            (FieldId::new(name, "k"), Type::Numeric(Integer)),
+            (FieldId::new(name, "v"), Type::Character(VarChar(None))),
+        ])
+    }
+
+    fn row_key(row: &MergedRow) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+        (
+            row.get(&FieldId::new("l", "v")).unwrap().clone(),
+            row.get(&FieldId::new("r", "v")).unwrap().clone(),
+        )
+    }
+
+    // Joins tables larger than the working memory, so both inputs are partitioned to disk, and
+    // checks every join type against a nested loop over the same rows
+    #[test]
+    fn grace_hash_join_matches_nested_loop() {
+        let home = TestHome::get();
+        let _tmp = home.tmp.lock().unwrap();
+        let dir = TempDir::new("hash_join");
+        let storage = dir.storage(200);
+        // duplicate keys on both sides, keys missing on either side and NULL keys
+        let left_keys = (0..1500).map(|i| (i % 7 != 0).then_some(i % 500));
+        let right_keys = (0..1200).map(|i| (i % 11 != 0).then_some(i % 600 + 200));
+        let left = test_table(&storage, dir.path(), "l", left_keys);
+        let right = test_table(&storage, dir.path(), "r", right_keys);
+
+        let left_rows = scan(&left, "l").collect::<Vec<_>>();
+        let right_rows = scan(&right, "r").collect::<Vec<_>>();
+        let key =
+            |row: &MergedRow, table: &str| row.get(&FieldId::new(table, "k")).unwrap().clone();
+        for join_type in [
+            JoinType::Inner,
+            JoinType::Left,
+            JoinType::Right,
+            JoinType::Full,
+        ] {
+            let mut expected = vec![];
+            let mut right_matched = HashSet::new();
+            for l in left_rows.iter() {
+                let mut matched = false;
+                for (idx, r) in right_rows.iter().enumerate() {
+                    if key(l, "l").is_some() && key(l, "l") == key(r, "r") {
+                        expected.push(row_key(&merge(l, r)));
+                        right_matched.insert(idx);
+                        matched = true;
+                    }
+                }
+                if !matched && join_type.keeps_left() {
+                    expected.push(row_key(&merge(l, &null_row(&headers("r")))));
+                }
+            }
+            if join_type.keeps_right() {
+                for (idx, r) in right_rows.iter().enumerate() {
+                    if !right_matched.contains(&idx) {
+                        expected.push(row_key(&merge(&null_row(&headers("l")), r)));
+                    }
+                }
+            }
+            expected.sort();
+
+            for build_left in [true, false] {
+                let mut fields_map = headers("l");
+                fields_map.extend(headers("r"));
+                let filter = JoinFilter::new(vec![], &fields_map);
+                let join = HashJoin::new(
+                    fields_map,
+                    join_type,
+                    scan(&left, "l"),
+                    scan(&right, "r"),
+                    vec![(FieldId::new("l", "k"), FieldId::new("r", "k"))],
+                    filter,
+                    build_left,
+                    headers("l"),
+                    headers("r"),
+                );
+                let mut found = join.map(|row| row_key(&row)).collect::<Vec<_>>();
+                found.sort();
+                assert_eq!(found, expected, "{join_type:?} building left: {build_left}");
+            }
+        }
+        // every partition file was removed once joined
+        assert_eq!(fs::read_dir(home.tmp_dir()).unwrap().count(), 0);
+    }
+}
@@ -1,17 +1,25 @@
+mod explain;
+mod hash_join;
+mod nested_loop_join;
 pub mod realize;
-mod utils;
+pub(crate) mod utils;
+
+pub use explain::Instrumented;
+pub use hash_join::HashJoin;
+pub use nested_loop_join::NestedLoopJoin;
 
 use utils::*;
 
 use super::tuple_table::TupleTable;
 use super::MergedRow;
 use crate::common::numerical::ByteMagic;
+use crate::index::Rid;
 use crate::query::algebra::GroupBy;
 use crate::query::concrete_types::ConcreteType;
 use crate::query::tuple_table::TupleTableIter;
 use crate::schema::schema::Field;
 use crate::schema::types::{NumericType, Type};
-use crate::sql::query::select::AggregateFunc;
+use crate::sql::query::select::{AggregateFunc, JoinType};
 use crate::table::btree_iter::BtreeIter;
 use crate::table::hash_iter::HashIter;
 use crate::table::heap_iter::TableIter;
@@ -21,9 +29,10 @@ use evalexpr::Value::Float;
 use evalexpr::{
     ContextWithMutableVariables, FloatType, HashMapContext, IntType, IterateVariablesContext, Value,
 };
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Debug, Formatter};
 use std::fs::write;
+use std::mem;
 use std::process::exit;
 // use genawaiter::
 
@@ -72,41 +81,92 @@ impl AccessMethod {
             AccessMethod::HeapIter(_, _) => unreachable!("Heap Does not Support Loading Keys"),
         }
     }
+    fn current_rid(&self) -> Option<Rid> {
+        match self {
+            AccessMethod::HeapIter(_, t) => t.current_rid(),
+            AccessMethod::HashIter(_, h) => h.current_rid(),
+            AccessMethod::BtreeIter(_, b) => b.current_rid(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum PhysicalNode {
     Project(Project),
     Select(Select),
-    MergeJoin(MergeJoin),
+    MergeJoin(Box<MergeJoin>),
+    HashJoin(HashJoin),
     IndexedLoopJoin(IndexedJoin),
+    NestedLoopJoin(NestedLoopJoin),
     AccessPath(Box<AccessMethod>),
     RemoveDuplicates(DeDup),
     Sort(Sort),
     GroupBy(Grouper),
+    Instrumented(Instrumented),
 }
 
 impl PhysicalNode {
     fn load_key(&mut self, key: &[u8]) {
         match self {
             PhysicalNode::AccessPath(a) => a.load_key(key),
+            PhysicalNode::Instrumented(a) => a.load_key(key),
             _ => unreachable!(),
         }
     }
+    /// The Rid of the base table tuple behind the last returned row.
+    ///
+    /// Only meaningful for plans over a single table, joins and aggregations have no single Rid
+    pub fn current_rid(&self) -> Option<Rid> {
+        match self {
+            PhysicalNode::Project(a) => a.child.current_rid(),
+            PhysicalNode::Select(a) => a.child.current_rid(),
+            PhysicalNode::AccessPath(a) => a.current_rid(),
+            PhysicalNode::Instrumented(a) => a.current_rid(),
+            _ => None,
+        }
+    }
+    /// Why the plan stopped before its last row, a filter or an aggregate failed on a value.
+    ///
+    /// Only known once the plan returned None
+    pub fn error(&self) -> Option<String> {
+        match self {
+            PhysicalNode::Project(a) => a.child.error(),
+            PhysicalNode::Select(a) => a.error.clone().or_else(|| a.child.error()),
+            PhysicalNode::MergeJoin(a) => join_error(&a.filter, &a.left, &a.right),
+            PhysicalNode::HashJoin(a) => join_error(&a.filter, &a.left, &a.right),
+            PhysicalNode::IndexedLoopJoin(a) => join_error(&a.filter, &a.left, &a.right),
+            PhysicalNode::NestedLoopJoin(a) => join_error(&a.filter, &a.left, &a.right),
+            PhysicalNode::AccessPath(_) => None,
+            PhysicalNode::RemoveDuplicates(a) => a.child.error(),
+            PhysicalNode::Sort(a) => a.child.error(),
+            PhysicalNode::GroupBy(a) => a.error.clone().or_else(|| a.child.error()),
+            PhysicalNode::Instrumented(a) => a.error(),
+        }
+    }
     pub fn get_type_map(&self) -> TypeMap {
         match self {
             PhysicalNode::Project(a) => a.fields_map.clone(),
             PhysicalNode::Select(a) => a.fields_map.clone(),
             PhysicalNode::MergeJoin(a) => a.fields_map.clone(),
+            PhysicalNode::HashJoin(a) => a.get_type_map(),
             PhysicalNode::IndexedLoopJoin(a) => a.fields_map.clone(),
+            PhysicalNode::NestedLoopJoin(a) => a.get_type_map(),
             PhysicalNode::AccessPath(_) => unreachable!(),
             PhysicalNode::RemoveDuplicates(a) => a.fields_map.clone(),
             PhysicalNode::Sort(a) => a.fields_map.clone(),
             PhysicalNode::GroupBy(a) => a.fields_map.clone(),
+            PhysicalNode::Instrumented(a) => a.get_type_map(),
         }
     }
 }
 
+fn join_error(filter: &JoinFilter, left: &PhysicalNode, right: &PhysicalNode) -> Option<String> {
+    filter
+        .error()
+        .or_else(|| left.error())
+        .or_else(|| right.error())
+}
+
 impl Iterator for PhysicalNode {
     type Item = MergedRow;
 
@@ -115,11 +175,14 @@ impl Iterator for PhysicalNode {
             PhysicalNode::Project(a) => a.next(),
             PhysicalNode::Select(a) => a.next(),
             PhysicalNode::MergeJoin(a) => a.next(),
+            PhysicalNode::HashJoin(a) => a.next(),
             PhysicalNode::IndexedLoopJoin(a) => a.next(),
+            PhysicalNode::NestedLoopJoin(a) => a.next(),
             PhysicalNode::AccessPath(a) => a.next(),
             PhysicalNode::RemoveDuplicates(a) => a.next(),
             PhysicalNode::Sort(a) => a.next(),
             PhysicalNode::GroupBy(a) => a.next(),
+            PhysicalNode::Instrumented(a) => a.next(),
         }
     }
 }
@@ -157,24 +220,35 @@ pub struct Select {
     context: HashMapContext,
     child: Box<PhysicalNode>,
     pub bridged: (bool, Option<Vec<u8>>), // (LOADED,KEY)
+    // why a row could not be checked, no more rows are returned then
+    error: Option<String>,
 }
 
 impl Select {
     fn next_normal(&mut self) -> Option<MergedRow> {
+        if self.error.is_some() {
+            return None;
+        }
         for next in self.child.by_ref() {
-            fill_ctx_map(&mut self.context, &next, &self.fields_map);
+            if let Err(error) = fill_ctx_map(&mut self.context, &next, &self.fields_map) {
+                self.error = Some(error);
+                return None;
+            }
+            // comparisons against NULL (an empty value) fail to evaluate, the row does not qualify
             if self
                 .condition
                 .eval_boolean_with_context(&self.context)
-                .unwrap()
+                .unwrap_or(false)
             {
                 return Some(next);
             }
         }
         None
     }
+    // The index returns the rows matching the clauses its key covers, the whole condition is still
+    // checked on them
     fn next_bridged(&mut self) -> Option<MergedRow> {
-        self.child.next()
+        self.next_normal()
     }
 }
 
@@ -197,18 +271,25 @@ impl Iterator for Select {
 #[derive(Debug)]
 pub struct MergeJoin {
     fields_map: TypeMap,
+    join_type: JoinType,
     left: Box<PhysicalNode>,
     right: Box<PhysicalNode>,
     left_table: Option<TupleTable>,
     right_table: Option<TupleTable>,
     left_iter: Option<TupleTableIter>,
     right_iter: Option<TupleTableIter>,
-    eq_fields: (FieldId, FieldId),
+    eq_fields: Vec<(FieldId, FieldId)>,
+    filter: JoinFilter,
     loaded: bool,
-    current_left_row: Option<MergedRow>,
-    current_right_row: Option<MergedRow>,
-    out1: bool,
-    just_returned: bool,
+    left_nulls: MergedRow,
+    right_nulls: MergedRow,
+    // right rows sharing the join key `group_key` each flagged once matched,
+    // the key is None once the right input is exhausted
+    group: Vec<(MergedRow, bool)>,
+    group_key: Option<Vec<ConcreteType>>,
+    // first right row past the current group
+    next_right_row: Option<MergedRow>,
+    pending: VecDeque<MergedRow>,
 }
 
 impl MergeJoin {
@@ -221,40 +302,41 @@ impl MergeJoin {
             // let next = next.into_iter().map(|(k,v)| (k.field,v)).collect();
             self.right_table.as_mut().unwrap().add_row_map(next);
         }
-        self.left_table
-            .as_mut()
-            .unwrap()
-            .sort_single(&self.eq_fields.0, false);
-        self.right_table
-            .as_mut()
-            .unwrap()
-            .sort_single(&self.eq_fields.1, false);
+        let (left_keys, right_keys): (Vec<_>, Vec<_>) = self.eq_fields.iter().cloned().unzip();
+        let desc = vec![false; self.eq_fields.len()];
+        self.left_table.as_mut().unwrap().sort(&left_keys, &desc);
+        self.right_table.as_mut().unwrap().sort(&right_keys, &desc);
         self.left_iter = Some(self.left_table.take().unwrap().into_iter());
         self.right_iter = Some(self.right_table.take().unwrap().into_iter());
-        self.current_left_row = self.left_iter.as_mut().unwrap().next();
-        self.current_right_row = self.right_iter.as_mut().unwrap().next();
+        self.next_group();
         self.loaded = true;
     }
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         fields_map: TypeMap,
+        join_type: JoinType,
         left: Box<PhysicalNode>,
         right: Box<PhysicalNode>,
-        eq_fields: (FieldId, FieldId),
+        eq_fields: Vec<(FieldId, FieldId)>,
+        filter: JoinFilter,
         left_headers: TypeMap,
         right_headers: TypeMap,
     ) -> Self {
+        let left_nulls = null_row(&left_headers);
+        let right_nulls = null_row(&right_headers);
         let left_table = Some(TupleTable::new(
-            &eq_fields.0.table,
+            &eq_fields[0].0.table,
             left_headers,
             MAX_WORKING_MEM,
         ));
         let right_table = Some(TupleTable::new(
-            &eq_fields.1.table,
+            &eq_fields[0].1.table,
             right_headers,
             MAX_WORKING_MEM,
         ));
         Self {
             fields_map,
+            join_type,
             left,
             right,
             left_table,
@@ -262,153 +344,124 @@ impl MergeJoin {
             left_iter: None,
             right_iter: None,
             eq_fields,
+            filter,
             loaded: false,
-            current_left_row: None,
-            current_right_row: None,
-            out1: false,
-            just_returned: false,
+            left_nulls,
+            right_nulls,
+            group: vec![],
+            group_key: None,
+            next_right_row: None,
+            pending: VecDeque::new(),
         }
     }
-    fn merged_row_to_val(&self, row: &MergedRow, field: &FieldId) -> ConcreteType {
-        let bytes = row
-            .get(field)
-            .unwrap()
-            .as_ref()
-            .map_or([].as_slice(), |d| d);
-        let datatype = *self.fields_map.get(field).unwrap();
-        ConcreteType::from_bytes(datatype, bytes)
+    /// The join key of a row, keys with a NULL never match anything
+    fn join_key(&self, row: &MergedRow, left: bool) -> Option<Vec<ConcreteType>> {
+        self.eq_fields
+            .iter()
+            .map(|(left_field, right_field)| {
+                let field = if left { left_field } else { right_field };
+                let bytes = row.get(field).unwrap().as_ref()?;
+                let datatype = *self.fields_map.get(field).unwrap();
+                Some(ConcreteType::from_bytes(datatype, bytes))
+            })
+            .collect()
+    }
+    /// Retires the current group of right rows and reads the next one.
+    ///
+    /// Right rows that never matched are queued padded with NULLs when the join keeps them
+    fn next_group(&mut self) {
+        let group = mem::take(&mut self.group);
+        if self.join_type.keeps_right() {
+            for (row, _) in group.into_iter().filter(|(_, matched)| !matched) {
+                self.pending.push_back(merge(&self.left_nulls, &row));
+            }
+        }
+        self.group_key = None;
+        while let Some(row) = self
+            .next_right_row
+            .take()
+            .or_else(|| self.right_iter.as_mut().unwrap().next())
+        {
+            match (self.join_key(&row, false), &self.group_key) {
+                (None, _) => {
+                    if self.join_type.keeps_right() {
+                        self.pending.push_back(merge(&self.left_nulls, &row));
+                    }
+                }
+                (Some(key), None) => {
+                    self.group_key = Some(key);
+                    self.group.push((row, false));
+                }
+                (Some(key), Some(group_key)) if key == *group_key => self.group.push((row, false)),
+                _ => {
+                    self.next_right_row = Some(row);
+                    break;
+                }
+            }
+        }
     }
 }
 
 impl Iterator for MergeJoin {
     type Item = MergedRow;
 
-    #[allow(clippy::collapsible_else_if)]
     fn next(&mut self) -> Option<Self::Item> {
         if !self.loaded {
             self.load();
         }
-
-        // setting the nexts
-        let mut left =
-            self.merged_row_to_val(self.current_left_row.as_ref().unwrap(), &self.eq_fields.0);
-        let mut right =
-            self.merged_row_to_val(self.current_right_row.as_ref().unwrap(), &self.eq_fields.1);
-
-        // loop until 2 rows match
         loop {
-            while !self.out1 && left != right {
-                // if right value of field is greater, shift left till it becomes equal or greater than right.
-                if left < right {
-                    if let Some(next_left) = self.left_iter.as_mut().unwrap().next() {
-                        self.current_left_row.replace(next_left);
-                        left = self.merged_row_to_val(
-                            self.current_left_row.as_ref().unwrap(),
-                            &self.eq_fields.0,
-                        );
-                    }
-                    // no more lefts so return no matched records to upper node
-                    else {
-                        return None;
-                    }
-                }
-                // if left value of field is greater, shift right till it becomes equal or greater than left.
-                else {
-                    if let Some(next_right) = self.right_iter.as_mut().unwrap().next() {
-                        self.current_right_row.replace(next_right);
-                        right = self.merged_row_to_val(
-                            self.current_right_row.as_ref().unwrap(),
-                            &self.eq_fields.1,
-                        );
-                    }
-                    // no more rights so return no matched records to upper node
-                    else {
-                        return None;
-                    }
-                }
+            if let Some(row) = self.pending.pop_front() {
+                return Some(row);
             }
-
-            // loop 1 broke due to matching records
-            // hold the current left record in a temp variable
-            // set the flag not to enter the upper loop again when calling next again
-            self.out1 = true;
-            let marked_left = self.current_left_row.as_ref().unwrap().clone();
-            // internal flag to seek left
-            let mut no_more_left = false;
-
-            // a loop getting all right records matching the held left record
-            loop {
-                // internal loop entered at least once (i.e; the upper loop broke in this condition already)
-                while left == right {
-                    // check if we returned this merge before
-                    if self.just_returned == false {
-                        let result = merge(
-                            self.current_left_row.as_ref().unwrap(),
-                            self.current_right_row.as_ref().unwrap(),
-                        );
-                        // set the just returned flag to force the next iteration to skip merging the records again
-                        self.just_returned = true;
-                        return Some(result);
-                    }
-                    // reset the flag after skipping merge for the next time
-                    self.just_returned = false;
-                    // seek left.next
-                    if let Some(next_left) = self.left_iter.as_mut().unwrap().next() {
-                        self.current_left_row.replace(next_left);
-                        left = self.merged_row_to_val(
-                            self.current_left_row.as_ref().unwrap(),
-                            &self.eq_fields.0,
-                        );
-                    } else {
-                        no_more_left = true;
-                        break;
-                    }
+            let left = match self.left_iter.as_mut().unwrap().next() {
+                Some(left) => left,
+                // only the right rows that never matched are left
+                None if self.join_type.keeps_right() && self.group_key.is_some() => {
+                    self.next_group();
+                    continue;
                 }
-
-                // get the next right record to compare it to the held value of left (i.e; not the just seeked one)
-                if let Some(next_right) = self.right_iter.as_mut().unwrap().next() {
-                    self.current_right_row.replace(next_right);
-                    right = self.merged_row_to_val(
-                        self.current_right_row.as_ref().unwrap(),
-                        &self.eq_fields.1,
-                    );
+                None => return None,
+            };
+            let key = match self.join_key(&left, true) {
+                Some(key) => key,
+                None if self.join_type.keeps_left() => {
+                    return Some(merge(&left, &self.right_nulls))
                 }
-                // no more right records so return None
-                else {
-                    return None;
-                }
-
-                // reconstruct the value of the held left to compare it to current right after fetching next right
-                let marked_left_val =
-                    self.merged_row_to_val(&marked_left.clone(), &self.eq_fields.0);
-                // the already held left matches the next right
-                // step back the call to next left and keep the held left as the iter position
-                if marked_left_val == right {
-                    self.left_iter.as_mut().unwrap().step_back();
-                    self.current_left_row.replace(marked_left.clone());
-                    left = self.merged_row_to_val(
-                        self.current_left_row.as_ref().unwrap(),
-                        &self.eq_fields.0,
-                    );
-                } else {
-                    if no_more_left {
-                        return None;
-                    } else {
-                        self.out1 = false;
-                        break;
+                None => continue,
+            };
+            // both inputs are sorted, skip the right groups smaller than the left key
+            while matches!(&self.group_key, Some(group_key) if *group_key < key) {
+                self.next_group();
+            }
+            let mut matched = false;
+            if matches!(&self.group_key, Some(group_key) if *group_key == key) {
+                for (right, right_matched) in self.group.iter_mut() {
+                    let row = merge(&left, right);
+                    if self.filter.accepts(&row) {
+                        *right_matched = true;
+                        matched = true;
+                        self.pending.push_back(row);
                     }
                 }
             }
+            if !matched && self.join_type.keeps_left() {
+                self.pending.push_back(merge(&left, &self.right_nulls));
+            }
         }
-        None
     }
 }
 
 #[derive(Debug)]
 pub struct IndexedJoin {
     fields_map: TypeMap,
+    join_type: JoinType,
     eq_fields: (FieldId, FieldId),
+    filter: JoinFilter,
     current_left_row: Option<MergedRow>,
+    // the right index was probed for the current left row, false for NULL keys
+    probed: bool,
+    matched: bool,
+    right_nulls: MergedRow,
     left: Box<PhysicalNode>,
     right: Box<PhysicalNode>,
 }
@@ -416,14 +469,22 @@ pub struct IndexedJoin {
 impl IndexedJoin {
     pub fn new(
         fields_map: TypeMap,
+        join_type: JoinType,
         eq_fields: (FieldId, FieldId),
+        filter: JoinFilter,
         left: Box<PhysicalNode>,
         right: Box<PhysicalNode>,
+        right_headers: TypeMap,
     ) -> Self {
         Self {
             fields_map,
+            join_type,
             eq_fields,
+            filter,
             current_left_row: None,
+            probed: false,
+            matched: false,
+            right_nulls: null_row(&right_headers),
             left,
             right,
         }
@@ -434,24 +495,31 @@ impl Iterator for IndexedJoin {
     type Item = MergedRow;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(left) = &self.current_left_row {
-            if let Some(right) = self.right.next() {
-                return Some(merge(left, &right));
-            } else if let Some(left) = &self.left.next() {
-                self.current_left_row.replace(left.clone());
-                let key = left.get(&self.eq_fields.0).unwrap().as_ref().unwrap();
+        loop {
+            if let Some(left) = &self.current_left_row {
+                if self.probed {
+                    if let Some(right) = self.right.next() {
+                        let row = merge(left, &right);
+                        if self.filter.accepts(&row) {
+                            self.matched = true;
+                            return Some(row);
+                        }
+                        continue;
+                    }
+                }
+                let left = self.current_left_row.take().unwrap();
+                if !self.matched && self.join_type.keeps_left() {
+                    return Some(merge(&left, &self.right_nulls));
+                }
+            }
+            let left = self.left.next()?;
+            self.probed = false;
+            self.matched = false;
+            if let Some(key) = left.get(&self.eq_fields.0).unwrap() {
                 self.right.load_key(key);
-                self.next()
-            } else {
-                None
+                self.probed = true;
             }
-        } else if let Some(left) = self.left.next() {
-            self.current_left_row.replace(left.clone());
-            let key = left.get(&self.eq_fields.0).unwrap().as_ref().unwrap();
-            self.right.load_key(key);
-            self.next()
-        } else {
-            None
+            self.current_left_row = Some(left);
         }
     }
 }
@@ -546,6 +614,8 @@ pub struct Grouper {
     fields_map: TypeMap,
     results: Vec<MergedRow>,
     loaded: bool,
+    // why the aggregates failed, the group has no results then
+    error: Option<String>,
 }
 
 impl Grouper {
@@ -562,7 +632,6 @@ impl Grouper {
         let desc = vec![false; self.group_on.len()];
         table.sort(&self.group_on, &desc);
         // table.print_all();
-        let mut iter = table.into_iter();
         let mut agg_fns = self
             .agg_ops
             .iter()
@@ -570,32 +639,51 @@ impl Grouper {
                 Box::<dyn AggregateFunction>::from((v.clone(), *child_map.get(k).unwrap()))
             })
             .collect::<Vec<_>>();
-        let current_row = iter.next().unwrap();
-        agg_fns.iter_mut().for_each(|func| func.apply(&current_row));
-        let grouping_set: HashSet<FieldId> = HashSet::from_iter(self.group_on.iter().cloned());
-        let mut current_group = current_row
-            .iter()
-            .filter(|(field, _)| grouping_set.contains(field) )
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect::<HashMap<_, _>>();
-        for row in iter {
-            if current_group.iter().all(|(k, v)| row.get(k).unwrap() == v) {
-                // same group , apply on aggregators
-                agg_fns.iter_mut().for_each(|func| func.apply(&row));
-            } else {
-                let mut group_result = current_group.clone();
-                group_result.retain(|f,_| self.fields_map.contains_key(f));
-                group_result.extend(agg_fns.iter_mut().flat_map(|func| func.finalize()));
-                self.results.push(group_result);
-                current_group
-                    .iter_mut()
-                    .for_each(|(k, v)| *v = row.get(k).unwrap().clone());
-                agg_fns.iter_mut().for_each(|func| func.apply(&row));
-            }
+        if let Err(error) = self.aggregate(table.into_iter(), &mut agg_fns) {
+            self.results.clear();
+            self.error = Some(error);
         }
         self.results.reverse();
         self.loaded = true
     }
+    /// Applies the aggregates on the rows sorted by group and keeps a result row per group.
+    ///
+    /// Fails when an aggregate fails on a value, like a sum going out of range
+    fn aggregate(
+        &mut self,
+        rows: TupleTableIter,
+        agg_fns: &mut [Box<dyn AggregateFunction>],
+    ) -> Result<(), String> {
+        let grouping_set: HashSet<FieldId> = HashSet::from_iter(self.group_on.iter().cloned());
+        let mut current_group: Option<MergedRow> = None;
+        for row in rows {
+            let same_group = current_group
+                .as_ref()
+                .is_some_and(|group| group.iter().all(|(k, v)| row.get(k).unwrap() == v));
+            if !same_group {
+                let group = row
+                    .iter()
+                    .filter(|(field, _)| grouping_set.contains(field))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                if let Some(group) = current_group.replace(group) {
+                    self.finish_group(group, agg_fns);
+                }
+            }
+            for func in agg_fns.iter_mut() {
+                func.apply(&row)?;
+            }
+        }
+        if let Some(group) = current_group {
+            self.finish_group(group, agg_fns);
+        }
+        Ok(())
+    }
+    fn finish_group(&mut self, mut group: MergedRow, agg_fns: &mut [Box<dyn AggregateFunction>]) {
+        group.retain(|f, _| self.fields_map.contains_key(f));
+        group.extend(agg_fns.iter_mut().flat_map(|func| func.finalize()));
+        self.results.push(group);
+    }
     pub fn new(
         group_on: Vec<FieldId>,
         agg_ops: HashMap<FieldId, AggregateField>,
@@ -609,6 +697,7 @@ impl Grouper {
             fields_map,
             results: vec![],
             loaded: false,
+            error: None,
         }
     }
 }
@@ -648,7 +737,7 @@ impl From<(AggregateField, Type)> for Box<dyn AggregateFunction> {
 }
 
 trait AggregateFunction {
-    fn apply(&mut self, row: &MergedRow);
+    fn apply(&mut self, row: &MergedRow) -> Result<(), String>;
     fn finalize(&mut self) -> Vec<(FieldId, Option<Vec<u8>>)>;
     // fn reset(&mut self);
 }
@@ -663,8 +752,11 @@ impl Count {
     }
 }
 impl AggregateFunction for Count {
-    fn apply(&mut self, row: &MergedRow) {
-        self.count += 1;
+    fn apply(&mut self, row: &MergedRow) -> Result<(), String> {
+        if row.get(&self.field.0).unwrap().is_some() {
+            self.count += 1;
+        }
+        Ok(())
     }
 
     fn finalize(&mut self) -> Vec<(FieldId, Option<Vec<u8>>)> {
@@ -693,9 +785,12 @@ impl Min {
     }
 }
 impl AggregateFunction for Min {
-    fn apply(&mut self, row: &MergedRow) {
-        let value = row.get(&self.field.0).unwrap().as_ref().unwrap();
-        let value = ConcreteType::from_bytes(self.field.1, value);
+    fn apply(&mut self, row: &MergedRow) -> Result<(), String> {
+        // aggregates skip NULLs, e.g. the padding of outer joins
+        let value = match row.get(&self.field.0).unwrap() {
+            Some(value) => ConcreteType::from_bytes(self.field.1, value),
+            None => return Ok(()),
+        };
         if self.current_min.is_some(){
             if let Some(current_min_val) = self.current_min.as_ref().unwrap().get(&self.field.0).unwrap() {
                 let current_min_val = ConcreteType::from_bytes(self.field.1, current_min_val);
@@ -709,12 +804,16 @@ impl AggregateFunction for Min {
         else {
             self.current_min = Some(row.clone())
         }
+        Ok(())
     }
 
     fn finalize(&mut self) -> Vec<(FieldId, Option<Vec<u8>>)> {
         let agg = AggregateField::new(AggregateFunc::Min, self.field.0.clone());
         let field =FieldId::from(agg);
-        let mut min_row = self.current_min.take().unwrap();
+        let mut min_row = match self.current_min.take() {
+            Some(row) => row,
+            None => return vec![(field, None)],
+        };
         let min_val = min_row.remove(&self.field.0).unwrap();
         min_row.insert(field,min_val);
         min_row.into_iter().collect::<Vec<_>>()
@@ -734,9 +833,11 @@ impl Max {
     }
 }
 impl AggregateFunction for Max {
-    fn apply(&mut self, row: &MergedRow) {
-        let value = row.get(&self.field.0).unwrap().as_ref().unwrap();
-        let value = ConcreteType::from_bytes(self.field.1, value);
+    fn apply(&mut self, row: &MergedRow) -> Result<(), String> {
+        let value = match row.get(&self.field.0).unwrap() {
+            Some(value) => ConcreteType::from_bytes(self.field.1, value),
+            None => return Ok(()),
+        };
         if self.current_max.is_some(){
             if let Some(current_max_val) = self.current_max.as_ref().unwrap().get(&self.field.0).unwrap() {
                 let current_max_val = ConcreteType::from_bytes(self.field.1, current_max_val);
@@ -750,12 +851,16 @@ impl AggregateFunction for Max {
         else {
             self.current_max = Some(row.clone())
         }
+        Ok(())
     }
 
     fn finalize(&mut self) -> Vec<(FieldId, Option<Vec<u8>>)> {
         let agg = AggregateField::new(AggregateFunc::Max, self.field.0.clone());
         let field =FieldId::from(agg);
-        let mut max_row = self.current_max.take().unwrap();
+        let mut max_row = match self.current_max.take() {
+            Some(row) => row,
+            None => return vec![(field, None)],
+        };
         let max_val = max_row.remove(&self.field.0).unwrap();
         max_row.insert(field,max_val);
         max_row.into_iter().collect::<Vec<_>>()
@@ -773,19 +878,22 @@ impl Sum {
     }
 }
 impl AggregateFunction for Sum {
-    fn apply(&mut self, row: &MergedRow) {
-        let value = row.get(&self.field.0).unwrap().as_ref().unwrap();
-        let value = ConcreteType::from_bytes(self.field.1, value);
-        if let Some(val) = &mut self.sum {
-            *val += value;
-        } else {
-            self.sum = Some(value);
-        }
+    fn apply(&mut self, row: &MergedRow) -> Result<(), String> {
+        let value = match row.get(&self.field.0).unwrap() {
+            Some(value) => ConcreteType::from_bytes(self.field.1, value),
+            None => return Ok(()),
+        };
+        self.sum = Some(match self.sum.take() {
+            Some(sum) => sum.checked_add(value)?,
+            None => value,
+        });
+        Ok(())
     }
 
     fn finalize(&mut self) -> Vec<(FieldId, Option<Vec<u8>>)> {
         let agg = AggregateField::new(AggregateFunc::Sum, self.field.0.clone());
-        vec![(FieldId::from(agg), self.sum.take().unwrap().to_bytes())]
+        let sum = self.sum.take().and_then(|sum| sum.to_bytes());
+        vec![(FieldId::from(agg), sum)]
     }
 }
 
@@ -805,22 +913,122 @@ impl Avg {
     }
 }
 impl AggregateFunction for Avg {
-    fn apply(&mut self, row: &MergedRow) {
+    fn apply(&mut self, row: &MergedRow) -> Result<(), String> {
+        let value = match row.get(&self.field.0).unwrap() {
+            Some(value) => ConcreteType::from_bytes(self.field.1, value),
+            None => return Ok(()),
+        };
         self.count += 1;
-        let value = row.get(&self.field.0).unwrap().as_ref().unwrap();
-        let value = ConcreteType::from_bytes(self.field.1, value);
-        if let Some(val) = &mut self.sum {
-            *val += value;
-        } else {
-            self.sum = Some(value);
-        }
+        self.sum = Some(match self.sum.take() {
+            Some(sum) => sum.checked_add(value)?,
+            None => value,
+        });
+        Ok(())
     }
 
     fn finalize(&mut self) -> Vec<(FieldId, Option<Vec<u8>>)> {
         let count = ConcreteType::BigInt(self.count as i64);
         self.count = 0;
-        let avg = self.sum.take().unwrap() / count;
+        let avg = self.sum.take().and_then(|sum| (sum / count).to_bytes());
         let agg = AggregateField::new(AggregateFunc::Avg, self.field.0.clone());
-        vec![(FieldId::from(agg), avg.to_bytes())]
+        vec![(FieldId::from(agg), avg)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::testing::TestSession;
+    use crate::interface::message::Status;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<Vec<String>> {
+        pairs
+            .iter()
+            .map(|(left, right)| vec![left.to_string(), right.to_string()])
+            .collect()
+    }
+
+    /// The plan of a query as a single text
+    fn plan(session: &mut TestSession, query: &str) -> String {
+        let lines = session.rows(&format!("explain {query}"));
+        lines.concat().join("\n")
+    }
+
+    // Outer joins return the rows of the sides they keep that match nothing, with the fields of
+    // the other side NULL. Keys of floating point run them as merge joins, keys with an index on
+    // the right table as indexed nested loop joins
+    #[test]
+    fn test_outer_joins_fill_nulls() {
+        let mut session = TestSession::open();
+        session.status("create table l (k double, a int)");
+        session.status("create table r (k double, b int)");
+        session.status("create table li (k int, a int)");
+        session.status("create table ri (k int, b int, create index hash rik on (k))");
+        for k in 1..=3 {
+            session.status(&format!("insert into l (k, a) values ({k}.0, {k})"));
+            session.status(&format!("insert into li (k, a) values ({k}, {k})"));
+        }
+        for k in 2..=4 {
+            session.status(&format!("insert into r (k, b) values ({k}.0, {k}0)"));
+            session.status(&format!("insert into ri (k, b) values ({k}, {k}0)"));
+        }
+        let matched = [("2", "20"), ("3", "30")];
+        let left = [[("1", "NULL")].as_slice(), &matched].concat();
+        let right = [&matched, [("NULL", "40")].as_slice()].concat();
+        let full = [left.as_slice(), &right[2..]].concat();
+        for (join, expected) in [("left", &left), ("right", &right), ("full", &full)] {
+            let query = format!("select l.a, r.b from l {join} outer join r on l.k == r.k");
+            assert!(plan(&mut session, &query).contains("Merge Join"));
+            assert_eq!(session.sorted_rows(&query), pairs(expected));
+        }
+        let query = "select li.a, ri.b from li left outer join ri on li.k == ri.k";
+        assert!(plan(&mut session, query).contains("Indexed Nested Loop Join LEFT"));
+        assert_eq!(session.sorted_rows(query), pairs(&left));
+    }
+
+    // DECIMAL values are filtered and summed exactly to the cent, every group getting its sum. A
+    // value too long for an expression to compare exactly fails the query instead of being
+    // rounded, as a sum out of range does
+    #[test]
+    fn test_decimal_filter_and_sum() {
+        let mut session = TestSession::open();
+        session.status("create table pay (id int, acc int, amount decimal(38, 2))");
+        let payments = [
+            (1, 1, "0.10"),
+            (2, 1, "0.20"),
+            (3, 1, "0.30"),
+            (4, 2, "1234567.89"),
+            (5, 2, "0.01"),
+            (6, 3, "-5.50"),
+        ];
+        for (id, acc, amount) in payments {
+            let insert =
+                format!("insert into pay (id, acc, amount) values ({id}, {acc}, {amount})");
+            assert!(matches!(session.status(&insert), Status::RecordInserted));
+        }
+        let query = "select id from pay where amount == 0.3 or amount > 1234567.88";
+        assert_eq!(session.sorted_rows(query), [["3"], ["4"]]);
+        // the sum comes before the account, the fields are in the order of their names
+        let query = "select acc, sum(amount) from pay group by acc";
+        let sums = [("-5.50", "3"), ("0.60", "1"), ("1234567.90", "2")];
+        assert_eq!(session.sorted_rows(query), pairs(&sums));
+        let query = "select acc, sum(amount) from pay where amount < 1 group by acc";
+        let sums = [("-5.50", "3"), ("0.01", "2"), ("0.60", "1")];
+        assert_eq!(session.sorted_rows(query), pairs(&sums));
+
+        let insert = "insert into pay (id, acc, amount) values (7, 4, 12345678901234567.89)";
+        assert!(matches!(session.status(insert), Status::RecordInserted));
+        let failed = |status| matches!(status, Status::Generic(e) if e.contains("exactly"));
+        assert!(failed(session.status("select id from pay where amount > 1")));
+        assert!(failed(session.status("delete from pay where amount > 1")));
+        assert_eq!(session.rows("select id from pay where id == 7"), [["7"]]);
+
+        session.status("create table big (g int, v decimal(38, 0))");
+        let largest = "9".repeat(38);
+        for _ in 0..2 {
+            let insert = format!("insert into big (g, v) values (1, {largest})");
+            assert!(matches!(session.status(&insert), Status::RecordInserted));
+        }
+        let status = session.status("select g, sum(v) from big group by g");
+        assert!(matches!(status, Status::Generic(e) if e.contains("out of range")));
     }
 }
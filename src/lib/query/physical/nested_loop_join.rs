@@ -0,0 +1,181 @@
+use super::hash_join::{read_build_input, SpillFile, SpillReader};
+use super::utils::{merge, null_row, JoinFilter};
+use super::{PhysicalNode, TypeMap};
+use crate::query::MergedRow;
+use crate::sql::query::select::JoinType;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+
+/// The right input of a nested loop join, read once and scanned again for every left block
+enum InnerInput {
+    Memory(Vec<MergedRow>),
+    Spilled(SpillReader),
+}
+
+impl InnerInput {
+    /// The row at `pos` of a scan, the scan must ask for the positions in order from 0
+    fn get(&mut self, pos: usize) -> Option<MergedRow> {
+        match self {
+            InnerInput::Memory(rows) => rows.get(pos).cloned(),
+            InnerInput::Spilled(reader) => {
+                if pos == 0 {
+                    reader.rewind();
+                }
+                reader.next()
+            }
+        }
+    }
+}
+
+/// Block nested loop join, evaluates the whole join condition on every pair of rows.
+///
+/// Used for the conditions without any `left.x == right.y` clause to join on.
+/// The left input is read in blocks of `MAX_WORKING_MEM` and the right input is scanned once per
+/// block, from memory or from a temporary file when it does not fit.
+/// Both sides keep track of their matched rows so outer joins can pad the others with NULLs
+pub struct NestedLoopJoin {
+    fields_map: TypeMap,
+    pub(super) join_type: JoinType,
+    pub(super) left: Box<PhysicalNode>,
+    pub(super) right: Box<PhysicalNode>,
+    pub(super) filter: JoinFilter,
+    left_nulls: MergedRow,
+    right_nulls: MergedRow,
+    inner: Option<InnerInput>,
+    // indexed by the position of the right rows in a scan of the inner input
+    right_matched: Vec<bool>,
+    block: Vec<(MergedRow, bool)>,
+    inner_pos: usize,
+    // the current block holds the last rows of the left input
+    left_done: bool,
+    pending: VecDeque<MergedRow>,
+}
+
+impl Debug for NestedLoopJoin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NestedLoopJoin")
+            .field("join_type", &self.join_type)
+            .field("filter", &self.filter)
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .finish()
+    }
+}
+
+impl NestedLoopJoin {
+    pub fn new(
+        fields_map: TypeMap,
+        join_type: JoinType,
+        left: Box<PhysicalNode>,
+        right: Box<PhysicalNode>,
+        filter: JoinFilter,
+        left_headers: TypeMap,
+        right_headers: TypeMap,
+    ) -> Self {
+        Self {
+            fields_map,
+            join_type,
+            left,
+            right,
+            filter,
+            left_nulls: null_row(&left_headers),
+            right_nulls: null_row(&right_headers),
+            inner: None,
+            right_matched: vec![],
+            block: vec![],
+            inner_pos: 0,
+            left_done: false,
+            pending: VecDeque::new(),
+        }
+    }
+    pub fn get_type_map(&self) -> TypeMap {
+        self.fields_map.clone()
+    }
+
+    /// Reads the whole right input, spilling it to a file if it does not fit in memory
+    fn load_inner(&mut self) {
+        let inner = match read_build_input(self.right.as_mut()) {
+            Ok(rows) => {
+                self.right_matched = vec![false; rows.len()];
+                InnerInput::Memory(rows)
+            }
+            Err(rows) => {
+                let mut file = SpillFile::new("nested_loop");
+                for row in rows.into_iter().chain(self.right.by_ref()) {
+                    file.push(&row);
+                    self.right_matched.push(false);
+                }
+                InnerInput::Spilled(file.into_reader())
+            }
+        };
+        self.inner = Some(inner);
+    }
+
+    /// Reads the next block of left rows, empty once the left input is exhausted
+    fn next_block(&mut self) {
+        self.block = if self.left_done {
+            vec![]
+        } else {
+            let rows = match read_build_input(self.left.as_mut()) {
+                Ok(rows) => {
+                    self.left_done = true;
+                    rows
+                }
+                Err(rows) => rows,
+            };
+            rows.into_iter().map(|row| (row, false)).collect()
+        };
+        self.inner_pos = 0;
+    }
+}
+
+impl Iterator for NestedLoopJoin {
+    type Item = MergedRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.inner.is_none() {
+            self.load_inner();
+            self.next_block();
+        }
+        loop {
+            if let Some(row) = self.pending.pop_front() {
+                return Some(row);
+            }
+            if self.block.is_empty() {
+                break;
+            }
+            match self.inner.as_mut().unwrap().get(self.inner_pos) {
+                Some(right) => {
+                    for (left, matched) in self.block.iter_mut() {
+                        let row = merge(left, &right);
+                        if self.filter.accepts(&row) {
+                            *matched = true;
+                            self.right_matched[self.inner_pos] = true;
+                            self.pending.push_back(row);
+                        }
+                    }
+                    self.inner_pos += 1;
+                }
+                None => {
+                    if self.join_type.keeps_left() {
+                        for (left, _) in self.block.iter().filter(|(_, matched)| !matched) {
+                            self.pending.push_back(merge(left, &self.right_nulls));
+                        }
+                    }
+                    self.next_block();
+                }
+            }
+        }
+        // every left block was joined, only the right rows that never matched are left
+        if !self.join_type.keeps_right() {
+            return None;
+        }
+        while let Some(right) = self.inner.as_mut().unwrap().get(self.inner_pos) {
+            self.inner_pos += 1;
+            if !self.right_matched[self.inner_pos - 1] {
+                return Some(merge(&self.left_nulls, &right));
+            }
+        }
+        None
+    }
+}
@@ -1,9 +1,12 @@
 use crate::common::boolean;
 use crate::common::boolean::*;
+use crate::common::{binary, temporal};
 use crate::database::plan_query::PlannerInfo;
+use crate::index::{composite_key, composite_key_range};
 use crate::query::algebra as Logical;
 use crate::query::physical as Physical;
 use crate::query::physical::PhysicalNode::AccessPath;
+use crate::sql::create_table::IndexType;
 use crate::table::tablemgr::TableManager;
 use std::str::FromStr;
 use Logical::*;
@@ -77,20 +80,25 @@ impl FromLogicalNode<Logical::Select> for Physical::Select {
         } = value;
         let child_is_base_rel = child.is_base_relation();
         let mut ctx_map = HashMapContext::new();
-        let shortcut = if context_vars.len() == 1
-            && child_is_base_rel
-            && boolean::get_all_binary_clauses(&condition).len() == 1
-        {
+        for f in &context_vars {
+            ctx_map.set_value(f.to_string(), Value::Empty);
+        }
+        temporal::add_functions(&mut ctx_map);
+        binary::add_functions(&mut ctx_map);
+        let clauses = boolean::get_all_binary_clauses(&condition);
+        let shortcut = if context_vars.len() == 1 && child_is_base_rel && clauses.len() == 1 {
             let children = condition.children();
-            if children.len() == 1 {
+            // the comparison has to be the whole condition, not a part of an arithmetic operand
+            if children.len() == 1 && children[0] == clauses[0] {
                 let key_type = fields_map.get(&context_vars[0]).unwrap();
                 let FieldId { table, field } = context_vars[0].clone();
                 let tbl_mgr = db_tables.get(&table).unwrap();
                 let (op, val) = get_single_binary_clause(&children[0]);
-                let val = boolean::value_as_bytes(&val, *key_type);
-                match op {
-                    Eq => {
-                        if let Some(hash) = tbl_mgr.hashscan_iter(&field) {
+                match (op, boolean::value_as_bytes(&val, *key_type)) {
+                    (Eq, Some(val)) => {
+                        if let Some(hash) =
+                            tbl_mgr.hashscan_iter(&field, planner_info.snapshot.clone())
+                        {
                             Some((
                                 PhysicalNode::AccessPath(Box::new(AccessMethod::HashIter(
                                     table, hash,
@@ -98,24 +106,28 @@ impl FromLogicalNode<Logical::Select> for Physical::Select {
                                 val,
                             ))
                         } else {
-                            tbl_mgr.btree_iter(&field, Eq).map(|mut btree| {
-                                (
-                                    PhysicalNode::AccessPath(Box::new(AccessMethod::BtreeIter(
-                                        table, btree,
-                                    ))),
-                                    val,
-                                )
-                            })
+                            tbl_mgr
+                                .btree_iter(&field, Eq, planner_info.snapshot.clone())
+                                .map(|mut btree| {
+                                    (
+                                        PhysicalNode::AccessPath(Box::new(
+                                            AccessMethod::BtreeIter(table, btree),
+                                        )),
+                                        val,
+                                    )
+                                })
                         }
                     }
-                    Lt | Gt | Leq | Geq => tbl_mgr.btree_iter(&field, op).map(|mut btree| {
-                        (
-                            PhysicalNode::AccessPath(Box::new(AccessMethod::BtreeIter(
-                                table, btree,
-                            ))),
-                            val,
-                        )
-                    }),
+                    (op @ (Lt | Gt | Leq | Geq), Some(val)) => tbl_mgr
+                        .btree_iter(&field, op, planner_info.snapshot.clone())
+                        .map(|mut btree| {
+                            (
+                                PhysicalNode::AccessPath(Box::new(AccessMethod::BtreeIter(
+                                    table, btree,
+                                ))),
+                                val,
+                            )
+                        }),
                     _ => None,
                 }
             } else {
@@ -124,6 +136,12 @@ impl FromLogicalNode<Logical::Select> for Physical::Select {
         } else {
             None
         };
+        let shortcut = match shortcut {
+            None if child_is_base_rel => {
+                composite_index_shortcut(&condition, &context_vars, planner_info, db_tables)
+            }
+            shortcut => shortcut,
+        };
         if let Some((child, key)) = shortcut {
             Physical::Select {
                 fields_map,
@@ -131,23 +149,106 @@ impl FromLogicalNode<Logical::Select> for Physical::Select {
                 context: ctx_map,
                 child: Box::new(child),
                 bridged: (false, Some(key)),
+                error: None,
             }
         } else {
             let child = PhysicalNode::from_logic(*child, planner_info, db_tables);
-            for f in context_vars {
-                ctx_map.set_value(f.to_string(), Value::Empty);
-            }
             Physical::Select {
                 fields_map,
                 condition,
                 context: ctx_map,
                 child: Box::new(child),
                 bridged: (false, None),
+                error: None,
             }
         }
     }
 }
 
+/// Picks the index on several columns of the table under a Select whose key prefix the condition
+/// fixes the furthest, with equalities on its first columns then a comparison on the next one.
+/// A hash index needs an equality on each of its columns.
+///
+/// Returns the access path over the index and the key to load in it
+fn composite_index_shortcut(
+    condition: &evalexpr::Node,
+    context_vars: &[FieldId],
+    planner_info: &PlannerInfo,
+    db_tables: &HashMap<String, TableManager>,
+) -> Option<(PhysicalNode, Vec<u8>)> {
+    use evalexpr::Operator::*;
+    let table = context_vars.first()?.table.clone();
+    let tbl_mgr = db_tables.get(&table)?;
+    let layout = tbl_mgr.get_layout();
+    let comparisons = split_conjunction(condition)
+        .iter()
+        .filter_map(|clause| {
+            let (identifier, op, value) = field_comparison(clause)?;
+            let FieldId { table: t, field } = identifier.parse::<FieldId>().ok()?;
+            if t != table || !tbl_mgr.field_exists(&field) {
+                return None;
+            }
+            let field_type = layout.get_type(&field);
+            let bytes = value_as_field_bytes(value, field_type)?;
+            Some((field, op, bytes, field_type))
+        })
+        .collect::<Vec<_>>();
+    let equality = |column: &str| {
+        comparisons
+            .iter()
+            .find(|(field, op, ..)| field == column && *op == Eq)
+            .map(|(_, _, bytes, field_type)| (Some(bytes.as_slice()), *field_type))
+    };
+    let range = |column: Option<&&str>| {
+        comparisons
+            .iter()
+            .find(|(field, op, ..)| Some(&field.as_str()) == column && *op != Eq)
+            .map(|(_, op, bytes, field_type)| (op.clone(), bytes.as_slice(), *field_type))
+    };
+    let mut best = None;
+    let mut best_score = 0;
+    for (index_type, columns) in tbl_mgr.composite_indexes() {
+        let prefix = columns
+            .iter()
+            .map_while(|column| equality(column))
+            .collect::<Vec<_>>();
+        let range = range(columns.get(prefix.len()));
+        // a hash index wins over a btree index on the same equalities
+        let score = match index_type {
+            IndexType::Hash if prefix.len() == columns.len() => 2 * prefix.len() + 1,
+            IndexType::Hash => 0,
+            IndexType::Btree => 2 * prefix.len() + usize::from(range.is_some()),
+        };
+        if score > best_score {
+            best_score = score;
+            best = Some((index_type, columns.join(","), prefix, range));
+        }
+    }
+    let (index_type, columns, prefix, range) = best?;
+    let snapshot = planner_info.snapshot.clone();
+    match index_type {
+        IndexType::Hash => tbl_mgr.hashscan_iter(&columns, snapshot).map(|hash| {
+            (
+                PhysicalNode::AccessPath(Box::new(AccessMethod::HashIter(table, hash))),
+                composite_key(&prefix),
+            )
+        }),
+        IndexType::Btree => {
+            let op = range.as_ref().map_or(Eq, |(op, ..)| op.clone());
+            let (low, high) = composite_key_range(&prefix, range);
+            tbl_mgr.btree_iter(&columns, op, snapshot).map(|btree| {
+                (
+                    PhysicalNode::AccessPath(Box::new(AccessMethod::BtreeIter(
+                        table,
+                        btree.bounded(high),
+                    ))),
+                    low,
+                )
+            })
+        }
+    }
+}
+
 impl FromLogicalNode<Logical::DeDuplicate> for Physical::DeDup {
     fn from_logic(
         value: DeDuplicate,
@@ -188,7 +289,7 @@ impl FromLogicalNode<Logical::BaseRelation> for PhysicalNode {
         db_tables: &HashMap<String, TableManager>,
     ) -> Self {
         let tbl_mgr = db_tables.get(&value.name).unwrap();
-        let iter = tbl_mgr.heapscan_iter();
+        let iter = tbl_mgr.heapscan_iter(planner_info.snapshot.clone());
         PhysicalNode::AccessPath(Box::new(AccessMethod::HeapIter(value.name, iter)))
     }
 }
@@ -206,56 +307,127 @@ impl FromLogicalNode<Logical::Join> for PhysicalNode {
             right,
             fields_map,
         } = value;
-        let mut identifiers = condition
-            .iter_read_variable_identifiers()
-            .map(|f| FieldId::from_str(f).unwrap())
-            .collect::<Vec<_>>();
-        let left_field = identifiers.pop().unwrap();
-        let right_field = identifiers.pop().unwrap();
+        let right_table = match right.as_ref() {
+            LogicalNode::Relation(r) => r.name.clone(),
+            _ => unreachable!("the right input of a join is always a base relation"),
+        };
         let left_field_map = left.get_fields_map();
+        let right_field_map = right.get_fields_map();
+        // `left.x == right.y` clauses become the join keys, everything else is checked on the
+        // joined rows
+        let mut eq_fields = vec![];
+        let mut residual = vec![];
+        for clause in split_conjunction(&condition) {
+            let key = variables_equality(&clause).and_then(|(first, second)| {
+                let first = FieldId::from_str(first).unwrap();
+                let second = FieldId::from_str(second).unwrap();
+                if left_field_map.contains_key(&first) && second.table == right_table {
+                    Some((first, second))
+                } else if left_field_map.contains_key(&second) && first.table == right_table {
+                    Some((second, first))
+                } else {
+                    None
+                }
+            });
+            match key {
+                Some(key) => eq_fields.push((key, clause)),
+                None => residual.push(clause),
+            }
+        }
+        let left_blocks = left.estimated_blocks(planner_info);
+        let right_blocks = right.estimated_blocks(planner_info);
         let left_child = Box::new(PhysicalNode::from_logic(*left, planner_info, db_tables));
-        let tbl_mgr = db_tables.get(&right_field.table).unwrap();
-        if planner_info
-            .table_info
-            .get(&right_field.table)
-            .unwrap()
-            .has_index_for(&right_field.field)
-        {
-            let access = if let Some(iter) = tbl_mgr.hashscan_iter(&right_field.field) {
-                AccessMethod::HashIter(right_field.table.clone(), iter)
-            } else {
-                let btree = tbl_mgr
-                    .btree_iter(&right_field.field, evalexpr::Operator::Eq)
-                    .unwrap();
-                AccessMethod::BtreeIter(right_field.table.clone(), btree)
-            };
+        let tbl_mgr = db_tables.get(&right_table).unwrap();
+        let snapshot = planner_info.snapshot.clone();
+        let heap_access = || {
+            Box::new(AccessPath(Box::new(AccessMethod::HeapIter(
+                right_table.clone(),
+                tbl_mgr.heapscan_iter(snapshot.clone()),
+            ))))
+        };
+        if eq_fields.is_empty() {
+            let filter = JoinFilter::new(residual, &fields_map);
+            return PhysicalNode::NestedLoopJoin(NestedLoopJoin::new(
+                fields_map,
+                join_type,
+                left_child,
+                heap_access(),
+                filter,
+                left_field_map,
+                right_field_map,
+            ));
+        }
+        // an index probe only sees the right rows matching some left row, so it can not produce
+        // the unmatched right rows of RIGHT and FULL joins.
+        // Probing once per left row only beats scanning the right table while the left is smaller
+        let right_info = planner_info.table_info.get(&right_table).unwrap();
+        let indexed_key = eq_fields
+            .iter()
+            .position(|((_, right_field), _)| right_info.has_index_for(&right_field.field))
+            .filter(|_| !join_type.keeps_right() && left_blocks <= right_blocks);
+        if let Some(idx) = indexed_key {
+            // the index answers a single key, the other ones are checked like the residual
+            let ((left_field, right_field), _) = eq_fields.remove(idx);
+            residual.extend(eq_fields.into_iter().map(|(_, clause)| clause));
+            let filter = JoinFilter::new(residual, &fields_map);
+            let access =
+                if let Some(iter) = tbl_mgr.hashscan_iter(&right_field.field, snapshot.clone()) {
+                    AccessMethod::HashIter(right_table.clone(), iter)
+                } else {
+                    let btree = tbl_mgr
+                        .btree_iter(&right_field.field, evalexpr::Operator::Eq, snapshot.clone())
+                        .unwrap();
+                    AccessMethod::BtreeIter(right_table.clone(), btree)
+                };
             let access = Box::new(AccessPath(Box::new(access)));
-            PhysicalNode::IndexedLoopJoin(IndexedJoin::new(
+            return PhysicalNode::IndexedLoopJoin(IndexedJoin::new(
                 fields_map,
+                join_type,
                 (left_field, right_field),
+                filter,
                 left_child,
                 access,
+                right_field_map,
+            ));
+        }
+        let filter = JoinFilter::new(residual, &fields_map);
+        let eq_fields = eq_fields
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        // hashing compares the raw bytes of the keys, that only agrees with their equality when
+        // both keys share a type that is not floating point (0.0 and -0.0 are equal)
+        let hashable = eq_fields.iter().all(|(left_field, right_field)| {
+            let key_type = fields_map.get(left_field).unwrap();
+            key_type == fields_map.get(right_field).unwrap()
+                && !matches!(
+                    key_type,
+                    Type::Numeric(NumericType::Single) | Type::Numeric(NumericType::Double)
+                )
+        });
+        if hashable {
+            PhysicalNode::HashJoin(HashJoin::new(
+                fields_map,
+                join_type,
+                left_child,
+                heap_access(),
+                eq_fields,
+                filter,
+                left_blocks < right_blocks,
+                left_field_map,
+                right_field_map,
             ))
         } else {
-            let heap_iter = tbl_mgr.heapscan_iter();
-            let access = Box::new(AccessPath(Box::new(AccessMethod::HeapIter(
-                right_field.table.clone(),
-                heap_iter,
-            ))));
-            // let left_field_map = fields_map.iter().filter(|(f,_)| f.table == left_field.table).map(|(f,t)| (f.clone(),*t)).collect();
-            let right_field_map = fields_map
-                .iter()
-                .filter(|(f, _)| f.table == right_field.table)
-                .map(|(f, t)| (f.clone(), *t))
-                .collect();
-            PhysicalNode::MergeJoin(MergeJoin::new(
+            PhysicalNode::MergeJoin(Box::new(MergeJoin::new(
                 fields_map,
+                join_type,
                 left_child,
-                access,
-                (left_field, right_field),
+                heap_access(),
+                eq_fields,
+                filter,
                 left_field_map,
                 right_field_map,
-            ))
+            )))
         }
     }
 }
@@ -278,3 +450,82 @@ impl FromLogicalNode<Logical::GroupBy> for Physical::Grouper {
 }
 
 // fn transform_logical(root: LogicalNode) {}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::testing::{TestHome, TestSession};
+    use std::fs;
+
+    /// The plan of a query as a single text
+    fn plan(session: &mut TestSession, query: &str) -> String {
+        let lines = session.rows(&format!("explain {query}"));
+        lines.concat().join("\n")
+    }
+
+    // Joins on two key fields, with and without a residual condition, on tables larger than the
+    // working memory so the hash join partitions its inputs to disk. A join with no equal keys runs
+    // as a nested loop join
+    #[test]
+    fn test_composite_and_non_equi_joins() {
+        let home = TestHome::get();
+        let _tmp = home.tmp.lock().unwrap();
+        let mut session = TestSession::open();
+        session.status("create table a (x int, y int, v int, pad varchar)");
+        session.status("create table b (x int, y int, w int, pad varchar)");
+        let pad = "p".repeat(40);
+        let a = (0..600).map(|i| (i % 10, i % 7, i)).collect::<Vec<_>>();
+        let b = (0..600)
+            .map(|i| (i % 10, i % 5, 599 - i))
+            .collect::<Vec<_>>();
+        for (x, y, v) in a.iter() {
+            session.status(&format!(
+                "insert into a (x, y, v, pad) values ({x}, {y}, {v}, \"{pad}\")"
+            ));
+        }
+        for (x, y, w) in b.iter() {
+            session.status(&format!(
+                "insert into b (x, y, w, pad) values ({x}, {y}, {w}, \"{pad}\")"
+            ));
+        }
+        let expected = |residual: fn(i32, i32) -> bool, keep_left: bool| {
+            let mut rows = vec![];
+            for (ax, ay, v) in a.iter() {
+                let before = rows.len();
+                for (bx, by, w) in b.iter() {
+                    if (ax, ay) == (bx, by) && residual(*v, *w) {
+                        rows.push(vec![v.to_string(), w.to_string()]);
+                    }
+                }
+                if keep_left && rows.len() == before {
+                    rows.push(vec![v.to_string(), "NULL".to_string()]);
+                }
+            }
+            rows.sort();
+            rows
+        };
+        let keys = "a.x == b.x and a.y == b.y";
+        for (join, keep_left) in [("join", false), ("left join", true)] {
+            let query = format!("select a.v, b.w from a {join} b on {keys}");
+            assert!(plan(&mut session, &query).contains("Hash Join"));
+            assert_eq!(
+                session.sorted_rows(&query),
+                expected(|_, _| true, keep_left)
+            );
+            let query = format!("select a.v, b.w from a {join} b on {keys} and a.v < b.w");
+            let filtered = plan(&mut session, &query);
+            assert!(filtered.contains("Hash Join") && filtered.contains("Filter: a.v < b.w"));
+            assert_eq!(
+                session.sorted_rows(&query),
+                expected(|v, w| v < w, keep_left)
+            );
+        }
+        // every partition file was removed once joined
+        assert_eq!(fs::read_dir(home.tmp_dir()).unwrap().count(), 0);
+
+        let query = "select a.v, b.w from a join b on a.v < b.w where a.v < 3 and b.w < 3";
+        assert!(plan(&mut session, query).contains("Nested Loop Join"));
+        let pairs = [("0", "1"), ("0", "2"), ("1", "2")];
+        let pairs = pairs.map(|(v, w)| vec![v.to_string(), w.to_string()]);
+        assert_eq!(session.sorted_rows(query), pairs.to_vec());
+    }
+}
@@ -1,24 +1,35 @@
 use super::{MergedRow, TypeMap};
+use crate::common::binary;
 use crate::common::numerical::ByteMagic;
+use crate::common::{decimal, temporal};
 use crate::query::concrete_types::ConcreteType;
-use crate::schema::types::{NumericType, Type};
+use crate::schema::types::{CharType, NumericType, Type};
 use crate::FieldId;
 use evalexpr::{
     ContextWithMutableVariables, FloatType, HashMapContext, IntType, IterateVariablesContext, Value,
 };
 use std::collections::HashMap;
 
-pub fn fill_ctx_map(ctx: &mut HashMapContext, row: &MergedRow, type_map: &TypeMap) {
+pub fn fill_ctx_map(
+    ctx: &mut HashMapContext,
+    row: &MergedRow,
+    type_map: &TypeMap,
+) -> Result<(), String> {
     for table_var in ctx.iter_variable_names().collect::<Vec<_>>() {
         let field_id = table_var.parse::<FieldId>().unwrap();
         let val = row.get(&field_id).unwrap();
-        let val = data_to_value(val.as_ref(), *type_map.get(&field_id).unwrap());
+        let val = data_to_value(val.as_ref(), *type_map.get(&field_id).unwrap())?;
         ctx.update_or_set_value(table_var, val);
     }
+    Ok(())
 }
-pub fn data_to_value(data: Option<&Vec<u8>>, schema_type: Type) -> Value {
+/// The value of a field in an expression, NULL is the empty value.
+///
+/// Fails for a decimal an expression cannot hold exactly, a decimal with no scale is an integer
+/// and one with a scale a float, which has to give back the same digits
+pub fn data_to_value(data: Option<&Vec<u8>>, schema_type: Type) -> Result<Value, String> {
     if let Some(data) = data {
-        match schema_type {
+        Ok(match schema_type {
             Type::Numeric(n) => match n {
                 NumericType::SmallInt => Value::Int(IntType::from(data.to_i16())),
                 NumericType::Integer => Value::Int(IntType::from(data.to_i32())),
@@ -26,13 +37,38 @@ pub fn data_to_value(data: Option<&Vec<u8>>, schema_type: Type) -> Value {
                 NumericType::Single => Value::Float(data.to_f32() as FloatType),
                 NumericType::Double => Value::Float(data.to_f64() as FloatType),
                 NumericType::Serial => Value::Int(IntType::from(data.to_i32())),
+                NumericType::Decimal(_, scale) => decimal_value(data.to_i128(), scale)?,
             },
+            // the blanks padding a char are not part of its value
+            Type::Character(CharType::Char(_)) => {
+                let text = String::from_utf8(data.to_vec()).unwrap();
+                Value::String(text.trim_end_matches(' ').to_string())
+            }
             Type::Character(c) => Value::String(String::from_utf8(data.to_vec()).unwrap()),
             Type::Boolean => Value::Boolean(data[0] == 1),
+            Type::Temporal(t) => Value::Int(temporal::from_bytes(t, data)),
+            Type::Binary => Value::String(binary::format(data)),
+        })
+    } else {
+        Ok(Value::Empty)
+    }
+}
+
+fn decimal_value(digits: i128, scale: u8) -> Result<Value, String> {
+    if scale == 0 {
+        if let Ok(integer) = IntType::try_from(digits) {
+            return Ok(Value::Int(integer));
         }
     } else {
-        Value::Empty
+        let float = decimal::to_f64(digits, scale);
+        if decimal::parse_exact(&float.to_string(), scale) == Some(digits) {
+            return Ok(Value::Float(float));
+        }
     }
+    Err(format!(
+        "{} has too many digits to be compared exactly",
+        decimal::format(digits, scale)
+    ))
 }
 
 pub fn row_to_merged_row(table: &str, row: HashMap<String, Option<Vec<u8>>>) -> MergedRow {
@@ -48,4 +84,63 @@ pub fn merge(left: &MergedRow, right: &MergedRow) -> MergedRow {
     left
 }
 
+/// The part of a join condition that is not used as join key, checked on every joined pair
+///
+/// A pair failing it counts as not matched, so outer joins still pad its rows with NULLs
+#[derive(Debug)]
+pub struct JoinFilter {
+    clauses: Vec<evalexpr::Node>,
+    context: HashMapContext,
+    fields_map: TypeMap,
+    // why a pair could not be checked, it counts as not matched
+    error: Option<String>,
+}
+
+impl JoinFilter {
+    pub fn new(clauses: Vec<evalexpr::Node>, fields_map: &TypeMap) -> Self {
+        let mut context = HashMapContext::new();
+        temporal::add_functions(&mut context);
+        binary::add_functions(&mut context);
+        for var in clauses
+            .iter()
+            .flat_map(|c| c.iter_read_variable_identifiers())
+        {
+            context.set_value(var.to_string(), Value::Empty).unwrap();
+        }
+        Self {
+            clauses,
+            context,
+            fields_map: fields_map.clone(),
+            error: None,
+        }
+    }
+    pub fn clauses(&self) -> &[evalexpr::Node] {
+        &self.clauses
+    }
+    /// Why the first pair that could not be checked failed
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+    pub fn accepts(&mut self, row: &MergedRow) -> bool {
+        if self.clauses.is_empty() {
+            return true;
+        }
+        if let Err(error) = fill_ctx_map(&mut self.context, row, &self.fields_map) {
+            self.error.get_or_insert(error);
+            return false;
+        }
+        // comparisons against NULL fail to evaluate, the pair does not match
+        self.clauses.iter().all(|clause| {
+            clause
+                .eval_boolean_with_context(&self.context)
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// A row of the given fields all set to NULL, pads the missing side of outer joins
+pub fn null_row(fields: &TypeMap) -> MergedRow {
+    fields.keys().map(|field| (field.clone(), None)).collect()
+}
+
 pub fn qualify_type_map() {}
@@ -17,7 +17,7 @@ impl NullBitMap {
 
     /// Sets the bit of a certain Null field by it's index
     pub fn set_null_field(&mut self, fld_index: usize) {
-        self.bitmap[fld_index / 8] |= 1 << fld_index;
+        self.bitmap[fld_index / 8] |= 1 << (fld_index % 8);
     }
 
     pub fn bitmap(&mut self) -> &mut Vec<u8> {
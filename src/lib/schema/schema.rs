@@ -1,11 +1,19 @@
-use super::types::Type;
+use super::types::{CharType, NumericType, Type};
+use crate::common::numerical::ByteMagic;
+use crate::common::{binary, temporal};
 use crate::index::IndexInfo;
-use crate::sql::create_table::IndexType;
+use crate::query::physical::utils::data_to_value;
+use crate::sql::create_table::{IndexType, OnDelete};
+use crate::storage::tuple::TUPLE_HEADER_SIZE;
+use evalexpr::{build_operator_tree, ContextWithMutableVariables, HashMapContext};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::str::FromStr;
 
-#[derive(Debug)]
+/// Length assumed for the values of variable length fields when estimating tuple sizes
+const AVG_VARLEN_SIZE: usize = 16;
+
+#[derive(Clone, Debug)]
 /// Vector of fields that are in a table (tuple)
 pub struct Schema {
     name: String,
@@ -53,6 +61,30 @@ impl Schema {
             char_limit,
         ))
     }
+    /// Sets what deleting a referenced row does to the rows referencing it through a field
+    pub fn set_on_delete(&mut self, name: &str, on_delete: OnDelete) {
+        for field in self.fields.iter_mut().filter(|f| f.name == name) {
+            field.on_delete = on_delete;
+        }
+    }
+    /// Sets the value a field gets when an insert leaves it out
+    pub fn set_default(&mut self, name: &str, default: Option<Vec<u8>>) {
+        for field in self.fields.iter_mut().filter(|f| f.name == name) {
+            field.default = default.clone();
+        }
+    }
+    /// Sets the sequence a field takes its value from when an insert leaves it out
+    pub fn set_sequence(&mut self, name: &str, sequence: String) {
+        for field in self.fields.iter_mut().filter(|f| f.name == name) {
+            field.sequence = Some(sequence.clone());
+        }
+    }
+    /// Sets the CHECK condition of a field
+    pub fn set_check(&mut self, name: &str, condition: String) {
+        for field in self.fields.iter_mut().filter(|f| f.name == name) {
+            field.check = Some(condition.clone());
+        }
+    }
     pub fn add_field_default_constraints(
         &mut self,
         name: &str,
@@ -61,15 +93,28 @@ impl Schema {
     ) {
         self.add_field(name, field_type, true, false, None, char_limit);
     }
-    pub fn add_index(&mut self, index_name: &str, fieldname: &str, index_type: IndexType) {
-        let key_type = self
-            .fields
-            .iter()
-            .find(|f| f.name == fieldname)
-            .unwrap()
-            .field_type;
-        self.indexes
-            .push(FieldIndex::new(index_name, fieldname, index_type, key_type))
+    pub fn add_index(&mut self, index_name: &str, fieldnames: &[String], index_type: IndexType) {
+        let key_type = self.index_key_type(fieldnames);
+        self.indexes.push(FieldIndex::new(
+            index_name,
+            fieldnames.to_vec(),
+            index_type,
+            key_type,
+        ))
+    }
+    /// The type of the keys of an index on some fields, the type of the field for an index on a
+    /// single one. The keys of an index on several fields are encoded as varchars
+    fn index_key_type(&self, fieldnames: &[String]) -> Type {
+        match fieldnames {
+            [fieldname] => {
+                self.fields
+                    .iter()
+                    .find(|f| f.name == *fieldname)
+                    .unwrap()
+                    .field_type
+            }
+            _ => Type::Character(CharType::VarChar(None)),
+        }
     }
 
     pub fn serialize(
@@ -79,22 +124,28 @@ impl Schema {
         Vec<Vec<(String, Option<Vec<u8>>)>>,
     ) {
         let mut ret = Vec::with_capacity(self.fields.len());
-        for field in &self.fields {
+        for (position, field) in self.fields.iter().enumerate() {
             let mut row = [
                 "tablename".to_string(),
                 "fieldname".to_string(),
+                "position".to_string(),
                 "fieldtype".to_string(),
                 "pkey_piece".to_string(),
                 "nullable".to_string(),
                 "unique".to_string(),
                 "foreign_table".to_string(),
                 "foreign_field".to_string(),
+                "on_delete".to_string(),
+                "default_value".to_string(),
+                "check_expr".to_string(),
+                "default_sequence".to_string(),
             ];
             let row = row
                 .into_iter()
                 .zip([
                     Some(self.name.clone().into_bytes()),
                     Some(field.name.clone().into_bytes()),
+                    Some((position as i16).to_ne_bytes().to_vec()),
                     Some(field.field_type.to_string().into_bytes()),
                     Some(if self.primary_key.contains(&field.name) {
                         1_u8.to_ne_bytes().to_vec()
@@ -119,6 +170,13 @@ impl Schema {
                         .foreign_reference
                         .as_ref()
                         .map(|(_, f_col)| f_col.clone().into_bytes()),
+                    field
+                        .foreign_reference
+                        .as_ref()
+                        .map(|_| field.on_delete.to_string().into_bytes()),
+                    field.default.clone(),
+                    field.check.clone().map(String::into_bytes),
+                    field.sequence.clone().map(String::into_bytes),
                 ])
                 .collect();
             // dbg!(&row);
@@ -127,10 +185,13 @@ impl Schema {
         (ret, self.serialize_indexes())
     }
 
+    /// Builds a schema back from its catalog rows, the fields are ordered by the position stored
+    /// with them since the rows of a table are not kept in the order they were inserted in
     pub fn deserialize(
-        row_bytes: Vec<HashMap<String, Option<Vec<u8>>>>,
+        mut row_bytes: Vec<HashMap<String, Option<Vec<u8>>>>,
         indexes: Vec<HashMap<String, Option<Vec<u8>>>>,
     ) -> Self {
+        row_bytes.sort_by_key(|row| row.get("position").unwrap().as_deref().unwrap().to_i16());
         let mut schema = Self::new();
         schema.set_name(
             String::from_utf8(
@@ -166,24 +227,41 @@ impl Schema {
                 )),
                 _ => unreachable!(),
             };
-            schema.add_field(name.as_str(), datatype, nullable, unique, foreign_ref, None);
+            schema.add_field(
+                name.as_str(),
+                datatype,
+                nullable,
+                unique,
+                foreign_ref,
+                datatype.char_limit(),
+            );
+            if let Some(on_delete) = field.remove("on_delete").unwrap() {
+                let on_delete = OnDelete::from_str(&String::from_utf8(on_delete).unwrap()).unwrap();
+                schema.set_on_delete(&name, on_delete);
+            }
+            schema.set_default(&name, field.remove("default_value").unwrap());
+            if let Some(check) = field.remove("check_expr").unwrap() {
+                schema.set_check(&name, String::from_utf8(check).unwrap());
+            }
+            if let Some(sequence) = field.remove("default_sequence").unwrap() {
+                schema.set_sequence(&name, String::from_utf8(sequence).unwrap());
+            }
             if pkey_piece {
                 schema.primary_key.push(name);
             }
         }
-        let type_map = schema
-            .field_types()
+        let indexes = indexes
             .into_iter()
-            .map(|(k, v)| (k.to_owned(), v))
-            .collect::<HashMap<_, _>>();
-        schema.indexes.extend(indexes.into_iter().map(|idx| {
-            let key_type = *type_map
-                .get(&*String::from_utf8_lossy(
-                    idx.get("fieldname").unwrap().as_ref().unwrap(),
-                ))
-                .unwrap();
-            FieldIndex::deserialize(idx, key_type)
-        }));
+            .map(|idx| {
+                let fieldnames =
+                    String::from_utf8_lossy(idx.get("fieldname").unwrap().as_ref().unwrap())
+                        .split(',')
+                        .map(String::from)
+                        .collect::<Vec<_>>();
+                FieldIndex::deserialize(idx, schema.index_key_type(&fieldnames))
+            })
+            .collect::<Vec<_>>();
+        schema.indexes.extend(indexes);
         schema
     }
     fn serialize_indexes(&self) -> Vec<Vec<(String, Option<Vec<u8>>)>> {
@@ -192,6 +270,164 @@ impl Schema {
             .map(|idx| idx.serialize(self.name()))
             .collect()
     }
+    /// Removes a field and the indexes built on it, alone or with other fields, which are returned
+    pub fn drop_field(&mut self, name: &str) -> Vec<FieldIndex> {
+        self.fields.retain(|f| f.name != name);
+        self.primary_key.retain(|k| k != name);
+        let (dropped, kept) = self
+            .indexes
+            .drain(..)
+            .partition(|idx| idx.fieldnames.iter().any(|f| f == name));
+        self.indexes = kept;
+        dropped
+    }
+    /// Removes an index of the schema and returns it
+    pub fn drop_index(&mut self, name: &str) -> Option<FieldIndex> {
+        let pos = self.indexes.iter().position(|idx| idx.name == name)?;
+        Some(self.indexes.remove(pos))
+    }
+    /// Renames a field, in the primary key and the indexes built on it too
+    pub fn rename_field(&mut self, name: &str, new_name: &str) {
+        for field in self.fields.iter_mut().filter(|f| f.name == name) {
+            field.name = new_name.to_string();
+        }
+        for key in self.primary_key.iter_mut().filter(|k| *k == name) {
+            *key = new_name.to_string();
+        }
+        self.primary_key.sort_unstable();
+        for fieldname in self
+            .indexes
+            .iter_mut()
+            .flat_map(|idx| idx.fieldnames.iter_mut())
+            .filter(|f| *f == name)
+        {
+            *fieldname = new_name.to_string();
+        }
+    }
+    /// Points the foreign references to a renamed table, or to a renamed field of it, at their new
+    /// names and returns whether any reference changed
+    pub fn rename_references(
+        &mut self,
+        table: &str,
+        new_table: &str,
+        field: Option<(&str, &str)>,
+    ) -> bool {
+        let mut renamed = false;
+        for (ref_table, ref_field) in self
+            .fields
+            .iter_mut()
+            .filter_map(|f| f.foreign_reference.as_mut())
+            .filter(|(ref_table, _)| ref_table == table)
+        {
+            match field {
+                Some((name, new_name)) if ref_field == name => *ref_field = new_name.to_string(),
+                Some(_) => continue,
+                None => {}
+            }
+            *ref_table = new_table.to_string();
+            renamed = true;
+        }
+        renamed
+    }
+    /// The fields referencing a field of a table
+    pub fn referencing_fields(&self, table: &str, field: &str) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter(|f| {
+                f.foreign_reference
+                    .as_ref()
+                    .is_some_and(|(t, f)| t == table && f == field)
+            })
+            .map(|f| f.name())
+            .collect()
+    }
+    /// The fields read by a CHECK condition, by the variables naming them in the condition.
+    ///
+    /// Fails unless the condition parses and names fields of the table, which it names alone
+    pub fn check_fields(&self, condition: &str) -> Result<Vec<(String, &Field)>, String> {
+        let tree = build_operator_tree(condition)
+            .map_err(|_| format!("CHECK ({}) is not a condition", condition))?;
+        let fields = self.fields_info();
+        tree.iter_read_variable_identifiers()
+            .map(|var| match fields.get(var) {
+                Some(field) => Ok((var.to_string(), *field)),
+                None if var.contains('.') => Err(format!(
+                    "CHECK ({}) reads {}, a CHECK names the columns without their table",
+                    condition, var
+                )),
+                None => Err(format!("Column {} does not exist", var)),
+            })
+            .collect()
+    }
+    /// The fields whose CHECK reads a field
+    pub fn checks_reading(&self, name: &str) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter(|f| {
+                f.check.as_ref().is_some_and(|condition| {
+                    self.check_fields(condition)
+                        .is_ok_and(|read| read.iter().any(|(_, field)| field.name == name))
+                })
+            })
+            .map(|f| f.name())
+            .collect()
+    }
+    /// Fails unless a row satisfies the CHECK conditions of the fields, a field the row leaves out
+    /// is NULL.
+    ///
+    /// A condition reading a NULL is unknown, which satisfies a CHECK as it does in SQL
+    pub fn check_row(&self, row: &HashMap<String, Option<Vec<u8>>>) -> Result<(), String> {
+        for field in &self.fields {
+            let condition = match &field.check {
+                Some(condition) => condition,
+                None => continue,
+            };
+            let mut context = HashMapContext::new();
+            temporal::add_functions(&mut context);
+            binary::add_functions(&mut context);
+            let mut unknown = false;
+            for (var, read) in self.check_fields(condition)? {
+                let value = row.get(&read.name).and_then(|v| v.as_ref());
+                unknown |= value.is_none();
+                context
+                    .set_value(var, data_to_value(value, read.field_type)?)
+                    .unwrap();
+            }
+            let satisfied = unknown
+                || build_operator_tree(condition)
+                    .and_then(|tree| tree.eval_boolean_with_context(&context))
+                    .unwrap_or(false);
+            if !satisfied {
+                return Err(format!("{} violates CHECK ({})", field.name, condition));
+            }
+        }
+        Ok(())
+    }
+    /// The foreign keys of the fields of the table
+    pub fn foreign_keys(&self) -> Vec<ForeignKey> {
+        self.fields
+            .iter()
+            .filter_map(|f| {
+                let (ref_table, ref_field) = f.foreign_reference.clone()?;
+                Some(ForeignKey {
+                    table: self.name.clone(),
+                    field: f.name.clone(),
+                    ref_table,
+                    ref_field,
+                    on_delete: f.on_delete,
+                })
+            })
+            .collect()
+    }
+    /// The sequences the SERIAL fields of the table own, they go away with their field
+    pub fn owned_sequences(&self) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter(|f| f.field_type == Type::Numeric(NumericType::Serial))
+            .filter_map(|f| f.sequence())
+            .collect()
+    }
+
     /// Convert the schema to a layout
     pub fn to_layout(&self) -> Layout {
         Layout::new(self)
@@ -224,6 +460,10 @@ pub struct Field {
     nullable: bool,
     unique: bool,
     foreign_reference: Option<(String, String)>,
+    on_delete: OnDelete,
+    default: Option<Vec<u8>>,
+    check: Option<String>,
+    sequence: Option<String>,
     char_limit: Option<u32>,
 }
 
@@ -242,6 +482,10 @@ impl Field {
             nullable,
             unique,
             foreign_reference,
+            on_delete: OnDelete::default(),
+            default: None,
+            check: None,
+            sequence: None,
             char_limit,
         }
     }
@@ -263,6 +507,31 @@ impl Field {
     pub fn foreign_reference(&self) -> &Option<(String, String)> {
         &self.foreign_reference
     }
+    pub fn on_delete(&self) -> OnDelete {
+        self.on_delete
+    }
+    /// The value the field gets when an insert leaves it out, NULL without a DEFAULT
+    pub fn default_value(&self) -> Option<&Vec<u8>> {
+        self.default.as_ref()
+    }
+    pub fn check(&self) -> Option<&str> {
+        self.check.as_deref()
+    }
+    /// The sequence giving the field its value when an insert leaves it out, a SERIAL field owns
+    /// the one it takes its values from
+    pub fn sequence(&self) -> Option<&str> {
+        self.sequence.as_deref()
+    }
+}
+
+/// A field of a table referencing a field of another table, or of the same one
+#[derive(Clone, Debug)]
+pub struct ForeignKey {
+    pub table: String,
+    pub field: String,
+    pub ref_table: String,
+    pub ref_field: String,
+    pub on_delete: OnDelete,
 }
 
 /// Entity used to order fields inside a tuple
@@ -315,6 +584,26 @@ impl Layout {
         self.map.keys().len()
     }
 
+    /// Size of an average tuple in a heap page, its tuple pointer included.
+    ///
+    /// Variable length fields are assumed `AVG_VARLEN_SIZE` bytes long, used by the planner to
+    /// estimate the number of tuples from the space used in a table
+    pub fn estimated_tuple_size(&self) -> usize {
+        let fields = self
+            .map
+            .values()
+            .map(|(field_type, _)| {
+                if field_type.needs_pointer() {
+                    4 + AVG_VARLEN_SIZE
+                } else {
+                    field_type.unit_size().unwrap() as usize
+                }
+            })
+            .sum::<usize>();
+        // tuple header, null bitmap and tuple pointer
+        fields + TUPLE_HEADER_SIZE + self.fields_count().div_ceil(8) + 4
+    }
+
     pub fn map(&self) -> &HashMap<String, (Type, u16)> {
         &self.map
     }
@@ -347,20 +636,30 @@ impl Layout {
 #[derive(Clone, Debug)]
 pub struct FieldIndex {
     name: String,
-    fieldname: String,
+    fieldnames: Vec<String>,
     index_type: IndexType,
     key_type: Type,
 }
 
 impl FieldIndex {
-    pub fn new(name: &str, fieldname: &str, index_type: IndexType, key_type: Type) -> Self {
+    pub fn new(name: &str, fieldnames: Vec<String>, index_type: IndexType, key_type: Type) -> Self {
         Self {
             name: name.to_string(),
-            fieldname: fieldname.to_string(),
+            fieldnames,
             index_type,
             key_type,
         }
     }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// The fields the index is built on, in the order its keys compare them
+    pub fn fieldnames(&self) -> &[String] {
+        &self.fieldnames
+    }
+    pub fn index_type(&self) -> IndexType {
+        self.index_type
+    }
 
     pub fn serialize(&self, tablename: &str) -> Vec<(String, Option<Vec<u8>>)> {
         vec![
@@ -371,7 +670,7 @@ impl FieldIndex {
             ),
             (
                 "fieldname".to_string(),
-                Some(self.fieldname.as_bytes().to_vec()),
+                Some(self.fieldnames.join(",").into_bytes()),
             ),
             (
                 "index_type".to_string(),
@@ -389,14 +688,18 @@ impl FieldIndex {
     }
     pub fn deserialize(mut row: HashMap<String, Option<Vec<u8>>>, key_type: Type) -> Self {
         let name = String::from_utf8(row.remove("index_name").unwrap().unwrap()).unwrap();
-        let fieldname = String::from_utf8(row.remove("fieldname").unwrap().unwrap()).unwrap();
+        let fieldnames = String::from_utf8(row.remove("fieldname").unwrap().unwrap())
+            .unwrap()
+            .split(',')
+            .map(String::from)
+            .collect();
         let index_type = IndexType::from_str(
             &String::from_utf8(row.remove("index_type").unwrap().unwrap()).unwrap(),
         )
         .unwrap();
         Self {
             name,
-            fieldname,
+            fieldnames,
             index_type,
             key_type,
         }
@@ -406,7 +709,7 @@ impl FieldIndex {
             db_name,
             self.name.clone(),
             self.index_type,
-            self.fieldname.clone(),
+            self.fieldnames.clone(),
             PathBuf::from(format!("{}_idx_file", &self.name)),
             PathBuf::from(format!("{}_idx_directory", &self.name)),
             self.key_type,
@@ -1,7 +1,10 @@
 use crate::common::numerical::ByteMagic;
 use crate::schema::types::CharType::{Char, VarChar};
-use crate::schema::types::NumericType::{BigInt, Double, Integer, Serial, Single, SmallInt};
+use crate::schema::types::NumericType::{
+    BigInt, Decimal, Double, Integer, Serial, Single, SmallInt,
+};
 use bincode::{Decode, Encode};
+use std::fmt::{Display, Formatter};
 use std::ops::Add;
 use std::str::FromStr;
 
@@ -14,6 +17,9 @@ pub enum NumericType {
     Single,
     Double,
     Serial,
+    /// An exact number of a precision, its count of digits, and a scale, the count of its digits
+    /// after the point. Stored as an i128 of its digits without the point
+    Decimal(u8, u8),
 }
 
 impl ToString for NumericType {
@@ -25,6 +31,7 @@ impl ToString for NumericType {
             NumericType::Single => String::from("single"),
             NumericType::Double => String::from("double"),
             NumericType::Serial => String::from("serial"),
+            NumericType::Decimal(precision, scale) => format!("decimal({},{})", precision, scale),
         }
     }
 }
@@ -40,6 +47,7 @@ impl NumericType {
             NumericType::Single => 4,
             NumericType::Double => 8,
             NumericType::Serial => 4,
+            NumericType::Decimal(_, _) => 16,
         }
     }
 
@@ -54,6 +62,7 @@ impl NumericType {
             NumericType::Single => &tuple[start_byte..start_byte + 4],
             NumericType::Double => &tuple[start_byte..start_byte + 8],
             NumericType::Serial => &tuple[start_byte..start_byte + 4],
+            NumericType::Decimal(_, _) => &tuple[start_byte..start_byte + 16],
         }
     }
 }
@@ -61,15 +70,18 @@ impl NumericType {
 /// An enumeration for the String data types in the database
 #[derive(Encode, Decode, Clone, Copy, PartialEq, Debug)]
 pub enum CharType {
-    Char,
-    VarChar,
+    /// A string of a length in bytes, stored inline and padded with blanks up to it
+    Char(u8),
+    /// A string of any length up to its limit in characters if it has one, stored behind a pointer
+    VarChar(Option<u32>),
 }
 
 impl ToString for CharType {
     fn to_string(&self) -> String {
         match self {
-            CharType::Char => String::from("char"),
-            VarChar => String::from("varchar"),
+            Char(length) => format!("char({})", length),
+            VarChar(Some(limit)) => format!("varchar({})", limit),
+            VarChar(None) => String::from("varchar"),
         }
     }
 }
@@ -77,15 +89,85 @@ impl ToString for CharType {
 impl CharType {
     /// Returns whether the current data type needs a pointer to store its offset and size (Varchar)
     pub fn needs_pointer(self) -> bool {
-        self == VarChar
+        matches!(self, VarChar(_))
     }
 
-    /// Reads a certain Char/Varchar from a tuple by its offset and size stored in its pointer
+    /// The declared length of the data type, None for a varchar of any length
+    pub fn limit(self) -> Option<u32> {
+        match self {
+            Char(length) => Some(length as u32),
+            VarChar(limit) => limit,
+        }
+    }
+
+    /// The bytes a Char of a length holds for a text, padded with blanks, None when the text is
+    /// longer
+    pub fn padded(text: &str, length: u8) -> Option<Vec<u8>> {
+        let mut bytes = text.as_bytes().to_vec();
+        if bytes.len() > length as usize {
+            return None;
+        }
+        bytes.resize(length as usize, b' ');
+        Some(bytes)
+    }
+
+    /// Reads a certain Char/Varchar from a tuple, a Char by its length and a Varchar by the offset
+    /// and size stored in its pointer
+    pub fn read_from_tuple(self, tuple: &[u8], start_byte: u16) -> &[u8] {
+        let start_byte = start_byte as usize;
+        if let Char(length) = self {
+            return &tuple[start_byte..start_byte + length as usize];
+        }
+        read_pointed(tuple, start_byte)
+    }
+}
+
+/// Reads a value stored behind a pointer from a tuple, by the offset and size in the pointer
+fn read_pointed(tuple: &[u8], start_byte: usize) -> &[u8] {
+    let offset = tuple.extract_u16(start_byte) as usize;
+    let length = tuple.extract_u16(start_byte + 2);
+    &tuple[offset..offset + length as usize]
+}
+
+/// An enumeration for the date and time data types in the database
+///
+/// Dates are stored as the number of days since 1970-01-01, times as the microseconds since
+/// midnight, timestamps as the microseconds since the epoch and intervals as a number of
+/// microseconds
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Debug)]
+pub enum TemporalType {
+    Date,
+    Time,
+    Timestamp,
+    Interval,
+}
+
+impl Display for TemporalType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemporalType::Date => write!(f, "date"),
+            TemporalType::Time => write!(f, "time"),
+            TemporalType::Timestamp => write!(f, "timestamp"),
+            TemporalType::Interval => write!(f, "interval"),
+        }
+    }
+}
+
+impl TemporalType {
+    /// Returns the number of bytes used to store each data type
+    #[inline(always)]
+    pub fn unit_size(self) -> u8 {
+        match self {
+            TemporalType::Date => 4,
+            TemporalType::Time | TemporalType::Timestamp | TemporalType::Interval => 8,
+        }
+    }
+
+    /// Reads a certain data type from a tuple by the specific number of bytes used to store it
+    #[inline(always)]
     pub fn read_from_tuple(self, tuple: &[u8], start_byte: u16) -> &[u8] {
         let start_byte = start_byte as usize;
-        let string_offset = tuple.extract_u16(start_byte) as usize;
-        let length = tuple.extract_u16(start_byte + 2);
-        &tuple[string_offset..string_offset + length as usize]
+        &tuple[start_byte..start_byte + self.unit_size() as usize]
     }
 }
 
@@ -95,6 +177,9 @@ pub enum Type {
     Numeric(NumericType),
     Character(CharType),
     Boolean,
+    Temporal(TemporalType),
+    /// Bytes of any length, stored behind a pointer like a varchar
+    Binary,
 }
 
 impl ToString for Type {
@@ -103,6 +188,8 @@ impl ToString for Type {
             Type::Numeric(num) => num.to_string(),
             Type::Character(char) => char.to_string(),
             Type::Boolean => String::from("bool"),
+            Type::Temporal(temporal) => temporal.to_string(),
+            Type::Binary => String::from("bytea"),
         }
     }
 }
@@ -118,10 +205,35 @@ impl FromStr for Type {
             "single" => Ok(Self::Numeric(Single)),
             "double" => Ok(Self::Numeric(Double)),
             "serial" => Ok(Self::Numeric(Serial)),
-            "char" => Ok(Self::Character(Char)),
-            "varchar" => Ok(Self::Character(VarChar)),
+            "char" => Ok(Self::Character(Char(1))),
+            "varchar" => Ok(Self::Character(VarChar(None))),
             "bool" => Ok(Self::Boolean),
-            _ => Err("Type Unknown".to_string()),
+            "date" => Ok(Self::Temporal(TemporalType::Date)),
+            "time" => Ok(Self::Temporal(TemporalType::Time)),
+            "timestamp" => Ok(Self::Temporal(TemporalType::Timestamp)),
+            "interval" => Ok(Self::Temporal(TemporalType::Interval)),
+            "bytea" => Ok(Self::Binary),
+            _ if s.starts_with("char(") || s.starts_with("varchar(") => {
+                let (name, length) = s.strip_suffix(')').unwrap_or(s).split_once('(').unwrap();
+                match (name, length.parse::<u32>()) {
+                    ("char", Ok(length)) if length <= u8::MAX as u32 => {
+                        Ok(Self::Character(Char(length as u8)))
+                    }
+                    ("varchar", Ok(limit)) => Ok(Self::Character(VarChar(Some(limit)))),
+                    _ => Err("Type Unknown".to_string()),
+                }
+            }
+            _ => match s
+                .strip_prefix("decimal(")
+                .and_then(|s| s.strip_suffix(')'))
+                .and_then(|s| s.split_once(','))
+            {
+                Some((precision, scale)) => match (precision.parse(), scale.parse()) {
+                    (Ok(precision), Ok(scale)) => Ok(Self::Numeric(Decimal(precision, scale))),
+                    _ => Err("Type Unknown".to_string()),
+                },
+                None => Err("Type Unknown".to_string()),
+            },
         }
     }
 }
@@ -131,20 +243,32 @@ impl Type {
     pub fn unit_size(self) -> Option<u8> {
         match self {
             Type::Numeric(numeric) => Some(numeric.unit_size()),
+            Type::Character(Char(length)) => Some(length),
             Type::Character(_) => Some(4_u8),
             Type::Boolean => Some(1),
+            Type::Temporal(temporal) => Some(temporal.unit_size()),
+            Type::Binary => Some(4_u8),
         }
     }
 
     /// Returns whether the current data type needs a pointer to store its offset and size
     ///
     /// Numeric types do not need pointers
-    /// Varchars need pointers
+    /// Varchars and binary values need pointers
     #[inline(always)]
     pub fn needs_pointer(self) -> bool {
         match self {
-            Type::Numeric(_) | Type::Boolean => false,
-            Type::Character(_) => true,
+            Type::Numeric(_) | Type::Boolean | Type::Temporal(_) => false,
+            Type::Character(char) => char.needs_pointer(),
+            Type::Binary => true,
+        }
+    }
+
+    /// The declared length of a character type, None for the other types
+    pub fn char_limit(self) -> Option<u32> {
+        match self {
+            Type::Character(char) => char.limit(),
+            _ => None,
         }
     }
 
@@ -154,6 +278,8 @@ impl Type {
             Type::Numeric(num) => num.read_from_tuple(tuple, start_byte),
             Type::Character(char) => char.read_from_tuple(tuple, start_byte),
             Type::Boolean => &tuple[start_byte as usize..=start_byte as usize],
+            Type::Temporal(temporal) => temporal.read_from_tuple(tuple, start_byte),
+            Type::Binary => read_pointed(tuple, start_byte as usize),
         }
     }
 }
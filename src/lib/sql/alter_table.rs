@@ -0,0 +1,170 @@
+use crate::schema::schema::Schema;
+use crate::sql::create_table::{Constraint, TableField};
+use crate::sql::query::insert::SqlInsert;
+
+/// A change to the definition of an existing table
+#[derive(Debug)]
+pub struct AlterTable {
+    table_name: String,
+    action: AlterAction,
+}
+
+#[derive(Debug)]
+pub enum AlterAction {
+    /// A new column, the rows already in the table get its default value or NULL without one
+    AddColumn(TableField),
+    DropColumn(String),
+    RenameColumn(String, String),
+    RenameTo(String),
+}
+
+impl AlterTable {
+    pub fn new(table_name: String, action: AlterAction) -> Self {
+        Self { table_name, action }
+    }
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+    pub fn action(&self) -> &AlterAction {
+        &self.action
+    }
+    /// The name of the table once altered
+    pub fn new_name(&self) -> &str {
+        match &self.action {
+            AlterAction::RenameTo(name) => name,
+            _ => &self.table_name,
+        }
+    }
+
+    /// The schema of the table once altered, fails if the change does not apply to the schema
+    pub fn alter_schema(&self, schema: &Schema) -> Result<Schema, String> {
+        let mut altered = schema.clone();
+        let fields = schema.fields_info();
+        match &self.action {
+            AlterAction::AddColumn(field) => {
+                if fields.contains_key(field.name()) {
+                    return Err(format!("Column {} already exists", field.name()));
+                }
+                if field.constraints().contains(&Constraint::PrimaryKey) {
+                    return Err("A primary key column cannot be added to a table".to_string());
+                }
+                field.add_to(&mut altered)?;
+                if altered.fields_info()[field.name()].sequence().is_some() {
+                    return Err(
+                        "A column taking its values from a sequence cannot be added to a table"
+                            .to_string(),
+                    );
+                }
+                if let Some(condition) = altered.fields_info()[field.name()].check() {
+                    altered.check_fields(condition)?;
+                }
+            }
+            AlterAction::DropColumn(name) => {
+                if !fields.contains_key(name.as_str()) {
+                    return Err(format!("Column {} does not exist", name));
+                }
+                if schema.primary_key().contains(name) {
+                    return Err(format!("Column {} is part of the primary key", name));
+                }
+                if fields.len() == 1 {
+                    return Err(format!("Column {} is the only column of the table", name));
+                }
+                if let Some(checked) = schema.checks_reading(name).into_iter().find(|f| f != name) {
+                    return Err(format!(
+                        "Column {} is read by the CHECK of {}",
+                        name, checked
+                    ));
+                }
+                altered.drop_field(name);
+            }
+            AlterAction::RenameColumn(name, new_name) => {
+                if !fields.contains_key(name.as_str()) {
+                    return Err(format!("Column {} does not exist", name));
+                }
+                if fields.contains_key(new_name.as_str()) {
+                    return Err(format!("Column {} already exists", new_name));
+                }
+                if let Some(checked) = schema.checks_reading(name).first() {
+                    return Err(format!(
+                        "Column {} is read by the CHECK of {}",
+                        name, checked
+                    ));
+                }
+                altered.rename_field(name, new_name);
+            }
+            AlterAction::RenameTo(name) => altered.set_name(name),
+        }
+        Ok(altered)
+    }
+
+    /// The value an added column gets in the rows already in the table
+    pub fn default_bytes(&self) -> Result<Option<Vec<u8>>, String> {
+        match &self.action {
+            AlterAction::AddColumn(field) => match field.default_value() {
+                Some(value) => {
+                    SqlInsert::field_bytes(field.name(), value.clone(), field.datatype())
+                }
+                None => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::parse_query;
+    use crate::sql::Sql;
+    use std::collections::HashMap;
+
+    fn alter(query: &str) -> AlterTable {
+        match parse_query(query).unwrap() {
+            Sql::AlterTable(alter) => alter,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_alter_schema() {
+        let schema = match parse_query("create table t (a int, b varchar, c int)").unwrap() {
+            Sql::CreateTable(ct) => ct.to_schema().unwrap(),
+            _ => unreachable!(),
+        };
+        let added = alter("alter table t add column d bool not null default true");
+        assert_eq!(added.default_bytes(), Ok(Some(vec![1])));
+        let schema = added.alter_schema(&schema).unwrap();
+        let schema = alter("alter table t drop column a")
+            .alter_schema(&schema)
+            .unwrap();
+        let schema = alter("alter table t rename column c to e")
+            .alter_schema(&schema)
+            .unwrap();
+        assert!(alter("alter table t drop column a")
+            .alter_schema(&schema)
+            .is_err());
+        assert!(alter("alter table t add column b int")
+            .alter_schema(&schema)
+            .is_err());
+        let names = |schema: &Schema| {
+            schema
+                .fields()
+                .iter()
+                .map(|f| f.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&schema), ["b", "e", "d"]);
+        // the catalog rows of a schema come back in any order
+        let (rows, indexes) = schema.serialize();
+        let rows = rows
+            .into_iter()
+            .rev()
+            .map(|row| row.into_iter().collect::<HashMap<_, _>>())
+            .collect();
+        let indexes = indexes
+            .into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect();
+        assert_eq!(names(&Schema::deserialize(rows, indexes)), ["b", "e", "d"]);
+    }
+}
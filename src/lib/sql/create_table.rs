@@ -1,8 +1,11 @@
 use crate::schema::schema::Schema;
-use crate::schema::types::Type;
+use crate::schema::types::{NumericType, Type};
 use crate::sql::create_table::Constraint::{NotNull, PrimaryKey, References, Unique};
+use crate::sql::query::insert::SqlInsert;
+use crate::sql::query::query::SqlValue;
 use std::any::Any;
 use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
 use std::str::FromStr;
 
 #[derive(Debug)]
@@ -29,45 +32,32 @@ impl CreateTable {
         }
         // Self { table_name, fields , indexes }
     }
-    pub fn to_schema(&self) -> Schema {
+    /// The schema of the table, fails if a constraint or an index does not fit its columns
+    pub fn to_schema(&self) -> Result<Schema, String> {
         let mut schema = Schema::new();
         let mut field_names = HashSet::new();
         schema.set_name(&self.table_name);
         for field in self.fields.iter() {
             field_names.insert(field.name.as_str());
-            let nullable = !field.constraints.contains(&NotNull);
-            let primary = field.constraints.contains(&PrimaryKey);
-            let unique = field.constraints.contains(&Unique);
-            let references = field
-                .constraints
-                .iter()
-                .find(|c| {
-                    !matches!(
-                        **c,
-                        Constraint::NotNull | Constraint::PrimaryKey | Constraint::Unique
-                    )
-                })
-                .map(|c| match c {
-                    PrimaryKey | NotNull | Unique => unreachable!(),
-                    References(c, t) => (c.to_owned(), t.to_owned()),
-                });
-            schema.add_field(
-                &field.name,
-                field.datatype,
-                nullable,
-                unique,
-                references,
-                None,
-            )
+            field.add_to(&mut schema)?;
+        }
+        // a CHECK may read the columns defined after its own
+        for field in schema.fields() {
+            if let Some(condition) = field.check() {
+                schema.check_fields(condition)?;
+            }
         }
         for idx in self.indexes.iter() {
-            if field_names.contains(idx.field.as_str()) {
-                schema.add_index(&idx.name, &idx.field, idx.index_type)
-            } else {
-                todo!()
+            match idx
+                .fields
+                .iter()
+                .find(|f| !field_names.contains(f.as_str()))
+            {
+                Some(missing) => return Err(format!("Column {} does not exist", missing)),
+                None => schema.add_index(&idx.name, &idx.fields, idx.index_type),
             }
         }
-        schema
+        Ok(schema)
     }
 }
 
@@ -86,6 +76,86 @@ impl TableField {
             constraints,
         }
     }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn datatype(&self) -> Type {
+        self.datatype
+    }
+    pub fn constraints(&self) -> &Vec<Constraint> {
+        &self.constraints
+    }
+    /// The value the field gets when an insert leaves it out, NULL without a DEFAULT
+    pub fn default_value(&self) -> Option<&SqlValue> {
+        self.constraints.iter().find_map(|c| match c {
+            Constraint::Default(value) => Some(value),
+            _ => None,
+        })
+    }
+    /// Adds the field to a schema with its constraints, a primary key field is NOT NULL.
+    ///
+    /// A SERIAL field is NOT NULL and takes its values from a sequence of its own, named after the
+    /// table and the field, a DEFAULT calling nextval takes them from the sequence it names.
+    /// Fails if the DEFAULT of the field is not of its type, or NULL while the field is NOT NULL
+    pub(crate) fn add_to(&self, schema: &mut Schema) -> Result<(), String> {
+        let serial = self.datatype == Type::Numeric(NumericType::Serial);
+        let nullable = !serial
+            && !self.constraints.contains(&NotNull)
+            && !self.constraints.contains(&PrimaryKey);
+        let unique = self.constraints.contains(&Unique);
+        let references = self.constraints.iter().find_map(|c| match c {
+            References(c, t, on_delete) => Some((c.to_owned(), t.to_owned(), *on_delete)),
+            _ => None,
+        });
+        let sequence = match self.default_value() {
+            Some(_) if serial => {
+                return Err(format!(
+                    "{} is SERIAL, its default is the next value of its sequence",
+                    self.name
+                ))
+            }
+            Some(SqlValue::NextVal(sequence)) => Some(sequence.to_owned()),
+            _ if serial => Some(format!("{}_{}_seq", schema.name(), self.name)),
+            _ => None,
+        };
+        let default = match self.default_value() {
+            Some(value) if sequence.is_none() => {
+                SqlInsert::field_bytes(&self.name, value.clone(), self.datatype)?
+            }
+            _ => None,
+        };
+        if !nullable && self.default_value() == Some(&SqlValue::NULL) {
+            return Err(format!(
+                "{} is NOT NULL, its default cannot be NULL",
+                self.name
+            ));
+        }
+        let check = self.constraints.iter().find_map(|c| match c {
+            Constraint::Check(condition) => Some(condition.to_owned()),
+            _ => None,
+        });
+        schema.add_field(
+            &self.name,
+            self.datatype,
+            nullable,
+            unique,
+            references
+                .as_ref()
+                .map(|(t, c, _)| (t.to_owned(), c.to_owned())),
+            self.datatype.char_limit(),
+        );
+        if let Some((_, _, on_delete)) = references {
+            schema.set_on_delete(&self.name, on_delete);
+        }
+        schema.set_default(&self.name, default);
+        if let Some(sequence) = sequence {
+            schema.set_sequence(&self.name, sequence);
+        }
+        if let Some(check) = check {
+            schema.set_check(&self.name, check);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -93,7 +163,48 @@ pub enum Constraint {
     PrimaryKey,
     NotNull,
     Unique,
-    References(String, String),
+    References(String, String, OnDelete),
+    Default(SqlValue),
+    /// A condition every row satisfies, reading the columns of the table by name
+    Check(String),
+}
+
+/// What deleting a row does to the rows whose foreign key references it
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OnDelete {
+    /// The delete fails while the row is referenced
+    #[default]
+    Restrict,
+    /// The referencing rows are deleted with it
+    Cascade,
+    /// The foreign key of the referencing rows is set to NULL
+    SetNull,
+}
+
+impl FromStr for OnDelete {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("restrict") {
+            Ok(Self::Restrict)
+        } else if s.eq_ignore_ascii_case("cascade") {
+            Ok(Self::Cascade)
+        } else if s.eq_ignore_ascii_case("set null") {
+            Ok(Self::SetNull)
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl Display for OnDelete {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            OnDelete::Restrict => write!(f, "restrict"),
+            OnDelete::Cascade => write!(f, "cascade"),
+            OnDelete::SetNull => write!(f, "set null"),
+        }
+    }
 }
 
 pub enum CreateTableEntry {
@@ -104,18 +215,27 @@ pub enum CreateTableEntry {
 #[derive(Debug, Eq, PartialEq)]
 pub struct Index {
     name: String,
-    field: String,
+    fields: Vec<String>,
     index_type: IndexType,
 }
 
 impl Index {
-    pub fn new(name: String, field: String, index_type: IndexType) -> Self {
+    pub fn new(name: String, fields: Vec<String>, index_type: IndexType) -> Self {
         Self {
             name,
-            field,
+            fields,
             index_type,
         }
     }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+    pub fn index_type(&self) -> IndexType {
+        self.index_type
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -138,11 +258,160 @@ impl FromStr for IndexType {
     }
 }
 
-impl ToString for IndexType {
-    fn to_string(&self) -> String {
+impl Display for IndexType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            IndexType::Hash => String::from("hash"),
-            IndexType::Btree => String::from("btree"),
+            IndexType::Hash => write!(f, "hash"),
+            IndexType::Btree => write!(f, "btree"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::types::CharType;
+    use crate::sql::parser::parse_query;
+    use crate::sql::Sql;
+    use std::collections::HashMap;
+
+    fn schema(query: &str) -> Schema {
+        match parse_query(query).unwrap() {
+            Sql::CreateTable(ct) => ct.to_schema().unwrap(),
+            _ => unreachable!(),
         }
     }
+
+    /// The schema read back from its catalog rows
+    fn from_catalog(schema: &Schema) -> Schema {
+        let (rows, indexes) = schema.serialize();
+        let rows = rows
+            .into_iter()
+            .map(|row| row.into_iter().collect::<HashMap<_, _>>())
+            .collect();
+        let indexes = indexes
+            .into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect();
+        Schema::deserialize(rows, indexes)
+    }
+
+    #[test]
+    fn test_foreign_key_actions() {
+        let schema = schema(
+            "create table c (a int references p(a), b int references p(b) on delete cascade, \
+             d int references p(d) on delete set null)",
+        );
+        let actions = |schema: &Schema| {
+            schema
+                .foreign_keys()
+                .into_iter()
+                .map(|fk| (fk.field, fk.ref_field, fk.on_delete))
+                .collect::<Vec<_>>()
+        };
+        let expected = [
+            ("a".to_string(), "a".to_string(), OnDelete::Restrict),
+            ("b".to_string(), "b".to_string(), OnDelete::Cascade),
+            ("d".to_string(), "d".to_string(), OnDelete::SetNull),
+        ];
+        assert_eq!(actions(&schema), expected);
+        assert_eq!(actions(&from_catalog(&schema)), expected);
+        // a NOT NULL foreign key cannot be set to NULL
+        assert!(
+            parse_query("create table c (a int not null references p(a) on delete set null)")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_default_and_check() {
+        let schema = from_catalog(&schema(
+            "create table t (id int primary key, qty int default 1 check (qty > 0 and qty < lim), \
+             lim int, name varchar default \"none\")",
+        ));
+        let fields = schema.fields_info();
+        assert!(!fields["id"].nullable());
+        assert_eq!(
+            fields["qty"].default_value(),
+            Some(&1_i32.to_ne_bytes().to_vec())
+        );
+        assert_eq!(fields["name"].default_value(), Some(&b"none".to_vec()));
+        assert_eq!(fields["lim"].default_value(), None);
+        assert_eq!(schema.checks_reading("lim"), ["qty"]);
+        let row = |qty: Option<i32>, lim: Option<i32>| {
+            HashMap::from([
+                ("qty".to_string(), qty.map(|v| v.to_ne_bytes().to_vec())),
+                ("lim".to_string(), lim.map(|v| v.to_ne_bytes().to_vec())),
+            ])
+        };
+        assert!(schema.check_row(&row(Some(5), Some(10))).is_ok());
+        assert!(schema.check_row(&row(Some(0), Some(10))).is_err());
+        assert!(schema.check_row(&row(Some(10), Some(10))).is_err());
+        // a check reading a NULL is unknown, which satisfies it
+        assert!(schema.check_row(&row(Some(50), None)).is_ok());
+        for query in [
+            "create table t (a int not null default null)",
+            "create table t (a int default \"x\")",
+            "create table t (a int check (b > 0))",
+            "create table t (a int check (t.a > 0))",
+        ] {
+            match parse_query(query).unwrap() {
+                Sql::CreateTable(ct) => assert!(ct.to_schema().is_err(), "{}", query),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_serial_sequences() {
+        let schema = from_catalog(&schema(
+            "create table t (id serial primary key, n int default nextval(\"counter\"), m int)",
+        ));
+        let fields = schema.fields_info();
+        assert!(!fields["id"].nullable());
+        assert_eq!(fields["id"].sequence(), Some("t_id_seq"));
+        assert_eq!(fields["n"].sequence(), Some("counter"));
+        assert_eq!(fields["n"].default_value(), None);
+        assert_eq!(fields["m"].sequence(), None);
+        assert_eq!(schema.owned_sequences(), ["t_id_seq"]);
+        match parse_query("create table t (id serial default 1)").unwrap() {
+            Sql::CreateTable(ct) => assert!(ct.to_schema().is_err()),
+            _ => unreachable!(),
+        }
+        assert!(matches!(
+            parse_query("create sequence s start with 10 increment by -2"),
+            Ok(Sql::CreateSequence(name, 10, -2)) if name == "s"
+        ));
+        assert!(matches!(
+            parse_query("create sequence s increment -1"),
+            Ok(Sql::CreateSequence(_, -1, -1))
+        ));
+        assert!(parse_query("create sequence s increment by 0").is_err());
+        assert!(matches!(
+            parse_query("select nextval(s), currval(\"s\")"),
+            Ok(Sql::SequenceValues(values))
+                if values == [SqlValue::NextVal("s".into()), SqlValue::CurrVal("s".into())]
+        ));
+    }
+
+    #[test]
+    fn test_char_lengths() {
+        let schema = from_catalog(&schema(
+            "create table t (c char(3) default \"ab\", f char, v varchar(2), n varchar)",
+        ));
+        let fields = schema.fields_info();
+        assert_eq!(fields["c"].field_type(), Type::Character(CharType::Char(3)));
+        assert_eq!(fields["c"].char_limit(), Some(3));
+        assert_eq!(fields["c"].default_value(), Some(&b"ab ".to_vec()));
+        assert_eq!(fields["f"].field_type(), Type::Character(CharType::Char(1)));
+        assert_eq!(fields["v"].char_limit(), Some(2));
+        assert_eq!(fields["n"].char_limit(), None);
+        assert!(!fields["c"].field_type().needs_pointer());
+        let v = fields["v"].field_type();
+        assert!(SqlInsert::field_bytes("v", SqlValue::Text("éé".into()), v).is_ok());
+        assert!(SqlInsert::field_bytes("v", SqlValue::Text("abc".into()), v).is_err());
+        assert!(parse_query("create table t (c char(0))").is_err());
+        assert!(parse_query("create table t (c char(256))").is_err());
+        assert!(parse_query("create table t (v varchar(0))").is_err());
+    }
 }
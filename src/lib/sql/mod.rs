@@ -1,16 +1,38 @@
-use crate::sql::create_table::CreateTable;
-use crate::sql::query::query::SqlQuery;
+use crate::sql::alter_table::AlterTable;
+use crate::sql::create_table::{CreateTable, Index};
+use crate::sql::query::query::{SqlQuery, SqlValue};
+use crate::sql::transaction::TransactionControl;
 
+pub mod alter_table;
 mod conditional;
 pub mod create_table;
 pub mod eval;
 pub mod parser;
 pub mod query;
+pub mod transaction;
 
 #[derive(Debug)]
 pub enum Sql {
     Query(SqlQuery),
     CreateTable(CreateTable),
+    AlterTable(AlterTable),
+    /// Removes a table, a table that does not exist is not an error with IF EXISTS
+    DropTable(String, bool),
+    /// Removes every row of a table
+    Truncate(String),
+    /// Builds an index on one or more columns of an existing table
+    CreateIndex(String, Index),
+    /// Removes an index, an index that does not exist is not an error with IF EXISTS
+    DropIndex(String, bool),
+    /// Creates a sequence with its first value and increment
+    CreateSequence(String, i64, i64),
+    /// Removes a sequence, a sequence that does not exist is not an error with IF EXISTS
+    DropSequence(String, bool),
+    /// A SELECT of nextval and currval calls, without a table
+    SequenceValues(Vec<SqlValue>),
+    Transaction(TransactionControl),
+    /// Removes the dead tuple versions of a table, or of every table without one
+    Vacuum(Option<String>),
 }
 
 impl Sql {
@@ -1,12 +1,17 @@
-use crate::schema::types::CharType::VarChar;
-use crate::schema::types::NumericType::{BigInt, Double, Integer, Serial, Single, SmallInt};
-use crate::schema::types::Type;
+use crate::common::{binary, decimal, temporal};
+use crate::schema::types::CharType::{self, Char, VarChar};
+use crate::schema::types::NumericType::{
+    BigInt, Decimal, Double, Integer, Serial, Single, SmallInt,
+};
+use crate::schema::types::{TemporalType, Type};
+use crate::sql::alter_table::{AlterAction, AlterTable};
 use crate::sql::create_table::Constraint::{NotNull, PrimaryKey, Unique};
 use crate::sql::create_table::{
-    Constraint, CreateTable, CreateTableEntry, Index, IndexType, TableField,
+    Constraint, CreateTable, CreateTableEntry, Index, IndexType, OnDelete, TableField,
 };
 use crate::sql::parser::Rule::{conditional_expression, foreign_key};
 use crate::sql::query::delete::SqlDelete;
+use crate::sql::query::explain::SqlExplain;
 use crate::sql::query::insert::SqlInsert;
 use crate::sql::query::query::{SqlQuery as QUERY, SqlValue};
 use crate::sql::query::select::{
@@ -14,11 +19,13 @@ use crate::sql::query::select::{
     Ordering, ProjectionTarget, SqlSelect,
 };
 use crate::sql::query::update::SqlUpdate;
+use crate::sql::transaction::TransactionControl;
 use crate::sql::Sql;
 use pest::error::ErrorVariant;
-use pest::iterators::Pairs;
+use pest::iterators::{Pair, Pairs};
 use pest::pratt_parser::PrattParser;
 use pest_consume::{match_nodes, match_nodes_, Error, Parser as PestParser};
+use std::str::FromStr;
 type Result<T> = std::result::Result<T, Error<Rule>>;
 pub type Node<'i> = pest_consume::Node<'i, Rule, ()>;
 
@@ -164,9 +171,39 @@ impl SqlParser {
         //          .op(Op::infix(AND, Left))
         //  };
         // // fn parse_me(pairs:Pairs<Rule>,pratt: &PrattParser<Rule>)
-        let mut txt = input
-            .as_str()
-            .to_string()
+        // temporal literals become their microseconds, hex literals the text expressions see binary
+        // values as and the functions are lowercased, the names they are registered under
+        let start = input.as_span().start();
+        let mut txt = input.as_str().to_string();
+        let mut rewrites = vec![];
+        for pair in input.as_pair().clone().into_inner().flatten() {
+            let span = pair.as_span();
+            let range = span.start() - start..span.end() - start;
+            match pair.as_rule() {
+                Rule::temporal_literal => {
+                    let (_, micros) = temporal_constant(pair).map_err(|message| {
+                        Error::new_from_span(ErrorVariant::CustomError { message }, span)
+                    })?;
+                    rewrites.push((range, micros.to_string()));
+                }
+                Rule::hex_literal => {
+                    let bytes = hex_constant(pair).map_err(|message| {
+                        Error::new_from_span(ErrorVariant::CustomError { message }, span)
+                    })?;
+                    // the backslash of the text is escaped in the string of the expression
+                    let text = binary::format(&bytes).replace('\\', "\\\\");
+                    rewrites.push((range, format!("\"{}\"", text)));
+                }
+                Rule::temporal_field | Rule::LENGTH | Rule::SUBSTRING => {
+                    rewrites.push((range, pair.as_str().to_lowercase()))
+                }
+                _ => {}
+            }
+        }
+        for (range, rewrite) in rewrites.into_iter().rev() {
+            txt.replace_range(range, &rewrite);
+        }
+        let mut txt = txt
             .replace(" AND ", " && ")
             .replace(" and ", " && ")
             .replace(" OR ", " || ")
@@ -196,11 +233,17 @@ impl SqlParser {
         Ok(txt)
     }
     fn join_type(input: Node) -> Result<JoinType> {
-        if input.as_str().eq_ignore_ascii_case("left") {
+        // the optional OUTER keyword does not change the join type
+        let join_type = input
+            .as_str()
+            .split_ascii_whitespace()
+            .next()
+            .unwrap_or_default();
+        if join_type.eq_ignore_ascii_case("left") {
             Ok(JoinType::Left)
-        } else if input.as_str().eq_ignore_ascii_case("right") {
+        } else if join_type.eq_ignore_ascii_case("right") {
             Ok(JoinType::Right)
-        } else if input.as_str().eq_ignore_ascii_case("full") {
+        } else if join_type.eq_ignore_ascii_case("full") {
             Ok(JoinType::Full)
         } else {
             Err(pest_consume::Error::new_from_span(
@@ -358,10 +401,32 @@ impl SqlParser {
         Ok(match_nodes!(
             input.into_children();
             [NULL(_)] => SqlValue::NULL,
+            [sequence_call(s)] => s,
+            [temporal_literal(t)] => t,
+            [hex_literal(h)] => h,
             [constant(c)] => c
 
         ))
     }
+    fn NEXTVAL(_input: Node) -> Result<()> {
+        Ok(())
+    }
+    fn CURRVAL(_input: Node) -> Result<()> {
+        Ok(())
+    }
+    fn sequence_ref(input: Node) -> Result<String> {
+        Ok(match_nodes!(
+            input.into_children();
+            [identifier(i)] => i
+        ))
+    }
+    fn sequence_call(input: Node) -> Result<SqlValue> {
+        Ok(match_nodes!(
+            input.into_children();
+            [NEXTVAL(_),sequence_ref(s)] => SqlValue::NextVal(s),
+            [CURRVAL(_),sequence_ref(s)] => SqlValue::CurrVal(s)
+        ))
+    }
     fn constant(input: Node) -> Result<SqlValue> {
         Ok(match_nodes!(
             input.into_children();
@@ -371,6 +436,19 @@ impl SqlParser {
             [FALSE(_)] => SqlValue::Bool(false)
         ))
     }
+    /// The text of a temporal literal, read with the type of the field it is given to
+    fn temporal_literal(input: Node) -> Result<SqlValue> {
+        let span = input.as_span();
+        temporal_constant(input.as_pair().clone())
+            .map(|(text, _)| SqlValue::Text(text))
+            .map_err(|message| Error::new_from_span(ErrorVariant::CustomError { message }, span))
+    }
+    fn hex_literal(input: Node) -> Result<SqlValue> {
+        let span = input.as_span();
+        hex_constant(input.as_pair().clone())
+            .map(SqlValue::Bytes)
+            .map_err(|message| Error::new_from_span(ErrorVariant::CustomError { message }, span))
+    }
     fn TRUE(_input: Node) -> Result<SqlValue> {
         Ok(SqlValue::Bool(true))
     }
@@ -406,39 +484,180 @@ impl SqlParser {
             [table_name(t)] => SqlDelete::new(t,None)
         ))
     }
+    fn ANALYZE(_input: Node) -> Result<()> {
+        Ok(())
+    }
+    fn SqlExplain(input: Node) -> Result<SqlExplain> {
+        Ok(match_nodes!(
+            input.into_children();
+            [ANALYZE(_),SqlSelect(s)] => SqlExplain::new(true,s),
+            [SqlSelect(s)] => SqlExplain::new(false,s)
+        ))
+    }
     fn SqlQuery(input: Node) -> Result<QUERY> {
         Ok(match_nodes!(
             input.into_children();
+            [SqlExplain(e)] => QUERY::EXPLAIN(e),
             [SqlSelect(s)] => QUERY::SELECT(s),
             [SqlUpdate(u)] => QUERY::UPDATE(u),
             [SqlDelete(d)] => QUERY::DELETE(d),
             [SqlInsert(i)] => QUERY::INSERT(i),
         ))
     }
+    fn SqlTransaction(input: Node) -> Result<TransactionControl> {
+        Ok(match input.into_children().single().unwrap().as_rule() {
+            Rule::BEGIN => TransactionControl::Begin,
+            Rule::COMMIT => TransactionControl::Commit,
+            Rule::ROLLBACK => TransactionControl::Rollback,
+            _ => unreachable!(),
+        })
+    }
+    fn SqlVacuum(input: Node) -> Result<Option<String>> {
+        Ok(match_nodes!(
+            input.into_children();
+            [table_name(t)] => Some(t),
+            [] => None
+        ))
+    }
+    fn IF_EXISTS(_input: Node) -> Result<()> {
+        Ok(())
+    }
+    fn SqlDropTable(input: Node) -> Result<Sql> {
+        Ok(match_nodes!(
+            input.into_children();
+            [IF_EXISTS(_),table_name(t)] => Sql::DropTable(t, true),
+            [table_name(t)] => Sql::DropTable(t, false)
+        ))
+    }
+    fn SqlTruncate(input: Node) -> Result<String> {
+        Ok(match_nodes!(
+            input.into_children();
+            [table_name(t)] => t
+        ))
+    }
+    fn SqlCreateIndex(input: Node) -> Result<Sql> {
+        Ok(match_nodes!(
+            input.into_children();
+            [identifier(i),index_method(im),table_name(t),identifier(f)..] => Sql::CreateIndex(t, Index::new(i,f.collect(),im)),
+            [identifier(i),table_name(t),identifier(f)..] => Sql::CreateIndex(t, Index::new(i,f.collect(),IndexType::Btree))
+        ))
+    }
+    fn SqlDropIndex(input: Node) -> Result<Sql> {
+        Ok(match_nodes!(
+            input.into_children();
+            [IF_EXISTS(_),identifier(i)] => Sql::DropIndex(i, true),
+            [identifier(i)] => Sql::DropIndex(i, false)
+        ))
+    }
+    fn sequence_start(input: Node) -> Result<String> {
+        Ok(input.into_children().single()?.as_str().to_string())
+    }
+    fn sequence_increment(input: Node) -> Result<String> {
+        Ok(input.into_children().single()?.as_str().to_string())
+    }
+    fn SqlCreateSequence(input: Node) -> Result<Sql> {
+        let span = input.as_span();
+        let mut children = input.into_children();
+        let name = SqlParser::identifier(children.next().unwrap())?;
+        let (mut start, mut increment) = (None, None);
+        for option in children {
+            match option.as_rule() {
+                Rule::sequence_start => start = Some(SqlParser::sequence_start(option)?),
+                _ => increment = Some(SqlParser::sequence_increment(option)?),
+            }
+        }
+        let (start, increment) = sequence_options(start, increment)
+            .map_err(|message| Error::new_from_span(ErrorVariant::CustomError { message }, span))?;
+        Ok(Sql::CreateSequence(name, start, increment))
+    }
+    fn SqlDropSequence(input: Node) -> Result<Sql> {
+        Ok(match_nodes!(
+            input.into_children();
+            [IF_EXISTS(_),identifier(i)] => Sql::DropSequence(i, true),
+            [identifier(i)] => Sql::DropSequence(i, false)
+        ))
+    }
+    fn SqlSequenceValues(input: Node) -> Result<Vec<SqlValue>> {
+        input
+            .into_children()
+            .map(SqlParser::sequence_call)
+            .collect()
+    }
     pub fn Sql(input: Node) -> Result<Sql> {
         Ok(match_nodes!(
             input.into_children();
             [SqlQuery(q),EOI(_)] => Sql::new_query(q),
-            [SqlCreateTable(ct),EOI(_)] => Sql::new_table(ct)
+            [SqlCreateTable(ct),EOI(_)] => Sql::new_table(ct),
+            [SqlAlterTable(at),EOI(_)] => Sql::AlterTable(at),
+            [SqlDropTable(dt),EOI(_)] => dt,
+            [SqlTruncate(t),EOI(_)] => Sql::Truncate(t),
+            [SqlCreateIndex(ci),EOI(_)] => ci,
+            [SqlDropIndex(di),EOI(_)] => di,
+            [SqlCreateSequence(cs),EOI(_)] => cs,
+            [SqlDropSequence(ds),EOI(_)] => ds,
+            [SqlSequenceValues(v),EOI(_)] => Sql::SequenceValues(v),
+            [SqlTransaction(t),EOI(_)] => Sql::Transaction(t),
+            [SqlVacuum(v),EOI(_)] => Sql::Vacuum(v)
         ))
     }
 
     fn datatype(input: Node) -> Result<Type> {
-        Ok(match input.into_children().single().unwrap().as_rule() {
+        let datatype = input.into_children().single().unwrap();
+        Ok(match datatype.as_rule() {
+            Rule::decimal_type => SqlParser::decimal_type(datatype)?,
+            Rule::BOOL => Type::Boolean,
             Rule::SMALLINT => Type::Numeric(SmallInt),
             Rule::INTEGER => Type::Numeric(Integer),
             Rule::BIGINT => Type::Numeric(BigInt),
             Rule::SINGLE => Type::Numeric(Single),
             Rule::DOUBLE => Type::Numeric(Double),
             Rule::SERIAL => Type::Numeric(Serial),
-            Rule::VARCHAR => Type::Character(VarChar),
+            Rule::char_type => SqlParser::char_type(datatype)?,
+            Rule::DATE => Type::Temporal(TemporalType::Date),
+            Rule::TIME => Type::Temporal(TemporalType::Time),
+            Rule::TIMESTAMP => Type::Temporal(TemporalType::Timestamp),
+            Rule::INTERVAL => Type::Temporal(TemporalType::Interval),
+            Rule::BYTEA => Type::Binary,
             _ => unreachable!(),
         })
     }
+    /// DECIMAL(p, s), the scale defaults to 0 and the precision to the largest one
+    fn decimal_type(input: Node) -> Result<Type> {
+        let span = input.as_span();
+        let mut numbers = input
+            .children()
+            .filter(|node| node.as_rule() == Rule::plain_number)
+            .map(|node| node.as_str());
+        decimal_arguments(numbers.next(), numbers.next())
+            .map(|(precision, scale)| Type::Numeric(Decimal(precision, scale)))
+            .map_err(|message| Error::new_from_span(ErrorVariant::CustomError { message }, span))
+    }
+    /// CHAR(n) and VARCHAR(n), a char is one byte long and a varchar of any length without a length
+    fn char_type(input: Node) -> Result<Type> {
+        let span = input.as_span();
+        let char = input.children().next().unwrap().as_rule() == Rule::CHAR;
+        let length = input
+            .children()
+            .find(|node| node.as_rule() == Rule::plain_number)
+            .map(|node| node.as_str());
+        char_length(char, length)
+            .map(Type::Character)
+            .map_err(|message| Error::new_from_span(ErrorVariant::CustomError { message }, span))
+    }
+    fn referential_action(input: Node) -> Result<OnDelete> {
+        OnDelete::from_str(input.as_str()).map_err(|_| input.error("Unknown referential action"))
+    }
+    fn on_delete(input: Node) -> Result<OnDelete> {
+        Ok(match_nodes!(
+            input.into_children();
+            [referential_action(a)] => a
+        ))
+    }
     fn foreign_key(input: Node) -> Result<Constraint> {
         Ok(match_nodes!(
             input.into_children();
-            [table_name(t),identifier(i)] => Constraint::References(t,i)
+            [table_name(t),identifier(i)] => Constraint::References(t,i,OnDelete::default()),
+            [table_name(t),identifier(i),on_delete(a)] => Constraint::References(t,i,a)
         ))
     }
     fn constraint(input: Node) -> Result<Constraint> {
@@ -450,16 +669,37 @@ impl SqlParser {
                 Rule::foreign_key => {
                     SqlParser::foreign_key(input.into_children().single().unwrap())?
                 }
+                Rule::column_default => Constraint::Default(SqlParser::column_default(
+                    input.into_children().single().unwrap(),
+                )?),
+                Rule::check => {
+                    Constraint::Check(SqlParser::check(input.into_children().single().unwrap())?)
+                }
                 _ => unreachable!(),
             },
         )
     }
 
     fn table_col(input: Node) -> Result<TableField> {
-        Ok(match_nodes!(
+        let span = input.as_span();
+        let field = match_nodes!(
             input.into_children();
             [identifier(i),datatype(d),constraint(c)..] => TableField::new(i,d,c.collect())
-        ))
+        );
+        let set_null = field
+            .constraints()
+            .iter()
+            .any(|c| matches!(c, Constraint::References(_, _, OnDelete::SetNull)));
+        if set_null && field.constraints().contains(&NotNull) {
+            Err(Error::new_from_span(
+                ErrorVariant::CustomError {
+                    message: format!("ON DELETE SET NULL needs {} to be nullable", field.name()),
+                },
+                span,
+            ))
+        } else {
+            Ok(field)
+        }
     }
 
     fn index_method(input: Node) -> Result<IndexType> {
@@ -491,6 +731,159 @@ impl SqlParser {
             // [table_name(t),table_col(tc)..,index(i)..] => CreateTable::new(t,tc.collect(),i.c),
         ))
     }
+    fn column_default(input: Node) -> Result<SqlValue> {
+        Ok(match_nodes!(
+            input.into_children();
+            [sql_value(v)] => v
+        ))
+    }
+    fn check(input: Node) -> Result<String> {
+        Ok(match_nodes!(
+            input.into_children();
+            [conditional_expression(c)] => c
+        ))
+    }
+    fn add_column(input: Node) -> Result<AlterAction> {
+        Ok(match_nodes!(
+            input.into_children();
+            [table_col(tc)] => AlterAction::AddColumn(tc)
+        ))
+    }
+    fn drop_column(input: Node) -> Result<AlterAction> {
+        Ok(match_nodes!(
+            input.into_children();
+            [identifier(i)] => AlterAction::DropColumn(i)
+        ))
+    }
+    fn rename_column(input: Node) -> Result<AlterAction> {
+        Ok(match_nodes!(
+            input.into_children();
+            [identifier(old),identifier(new)] => AlterAction::RenameColumn(old,new)
+        ))
+    }
+    fn rename_table(input: Node) -> Result<AlterAction> {
+        Ok(match_nodes!(
+            input.into_children();
+            [table_name(t)] => AlterAction::RenameTo(t)
+        ))
+    }
+    fn SqlAlterTable(input: Node) -> Result<AlterTable> {
+        Ok(match_nodes!(
+            input.into_children();
+            [table_name(t),add_column(a)] => AlterTable::new(t,a),
+            [table_name(t),drop_column(a)] => AlterTable::new(t,a),
+            [table_name(t),rename_column(a)] => AlterTable::new(t,a),
+            [table_name(t),rename_table(a)] => AlterTable::new(t,a)
+        ))
+    }
+}
+
+/// The first value and the increment of a sequence from the text of its options, a descending
+/// sequence starts from -1 unless told otherwise
+fn sequence_options(
+    start: Option<String>,
+    increment: Option<String>,
+) -> std::result::Result<(i64, i64), String> {
+    let number = |text: &str| {
+        text.parse::<i64>()
+            .map_err(|_| format!("{} is not a bigint", text))
+    };
+    let increment = match increment {
+        Some(text) => number(&text)?,
+        None => 1,
+    };
+    if increment == 0 {
+        return Err("The INCREMENT of a sequence cannot be zero".to_string());
+    }
+    match start {
+        Some(text) => Ok((number(&text)?, increment)),
+        None => Ok((increment.signum(), increment)),
+    }
+}
+
+/// The precision and the scale of a decimal type from their text
+fn decimal_arguments(
+    precision: Option<&str>,
+    scale: Option<&str>,
+) -> std::result::Result<(u8, u8), String> {
+    let precision = match precision {
+        Some(text) => match text.parse::<u8>() {
+            Ok(precision) if (1..=decimal::MAX_PRECISION).contains(&precision) => precision,
+            _ => {
+                return Err(format!(
+                    "The precision of a DECIMAL must be between 1 and {}, not {}",
+                    decimal::MAX_PRECISION,
+                    text
+                ))
+            }
+        },
+        None => decimal::MAX_PRECISION,
+    };
+    match scale {
+        Some(text) => match text.parse::<u8>() {
+            Ok(scale) if scale <= precision => Ok((precision, scale)),
+            _ => Err(format!(
+                "The scale of a DECIMAL must be between 0 and its precision {}, not {}",
+                precision, text
+            )),
+        },
+        None => Ok((precision, 0)),
+    }
+}
+
+/// The character type of a declared length, a char when `char` is set and a varchar otherwise
+fn char_length(char: bool, length: Option<&str>) -> std::result::Result<CharType, String> {
+    match (char, length) {
+        (true, None) => Ok(Char(1)),
+        (false, None) => Ok(VarChar(None)),
+        (true, Some(text)) => match text.parse::<u8>() {
+            Ok(length) if length > 0 => Ok(Char(length)),
+            _ => Err(format!(
+                "The length of a CHAR must be between 1 and {}, not {}",
+                u8::MAX,
+                text
+            )),
+        },
+        (false, Some(text)) => match text.parse::<u32>() {
+            Ok(limit) if limit > 0 => Ok(VarChar(Some(limit))),
+            _ => Err(format!(
+                "The length of a VARCHAR must be between 1 and {}, not {}",
+                u32::MAX,
+                text
+            )),
+        },
+    }
+}
+
+/// The text of a temporal literal and the microseconds it stands for
+fn temporal_constant(pair: Pair<Rule>) -> std::result::Result<(String, i64), String> {
+    let mut inner = pair.into_inner();
+    let temporal = match inner.next().unwrap().as_rule() {
+        Rule::DATE => TemporalType::Date,
+        Rule::TIME => TemporalType::Time,
+        Rule::TIMESTAMP => TemporalType::Timestamp,
+        _ => TemporalType::Interval,
+    };
+    let text = inner
+        .next()
+        .unwrap()
+        .as_str()
+        .trim_matches('\"')
+        .to_string();
+    let micros = temporal::parse(temporal, &text)?;
+    Ok((text, micros))
+}
+
+/// The bytes of a hex literal
+fn hex_constant(pair: Pair<Rule>) -> std::result::Result<Vec<u8>, String> {
+    let text = pair.as_str();
+    let digits = &text[2..text.len() - 1];
+    binary::parse_hex(digits).ok_or_else(|| {
+        format!(
+            "The {} digits of a hex literal are not whole bytes",
+            digits.len()
+        )
+    })
 }
 
 pub fn parse_query(query: &str) -> Result<Sql> {
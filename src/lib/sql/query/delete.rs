@@ -11,4 +11,10 @@ impl SqlDelete {
             where_clause,
         }
     }
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+    pub fn where_clause(&self) -> Option<&String> {
+        self.where_clause.as_ref()
+    }
 }
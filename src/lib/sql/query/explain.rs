@@ -0,0 +1,20 @@
+use crate::sql::query::select::SqlSelect;
+
+#[derive(Debug)]
+pub struct SqlExplain {
+    analyze: bool,
+    query: SqlSelect,
+}
+
+impl SqlExplain {
+    pub fn new(analyze: bool, query: SqlSelect) -> Self {
+        Self { analyze, query }
+    }
+    /// EXPLAIN ANALYZE runs the query to report what each node of the plan actually did
+    pub fn analyze(&self) -> bool {
+        self.analyze
+    }
+    pub fn into_query(self) -> SqlSelect {
+        self.query
+    }
+}
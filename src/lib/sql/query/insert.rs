@@ -1,5 +1,6 @@
+use crate::common::{decimal, temporal};
 use crate::schema::schema::Schema;
-use crate::schema::types::{NumericType, Type};
+use crate::schema::types::{CharType, NumericType, Type};
 use crate::sql::query::query::SqlValue;
 use std::num::ParseIntError;
 
@@ -28,16 +29,70 @@ impl SqlInsert {
                 if !field.nullable() && matches!(col_val, SqlValue::NULL) {
                     return Err(format!("{} is NULL but NOT NULL Constraint", col_name));
                 }
-                let bytes = Self::column_bytes(col_val, field.field_type())
-                    .map_err(|_| format!("Wrong data type:{}", col_name))?;
+                let bytes = Self::field_bytes(&col_name, col_val, field.field_type())?;
                 ret.push((col_name, bytes))
             } else {
                 return Err(format!("{} does not exist", col_name));
             }
         }
+        // the fields left out of the insert get their default, NULL without one
+        for field in schema.fields() {
+            if ret.iter().any(|(name, _)| name == field.name()) {
+                continue;
+            }
+            match field.default_value() {
+                Some(default) => ret.push((field.name().to_string(), Some(default.clone()))),
+                None if !field.nullable() => {
+                    return Err(format!("{} is NULL but NOT NULL Constraint", field.name()))
+                }
+                None => {}
+            }
+        }
         Ok(ret)
     }
-    fn column_bytes(field: SqlValue, field_type: Type) -> Result<Option<Vec<u8>>, ()> {
+    /// Calls nextval for the fields left out of the insert that take their default from a sequence
+    pub fn add_sequence_defaults(&mut self, schema: &Schema) {
+        for field in schema.fields() {
+            if let Some(sequence) = field.sequence() {
+                if !self.record.iter().any(|(name, _)| name == field.name()) {
+                    self.record.push((
+                        field.name().to_string(),
+                        SqlValue::NextVal(sequence.to_string()),
+                    ));
+                }
+            }
+        }
+    }
+    /// The values of the record, in the order they were written
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut SqlValue> {
+        self.record.iter_mut().map(|(_, value)| value)
+    }
+    /// The bytes of the value of a field, failing with the reason the field cannot hold it.
+    ///
+    /// A varchar limits its count of characters, a char its count of bytes
+    pub(crate) fn field_bytes(
+        name: &str,
+        value: SqlValue,
+        field_type: Type,
+    ) -> Result<Option<Vec<u8>>, String> {
+        if let (SqlValue::Text(text), Some(limit)) = (&value, field_type.char_limit()) {
+            let (length, unit) = match field_type {
+                Type::Character(CharType::Char(_)) => (text.len(), "bytes"),
+                _ => (text.chars().count(), "characters"),
+            };
+            if length > limit as usize {
+                return Err(format!(
+                    "Value too long for {}: {} {} do not fit in {}",
+                    name,
+                    length,
+                    unit,
+                    field_type.to_string()
+                ));
+            }
+        }
+        Self::column_bytes(value, field_type).map_err(|_| format!("Wrong data type:{}", name))
+    }
+    pub(crate) fn column_bytes(field: SqlValue, field_type: Type) -> Result<Option<Vec<u8>>, ()> {
         match field_type {
             Type::Numeric(num) => match field {
                 SqlValue::NULL => Ok(None),
@@ -66,12 +121,22 @@ impl SqlInsert {
                         Ok(n) => Ok(Some(n.to_ne_bytes().to_vec())),
                         Err(_) => Err(()),
                     },
+                    // the digits past the scale are rounded, the ones before it must fit
+                    NumericType::Decimal(precision, scale) => match decimal::parse(&txt, scale) {
+                        Some(n) if decimal::fits(n, precision) => {
+                            Ok(Some(n.to_ne_bytes().to_vec()))
+                        }
+                        _ => Err(()),
+                    },
                 },
                 _ => Err(()),
             },
-            Type::Character(_) => match field {
+            Type::Character(char) => match field {
                 SqlValue::NULL => Ok(None),
-                SqlValue::Text(t) => Ok(Some(t.as_bytes().to_vec())),
+                SqlValue::Text(t) => match char {
+                    CharType::Char(length) => CharType::padded(&t, length).ok_or(()).map(Some),
+                    CharType::VarChar(_) => Ok(Some(t.as_bytes().to_vec())),
+                },
                 _ => Err(()),
             },
             Type::Boolean => match field {
@@ -79,6 +144,19 @@ impl SqlInsert {
                 SqlValue::Bool(b) => Ok(Some(if b { vec![1] } else { vec![0] })),
                 _ => Err(()),
             },
+            Type::Temporal(temporal) => match field {
+                SqlValue::NULL => Ok(None),
+                SqlValue::Text(t) => match temporal::parse(temporal, &t) {
+                    Ok(micros) => temporal::to_bytes(temporal, micros).ok_or(()).map(Some),
+                    Err(_) => Err(()),
+                },
+                _ => Err(()),
+            },
+            Type::Binary => match field {
+                SqlValue::NULL => Ok(None),
+                SqlValue::Bytes(b) => Ok(Some(b)),
+                _ => Err(()),
+            },
         }
     }
     pub fn target_table(&self) -> &str {
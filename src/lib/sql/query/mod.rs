@@ -1,4 +1,5 @@
 pub mod delete;
+pub mod explain;
 pub mod insert;
 pub mod query;
 pub mod select;
@@ -1,5 +1,5 @@
 use crate::sql::query::{
-    delete::SqlDelete, insert::SqlInsert, select::SqlSelect, update::SqlUpdate,
+    delete::SqlDelete, explain::SqlExplain, insert::SqlInsert, select::SqlSelect, update::SqlUpdate,
 };
 
 #[allow(non_snake_case, clippy::upper_case_acronyms)]
@@ -9,6 +9,7 @@ pub enum SqlQuery {
     INSERT(SqlInsert),
     DELETE(SqlDelete),
     UPDATE(SqlUpdate),
+    EXPLAIN(SqlExplain),
 }
 
 #[allow(non_snake_case, clippy::upper_case_acronyms)]
@@ -25,12 +26,21 @@ impl SqlQuery {
     pub fn DELETE(query: SqlDelete) -> Self {
         Self::DELETE(query)
     }
+    pub fn EXPLAIN(query: SqlExplain) -> Self {
+        Self::EXPLAIN(query)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum SqlValue {
     Text(String),
     Numeric(String),
     Bool(bool),
+    /// The bytes of a hex literal
+    Bytes(Vec<u8>),
     NULL,
+    /// The next value of a sequence, taken when the statement is planned
+    NextVal(String),
+    /// The value nextval last gave the session for a sequence
+    CurrVal(String),
 }
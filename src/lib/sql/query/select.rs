@@ -197,6 +197,13 @@ impl FromClause {
             FromClause::JoinClause(j) => Some(j.clone()),
         }
     }
+    /// The tables the clause reads from
+    pub fn tables(&self) -> Vec<String> {
+        match self {
+            FromClause::Table(t) => vec![t.to_string()],
+            FromClause::JoinClause(j) => j.get_tables(),
+        }
+    }
 }
 #[derive(Debug, Clone)]
 pub struct Join {
@@ -259,3 +266,14 @@ pub enum JoinType {
     Right,
     Full,
 }
+
+impl JoinType {
+    /// Rows of the left input without a match are kept, padded with NULLs
+    pub fn keeps_left(&self) -> bool {
+        matches!(self, JoinType::Left | JoinType::Full)
+    }
+    /// Rows of the right input without a match are kept, padded with NULLs
+    pub fn keeps_right(&self) -> bool {
+        matches!(self, JoinType::Right | JoinType::Full)
+    }
+}
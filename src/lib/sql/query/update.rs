@@ -1,5 +1,9 @@
+use crate::schema::schema::Schema;
+use crate::sql::query::insert::SqlInsert;
 use crate::sql::query::query::SqlValue;
 
+type Record = Vec<(String, Option<Vec<u8>>)>;
+
 #[derive(Debug)]
 pub struct SqlUpdate {
     table: String,
@@ -19,4 +23,35 @@ impl SqlUpdate {
             where_clause,
         }
     }
+    /// Converts the SET pairs to the bytes of their columns, checking them against the schema the
+    /// same way an insert does
+    pub fn raw_bytes(&self, schema: &Schema) -> Result<Record, String> {
+        let mut ret = vec![];
+        let fields = schema.fields_info();
+        for (col_name, col_val) in &self.pairs {
+            if let Some(field) = fields.get(col_name.as_str()) {
+                if !field.nullable() && matches!(col_val, SqlValue::NULL) {
+                    return Err(format!("{} is NULL but NOT NULL Constraint", col_name));
+                }
+                let bytes = SqlInsert::field_bytes(col_name, col_val.clone(), field.field_type())?;
+                ret.push((col_name.clone(), bytes))
+            } else {
+                return Err(format!("{} does not exist", col_name));
+            }
+        }
+        Ok(ret)
+    }
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+    pub fn pairs(&self) -> &Vec<(String, SqlValue)> {
+        &self.pairs
+    }
+    /// The values the columns are set to, in the order they were written
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut SqlValue> {
+        self.pairs.iter_mut().map(|(_, value)| value)
+    }
+    pub fn where_clause(&self) -> Option<&String> {
+        self.where_clause.as_ref()
+    }
 }
@@ -0,0 +1,7 @@
+/// The statements delimiting a transaction block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionControl {
+    Begin,
+    Commit,
+    Rollback,
+}
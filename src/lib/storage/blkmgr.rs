@@ -100,6 +100,15 @@ impl BlockManager {
             .collect()
     }
 
+    /// Closes a file and removes it from the disk
+    pub fn remove_file(&mut self, filename: &str) {
+        let filepath = self.db_dir.join(filename);
+        self.open_files.remove(&filepath);
+        if filepath.exists() {
+            fs::remove_file(filepath).unwrap();
+        }
+    }
+
     /// Returns a specific file from the open files setting its RW modes to true
     ///
     /// If the file name is not in the open files, it creates a new file and returns it with
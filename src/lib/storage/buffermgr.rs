@@ -3,16 +3,17 @@ use super::page::Page;
 use crate::storage::blkmgr;
 use crate::storage::blkmgr::BlockManager;
 use crate::storage::frame::Frame;
+use crate::storage::logmgr::LogManager;
 use chrono::prelude::Utc;
-use std::cell::RefCell;
 use std::char::MAX;
 use std::cmp::min;
 use std::collections::{HashMap, HashSet};
 use std::process::id;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::RwLock;
 use std::thread::sleep;
 
-pub type FrameRef = Rc<RefCell<Frame>>;
+pub type FrameRef = Arc<RwLock<Frame>>;
 
 /// Page replacement policy: LIRS
 /// BufferManager is an entity owned by the database that acts as cache for the pages most used by the database
@@ -30,7 +31,7 @@ impl BufferManager {
     pub fn new(page_size: usize, max_slots: u32) -> Self {
         let mut frame_pool = Vec::with_capacity(max_slots as usize);
         for _ in 0..max_slots {
-            frame_pool.push(FrameRef::new(RefCell::new(Frame::new(page_size))));
+            frame_pool.push(FrameRef::new(RwLock::new(Frame::new(page_size))));
         }
         BufferManager {
             frame_pool,
@@ -50,28 +51,48 @@ impl BufferManager {
 
     /// Uses the frame API to write the frame to the disk.
     /// This function writes the block corresponding to the frame we want to flush only
-    pub fn flush_frame(&mut self, frame_idx: usize, blk_mgr: &mut BlockManager) {
+    pub fn flush_frame(
+        &mut self,
+        frame_idx: usize,
+        blk_mgr: &mut BlockManager,
+        log_mgr: &mut LogManager,
+    ) {
         self.frame_pool[frame_idx]
-            .try_borrow_mut()
+            .try_write()
             .unwrap()
-            .flush(blk_mgr);
+            .flush(blk_mgr, log_mgr);
     }
 
-    pub fn flush_all(&mut self, blk_mgr: &mut BlockManager) {
+    pub fn flush_all(&mut self, blk_mgr: &mut BlockManager, log_mgr: &mut LogManager) {
         for frame in &self.frame_pool {
-            frame.borrow_mut().flush(blk_mgr);
+            frame.write().unwrap().flush(blk_mgr, log_mgr);
         }
     }
-    pub fn force_flush_all(&mut self, blk_mgr: &mut BlockManager) {
+    pub fn force_flush_all(&mut self, blk_mgr: &mut BlockManager, log_mgr: &mut LogManager) {
         for frame in &self.frame_pool {
-            frame.borrow_mut().force_flush(blk_mgr)
+            frame.write().unwrap().force_flush(blk_mgr, log_mgr)
+        }
+    }
+
+    /// Hands the pending redo records of every frame to the log manager
+    pub fn log_all(&mut self, log_mgr: &mut LogManager) {
+        for frame in &self.frame_pool {
+            let mut frame = frame.write().unwrap();
+            if frame.dirty {
+                log_mgr.log_frame(&mut frame);
+            }
         }
     }
 
     /// Try to find if an unpinned page is still in memory and has not been replaced out
     /// if it still exists, pin it and return index to its frame,
     /// else load it into memory and pin it then return index to the frame it got written to.
-    pub fn try_pin(&mut self, blk: &BlockId, blkmgr: &mut BlockManager) -> Option<usize> {
+    pub fn try_pin(
+        &mut self,
+        blk: &BlockId,
+        blkmgr: &mut BlockManager,
+        logmgr: &mut LogManager,
+    ) -> Option<usize> {
         let mut idx = self.locate_existing_block(blk);
         if idx.is_none() {
             idx = self.find_victim_page();
@@ -79,11 +100,11 @@ impl BufferManager {
                 return None;
             }
             let idx = idx.unwrap();
-            let mut frame = self.frame_pool[idx].borrow_mut();
+            let mut frame = self.frame_pool[idx].write().unwrap();
             if let Some(block) = frame.blockid.as_ref() {
                 self.block_map.remove(block);
             }
-            frame.load_block(&blk, blkmgr);
+            frame.load_block(&blk, blkmgr, logmgr);
             self.block_map.insert(blk.to_owned(), idx);
             if frame.is_free() {
                 self.available_slots -= 1;
@@ -92,7 +113,7 @@ impl BufferManager {
             Some(idx)
         } else {
             let idx = idx.unwrap();
-            let mut frame = self.frame_pool[idx].borrow_mut();
+            let mut frame = self.frame_pool[idx].write().unwrap();
             if frame.is_free() {
                 self.available_slots -= 1;
             }
@@ -102,12 +123,17 @@ impl BufferManager {
     }
 
     ///  pin a block to a frame and return a reference to this frame
-    pub fn pin(&mut self, blk: BlockId, blkmgr: &mut BlockManager) -> Option<FrameRef> {
+    pub fn pin(
+        &mut self,
+        blk: BlockId,
+        blkmgr: &mut BlockManager,
+        logmgr: &mut LogManager,
+    ) -> Option<FrameRef> {
         let time_stamp = Utc::now().timestamp_millis();
-        let mut idx = self.try_pin(&blk, blkmgr);
+        let mut idx = self.try_pin(&blk, blkmgr, logmgr);
         while idx.is_none() && !self.timeout(time_stamp) {
             //sleep(1);
-            idx = self.try_pin(&blk, blkmgr);
+            idx = self.try_pin(&blk, blkmgr, logmgr);
         }
         match idx {
             None => None,
@@ -122,7 +148,7 @@ impl BufferManager {
 
     /// Unpins a frame in the buffer. This doesn't remove the frame from memory, It just becomes unpinned
     pub fn unpin(&mut self, frame: FrameRef) {
-        let mut frame = frame.borrow_mut();
+        let mut frame = frame.write().unwrap();
         if frame.num_pins as i32 - 1 < 0 {
             frame.num_pins = 0;
         } else {
@@ -137,7 +163,7 @@ impl BufferManager {
     pub fn find_clean_frame(&self) -> Option<usize> {
         self.frame_pool
             .iter()
-            .position(|frame| frame.borrow().blockid.is_none())
+            .position(|frame| frame.read().unwrap().blockid.is_none())
     }
 
     /// Returns the index to the frame used for replacement.
@@ -159,8 +185,8 @@ impl BufferManager {
             .frame_pool
             .iter()
             .enumerate()
-            .filter(|&(idx, frame)| frame.borrow().is_free())
-            .max_by_key(|&(x, y)| y.borrow().lirs_weight(now));
+            .filter(|&(idx, frame)| frame.read().unwrap().is_free())
+            .max_by_key(|&(x, y)| y.read().unwrap().lirs_weight(now));
         match victim {
             None => None,
             Some((idx, _)) => Some(idx),
@@ -172,13 +198,32 @@ impl BufferManager {
         self.block_map.get(blk).copied()
     }
 
+    /// Forgets the blocks of a file held in the frame pool without writing them, their frames
+    /// become clean frames
+    pub fn discard_file(&mut self, filename: &str) {
+        for frame in &self.frame_pool {
+            let mut frame = frame.write().unwrap();
+            if frame
+                .blockid
+                .as_ref()
+                .is_some_and(|blk| blk.filename == filename)
+            {
+                self.block_map.remove(frame.blockid.as_ref().unwrap());
+                if !frame.is_free() {
+                    self.available_slots += 1;
+                }
+                frame.reset();
+            }
+        }
+    }
+
     pub fn get_available_slots(&self) -> u32 {
         self.available_slots
     }
 
     pub fn print_pinned_blocks(&self) {
         for frame in &self.frame_pool {
-            let borrowed_frame = frame.borrow();
+            let borrowed_frame = frame.read().unwrap();
             if !borrowed_frame.is_free() {
                 dbg!(&borrowed_frame.blockid);
             }
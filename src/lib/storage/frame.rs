@@ -1,5 +1,6 @@
 use crate::storage::blkmgr::BlockManager;
 use crate::storage::blockid::BlockId;
+use crate::storage::logmgr::{LogManager, LogRecord};
 use crate::storage::page::Page;
 use chrono::Utc;
 use std::cmp::max;
@@ -19,7 +20,12 @@ pub struct Frame {
     pub second_last_access_time: i64,
     pub reuse_distance: i64,
     //garbage_frame : bool
-    // log sequence number
+    /// LSN of the last log record describing the page
+    pub page_lsn: u64,
+    /// Redo records of changes not yet handed to the log manager
+    pub log_records: Vec<LogRecord>,
+    /// Whether the page was modified without a redo record since it was last logged
+    pub unlogged: bool,
 }
 
 impl Frame {
@@ -34,6 +40,9 @@ impl Frame {
             last_access_time: -1,
             second_last_access_time: -1,
             reuse_distance: -1,
+            page_lsn: 0,
+            log_records: vec![],
+            unlogged: false,
         }
     }
 
@@ -73,9 +82,14 @@ impl Frame {
 
     /// Loads a new disk block into the memory frame.
     /// Resets the metadata in the frame after reading the block.
-    pub fn load_block(&mut self, blk: &BlockId, blkmgr: &mut BlockManager) {
+    pub fn load_block(
+        &mut self,
+        blk: &BlockId,
+        blkmgr: &mut BlockManager,
+        logmgr: &mut LogManager,
+    ) {
         if self.dirty {
-            self.flush(blkmgr);
+            self.flush(blkmgr, logmgr);
         }
         blkmgr.read(&blk, &mut self.page);
         self.blockid = Some(blk.clone());
@@ -83,10 +97,24 @@ impl Frame {
         self.reset_time_stats();
     }
 
+    /// Empties the frame, the page it holds is dropped without being written
+    pub fn reset(&mut self) {
+        self.blockid = None;
+        self.num_pins = 0;
+        self.dirty = false;
+        self.transaction_num = None;
+        self.log_records.clear();
+        self.unlogged = false;
+        self.reset_time_stats();
+    }
+
     /// Writes the page contained in the frame to the disk and resets the necessary stats in the frame.
-    pub fn flush(&mut self, blkmgr: &mut BlockManager) {
-        // log manager flush here
+    ///
+    /// The log records describing the page are forced to the log before the page is written.
+    pub fn flush(&mut self, blkmgr: &mut BlockManager, logmgr: &mut LogManager) {
         if self.dirty {
+            logmgr.log_frame(self);
+            logmgr.flush(self.page_lsn);
             let blk = self.blockid.as_ref().unwrap();
             blkmgr.write(blk, &mut self.page);
             self.transaction_num = None;
@@ -94,9 +122,9 @@ impl Frame {
         }
     }
 
-    pub fn force_flush(&mut self, blkmgr: &mut BlockManager) {
+    pub fn force_flush(&mut self, blkmgr: &mut BlockManager, logmgr: &mut LogManager) {
         self.dirty = true;
-        self.flush(blkmgr);
+        self.flush(blkmgr, logmgr);
     }
 
     /// Writes data(as bytes) to the page contained in the frame.
@@ -109,5 +137,13 @@ impl Frame {
         // check if data.len > page.len
         self.page.write_bytes(data, offset);
         self.dirty = true;
+        self.unlogged = true;
+    }
+
+    /// Applies a change described by a redo record to the page and keeps the record to be logged.
+    pub fn apply(&mut self, record: LogRecord) {
+        record.redo(&mut self.page);
+        self.log_records.push(record);
+        self.dirty = true;
     }
 }
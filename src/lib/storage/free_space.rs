@@ -1,6 +1,8 @@
 use crate::common::btree_multimap::BTreeMultimap;
 use crate::common::fileops::write_file;
 use crate::storage::blockid::BlockId;
+use crate::storage::logmgr::LogRecord;
+use crate::storage::storagemgr::StorageManager;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -42,6 +44,34 @@ impl FreeMap {
         self.btree.insert_element(space, blkid.clone());
     }
 
+    /// Replaces the space recorded for a BlockId, used when a block gains space back after its
+    /// deleted tuples have been compacted
+    pub fn update_blockspace(&mut self, space: u16, blkid: &BlockId) {
+        self.btree.remove_element(blkid);
+        self.add_blockspace(space, blkid);
+    }
+
+    /// Replaces the space recorded for a BlockId and logs the change, for the map to be recovered
+    /// along with the block after a crash
+    pub fn set_blockspace(
+        &mut self,
+        space: u16,
+        blkid: &BlockId,
+        storage_mgr: &mut StorageManager,
+    ) {
+        self.update_blockspace(space, blkid);
+        let record = LogRecord::FreeSpace {
+            blk: blkid.clone(),
+            space,
+        };
+        storage_mgr.log_file(&self.file, record);
+    }
+
+    /// Forgets the space of every block, for the map to be filled again from the blocks
+    pub fn clear(&mut self) {
+        self.btree = BTreeMultimap::new();
+    }
+
     /// Get the smallest block fitting a tuple and a surplus of 10 bytes
     pub fn get_smallest_fit(&mut self, tuple_size: u16) -> Option<(u16, BlockId)> {
         self.btree.pop_first_bigger_than(tuple_size + 10)
@@ -4,15 +4,18 @@ use crate::schema::null_bitmap::NullBitMap;
 use crate::schema::schema::Layout;
 use crate::storage::blockid::BlockId;
 use crate::storage::buffermgr::FrameRef;
+use crate::storage::logmgr::LogRecord;
+use crate::storage::overflow::{OverflowFile, OverflowPointer, EXTERNAL_FLAG};
 use crate::storage::page::Page;
-use crate::storage::tuple::Tuple;
+use crate::storage::storagemgr::StorageManager;
+use crate::storage::tuple::{Tuple, TUPLE_HEADER_SIZE, XMAX_OFFSET, XMIN_OFFSET};
 use positioned_io2::{Size, WriteAt};
-use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::iter::zip;
 use std::ops::Deref;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::RwLock;
 
 /// An Entity that is owned by a certain Heap Page that encapsulates the byte that indicates the
 /// start and the end of the free space inside a Heap Page
@@ -73,6 +76,13 @@ impl TuplePointer {
     }
 }
 
+/// The bytes of a field as its tuple stores them, a value moved to the overflow file is stored as
+/// the pointer to its chunks
+enum StoredField {
+    Inline(Vec<u8>),
+    External(OverflowPointer),
+}
+
 /// HeapPage is
 #[derive(Debug, Clone)]
 pub struct HeapPage {
@@ -81,8 +91,9 @@ pub struct HeapPage {
     pub frame: FrameRef,
     pub header: PageHeader,
     pub tuple_pointers: Vec<TuplePointer>,
-    pub layout: Rc<Layout>,
+    pub layout: Arc<Layout>,
     vacuuming: bool,
+    overflow: Option<OverflowFile>,
 }
 
 impl HeapPage {
@@ -96,10 +107,10 @@ impl HeapPage {
     ///
     /// The function reads the page header from the payload of the page, reads tuple pointers and push
     /// them into the tuple pointers vector
-    pub fn new(frame: FrameRef, blk: &BlockId, layout: Rc<Layout>) -> Self {
+    pub fn new(frame: FrameRef, blk: &BlockId, layout: Arc<Layout>) -> Self {
         let blk = blk.clone();
         let heap_frame = frame.clone();
-        let mut frame_ref = heap_frame.borrow_mut();
+        let mut frame_ref = heap_frame.write().unwrap();
         frame_ref.update_replace_stats();
         let header = PageHeader::new(frame_ref.page.payload.as_slice());
         let mut current_offset = 6_usize;
@@ -120,9 +131,17 @@ impl HeapPage {
             tuple_pointers,
             layout,
             vacuuming: false,
+            overflow: None,
         }
     }
 
+    /// Lets the page read back the values its tuples keep in the overflow file of its heap file,
+    /// without it reading one of them panics
+    pub fn with_overflow(mut self, storage_mgr: Arc<RwLock<StorageManager>>) -> Self {
+        self.overflow = Some(OverflowFile::new(&self.blk.filename, storage_mgr));
+        self
+    }
+
     /// A helper function called by get_field and get_multiple_fields
     ///
     /// Extracts the bytes of a certain field from the tuple given its name and the null bitmap of
@@ -133,7 +152,7 @@ impl HeapPage {
         field_name: &str,
         tuple: &[u8],
         mut bitmap: NullBitMap,
-    ) -> Option<Vec<u8>> {
+    ) -> Option<StoredField> {
         let bitmap_len = bitmap.bitmap().len();
         let field_index = *self.layout.index_map().get(field_name).unwrap() as usize;
         // the field is already null from the bitmap
@@ -152,11 +171,54 @@ impl HeapPage {
                     .unit_size()
                     .unwrap()) as u8) as u16;
         }
-        Some(
-            field_type
-                .read_from_tuple(&tuple[1 + bitmap_len..], start_byte)
-                .to_vec(),
-        )
+        let data = &tuple[TUPLE_HEADER_SIZE + bitmap_len..];
+        if field_type.needs_pointer()
+            && data.extract_u16(start_byte as usize + 2) & EXTERNAL_FLAG != 0
+        {
+            let offset = data.extract_u16(start_byte as usize) as usize;
+            return Some(StoredField::External(OverflowPointer::from_bytes(
+                &data[offset..offset + OverflowPointer::SIZE],
+            )));
+        }
+        Some(StoredField::Inline(
+            field_type.read_from_tuple(data, start_byte).to_vec(),
+        ))
+    }
+
+    /// The stored bytes of some fields of the tuple at a slot
+    fn stored_fields(&self, field_names: &[String], index: u16) -> Vec<Option<StoredField>> {
+        let pointer = &self.tuple_pointers[index as usize];
+        let mut frame = self.frame.write().unwrap();
+        frame.update_replace_stats();
+        let tuple = &frame.page.payload[pointer.offset..(pointer.offset + pointer.size as usize)];
+        self.tuple_stored_fields(field_names, tuple)
+    }
+
+    /// The stored bytes of some fields of a tuple of the page
+    fn tuple_stored_fields(
+        &self,
+        field_names: &[String],
+        tuple: &[u8],
+    ) -> Vec<Option<StoredField>> {
+        let mut bitmap = NullBitMap::new(self.layout.as_ref().clone());
+        let bitmap_len = bitmap.bitmap().len();
+        bitmap.read_bitmap(&tuple[TUPLE_HEADER_SIZE..(bitmap_len + TUPLE_HEADER_SIZE)]);
+        field_names
+            .iter()
+            .map(|field_name| self.extract_field_from_tuple(field_name, tuple, bitmap.clone()))
+            .collect()
+    }
+
+    /// The bytes of a stored field, a value in the overflow file is read back from its chunks
+    fn load_field(&self, field: StoredField) -> Vec<u8> {
+        match field {
+            StoredField::Inline(bytes) => bytes,
+            StoredField::External(pointer) => self
+                .overflow
+                .as_ref()
+                .expect("the heap page reads a value in the overflow file without it")
+                .read(&pointer),
+        }
     }
 
     /// Returns Some bytes of the required field if not Null
@@ -165,15 +227,11 @@ impl HeapPage {
     /// Uses the function extract_field_from_tuple to do the algorithm of physically getting the
     /// field bytes
     pub fn get_field(&self, field_name: &str, index: u16) -> Option<Vec<u8>> {
-        let layout = self.layout.as_ref().clone();
-        let mut bitmap = NullBitMap::new(layout);
-        let pointer = &self.tuple_pointers[index as usize];
-        let mut frame = self.frame.borrow_mut();
-        frame.update_replace_stats();
-        let tuple = &frame.page.payload[pointer.offset..(pointer.offset + pointer.size as usize)];
-        let bitmap_len = bitmap.bitmap().len();
-        bitmap.read_bitmap(&tuple[1..(bitmap_len + 1)]);
-        self.extract_field_from_tuple(field_name, tuple, bitmap.clone())
+        let field = self
+            .stored_fields(&[field_name.to_string()], index)
+            .pop()
+            .unwrap();
+        field.map(|field| self.load_field(field))
     }
 
     /// Returns a vector of Some bytes of the required fields if not Null
@@ -185,22 +243,10 @@ impl HeapPage {
         field_names: Vec<String>,
         index: u16,
     ) -> HashMap<String, Option<Vec<u8>>> {
-        let layout = self.layout.as_ref().clone();
-        let mut bitmap = NullBitMap::new(layout);
-        let pointer = &self.tuple_pointers[index as usize];
-        let mut frame = self.frame.borrow_mut();
-        frame.update_replace_stats();
-        let tuple = &frame.page.payload[pointer.offset..(pointer.offset + pointer.size as usize)];
-        let bitmap_len = bitmap.bitmap().len();
-        bitmap.read_bitmap(&tuple[1..(bitmap_len + 1)]);
-        let mut fields = Vec::new();
-        for field_name in &field_names {
-            fields.push(self.extract_field_from_tuple(
-                field_name.as_str(),
-                tuple.clone(),
-                bitmap.clone(),
-            ));
-        }
+        let fields = self
+            .stored_fields(&field_names, index)
+            .into_iter()
+            .map(|field| field.map(|field| self.load_field(field)));
         let map: HashMap<String, Option<Vec<u8>>> = zip(field_names, fields).collect();
         map
     }
@@ -216,20 +262,113 @@ impl HeapPage {
         self.get_multiple_fields(fields, slot_num as u16)
     }
 
+    /// The pointers to the values the tuple at a slot keeps in the overflow file
+    pub fn overflow_pointers(&self, slot_num: usize) -> Vec<OverflowPointer> {
+        let fields: Vec<String> = self
+            .layout
+            .map()
+            .iter()
+            .filter(|(_, (field_type, _))| field_type.needs_pointer())
+            .map(|(name, _)| name.to_string())
+            .collect();
+        self.stored_fields(&fields, slot_num as u16)
+            .into_iter()
+            .filter_map(|field| match field {
+                Some(StoredField::External(pointer)) => Some(pointer),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The pointer of a slot as the page records it at the moment, None when the page has no such
+    /// slot or its tuple was removed or marked deleted
+    fn live_pointer(payload: &[u8], slot_num: usize) -> Option<TuplePointer> {
+        let offset = PageHeader::disk_space() + 4 * slot_num;
+        if offset >= payload.extract_u16(0) as usize {
+            return None;
+        }
+        let pointer = TuplePointer::read_pointer(payload, offset);
+        (pointer.size != 0 && payload[pointer.offset] != 1).then_some(pointer)
+    }
+
+    /// Returns the fields of the tuple at a slot if the ids of the transactions that created and
+    /// deleted it pass a visibility check, None for a slot with no live tuple.
+    ///
+    /// Unlike the other reads the slot is looked up again in the page and checked under the same
+    /// latch its fields are read under, so it suits the readers of a page the writers of other
+    /// sessions insert into and compact meanwhile
+    pub fn current_fields(
+        &self,
+        field_names: Vec<String>,
+        slot_num: usize,
+        visible: impl Fn(u64, u64) -> bool,
+    ) -> Option<HashMap<String, Option<Vec<u8>>>> {
+        let mut frame = self.frame.write().unwrap();
+        frame.update_replace_stats();
+        let payload = frame.page.payload.as_slice();
+        let pointer = Self::live_pointer(payload, slot_num)?;
+        let tuple = &payload[pointer.offset..(pointer.offset + pointer.size as usize)];
+        if !visible(
+            tuple.extract_u64(XMIN_OFFSET),
+            tuple.extract_u64(XMAX_OFFSET),
+        ) {
+            return None;
+        }
+        let fields = self.tuple_stored_fields(&field_names, tuple);
+        // the values in the overflow file are read with the page released
+        drop(frame);
+        let fields = fields
+            .into_iter()
+            .map(|field| field.map(|field| self.load_field(field)));
+        Some(zip(field_names, fields).collect())
+    }
+
+    /// Every field of the tuple at a slot, see `current_fields`
+    pub fn current_tuple_fields(
+        &self,
+        slot_num: usize,
+        visible: impl Fn(u64, u64) -> bool,
+    ) -> Option<HashMap<String, Option<Vec<u8>>>> {
+        let fields = self.layout.index_map().keys().cloned().collect();
+        self.current_fields(fields, slot_num, visible)
+    }
+
     /// Virtually deleting a tuple in a specific slot inside the page by setting the deleted byte
     pub fn mark_delete(&self, slot_num: usize) {
         let pointer = &self.tuple_pointers[slot_num];
-        let mut frame = self.frame.borrow_mut();
+        let mut frame = self.frame.write().unwrap();
         frame.update_replace_stats();
-        let offset = pointer.offset;
-        frame.page.payload[offset] = 1_u8;
+        let offset = pointer.offset as u16;
+        frame.apply(LogRecord::HeapDelete { offset });
+    }
+
+    /// Returns the ids of the transactions that created and deleted the tuple version at a specific
+    /// slot, a version not deleted has a deleting id of 0
+    pub fn xids(&self, slot_num: usize) -> (u64, u64) {
+        let pointer = &self.tuple_pointers[slot_num];
+        let frame = self.frame.read().unwrap();
+        let payload = frame.page.payload.as_slice();
+        (
+            payload.extract_u64(pointer.offset + XMIN_OFFSET),
+            payload.extract_u64(pointer.offset + XMAX_OFFSET),
+        )
+    }
+
+    /// Sets the id of the transaction that deleted the tuple version at a specific slot, the tuple
+    /// stays in the page for the transactions that still see it
+    pub fn set_xmax(&self, slot_num: usize, xmax: u64) {
+        let pointer = &self.tuple_pointers[slot_num];
+        let mut frame = self.frame.write().unwrap();
+        frame.update_replace_stats();
+        let offset = pointer.offset as u16;
+        frame.apply(LogRecord::HeapSetXmax { offset, xmax });
     }
 
     /// Returns a vector of bytes containing the tuple that exists at a specific slot inside the page
     /// as bytes
     pub fn get_tuple(&self, slot_num: usize) -> Vec<u8> {
         let pointer = &self.tuple_pointers[slot_num];
-        let mut frame = self.frame.borrow_mut();
+        let mut frame = self.frame.write().unwrap();
         if !self.vacuuming {
             frame.update_replace_stats();
         }
@@ -238,9 +377,28 @@ impl HeapPage {
         frame.page.payload[offset..(offset + size as usize)].to_vec()
     }
 
+    /// Returns the fields of the tuple at a slot as format version 1 of the data files wrote it, the
+    /// header of its tuples was the deleted byte alone
+    pub fn v1_tuple(&self, slot_num: usize) -> Vec<(String, Option<Vec<u8>>)> {
+        let mut tuple = self.get_tuple(slot_num);
+        // widened to the current header, the version of no transaction that every snapshot sees
+        tuple.splice(1..1, [0; TUPLE_HEADER_SIZE - 1]);
+        let mut bitmap = NullBitMap::new(self.layout.as_ref().clone());
+        let bitmap_len = bitmap.bitmap().len();
+        bitmap.read_bitmap(&tuple[TUPLE_HEADER_SIZE..(bitmap_len + TUPLE_HEADER_SIZE)]);
+        let name_map = self.layout.name_map();
+        (0..self.layout.fields_count() as u8)
+            .map(|index| {
+                let name = name_map[&index].clone();
+                let field = self.extract_field_from_tuple(&name, &tuple, bitmap.clone());
+                (name, field.map(|field| self.load_field(field)))
+            })
+            .collect()
+    }
+
     /// A helper function used by new_from_empty to create an empty HeapPage
     pub fn init_heap(frame: &FrameRef, special_area: u16) {
-        let mut frame = frame.borrow_mut();
+        let mut frame = frame.write().unwrap();
         let mut header: Vec<u8> = Vec::new();
         if special_area == 0 {
             header = [
@@ -261,8 +419,19 @@ impl HeapPage {
         frame.write(header.as_slice())
     }
 
+    /// Formats the page of a block as an empty heap page unless it already is a heap page.
+    ///
+    /// A block the file is extended with stays zeroed on the disk until its page is first written
+    /// back, a crash before that leaves it unformatted
+    pub fn format_if_zeroed(frame: &FrameRef) {
+        let space_start = frame.read().unwrap().page.payload.as_slice().extract_u16(0);
+        if space_start == 0 {
+            HeapPage::init_heap(frame, 0);
+        }
+    }
+
     /// Creates an empty Heap Page and returns it
-    pub fn new_from_empty(frame: FrameRef, blk: &BlockId, layout: Rc<Layout>) -> Self {
+    pub fn new_from_empty(frame: FrameRef, blk: &BlockId, layout: Arc<Layout>) -> Self {
         HeapPage::init_heap(&frame, 0);
         HeapPage::new(frame, blk, layout)
     }
@@ -271,7 +440,7 @@ impl HeapPage {
     pub fn new_from_empty_special(
         frame: FrameRef,
         blk: &BlockId,
-        layout: Rc<Layout>,
+        layout: Arc<Layout>,
         special_area: u16,
     ) -> Self {
         HeapPage::init_heap(&frame, special_area);
@@ -283,10 +452,16 @@ impl HeapPage {
     pub fn pointer_and_tuple_exist(&self, tuple_pointer: usize) -> (bool, bool) {
         match self.tuple_pointers.get(tuple_pointer) {
             None => (false, false),
-            Some(tuple) => (true, tuple.size != 0),
+            Some(tuple) => (true, tuple.size != 0 && !self.is_deleted(tuple_pointer)),
         }
     }
 
+    /// Checks the deleted byte of the tuple at a specific slot
+    pub fn is_deleted(&self, slot_num: usize) -> bool {
+        let pointer = &self.tuple_pointers[slot_num];
+        self.frame.read().unwrap().page.payload[pointer.offset] == 1
+    }
+
     /// Returns the number of tuple pointers inside a Heap Page
     pub fn pointer_count(&self) -> usize {
         self.tuple_pointers.len()
@@ -299,34 +474,35 @@ impl HeapPage {
     /// If there is no empty tuple pointers, add a new tuple pointer
     /// Write the tuple at its correct position
     pub fn insert_tuple(&mut self, tuple: Tuple) -> usize {
-        let tuple_size = tuple.tuple_size();
+        let tuple_size = tuple.tuple_size() as u16;
         let pointer_pos = self
             .tuple_pointers
             .iter_mut()
             .position(|pointer| pointer.size == 0);
-        let mut inserted_at = 0;
-        let (tuple_pointer_bytes, index) = if pointer_pos.is_some() {
-            let tuple_pointer = self.tuple_pointers.get_mut(pointer_pos.unwrap()).unwrap();
+        let inserted_at = if let Some(pos) = pointer_pos {
+            let tuple_pointer = self.tuple_pointers.get_mut(pos).unwrap();
             tuple_pointer.offset = self.header.space_end - tuple_size as usize;
             tuple_pointer.size = tuple_size;
             self.header.space_end = tuple_pointer.offset;
-            inserted_at = pointer_pos.unwrap();
-            (tuple_pointer.clone().to_bytes(), inserted_at)
+            pos
         } else {
             let mut tuple_pointer =
                 TuplePointer::new(self.header.space_end - tuple_size as usize, tuple_size);
             self.header.space_start += 4;
             self.header.space_end = tuple_pointer.offset;
-            self.tuple_pointers.push(tuple_pointer.clone());
-            inserted_at = self.tuple_pointers.len() - 1;
-            (tuple_pointer.to_bytes(), inserted_at + 1)
+            self.tuple_pointers.push(tuple_pointer);
+            self.tuple_pointers.len() - 1
+        };
+        let record = LogRecord::HeapInsert {
+            slot: inserted_at as u16,
+            offset: self.header.space_end as u16,
+            tuple: tuple.to_bytes(),
+            space_start: self.header.space_start as u16,
+            space_end: self.header.space_end as u16,
         };
-        let mut borrowed_frame = self.frame.borrow_mut();
+        let mut borrowed_frame = self.frame.write().unwrap();
         borrowed_frame.update_replace_stats();
-        borrowed_frame.write_at(tuple_pointer_bytes.as_slice(), ((index * 4) + 2) as u64);
-        borrowed_frame.write_at(tuple.to_bytes().as_slice(), self.header.space_end as u64);
-        borrowed_frame.write_at((self.header.space_start as u16).to_ne_bytes().as_slice(), 0);
-        borrowed_frame.write_at((self.header.space_end as u16).to_ne_bytes().as_slice(), 2);
+        borrowed_frame.apply(record);
         inserted_at
     }
 
@@ -357,7 +533,8 @@ impl HeapPage {
         let mut special_area = self.header.special_area_offset as u16;
         for mut tuple_pointer_index in 0..self.tuple_pointers.len() {
             let tuple = self.get_tuple(tuple_pointer_index);
-            if tuple[0] == 1 {
+            // slots emptied by an earlier vacuum have no tuple data left
+            if tuple.is_empty() || tuple[0] == 1 {
                 self.tuple_pointers[tuple_pointer_index].offset = 0;
                 self.tuple_pointers[tuple_pointer_index].size = 0;
                 new_page.write_bytes(
@@ -387,9 +564,9 @@ impl HeapPage {
         new_page.write_bytes(space_end.to_ne_bytes().as_slice(), 2);
         new_page.write_bytes(special_area.to_ne_bytes().as_slice(), 4);
         self.frame
-            .borrow_mut()
-            .page
-            .write_bytes(new_page.payload.as_slice(), 0);
+            .write()
+            .unwrap()
+            .apply(LogRecord::PageImage(new_page.payload));
         self.header.space_start = space_start as usize;
         self.header.space_end = space_end as usize;
         self.vacuuming = false;
@@ -404,9 +581,9 @@ impl HeapPage {
     }
 
     pub fn get_special_area(&self) -> Vec<u8> {
-        self.frame.borrow_mut().update_replace_stats();
+        self.frame.write().unwrap().update_replace_stats();
         let special_area_vector =
-            self.frame.borrow().page.payload[self.header.special_area_offset..].to_vec();
+            self.frame.read().unwrap().page.payload[self.header.special_area_offset..].to_vec();
         if special_area_vector.len() == 1 {
             Vec::new()
         } else {
@@ -415,7 +592,7 @@ impl HeapPage {
     }
 
     pub fn write_special_area(&mut self, special_area_bytes: Vec<u8>) {
-        self.frame.borrow_mut().write_at(
+        self.frame.write().unwrap().write_at(
             special_area_bytes.as_slice(),
             self.header.special_area_offset as u64,
         );
@@ -428,7 +605,8 @@ impl HeapPage {
             .flat_map(|pointer| pointer.clone().to_bytes())
             .collect::<Vec<u8>>();
         self.frame
-            .borrow_mut()
+            .write()
+            .unwrap()
             .write_at(tuple_pointers_bytes.as_slice(), 6)
     }
 }
@@ -1,8 +1,356 @@
+use crate::common::fileops::write_file;
+use crate::common::numerical::ByteMagic;
+use crate::storage::blkmgr::BlockManager;
+use crate::storage::blockid::BlockId;
+use crate::storage::frame::Frame;
+use crate::storage::free_space::FreeMap;
+use crate::storage::page::Page;
+use crate::storage::tuple::XMAX_OFFSET;
+use bincode::{Decode, Encode};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const LOG_FILE: &str = "aqua_wal";
+
+/// A redo record describing a change made to a single page, or to one of the small files kept
+/// outside the pages like the bucket directory of a hash index and the FSM of a table.
+///
+/// Records are physical, replaying the same record twice leaves the page in the same state
+/// so recovery can blindly redo everything since the last checkpoint.
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub enum LogRecord {
+    /// A tuple written to a heap page alongside its tuple pointer and the new page header
+    HeapInsert {
+        slot: u16,
+        offset: u16,
+        tuple: Vec<u8>,
+        space_start: u16,
+        space_end: u16,
+    },
+    /// The deleted byte of the tuple starting at offset got set
+    HeapDelete { offset: u16 },
+    /// The deleting transaction id of the tuple version starting at offset got set, 0 clears it
+    HeapSetXmax { offset: u16, xmax: u64 },
+    /// An index entry appended to a hash bucket and the new bucket record count
+    IndexInsert {
+        offset: u16,
+        entry: Vec<u8>,
+        num_records: u16,
+    },
+    /// The deleted byte of the hash index entry starting at offset got set
+    IndexDelete { offset: u16 },
+    /// The whole content of a page, used for changes that restructure the page
+    PageImage(Vec<u8>),
+    /// The whole content of a file kept outside the pages, like a bucket directory
+    FileImage(Vec<u8>),
+    /// The free space of a block recorded in an FSM
+    FreeSpace { blk: BlockId, space: u16 },
+}
+
+impl LogRecord {
+    /// Applies the change described by the record to an in-memory page
+    pub fn redo(&self, page: &mut Page) {
+        match self {
+            LogRecord::HeapInsert {
+                slot,
+                offset,
+                tuple,
+                space_start,
+                space_end,
+            } => {
+                let pointer = [offset.to_ne_bytes(), (tuple.len() as u16).to_ne_bytes()].concat();
+                page.write_bytes(pointer.as_slice(), 6 + *slot as u64 * 4);
+                page.write_bytes(tuple.as_slice(), *offset as u64);
+                page.write_bytes(space_start.to_ne_bytes().as_slice(), 0);
+                page.write_bytes(space_end.to_ne_bytes().as_slice(), 2);
+            }
+            LogRecord::HeapDelete { offset } | LogRecord::IndexDelete { offset } => {
+                page.write_bytes(&[1], *offset as u64)
+            }
+            LogRecord::HeapSetXmax { offset, xmax } => page.write_bytes(
+                xmax.to_ne_bytes().as_slice(),
+                (*offset as usize + XMAX_OFFSET) as u64,
+            ),
+            LogRecord::IndexInsert {
+                offset,
+                entry,
+                num_records,
+            } => {
+                page.write_bytes(num_records.to_ne_bytes().as_slice(), 1);
+                page.write_bytes(entry.as_slice(), *offset as u64);
+            }
+            LogRecord::PageImage(image) => page.write_bytes(image.as_slice(), 0),
+            LogRecord::FileImage(_) | LogRecord::FreeSpace { .. } => {}
+        }
+    }
+
+    /// Whether the record describes a change to a file kept outside the pages
+    fn is_file_record(&self) -> bool {
+        matches!(self, LogRecord::FileImage(_) | LogRecord::FreeSpace { .. })
+    }
+}
+
+/// Applies the changes to the files kept outside the pages in log order, then writes the files.
+///
+/// The changes to an FSM that no longer exists are skipped, the table was dropped after them
+fn redo_files(records: Vec<(PathBuf, LogRecord)>) {
+    let mut free_maps: HashMap<PathBuf, FreeMap> = HashMap::new();
+    for (file, record) in records {
+        match record {
+            LogRecord::FileImage(image) => write_file(&file, image).unwrap(),
+            LogRecord::FreeSpace { blk, space } => {
+                if !free_maps.contains_key(&file) && !file.exists() {
+                    continue;
+                }
+                free_maps
+                    .entry(file.clone())
+                    .or_insert_with(|| FreeMap::new(file))
+                    .update_blockspace(space, &blk);
+            }
+            _ => {}
+        }
+    }
+    for free_map in free_maps.values() {
+        free_map.flush_map();
+    }
+}
+
+/// The unit written to the log file, a record tagged with its sequence number and target block
+#[derive(Encode, Decode, Debug)]
+struct LogEntry {
+    lsn: u64,
+    blk: BlockId,
+    record: LogRecord,
+}
+
+/// LogManager owns the write-ahead log of the storage engine.
+///
+/// Every change to a buffered page is turned into a log record, records are kept in an in-memory
+/// tail and forced to the log file before any page they describe is written back to its block.
+///
+/// The files kept outside the pages are only written from their records, at a checkpoint or when
+/// they are read again, so they never hold a change the log may lose in a crash.
+///
+/// On startup the log is replayed over the data files to redo changes that did not make it to disk.
+/// The log is truncated whenever the buffer pool is fully flushed since all logged changes are durable then.
 pub struct LogManager {
-    dummy: u8,
+    log_path: PathBuf,
+    log_file: File,
+    log_tail: Vec<u8>,
+    next_lsn: u64,
+    flushed_lsn: u64,
+    /// The records of the files kept outside the pages not written to their files yet
+    file_records: Vec<(PathBuf, LogRecord)>,
 }
+
 impl LogManager {
-    pub fn new() -> Self {
-        Self { dummy: 0 }
+    pub fn new(db_dir: &str) -> Self {
+        let log_path = Path::new(db_dir).join(LOG_FILE);
+        let log_file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&log_path)
+            .unwrap();
+        Self {
+            log_path,
+            log_file,
+            log_tail: vec![],
+            next_lsn: 1,
+            flushed_lsn: 0,
+            file_records: vec![],
+        }
+    }
+
+    /// Appends a record to the log tail and returns its log sequence number.
+    ///
+    /// The record is not durable until the log is flushed past the returned LSN
+    pub fn append(&mut self, blk: &BlockId, record: LogRecord) -> u64 {
+        let lsn = self.next_lsn;
+        self.next_lsn += 1;
+        let entry = LogEntry {
+            lsn,
+            blk: blk.clone(),
+            record,
+        };
+        let bytes = bincode::encode_to_vec(entry, bincode::config::standard()).unwrap();
+        self.log_tail
+            .extend_from_slice((bytes.len() as u32).to_ne_bytes().as_slice());
+        self.log_tail.extend(bytes);
+        lsn
+    }
+
+    /// Appends the record of a change to a file kept outside the pages, the file is written with it
+    /// at the next checkpoint or call to `write_files`
+    pub fn append_file(&mut self, file: &Path, record: LogRecord) -> u64 {
+        let lsn = self.append(&BlockId::new(file.to_str().unwrap(), 0), record.clone());
+        self.file_records.push((file.to_owned(), record));
+        lsn
+    }
+
+    /// Writes the pending changes of the files kept outside the pages, only valid once the log is
+    /// flushed past them
+    pub fn write_files(&mut self) {
+        redo_files(std::mem::take(&mut self.file_records));
+    }
+
+    /// Moves the pending records of a frame to the log tail.
+    ///
+    /// A frame that was modified without describing the change through a record is logged as a full page image
+    pub(crate) fn log_frame(&mut self, frame: &mut Frame) {
+        let blk = match frame.blockid.as_ref() {
+            None => return,
+            Some(blk) => blk.clone(),
+        };
+        if frame.unlogged {
+            frame.log_records.clear();
+            frame.unlogged = false;
+            frame.page_lsn = self.append(&blk, LogRecord::PageImage(frame.page.payload.clone()));
+        } else {
+            for record in frame.log_records.drain(..).collect::<Vec<_>>() {
+                frame.page_lsn = self.append(&blk, record);
+            }
+        }
+    }
+
+    /// Forces the log tail to the disk if it contains records up to the given LSN
+    pub fn flush(&mut self, lsn: u64) {
+        if lsn <= self.flushed_lsn || self.log_tail.is_empty() {
+            return;
+        }
+        self.log_file.seek(SeekFrom::End(0)).unwrap();
+        self.log_file.write_all(self.log_tail.as_slice()).unwrap();
+        self.log_file.sync_data().unwrap();
+        self.log_tail.clear();
+        self.flushed_lsn = self.next_lsn - 1;
+    }
+
+    /// Forces every appended record to the disk
+    pub fn flush_all(&mut self) {
+        self.flush(self.next_lsn - 1);
+    }
+
+    /// Discards the log, only valid when all pages described by the log have been written to disk.
+    ///
+    /// The pending changes of the files kept outside the pages are written first
+    pub fn checkpoint(&mut self) {
+        redo_files(std::mem::take(&mut self.file_records));
+        self.log_tail.clear();
+        self.log_file.set_len(0).unwrap();
+        self.log_file.sync_all().unwrap();
+        self.flushed_lsn = self.next_lsn - 1;
+    }
+
+    /// Reads every complete entry in the log file, a torn entry at the end of the log is ignored
+    fn read_entries(&mut self) -> Vec<LogEntry> {
+        let mut log = vec![];
+        self.log_file.rewind().unwrap();
+        self.log_file.read_to_end(&mut log).unwrap();
+        let mut entries = vec![];
+        let mut pos = 0_usize;
+        while pos + 4 <= log.len() {
+            let len = log.as_slice().extract_u32(pos) as usize;
+            pos += 4;
+            if pos + len > log.len() {
+                break;
+            }
+            match bincode::decode_from_slice::<LogEntry, _>(
+                &log[pos..pos + len],
+                bincode::config::standard(),
+            ) {
+                Ok((entry, _)) => entries.push(entry),
+                Err(_) => break,
+            }
+            pos += len;
+        }
+        entries
+    }
+
+    /// Redoes every logged change over the data files then checkpoints the log.
+    ///
+    /// Returns the number of replayed records
+    pub fn recover(&mut self, blkmgr: &mut BlockManager, block_size: usize) -> usize {
+        let entries = self.read_entries();
+        let replayed = entries.len();
+        let mut pages: HashMap<BlockId, Page> = HashMap::new();
+        let mut files = vec![];
+        for entry in entries {
+            self.next_lsn = self.next_lsn.max(entry.lsn + 1);
+            if entry.record.is_file_record() {
+                files.push((PathBuf::from(&entry.blk.filename), entry.record));
+                continue;
+            }
+            let page = pages.entry(entry.blk.clone()).or_insert_with(|| {
+                let mut page = Page::new(block_size);
+                blkmgr.read(&entry.blk, &mut page);
+                page
+            });
+            entry.record.redo(page);
+        }
+        for (blk, mut page) in pages {
+            blkmgr.write(&blk, &mut page);
+        }
+        redo_files(files);
+        self.checkpoint();
+        replayed
+    }
+
+    pub fn log_path(&self) -> &Path {
+        &self.log_path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::testing::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_redo_is_idempotent() {
+        let mut page = Page::new(64);
+        let record = LogRecord::HeapInsert {
+            slot: 0,
+            offset: 50,
+            tuple: vec![0, 7, 7, 7],
+            space_start: 10,
+            space_end: 50,
+        };
+        record.redo(&mut page);
+        let once = page.payload.clone();
+        record.redo(&mut page);
+        assert_eq!(once, page.payload);
+        assert_eq!(once.as_slice().extract_u16(6), 50);
+        assert_eq!(once.as_slice().extract_u16(8), 4);
+        assert_eq!(&once[50..54], &[0, 7, 7, 7]);
+        LogRecord::HeapDelete { offset: 50 }.redo(&mut page);
+        assert_eq!(page.payload[50], 1);
+    }
+
+    #[test]
+    fn test_recover_unflushed_pages() {
+        let dir = TempDir::new("wal");
+        let db_dir = dir.to_str();
+        let blk = BlockId::new("wal_heap", 0);
+        let mut blkmgr = BlockManager::new(db_dir, 64);
+        blkmgr.extend_file("wal_heap");
+        {
+            let mut logmgr = LogManager::new(db_dir);
+            logmgr.append(&blk, LogRecord::PageImage(vec![3; 64]));
+            let lsn = logmgr.append(&blk, LogRecord::HeapDelete { offset: 10 });
+            logmgr.flush(lsn);
+            // never flushed, lost on crash
+            logmgr.append(&blk, LogRecord::HeapDelete { offset: 11 });
+        }
+        let mut logmgr = LogManager::new(db_dir);
+        assert_eq!(logmgr.recover(&mut blkmgr, 64), 2);
+        let mut page = Page::new(64);
+        blkmgr.read(&blk, &mut page);
+        assert_eq!(page.payload[10], 1);
+        assert_eq!(page.payload[11], 3);
+        assert_eq!(fs::metadata(logmgr.log_path()).unwrap().len(), 0);
     }
 }
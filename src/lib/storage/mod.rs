@@ -7,6 +7,7 @@ mod frame;
 pub mod free_space;
 pub mod heap;
 pub mod logmgr;
+pub mod overflow;
 pub mod page;
 pub mod storagemgr;
 pub mod tuple;
@@ -0,0 +1,186 @@
+use crate::common::numerical::ByteMagic;
+use crate::index::Rid;
+use crate::schema::schema::{Layout, Schema};
+use crate::schema::types::{CharType, NumericType, Type};
+use crate::storage::heap::HeapPage;
+use crate::storage::storagemgr::StorageManager;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, RwLock};
+
+// A value too large to leave in its tuple is cut in chunks stored as the tuples of the overflow
+// file of the table, each chunk pointing to the next one. The tuple keeps an OverflowPointer to
+// the first chunk in place of the value, and the length in the pointer of the field has its
+// EXTERNAL_FLAG set
+
+/// A tuple larger than this has its largest variable length values moved to the overflow file, a
+/// heap page holds two tuples of this size
+pub const OVERFLOW_THRESHOLD: usize = 2000;
+/// The number of bytes of a value each chunk holds, a chunk tuple fits in an empty heap page
+pub const CHUNK_SIZE: usize = 4000;
+/// Set in the length of the pointer of a field whose bytes are an OverflowPointer, the length of a
+/// value inside a page never reaches it
+pub const EXTERNAL_FLAG: u16 = 0x8000;
+
+/// The name of the overflow file of a heap file
+pub fn overflow_file(heap_file: &str) -> String {
+    format!("{}_overflow", heap_file)
+}
+
+/// The name of the free space map of the overflow file of a heap file
+pub fn overflow_freemap_file(heap_file: &str) -> String {
+    format!("{}_freemap", overflow_file(heap_file))
+}
+
+/// The layout of the chunks in an overflow file, the last chunk of a value has no next one
+pub fn chunk_layout() -> Arc<Layout> {
+    let mut schema = Schema::new();
+    schema.add_field_default_constraints("next_block", Type::Numeric(NumericType::BigInt), None);
+    schema.add_field_default_constraints("next_slot", Type::Numeric(NumericType::SmallInt), None);
+    schema.add_field(
+        "data",
+        Type::Character(CharType::VarChar(None)),
+        false,
+        false,
+        None,
+        None,
+    );
+    Arc::new(schema.to_layout())
+}
+
+/// The fields of a chunk holding some bytes of a value, followed by the chunk at a Rid
+pub fn chunk_fields(data: &[u8], next: Option<&Rid>) -> Vec<(String, Option<Vec<u8>>)> {
+    vec![
+        (
+            "next_block".to_string(),
+            next.map(|rid| (rid.block_num() as i64).to_ne_bytes().to_vec()),
+        ),
+        (
+            "next_slot".to_string(),
+            next.map(|rid| (rid.slot_num() as i16).to_ne_bytes().to_vec()),
+        ),
+        ("data".to_string(), Some(data.to_vec())),
+    ]
+}
+
+/// What a tuple keeps of a value moved to the overflow file: its length and the Rid of its first
+/// chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverflowPointer {
+    length: u32,
+    first_chunk: Rid,
+}
+
+impl OverflowPointer {
+    /// The number of bytes of a pointer in a tuple
+    pub const SIZE: usize = 14;
+
+    pub fn new(length: usize, first_chunk: Rid) -> Self {
+        Self {
+            length: length as u32,
+            first_chunk,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.length.to_ne_bytes().to_vec();
+        bytes.extend(self.first_chunk.block_num().to_ne_bytes());
+        bytes.extend(self.first_chunk.slot_num().to_ne_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            length: bytes.extract_u32(0),
+            first_chunk: Rid::new(bytes.extract_u64(4), bytes.extract_u16(12)),
+        }
+    }
+}
+
+/// The overflow file of a heap file, which the pages of the heap file read the values their
+/// tuples keep there from
+#[derive(Clone)]
+pub struct OverflowFile {
+    storage_mgr: Arc<RwLock<StorageManager>>,
+    filename: String,
+    layout: Arc<Layout>,
+}
+
+impl Debug for OverflowFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OverflowFile")
+            .field("filename", &self.filename)
+            .finish()
+    }
+}
+
+impl OverflowFile {
+    pub fn new(heap_file: &str, storage_mgr: Arc<RwLock<StorageManager>>) -> Self {
+        Self {
+            storage_mgr,
+            filename: overflow_file(heap_file),
+            layout: chunk_layout(),
+        }
+    }
+
+    /// The Rids and the bytes of the chunks of a value, in order
+    fn chunks(&self, pointer: &OverflowPointer) -> Vec<(Rid, Vec<u8>)> {
+        let mut chunks = vec![];
+        let mut next = Some(pointer.first_chunk.clone());
+        while let Some(rid) = next {
+            let (blk, slot) = rid.rid_blk_num(&self.filename);
+            let frame = self.storage_mgr.write().unwrap().pin(blk.clone()).unwrap();
+            let mut chunk = HeapPage::new(frame.clone(), &blk, self.layout.clone())
+                .current_fields(
+                    vec![
+                        "next_block".to_string(),
+                        "next_slot".to_string(),
+                        "data".to_string(),
+                    ],
+                    slot,
+                    |_, _| true,
+                )
+                .expect("the chunks of a value are kept as long as a tuple points to them");
+            self.storage_mgr.write().unwrap().unpin(frame);
+            next = match (chunk.remove("next_block"), chunk.remove("next_slot")) {
+                (Some(Some(block)), Some(Some(slot))) => Some(Rid::new(
+                    block.as_slice().to_i64() as u64,
+                    slot.as_slice().to_i16() as u16,
+                )),
+                _ => None,
+            };
+            chunks.push((rid, chunk.remove("data").unwrap().unwrap()));
+        }
+        chunks
+    }
+
+    /// Reads a value back from its chunks
+    pub fn read(&self, pointer: &OverflowPointer) -> Vec<u8> {
+        let mut value = Vec::with_capacity(pointer.length as usize);
+        for (_, data) in self.chunks(pointer) {
+            value.extend(data);
+        }
+        value
+    }
+
+    /// The Rids of the chunks of a value, deleting them frees the space of the value
+    pub fn chunk_rids(&self, pointer: &OverflowPointer) -> Vec<Rid> {
+        self.chunks(pointer)
+            .into_iter()
+            .map(|(rid, _)| rid)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::Rid;
+    use crate::storage::overflow::OverflowPointer;
+
+    #[test]
+    fn test_pointer_bytes() {
+        let pointer = OverflowPointer::new(123_456, Rid::new(7, 42));
+        let bytes = pointer.to_bytes();
+        assert_eq!(bytes.len(), OverflowPointer::SIZE);
+        assert_eq!(OverflowPointer::from_bytes(&bytes), pointer);
+    }
+}
@@ -3,10 +3,25 @@ use crate::storage::blockid::BlockId;
 use crate::storage::buffermgr::FrameRef;
 use crate::storage::frame::Frame;
 use crate::storage::heap::HeapPage;
+use crate::storage::logmgr::LogRecord;
 use crate::storage::{blkmgr::BlockManager, buffermgr::BufferManager, logmgr::LogManager};
 use std::borrow::BorrowMut;
-use std::cell::RefCell;
-use std::path::PathBuf;
+use std::cell::Cell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// The version of the format of the data files, raised with any change that leaves the files
+/// written before it unreadable like the widening of the tuple header. The directories created
+/// before the format had a version are in version 1
+pub const FORMAT_VERSION: u32 = 2;
+/// The file of a data directory recording the version of the format of its files
+const VERSION_FILE: &str = "AQUA_VERSION";
+
+thread_local! {
+    // number of pin requests served to the thread, read by EXPLAIN ANALYZE
+    static PINS: Cell<u64> = const { Cell::new(0) };
+}
 
 /// Represents database configuration
 ///
@@ -47,12 +62,17 @@ pub struct StorageManager {
 impl StorageManager {
     /// Creates a StorageManager instance, this should be called by the DatabaseManager and typically
     /// only one will exist per database
+    ///
+    /// Changes found in the write-ahead log are redone on the data files before the manager is returned
     pub fn new(db_dir: &str, block_size: usize, max_buffer_slots: u32) -> Self {
+        let mut block_manager = BlockManager::new(db_dir, block_size);
+        let mut log_manager = LogManager::new(db_dir);
+        log_manager.recover(&mut block_manager, block_size);
         Self {
             database_info: DatabaseInfo::new(db_dir, block_size),
             buffer_manager: BufferManager::new(block_size, max_buffer_slots),
-            block_manager: BlockManager::new(db_dir, block_size),
-            log_manager: LogManager::new(),
+            block_manager,
+            log_manager,
         }
     }
     /// Tries to pin disk block to a frame and returns a reference to that frame
@@ -61,12 +81,18 @@ impl StorageManager {
     /// if 2 or more users try to mutably borrow it concurrently, it is the responsibility of the
     /// **Requester** to avoid that
     pub fn pin(&mut self, blk: BlockId) -> Option<FrameRef> {
-        self.buffer_manager.pin(blk, &mut self.block_manager)
+        PINS.with(|pins| pins.set(pins.get() + 1));
+        self.buffer_manager
+            .pin(blk, &mut self.block_manager, &mut self.log_manager)
     }
 
-    /// Unpins a frame
+    /// Unpins a frame, the changes made to the frame are handed to the log manager
     pub fn unpin(&mut self, frame: FrameRef) {
-        let frm = frame.borrow().blockid.as_ref().unwrap().clone();
+        if frame.read().unwrap().dirty {
+            let mut frm = frame.try_write().unwrap();
+            self.log_manager.log_frame(&mut frm);
+        }
+        let frm = frame.read().unwrap().blockid.as_ref().unwrap().clone();
         // dbg!(&frm);
         if frm.block_num == 0 && frm.filename.ends_with("s_id_idx_file") {
             return;
@@ -89,24 +115,101 @@ impl StorageManager {
         self.block_manager.read_raw(blockid, byte_count)
     }
 
+    /// Records that the files of a new data directory are written in the current format
+    pub fn write_format_version(data_dir: &str) {
+        fs::write(
+            Path::new(data_dir).join(VERSION_FILE),
+            FORMAT_VERSION.to_string(),
+        )
+        .expect("could not write the format version of the data directory");
+    }
+
+    /// The version of the format of the files of a data directory, a directory with no version
+    /// file was written before the files recorded it and is in format version 1
+    pub fn format_version(data_dir: &str) -> Result<u32, String> {
+        match fs::read_to_string(Path::new(data_dir).join(VERSION_FILE)) {
+            Ok(version) => version.trim().parse::<u32>().map_err(|_| {
+                format!(
+                    "The format version of the data directory {} is unreadable",
+                    data_dir
+                )
+            }),
+            Err(_) => Ok(1),
+        }
+    }
+
+    /// Fails unless the files of a data directory are written in the current format, it has to be
+    /// checked before a manager recovers the directory
+    pub fn check_format_version(data_dir: &str) -> Result<(), String> {
+        let version = Self::format_version(data_dir)?;
+        if version != FORMAT_VERSION {
+            return Err(format!(
+                "The data directory {} is in format version {}, this server reads version {} only",
+                data_dir, version, FORMAT_VERSION
+            ));
+        }
+        Ok(())
+    }
+
+    /// Flushes every dirty frame then checkpoints the log since no change is left only in memory
     pub fn flush_all(&mut self) {
-        self.buffer_manager.flush_all(&mut self.block_manager);
+        self.buffer_manager
+            .flush_all(&mut self.block_manager, &mut self.log_manager);
+        self.log_manager.checkpoint();
     }
 
     pub fn force_flush_all(&mut self) {
-        self.buffer_manager.force_flush_all(&mut self.block_manager);
+        self.buffer_manager
+            .force_flush_all(&mut self.block_manager, &mut self.log_manager);
+        self.log_manager.checkpoint();
     }
 
     /// Flushes a memory frame to the disk block it is currently pinned to , resetting its stats
     pub fn flush_frame(&mut self, frame: FrameRef) {
-        let mut frm = frame.try_borrow_mut().unwrap();
-        frm.flush(&mut self.block_manager)
+        let mut frm = frame.try_write().unwrap();
+        frm.flush(&mut self.block_manager, &mut self.log_manager)
     }
 
     fn force_flush(&mut self, frame: FrameRef) {
-        let mut frm = frame.try_borrow_mut().unwrap();
+        let mut frm = frame.try_write().unwrap();
         frm.dirty = true;
-        frm.flush(&mut self.block_manager)
+        frm.flush(&mut self.block_manager, &mut self.log_manager)
+    }
+
+    /// Forces the log records of every change made so far to the disk without writing the pages,
+    /// after this returns the changes survive a crash
+    pub fn flush_log(&mut self) {
+        self.buffer_manager.log_all(&mut self.log_manager);
+        self.log_manager.flush_all();
+    }
+
+    /// Logs a change to a file kept outside the pages like a bucket directory or an FSM, the file is
+    /// written with it at the next checkpoint
+    pub fn log_file(&mut self, file: &Path, record: LogRecord) {
+        self.log_manager.append_file(file, record);
+    }
+
+    /// Writes the logged changes of the files kept outside the pages before they are read again,
+    /// the log is forced first so no file holds a change a crash could take back from the pages
+    pub fn write_files(&mut self) {
+        self.flush_log();
+        self.log_manager.write_files();
+    }
+
+    /// Removes a file and the blocks of it held in memory.
+    ///
+    /// Every change is written and the log checkpointed first, so recovery never writes to the
+    /// removed file again
+    pub fn remove_file(&mut self, filename: &str) {
+        self.flush_all();
+        self.buffer_manager.discard_file(filename);
+        self.block_manager.remove_file(filename);
+    }
+
+    /// Number of blocks pinned by the current thread. Every session runs on a thread of its own,
+    /// so the blocks pinned by the other sessions are not counted
+    pub fn thread_pins() -> u64 {
+        PINS.with(Cell::get)
     }
 
     /// Returns the disk block size used for the database
@@ -139,3 +242,46 @@ impl StorageManager {
         self.buffer_manager.get_available_slots()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::common::testing::TempDir;
+    use crate::storage::storagemgr::{StorageManager, FORMAT_VERSION, VERSION_FILE};
+    use std::fs;
+    use std::thread;
+
+    #[test]
+    fn test_format_version() {
+        let dir = TempDir::new("version");
+        // written before the files recorded their version
+        assert_eq!(StorageManager::format_version(dir.to_str()), Ok(1));
+        assert!(StorageManager::check_format_version(dir.to_str()).is_err());
+        StorageManager::write_format_version(dir.to_str());
+        assert_eq!(
+            StorageManager::format_version(dir.to_str()),
+            Ok(FORMAT_VERSION)
+        );
+        assert!(StorageManager::check_format_version(dir.to_str()).is_ok());
+        fs::write(dir.join(VERSION_FILE), "1").unwrap();
+        assert!(StorageManager::check_format_version(dir.to_str()).is_err());
+    }
+
+    #[test]
+    fn test_pins_are_counted_per_thread() {
+        let dir = TempDir::new("pins");
+        let storage = dir.storage(10);
+        let blocks = storage.write().unwrap().empty_heap_pages("t", 1);
+        let pins = StorageManager::thread_pins();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut storage = storage.write().unwrap();
+                let frame = storage.pin(blocks[0].clone()).unwrap();
+                storage.unpin(frame);
+            });
+        });
+        assert_eq!(StorageManager::thread_pins(), pins);
+        let frame = storage.write().unwrap().pin(blocks[0].clone()).unwrap();
+        storage.write().unwrap().unpin(frame);
+        assert_eq!(StorageManager::thread_pins(), pins + 1);
+    }
+}
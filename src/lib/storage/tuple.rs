@@ -1,8 +1,18 @@
 use crate::schema::null_bitmap::NullBitMap;
 use crate::schema::schema::Layout;
+use crate::storage::overflow::EXTERNAL_FLAG;
 use positioned_io2::WriteAt;
 use std::ops::Index;
-use std::rc::Rc;
+use std::sync::Arc;
+
+/// The bytes every tuple starts with, its deleted byte followed by the ids of the transactions that
+/// created and deleted this version of it
+pub const TUPLE_HEADER_SIZE: usize = 17;
+/// Offset of the id of the transaction that created a tuple version, 0 for versions every
+/// transaction sees
+pub const XMIN_OFFSET: usize = 1;
+/// Offset of the id of the transaction that deleted a tuple version, 0 while it is not deleted
+pub const XMAX_OFFSET: usize = 9;
 
 #[derive(Debug)]
 /// A helper Struct that acts as an converter between the raw form of a tuple supplied by the result
@@ -12,28 +22,45 @@ use std::rc::Rc;
 /// and constructing the associated Null BitMap
 pub struct Tuple {
     deleted: u8,
+    xmin: u64,
     bitmap: NullBitMap,
     data: Vec<(String, Option<Vec<u8>>)>,
-    layout: Rc<Layout>,
+    layout: Arc<Layout>,
+    external: Vec<String>,
 }
 
 impl Tuple {
     ///Creates a Tuple instance from a (Fieldname , Field Data Bytes) Vector and a layout supplied from
     /// the table schema
-    pub fn new(data: Vec<(String, Option<Vec<u8>>)>, layout: Rc<Layout>) -> Self {
+    pub fn new(data: Vec<(String, Option<Vec<u8>>)>, layout: Arc<Layout>) -> Self {
         let layout_copy = layout.as_ref().clone();
         let bitmap = NullBitMap::new(layout_copy);
         Self {
             deleted: 0,
+            xmin: 0,
             bitmap,
             data,
             layout,
+            external: vec![],
+        }
+    }
+    /// Stamps the tuple as a version created by a transaction, only the transactions whose snapshot
+    /// sees that transaction committed see the tuple
+    pub fn set_xmin(&mut self, xid: u64) {
+        self.xmin = xid;
+    }
+    /// Replaces the value of a variable length field with the pointer to its chunks in the overflow
+    /// file, the pointer of the field is flagged so readers know to follow it
+    pub fn set_external(&mut self, field_name: &str, overflow_pointer: Vec<u8>) {
+        for (name, data) in self.data.iter_mut().filter(|(name, _)| name == field_name) {
+            *data = Some(overflow_pointer.clone());
+            self.external.push(name.clone());
         }
     }
     /// Returns the length of the tuple when it has been reordered and with the necessary metadata and
     /// internal field pointers added , used by the heap file interface for tuple insertion and
     /// navigation
-    pub fn tuple_size(&self) -> u16 {
+    pub fn tuple_size(&self) -> usize {
         let mut size = 0;
         let mut bitmap = self.bitmap.clone();
         for (fieldname, data) in &self.data {
@@ -42,12 +69,12 @@ impl Tuple {
             }
             let (field_type, _) = self.layout.field_data(fieldname);
             if !field_type.needs_pointer() {
-                size += field_type.unit_size().unwrap() as u16;
+                size += field_type.unit_size().unwrap() as usize;
             } else {
-                size += (data.clone().unwrap().len() as u16 + 4);
+                size += data.as_ref().unwrap().len() + 4;
             }
         }
-        size + 1 + (bitmap.bitmap().len() as u16)
+        size + TUPLE_HEADER_SIZE + bitmap.bitmap().len()
     }
 
     /// Consumes the Tuple instance to create the record form to be placed in a heap file.
@@ -77,9 +104,14 @@ impl Tuple {
     /// and the start of the second var field data is directly after the end of the preceding one
     pub fn to_bytes(mut self) -> Vec<u8> {
         let size = self.tuple_size();
-        let mut tuple = vec![0; (size - 1 - (self.bitmap.bitmap().len() as u16)) as usize];
+        let header_size = TUPLE_HEADER_SIZE + self.bitmap.bitmap().len();
+        let mut tuple = vec![0; size - header_size];
         let index_map = self.layout.index_map();
-        let mut ordered_tuple = vec![("".to_string(), None); self.data.len()];
+        // the fields missing from the data are NULL
+        let name_map = self.layout.name_map();
+        let mut ordered_tuple = (0..self.layout.fields_count() as u8)
+            .map(|index| (name_map[&index].clone(), None))
+            .collect::<Vec<_>>();
         for field in self.data {
             let index = index_map.get(field.0.as_str()).unwrap().clone();
             ordered_tuple[index as usize] = field.clone();
@@ -122,10 +154,11 @@ impl Tuple {
                 offset -= null_size;
                 tuple.write_at(offset as u64, curr_string_start.to_ne_bytes().as_slice());
                 let field_bytes = field.1.unwrap();
-                tuple.write_at(
-                    (offset + 2) as u64,
-                    (field_bytes.len() as u16).to_ne_bytes().as_slice(),
-                );
+                let mut length = field_bytes.len() as u16;
+                if self.external.contains(&field.0) {
+                    length |= EXTERNAL_FLAG;
+                }
+                tuple.write_at((offset + 2) as u64, length.to_ne_bytes().as_slice());
                 tuple.write_at(curr_string_start as u64, field_bytes.as_slice());
                 curr_string_start += field_bytes.len() as u16;
             } else {
@@ -134,7 +167,9 @@ impl Tuple {
             }
             field_pos += 1;
         }
-        let mut tuple_all = vec![0_u8; 1];
+        let mut tuple_all = vec![self.deleted];
+        tuple_all.extend(self.xmin.to_ne_bytes());
+        tuple_all.extend(0_u64.to_ne_bytes());
         tuple_all.append(self.bitmap.bitmap());
         tuple_all.append(&mut tuple);
         tuple_all
@@ -3,37 +3,72 @@ use crate::index::Rid;
 use crate::storage::storagemgr::StorageManager;
 use crate::table::direct_access::DirectAccessor;
 use crate::table::tablemgr::TableManager;
-use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::{RwLock, RwLockWriteGuard};
 
 pub struct BtreeIter {
     direct_access: DirectAccessor,
-    index: BPTree,
+    index: Arc<RwLock<BPTree>>,
+    index_field: String,
     rids: Vec<Rid>,
+    current_rid: Option<Rid>,
     op: evalexpr::Operator,
+    upper: Option<Vec<u8>>,
 }
 
 impl BtreeIter {
     pub fn load_key(&mut self, key: &[u8]) {
         use evalexpr::Operator::*;
         let key = key.to_vec();
+        let index = self.index.read().unwrap();
+        if let Some(upper) = &self.upper {
+            self.rids.extend(index.get_range(key, upper.clone()));
+            return;
+        }
         let rids = match self.op {
-            Eq => self.index.search(key),
-            Lt => self.index.get_less_than(key),
-            Gt => self.index.get_greater_than(key),
-            Leq => self.index.get_less_than_or_equal(key),
-            Geq => self.index.get_greater_than_or_equal(key),
+            Eq => index.search(key),
+            Lt => index.get_less_than(key),
+            Gt => index.get_greater_than(key),
+            Leq => index.get_less_than_or_equal(key),
+            Geq => index.get_greater_than_or_equal(key),
             _ => unreachable!(),
         };
         self.rids.extend(rids.unwrap_or_default());
     }
-    pub fn new(direct_access: DirectAccessor, index: BPTree, op: evalexpr::Operator) -> Self {
+    /// The Rid of the last tuple returned by `next`
+    pub fn current_rid(&self) -> Option<Rid> {
+        self.current_rid.clone()
+    }
+    /// The fields the index is built on, joined by commas
+    pub fn index_field(&self) -> &str {
+        &self.index_field
+    }
+    /// The comparison the loaded key is searched with, for a bounded iterator the comparison on
+    /// the last column of the key prefix it scans
+    pub fn operator(&self) -> &evalexpr::Operator {
+        &self.op
+    }
+    /// Bounds the keys the iterator scans, the loaded key becomes the inclusive lower bound of a
+    /// range ending before `upper`
+    pub fn bounded(mut self, upper: Vec<u8>) -> Self {
+        self.upper = Some(upper);
+        self
+    }
+    pub fn new(
+        direct_access: DirectAccessor,
+        index: Arc<RwLock<BPTree>>,
+        index_field: &str,
+        op: evalexpr::Operator,
+    ) -> Self {
         Self {
             direct_access,
             index,
+            index_field: index_field.to_string(),
             rids: vec![],
+            current_rid: None,
             op,
+            upper: None,
         }
     }
 }
@@ -42,10 +77,12 @@ impl Iterator for BtreeIter {
     type Item = HashMap<String, Option<Vec<u8>>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(rid) = self.rids.pop() {
-            Some(self.direct_access.get_tuple(rid))
-        } else {
-            None
+        while let Some(rid) = self.rids.pop() {
+            if let Some(tuple) = self.direct_access.get_tuple(rid.clone()) {
+                self.current_rid = Some(rid);
+                return Some(tuple);
+            }
         }
+        None
     }
 }
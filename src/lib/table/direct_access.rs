@@ -1,39 +1,49 @@
+use crate::database::txnmgr::Snapshot;
 use crate::index::Rid;
 use crate::schema::schema::Layout;
 use crate::storage::blockid::BlockId;
 use crate::storage::heap::HeapPage;
 use crate::storage::storagemgr::StorageManager;
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::RwLock;
 
 pub struct DirectAccessor {
     table_blocks: Vec<BlockId>,
-    storage: Rc<RefCell<StorageManager>>,
-    layout: Rc<Layout>,
+    storage: Arc<RwLock<StorageManager>>,
+    layout: Arc<Layout>,
+    snapshot: Option<Arc<Snapshot>>,
 }
 impl DirectAccessor {
     pub fn new(
         table_blocks: Vec<BlockId>,
-        storage: Rc<RefCell<StorageManager>>,
-        layout: Rc<Layout>,
+        storage: Arc<RwLock<StorageManager>>,
+        layout: Arc<Layout>,
+        snapshot: Option<Arc<Snapshot>>,
     ) -> Self {
         Self {
             table_blocks,
             storage,
             layout,
+            snapshot,
         }
     }
-    pub fn get_tuple(&self, rid: Rid) -> HashMap<String, Option<Vec<u8>>> {
+    /// Reads the tuple at a Rid, None if the version there is not visible to the snapshot or was
+    /// removed since the Rid was found
+    pub fn get_tuple(&self, rid: Rid) -> Option<HashMap<String, Option<Vec<u8>>>> {
         let (blk, slot) = rid.rid_blk_num(&self.table_blocks[0].filename);
-        let frame = self.storage.borrow_mut().pin(blk.clone()).unwrap();
-        let heap = HeapPage::new(frame.clone(), &blk, self.layout.clone());
-        let ret = heap.get_tuple_fields(rid.slot_num() as usize);
+        let frame = self.storage.write().unwrap().pin(blk.clone()).unwrap();
+        let heap = HeapPage::new(frame.clone(), &blk, self.layout.clone())
+            .with_overflow(self.storage.clone());
+        let ret = heap.current_tuple_fields(slot, |xmin, xmax| match &self.snapshot {
+            Some(snapshot) => snapshot.sees(xmin, xmax),
+            None => true,
+        });
         drop(heap);
-        self.storage.borrow_mut().unpin(frame);
+        self.storage.write().unwrap().unpin(frame);
         ret
     }
-    pub fn get_storage(&self) -> Rc<RefCell<StorageManager>> {
+    pub fn get_storage(&self) -> Arc<RwLock<StorageManager>> {
         self.storage.clone()
     }
 }
@@ -3,28 +3,43 @@ use crate::index::Rid;
 use crate::storage::storagemgr::StorageManager;
 use crate::table::direct_access::DirectAccessor;
 use crate::table::tablemgr::TableManager;
-use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::{RwLock, RwLockWriteGuard};
 
 pub struct HashIter {
     direct_access: DirectAccessor,
-    index: HashIndex,
+    index: Arc<RwLock<HashIndex>>,
+    index_field: String,
     rids: Vec<Rid>,
+    current_rid: Option<Rid>,
 }
 
 impl HashIter {
     pub fn load_key(&mut self, key: &[u8]) {
-        self.rids.extend(
-            self.index
-                .get_rids(key, self.direct_access.get_storage().borrow_mut()),
-        );
+        let index = self.index.read().unwrap();
+        self.rids
+            .extend(index.get_rids(key, self.direct_access.get_storage().write().unwrap()));
     }
-    pub fn new(direct_access: DirectAccessor, index: HashIndex) -> Self {
+    /// The Rid of the last tuple returned by `next`
+    pub fn current_rid(&self) -> Option<Rid> {
+        self.current_rid.clone()
+    }
+    /// The field the index is built on
+    pub fn index_field(&self) -> &str {
+        &self.index_field
+    }
+    pub fn new(
+        direct_access: DirectAccessor,
+        index: Arc<RwLock<HashIndex>>,
+        index_field: &str,
+    ) -> Self {
         Self {
             direct_access,
             index,
+            index_field: index_field.to_string(),
             rids: vec![],
+            current_rid: None,
         }
     }
 }
@@ -33,10 +48,12 @@ impl Iterator for HashIter {
     type Item = HashMap<String, Option<Vec<u8>>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(rid) = self.rids.pop() {
-            Some(self.direct_access.get_tuple(rid))
-        } else {
-            None
+        while let Some(rid) = self.rids.pop() {
+            if let Some(tuple) = self.direct_access.get_tuple(rid.clone()) {
+                self.current_rid = Some(rid);
+                return Some(tuple);
+            }
         }
+        None
     }
 }
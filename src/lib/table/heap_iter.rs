@@ -1,20 +1,26 @@
+use crate::database::txnmgr::Snapshot;
+use crate::index::Rid;
 use crate::schema::schema::Layout;
 use crate::storage::blockid::BlockId;
 use crate::storage::heap::HeapPage;
 use crate::storage::storagemgr::StorageManager;
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::RwLock;
 
 /// A Sequential Iterator over ALL tuples in a database table
+///
+/// With a snapshot only the tuple versions visible to it are returned
 pub struct TableIter {
     table_blocks: Vec<BlockId>,
-    storage_mgr: Rc<RefCell<StorageManager>>,
-    layout: Rc<Layout>,
+    storage_mgr: Arc<RwLock<StorageManager>>,
+    layout: Arc<Layout>,
     current_block_index: usize,
     current_page: HeapPage,
     current_tuple_index: usize,
     current_page_pointer_count: usize,
+    current_rid: Option<Rid>,
+    snapshot: Option<Arc<Snapshot>>,
 }
 
 impl Iterator for TableIter {
@@ -24,11 +30,16 @@ impl Iterator for TableIter {
         while self.current_block_index != (self.table_blocks.len()) {
             while self.current_tuple_index < self.current_page_pointer_count {
                 // dbg!((self.current_block_index,self.current_tuple_index,self.current_page_pointer_count));
-                let (pointer_exist, tuple_exist) = self
+                let tuple = self
                     .current_page
-                    .pointer_and_tuple_exist(self.current_tuple_index);
-                if tuple_exist {
-                    let tuple = self.current_page.get_tuple_fields(self.current_tuple_index);
+                    .current_tuple_fields(self.current_tuple_index, |xmin, xmax| {
+                        self.visible(xmin, xmax)
+                    });
+                if let Some(tuple) = tuple {
+                    self.current_rid = Some(Rid::new(
+                        self.current_page.blk.block_num,
+                        self.current_tuple_index as u16,
+                    ));
                     self.current_tuple_index += 1;
                     return Some(tuple);
                 }
@@ -36,14 +47,15 @@ impl Iterator for TableIter {
             }
             self.current_tuple_index = 0;
             self.current_block_index += 1;
-            let mut storage_mgr = self.storage_mgr.borrow_mut();
+            let mut storage_mgr = self.storage_mgr.write().unwrap();
             storage_mgr.unpin(self.current_page.frame.clone());
             if self.current_block_index == self.table_blocks.len() {
                 break;
             }
             let block = &self.table_blocks[self.current_block_index];
             let frame = storage_mgr.pin(block.clone()).unwrap();
-            self.current_page = HeapPage::new(frame, block, self.layout.clone());
+            self.current_page = HeapPage::new(frame, block, self.layout.clone())
+                .with_overflow(self.storage_mgr.clone());
             self.current_page_pointer_count = self.current_page.pointer_count();
         }
         None
@@ -55,14 +67,17 @@ impl TableIter {
     /// representing the Table.
     pub fn new(
         table_blocks: Vec<BlockId>,
-        storage_mgr: Rc<RefCell<StorageManager>>,
-        layout: Rc<Layout>,
+        storage_mgr: Arc<RwLock<StorageManager>>,
+        layout: Arc<Layout>,
+        snapshot: Option<Arc<Snapshot>>,
     ) -> Self {
         let frame = storage_mgr
-            .borrow_mut()
+            .write()
+            .unwrap()
             .pin(table_blocks[0].clone())
             .unwrap();
-        let heap_page = HeapPage::new(frame, &table_blocks[0], layout.clone());
+        let heap_page = HeapPage::new(frame, &table_blocks[0], layout.clone())
+            .with_overflow(storage_mgr.clone());
         Self {
             table_blocks,
             storage_mgr,
@@ -71,6 +86,20 @@ impl TableIter {
             current_tuple_index: 0,
             current_page_pointer_count: heap_page.pointer_count(),
             current_page: heap_page,
+            current_rid: None,
+            snapshot,
+        }
+    }
+
+    /// The Rid of the last tuple returned by `next`
+    pub fn current_rid(&self) -> Option<Rid> {
+        self.current_rid.clone()
+    }
+
+    fn visible(&self, xmin: u64, xmax: u64) -> bool {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.sees(xmin, xmax),
+            None => true,
         }
     }
 
@@ -93,7 +122,7 @@ impl TableIter {
     //         }
     //         self.current_tuple_index = 0;
     //         self.current_block_index += 1;
-    //         let mut storage_mgr = self.storage_mgr.borrow_mut();
+    //         let mut storage_mgr = self.storage_mgr.write().unwrap();
     //         storage_mgr.unpin(self.current_page.frame.clone());
     //         if self.current_block_index == self.table_blocks.len() {
     //             break;
@@ -1,24 +1,29 @@
 use crate::common::numerical::ByteMagic;
 use crate::database::plan_query::TableInfo;
+use crate::database::txnmgr::{Snapshot, TransactionId, TransactionManager};
 use crate::index::btree_index::BPTree;
 use crate::index::hash_index::HashIndex;
 use crate::index::Rid;
-use crate::index::{Index, IndexInfo};
+use crate::index::{composite_key, Index, IndexInfo};
 use crate::schema::schema::Layout;
 use crate::sql::create_table::IndexType;
 use crate::storage::blockid::BlockId;
 use crate::storage::free_space::FreeMap;
 use crate::storage::heap::{HeapPage, PageIter};
+use crate::storage::overflow::{
+    chunk_fields, chunk_layout, overflow_file, overflow_freemap_file, OverflowFile,
+    OverflowPointer, CHUNK_SIZE, OVERFLOW_THRESHOLD,
+};
 use crate::storage::storagemgr::StorageManager;
 use crate::storage::tuple::Tuple;
 use crate::table::btree_iter::BtreeIter;
 use crate::table::direct_access::DirectAccessor;
 use crate::table::hash_iter::HashIter;
 use crate::table::heap_iter::TableIter;
-use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::RwLock;
 
 /// TableManager is an entity owned by a database that is responsible for executing operations
 /// against the heap pages that represents a database table on disk
@@ -27,10 +32,15 @@ use std::rc::Rc;
 pub struct TableManager {
     pub free_map: FreeMap,
     pub table_blocks: Vec<BlockId>,
-    storage_mgr: Rc<RefCell<StorageManager>>,
-    layout: Rc<Layout>,
-    hash_indexes: HashMap<String, HashIndex>,
-    btree_indexes: HashMap<String, BPTree>,
+    storage_mgr: Arc<RwLock<StorageManager>>,
+    layout: Arc<Layout>,
+    /// The indexes are shared with the iterators reading them, which may outlive the statement
+    /// that created them. An index is changed under its write latch and read under its read one
+    hash_indexes: HashMap<String, Arc<RwLock<HashIndex>>>,
+    btree_indexes: HashMap<String, Arc<RwLock<BPTree>>>,
+    /// The table keeping the chunks of the values too large for the tuples of this one, created
+    /// with the first of them
+    overflow: Option<Box<TableManager>>,
 }
 
 impl TableManager {
@@ -40,12 +50,12 @@ impl TableManager {
     /// If no (FSM) is passed , new() creates it and saves it to disk
     pub fn new(
         blocks: Vec<BlockId>,
-        storage_mgr: Rc<RefCell<StorageManager>>,
+        storage_mgr: Arc<RwLock<StorageManager>>,
         free_map: FreeMap,
-        layout: Rc<Layout>,
+        layout: Arc<Layout>,
         indexes: Vec<IndexInfo>,
     ) -> Self {
-        // let mut strg_mgr = storage_mgr.borrow_mut();
+        // let mut strg_mgr = storage_mgr.write().unwrap();
         // for block in &blocks {
         //     let blk_header = strg_mgr.read_raw(block, 4);
         //     let space_start = blk_header.as_slice().extract_u16(0);
@@ -56,73 +66,174 @@ impl TableManager {
         //     };
         // }
 
-        let mut hash_idx = HashMap::new();
-        let mut btree_idx = HashMap::new();
-        for idx in indexes {
-            match idx.index_type {
-                IndexType::Hash => {
-                    hash_idx.insert(
-                        idx.column.clone(),
-                        HashIndex::new(
-                            &idx.directory_file_path,
-                            idx.index_name,
-                            storage_mgr.borrow_mut().file_blks(idx.index_file_path),
-                            idx.column,
-                        ),
-                    );
-                }
-                IndexType::Btree => {
-                    let root = BlockId::new(idx.index_file_path.as_os_str().to_str().unwrap(), 0);
-                    let key_type = layout.get_type(&idx.column);
-                    btree_idx.insert(
-                        idx.column,
-                        BPTree::new(
-                            root,
-                            key_type,
-                            storage_mgr.clone(),
-                            idx.index_file_path.to_str().unwrap().to_string(),
-                        ),
-                    );
-                }
-            }
-        }
         // let indexes = indexes
         //     .into_iter()
         //     .map(|idx| {
         //         let column = idx.column.clone();
         //         let blks = storage_mgr
-        //             .borrow_mut()
+        //             .write().unwrap()
         //             .file_blks(idx.index_file_path.clone());
         //         (column, Index::load_index(idx, blks))
         //     })
         //     .collect();
-        Self {
+        let overflow = Self::open_overflow(&blocks[0].filename, &storage_mgr);
+        let mut table = Self {
             free_map,
             table_blocks: blocks,
             storage_mgr,
             layout,
-            hash_indexes: hash_idx,
-            btree_indexes: btree_idx,
+            hash_indexes: HashMap::new(),
+            btree_indexes: HashMap::new(),
+            overflow,
+        };
+        table.format_lost_blocks();
+        for idx in indexes {
+            table.open_index(idx);
+        }
+        table
+    }
+
+    /// Formats the blocks the heap file was extended with before a crash but whose pages never
+    /// reached the disk and gives them to the FSM.
+    ///
+    /// The page of a block is logged before the page of any block added after it, so such blocks
+    /// are only found at the end of the file
+    fn format_lost_blocks(&mut self) {
+        let mut storage_mgr = self.storage_mgr.write().unwrap();
+        for block in self.table_blocks.iter().rev() {
+            let frame = storage_mgr.pin(block.clone()).unwrap();
+            let zeroed = frame.read().unwrap().page.payload.as_slice().extract_u16(0) == 0;
+            if zeroed {
+                HeapPage::format_if_zeroed(&frame);
+                let heap_page = HeapPage::new(frame.clone(), block, self.layout.clone());
+                self.free_map
+                    .set_blockspace(heap_page.free_space(), block, &mut storage_mgr);
+            }
+            storage_mgr.unpin(frame);
+            if !zeroed {
+                break;
+            }
+        }
+    }
+
+    /// Loads the overflow file of a heap file, None while the heap file has not needed one
+    fn open_overflow(
+        heap_file: &str,
+        storage_mgr: &Arc<RwLock<StorageManager>>,
+    ) -> Option<Box<TableManager>> {
+        let freemap_file = PathBuf::from(overflow_freemap_file(heap_file));
+        if !freemap_file.exists() {
+            return None;
+        }
+        Some(Box::new(Self::from_file(
+            storage_mgr.clone(),
+            PathBuf::from(overflow_file(heap_file)),
+            chunk_layout(),
+            vec![],
+            freemap_file,
+        )))
+    }
+
+    /// Loads an index on columns of the table from its files, the index is known by its columns
+    /// joined by commas
+    fn open_index(&mut self, idx: IndexInfo) {
+        let columns = idx.key_columns();
+        match idx.index_type {
+            IndexType::Hash => {
+                let index = HashIndex::new(
+                    &idx.directory_file_path,
+                    idx.index_name,
+                    self.storage_mgr
+                        .write()
+                        .unwrap()
+                        .file_blks(idx.index_file_path),
+                    columns.clone(),
+                );
+                self.hash_indexes
+                    .insert(columns, Arc::new(RwLock::new(index)));
+            }
+            IndexType::Btree => {
+                let root = BlockId::new(idx.index_file_path.as_os_str().to_str().unwrap(), 0);
+                let index = BPTree::new(
+                    root,
+                    idx.key_type,
+                    self.storage_mgr.clone(),
+                    idx.index_file_path.to_str().unwrap().to_string(),
+                );
+                self.btree_indexes
+                    .insert(columns, Arc::new(RwLock::new(index)));
+            }
+        }
+    }
+
+    /// Loads a new index on columns of the table and fills it with the keys of every tuple version
+    /// in the heap, the structures of the index are expected empty.
+    ///
+    /// Returns the number of entries added to the index
+    pub fn add_index(&mut self, idx: IndexInfo) -> usize {
+        let columns = idx.key_columns();
+        let index_type = idx.index_type;
+        self.open_index(idx);
+        let mut entries = 0;
+        let mut heap_iter = self.heapscan_iter(None);
+        while let Some(row) = heap_iter.next() {
+            let fields = row.iter().map(|(k, v)| (k.as_str(), v)).collect();
+            let key = match index_key(&columns, &fields, &self.layout) {
+                Some(key) => key,
+                None => continue,
+            };
+            let rid = heap_iter.current_rid().unwrap();
+            match index_type {
+                IndexType::Hash => self.hash_indexes[&columns].write().unwrap().insert_record(
+                    &key,
+                    rid.block_num(),
+                    rid.slot_num(),
+                    self.storage_mgr.write().unwrap(),
+                ),
+                IndexType::Btree => {
+                    self.btree_indexes[&columns]
+                        .write()
+                        .unwrap()
+                        .insert(key, rid);
+                }
+            }
+            entries += 1;
+        }
+        entries
+    }
+
+    /// Stops maintaining an index on columns of the table, its files are left to the caller
+    pub fn drop_index(&mut self, columns: &str, index_type: IndexType) {
+        match index_type {
+            IndexType::Hash => {
+                self.hash_indexes.remove(columns);
+            }
+            IndexType::Btree => {
+                self.btree_indexes.remove(columns);
+            }
         }
     }
 
     pub fn from_file(
-        storage_mgr: Rc<RefCell<StorageManager>>,
+        storage_mgr: Arc<RwLock<StorageManager>>,
         filepath: PathBuf,
-        layout: Rc<Layout>,
+        layout: Arc<Layout>,
         indexes: Vec<IndexInfo>,
         freemap_file: PathBuf,
     ) -> Self {
+        // the FSM and the bucket directories are read again, their logged changes are written first
+        storage_mgr.write().unwrap().write_files();
         let mut freemap = FreeMap::new(freemap_file);
-        let mut blks = storage_mgr.borrow().file_blks(filepath.clone());
+        let mut blks = storage_mgr.read().unwrap().file_blks(filepath.clone());
         if blks.is_empty() {
             blks.append(
                 &mut storage_mgr
-                    .borrow_mut()
+                    .write()
+                    .unwrap()
                     .empty_heap_pages(filepath.to_str().unwrap(), 1),
             );
             freemap.add_blockspace(
-                HeapPage::default_free_space(storage_mgr.borrow().blk_size()) as u16,
+                HeapPage::default_free_space(storage_mgr.read().unwrap().blk_size()) as u16,
                 &blks[0],
             );
             freemap.flush_map();
@@ -130,13 +241,54 @@ impl TableManager {
         Self::new(blks, storage_mgr, freemap, layout, indexes)
     }
 
-    /// Marks a tuple for deletion using it's BlockId and Page slot number
+    /// Marks a tuple for deletion using it's Rid and removes its entries from the indexes on the table
     ///
     /// When a tuple is marked for deletion , it is not acutally deleted but only marked and is actually
-    /// removed during compaction in vacuuming
-    pub fn delete_tuple(&self, blk: &BlockId, slot_num: usize) {
-        let mut heap_page = self.get_heap_page(blk);
-        heap_page.mark_delete(slot_num)
+    /// removed during compaction in vacuuming. The chunks of its values in the overflow file are
+    /// deleted with it
+    pub fn delete_tuple(&mut self, rid: &Rid) {
+        let (blk, slot) = rid.rid_blk_num(self.get_heapfile_name());
+        let frame = self.storage_mgr.write().unwrap().pin(blk.clone()).unwrap();
+        let heap_page = HeapPage::new(frame.clone(), &blk, self.layout.clone())
+            .with_overflow(self.storage_mgr.clone());
+        let old_tuple = heap_page.get_tuple_fields(slot);
+        let overflow_pointers = heap_page.overflow_pointers(slot);
+        heap_page.mark_delete(slot);
+        self.storage_mgr.write().unwrap().unpin(frame);
+        self.unindex_fields(rid, &old_tuple.into_iter().collect::<Vec<_>>());
+        if !overflow_pointers.is_empty() {
+            let overflow_file =
+                OverflowFile::new(self.get_heapfile_name(), self.storage_mgr.clone());
+            let chunks = overflow_pointers
+                .iter()
+                .flat_map(|pointer| overflow_file.chunk_rids(pointer))
+                .collect::<Vec<_>>();
+            self.overflow.as_mut().unwrap().delete_tuples(&chunks);
+        }
+    }
+
+    /// Deletes multiple tuples then compacts the blocks they were stored in , the space reclaimed
+    /// from each block is given back to the FSM
+    ///
+    /// Compaction keeps the slot numbers of the remaining tuples so their Rids stay valid.
+    /// Returns the number of deleted tuples
+    pub fn delete_tuples(&mut self, rids: &[Rid]) -> usize {
+        let mut blocks = HashSet::new();
+        for rid in rids {
+            self.delete_tuple(rid);
+            blocks.insert(rid.block_num());
+        }
+        for block_num in blocks {
+            let blk = BlockId::new(self.get_heapfile_name(), block_num);
+            let mut storage_mgr = self.storage_mgr.write().unwrap();
+            let frame = storage_mgr.pin(blk.clone()).unwrap();
+            let mut heap_page = HeapPage::new(frame.clone(), &blk, self.layout.clone());
+            heap_page.vacuum();
+            self.free_map
+                .set_blockspace(heap_page.free_space(), &blk, &mut storage_mgr);
+            storage_mgr.unpin(frame);
+        }
+        rids.len()
     }
 
     /// Get multiple fields of a tuple as bytes , reinterpreting them is the responsibility of the caller
@@ -178,21 +330,44 @@ impl TableManager {
         self.layout.type_map().contains_key(field)
     }
 
-    /* !!!  ADD RECORDS TO INDEXES WHEN YOU INSERT!!!! */
     /// Insert a tuple into a table
     /// Searches the FSM first for a block that has the least free space required for a tuple to insert
     /// it in , if None exists , the Heap File representing the table is extended by 1 block and the
     /// tuple is inserted into this page and the remaining space in it is added to the FSM
-    pub fn try_insert_tuple(&mut self, tuple_bytes: Vec<(String, Option<Vec<u8>>)>) {
-        let tuple = Tuple::new(tuple_bytes.clone(), self.layout.clone());
-        let target_block = self.free_map.get_smallest_fit(tuple.tuple_size());
-        let mut storage_mgr = self.storage_mgr.borrow_mut();
+    ///
+    /// The tuple is added to every index on the table and its Rid is returned, it is visible to every
+    /// transaction
+    pub fn try_insert_tuple(&mut self, tuple_bytes: Vec<(String, Option<Vec<u8>>)>) -> Rid {
+        self.insert_version(tuple_bytes, 0)
+    }
+
+    /// Inserts a tuple version created by a transaction, see `try_insert_tuple`
+    pub fn insert_version(
+        &mut self,
+        tuple_bytes: Vec<(String, Option<Vec<u8>>)>,
+        xid: TransactionId,
+    ) -> Rid {
+        let mut tuple = Tuple::new(tuple_bytes.clone(), self.layout.clone());
+        tuple.set_xmin(xid);
+        if tuple.tuple_size() > OVERFLOW_THRESHOLD {
+            self.move_to_overflow(&mut tuple, &tuple_bytes);
+        }
+        let rid = self.place_tuple(tuple);
+        self.index_fields(&rid, &tuple_bytes);
+        rid
+    }
+
+    /// Writes a tuple in the heap page with the least free space that fits it, the heap file is
+    /// extended when none does
+    fn place_tuple(&mut self, tuple: Tuple) -> Rid {
+        let target_block = self.free_map.get_smallest_fit(tuple.tuple_size() as u16);
+        let mut storage_mgr = self.storage_mgr.write().unwrap();
         let (blk, slot) = if let Some((free_size, block)) = target_block {
             let mut frame = storage_mgr.pin(block.clone()).unwrap();
             let mut target_page = HeapPage::new(frame.clone(), &block, self.layout.clone());
             let idx = target_page.insert_tuple(tuple);
             self.free_map
-                .add_blockspace(target_page.free_space(), &block);
+                .set_blockspace(target_page.free_space(), &block, &mut storage_mgr);
             storage_mgr.unpin(frame);
             (block, idx)
         } else {
@@ -203,85 +378,260 @@ impl TableManager {
                 HeapPage::new_from_empty(frame.clone(), &blkid, self.layout.clone());
             let idx = target_page.insert_tuple(tuple);
             self.free_map
-                .add_blockspace(target_page.free_space(), &blkid);
+                .set_blockspace(target_page.free_space(), &blkid, &mut storage_mgr);
             storage_mgr.unpin(frame);
             (blkid, idx)
         };
-        drop(storage_mgr);
-        for (k, data) in &tuple_bytes {
-            if let Some(data) = data {
-                self.hash_indexes
-                    .iter_mut()
-                    .filter(|(field, _)| *field == k)
-                    .for_each(|(_, v)| {
-                        v.insert_record(
-                            data,
-                            blk.block_num,
-                            slot as u16,
-                            self.storage_mgr.borrow_mut(),
-                        );
-                        // v.flush_all(&mut self.storage_mgr.borrow_mut());
-                    })
+        Rid::new(blk.block_num, slot as u16)
+    }
+
+    /// Moves the largest variable length values of a tuple to the overflow file of the table, until
+    /// the tuple is no larger than `OVERFLOW_THRESHOLD` or has none left larger than their pointer
+    fn move_to_overflow(&mut self, tuple: &mut Tuple, fields: &[(String, Option<Vec<u8>>)]) {
+        let mut values = fields
+            .iter()
+            .filter(|(name, _)| self.layout.get_type(name).needs_pointer())
+            .filter_map(|(name, data)| Some((name, data.as_ref()?)))
+            .filter(|(_, data)| data.len() > OverflowPointer::SIZE)
+            .collect::<Vec<_>>();
+        values.sort_by_key(|(_, data)| std::cmp::Reverse(data.len()));
+        for (name, data) in values {
+            if tuple.tuple_size() <= OVERFLOW_THRESHOLD {
+                break;
             }
+            let pointer = self.store_overflow(data);
+            tuple.set_external(name, pointer.to_bytes());
         }
+    }
 
-        for (k, data) in tuple_bytes {
-            if let Some(data) = data {
-                self.btree_indexes
-                    .iter_mut()
-                    .filter(|(field, _)| **field == k)
-                    .for_each(|(_, v)| {
-                        v.insert(data.clone(), Rid::new(blk.block_num, slot as u16));
-                        // v.flush_all(&mut self.storage_mgr.borrow_mut());
-                    })
+    /// Stores a value in the overflow file of the table as a chain of chunks, the file is created
+    /// with the first value stored in it
+    fn store_overflow(&mut self, value: &[u8]) -> OverflowPointer {
+        if self.overflow.is_none() {
+            let heap_file = self.get_heapfile_name().to_string();
+            let freemap_file = PathBuf::from(overflow_freemap_file(&heap_file));
+            FreeMap::init(freemap_file, 0, &BlockId::new("", 0));
+            self.overflow = Self::open_overflow(&heap_file, &self.storage_mgr);
+        }
+        let overflow = self.overflow.as_mut().unwrap();
+        // the chunks are stored from the last one so each of them knows the Rid of the next
+        let mut next: Option<Rid> = None;
+        for chunk in value.chunks(CHUNK_SIZE).rev() {
+            let fields = chunk_fields(chunk, next.as_ref());
+            next = Some(overflow.place_tuple(Tuple::new(fields, overflow.layout.clone())));
+        }
+        OverflowPointer::new(value.len(), next.unwrap())
+    }
+
+    /// Marks the tuple version at a Rid as deleted by a transaction.
+    ///
+    /// The version stays in its page and in the indexes for the snapshots that still see it, vacuum
+    /// removes it once no snapshot does
+    pub fn delete_version(&mut self, rid: &Rid, xid: TransactionId) {
+        let (blk, slot) = rid.rid_blk_num(self.get_heapfile_name());
+        let frame = self.storage_mgr.write().unwrap().pin(blk.clone()).unwrap();
+        let heap_page = HeapPage::new(frame.clone(), &blk, self.layout.clone());
+        heap_page.set_xmax(slot, xid);
+        self.storage_mgr.write().unwrap().unpin(frame);
+    }
+
+    /// Takes back the deletion of the tuple version at a Rid, when the deleting transaction rolls back
+    pub fn restore_version(&mut self, rid: &Rid) {
+        self.delete_version(rid, 0);
+    }
+
+    /// Replaces the tuple version at a Rid with a new version holding new field values, both created
+    /// by a transaction, and returns the Rid of the new version.
+    ///
+    /// The old version is marked deleted and the new one is inserted like any other tuple, so both
+    /// stay indexed until vacuum removes the old one
+    pub fn update_version(
+        &mut self,
+        rid: &Rid,
+        tuple_bytes: Vec<(String, Option<Vec<u8>>)>,
+        xid: TransactionId,
+    ) -> Rid {
+        self.delete_version(rid, xid);
+        self.insert_version(tuple_bytes, xid)
+    }
+
+    /// The ids of the transactions that created and deleted the tuple version at a Rid
+    pub fn get_xids(&self, rid: &Rid) -> (TransactionId, TransactionId) {
+        let (blk, slot) = rid.rid_blk_num(self.get_heapfile_name());
+        let heap_page = self.get_heap_page(&blk);
+        let xids = heap_page.xids(slot);
+        self.storage_mgr.write().unwrap().unpin(heap_page.frame);
+        xids
+    }
+
+    /// Adds a tuple to the indexes on the table, see `index_key` for the tuples an index skips
+    fn index_fields(&mut self, rid: &Rid, fields: &[(String, Option<Vec<u8>>)]) {
+        let fields = fields.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        for (columns, hash) in &self.hash_indexes {
+            if let Some(key) = index_key(columns, &fields, &self.layout) {
+                hash.write().unwrap().insert_record(
+                    &key,
+                    rid.block_num(),
+                    rid.slot_num(),
+                    self.storage_mgr.write().unwrap(),
+                );
             }
         }
-        // self.flush_all();
+        for (columns, btree) in &self.btree_indexes {
+            if let Some(key) = index_key(columns, &fields, &self.layout) {
+                btree.write().unwrap().insert(key, rid.clone());
+            }
+        }
+    }
+
+    /// Removes the entries pointing to a Rid from the indexes on the table
+    fn unindex_fields(&mut self, rid: &Rid, fields: &[(String, Option<Vec<u8>>)]) {
+        let fields = fields.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        for (columns, hash) in &self.hash_indexes {
+            if let Some(key) = index_key(columns, &fields, &self.layout) {
+                hash.write()
+                    .unwrap()
+                    .delete_record(&key, rid, self.storage_mgr.write().unwrap());
+            }
+        }
+        let mut freed = vec![];
+        for (columns, btree) in &self.btree_indexes {
+            if let Some(key) = index_key(columns, &fields, &self.layout) {
+                freed.extend(
+                    btree
+                        .write()
+                        .unwrap()
+                        .delete(key, rid.clone())
+                        .unwrap_or_default(),
+                );
+            }
+        }
+        for blk in freed {
+            self.remove_index_block(&blk);
+        }
     }
+
     /// Flush the frame holding a BlockId to disk , resetting the necessary stats
     pub fn flush(&self, blk: &BlockId) {
-        let mut storage_mgr = self.storage_mgr.borrow_mut();
+        let mut storage_mgr = self.storage_mgr.write().unwrap();
         let mut frame = storage_mgr.pin(blk.clone()).unwrap();
         storage_mgr.flush_frame(frame);
     }
 
     /// Flush all the table blocks to disk , resetting the necessary stats for each
     pub fn flush_all(&self) {
-        let mut storage_mgr = self.storage_mgr.borrow_mut();
+        let mut storage_mgr = self.storage_mgr.write().unwrap();
         for blk in &self.table_blocks {
             let mut frame = storage_mgr.pin(blk.clone()).unwrap();
             storage_mgr.flush_frame(frame.clone());
             storage_mgr.unpin(frame);
         }
 
-        for (_, hash) in &self.hash_indexes {
-            hash.flush_all(&mut storage_mgr);
+        drop(storage_mgr);
+        // an index is latched before the storage manager, like its writers do
+        for hash in self.hash_indexes.values() {
+            let hash = hash.read().unwrap();
+            hash.flush_all(&mut self.storage_mgr.write().unwrap());
         }
         self.free_map.flush_map();
+        if let Some(overflow) = &self.overflow {
+            overflow.flush_all();
+        }
         // for idx in self.indexes().values() {
         //     idx.flush_all(&mut storage_mgr)
         // }
     }
 
-    /// Compacts each block in the heap file representing a table by rewriting them in place ,
-    /// discarding deleted tuples
-    pub fn vacuum(&mut self) {
-        let mut storage_mgr = self.storage_mgr.borrow_mut();
+    /// Removes the tuple versions no snapshot sees anymore, the ones deleted by committed
+    /// transactions older than the horizon of the running ones and the ones created by aborted
+    /// transactions, then compacts each block in the heap file representing a table by rewriting
+    /// them in place. The space reclaimed from each block is given back to the FSM.
+    ///
+    /// The deletions made by aborted transactions are taken back.
+    /// Returns the number of removed versions
+    pub fn vacuum(&mut self, transactions: &TransactionManager) -> usize {
+        let horizon = transactions.horizon();
+        let ended = |xid: TransactionId| xid != 0 && xid < horizon;
+        let mut dead = vec![];
+        let mut restored = vec![];
+        for block in &self.table_blocks {
+            let heap_page = self.get_heap_page(block);
+            for slot in 0..heap_page.pointer_count() {
+                if !heap_page.pointer_and_tuple_exist(slot).1 {
+                    continue;
+                }
+                let rid = Rid::new(block.block_num, slot as u16);
+                let (xmin, xmax) = heap_page.xids(slot);
+                let aborted = |xid| ended(xid) && !transactions.committed(xid);
+                if aborted(xmin) || ended(xmax) && !aborted(xmax) {
+                    dead.push(rid);
+                } else if aborted(xmax) {
+                    restored.push(rid);
+                }
+            }
+            self.storage_mgr.write().unwrap().unpin(heap_page.frame);
+        }
+        for rid in &restored {
+            self.restore_version(rid);
+        }
+        for rid in &dead {
+            self.delete_tuple(rid);
+        }
+        let mut storage_mgr = self.storage_mgr.write().unwrap();
         for block in &self.table_blocks {
             let frame = storage_mgr.pin(block.clone()).unwrap();
             let mut heap_page = HeapPage::new(frame.clone(), block, self.layout.clone());
             heap_page.vacuum();
+            self.free_map
+                .set_blockspace(heap_page.free_space(), block, &mut storage_mgr);
             storage_mgr.unpin(frame);
         }
+        dead.len()
+    }
+    /// Rewrites every tuple version of the table to a new layout, converting its fields on the way,
+    /// and returns the number of rewritten versions.
+    ///
+    /// The versions are read with the old layout and taken out of the table, then inserted again
+    /// with the new one. They keep the transactions that created and deleted them so every snapshot
+    /// sees the same rows as before. The indexes on any field the new layout does not have are
+    /// dropped
+    pub fn rewrite<F>(&mut self, layout: Arc<Layout>, convert: F) -> usize
+    where
+        F: Fn(HashMap<String, Option<Vec<u8>>>) -> Vec<(String, Option<Vec<u8>>)>,
+    {
+        let mut rids = vec![];
+        let mut versions = vec![];
+        for block in &self.table_blocks {
+            let heap_page = self.get_heap_page(block);
+            for slot in 0..heap_page.pointer_count() {
+                if heap_page.pointer_and_tuple_exist(slot).1 {
+                    rids.push(Rid::new(block.block_num, slot as u16));
+                    versions.push((heap_page.get_tuple_fields(slot), heap_page.xids(slot)));
+                }
+            }
+            self.storage_mgr.write().unwrap().unpin(heap_page.frame);
+        }
+        self.delete_tuples(&rids);
+        let in_layout = |columns: &String| columns.split(',').all(|c| layout.map().contains_key(c));
+        self.hash_indexes.retain(|columns, _| in_layout(columns));
+        self.btree_indexes.retain(|columns, _| in_layout(columns));
+        self.layout = layout;
+        for (fields, (xmin, xmax)) in versions {
+            let rid = self.insert_version(convert(fields), xmin);
+            if xmax != 0 {
+                self.delete_version(&rid, xmax);
+            }
+        }
+        rids.len()
     }
     /// Helper function to pin a block to a frame and construct a heap page out of it
     fn get_heap_page(&self, blk: &BlockId) -> HeapPage {
-        let mut storage_mgr = self.storage_mgr.borrow_mut();
+        let mut storage_mgr = self.storage_mgr.write().unwrap();
         let frame = storage_mgr.pin(blk.clone()).unwrap();
-        HeapPage::new(frame, blk, self.layout.clone())
+        HeapPage::new(frame, blk, self.layout.clone()).with_overflow(self.storage_mgr.clone())
     }
 
-    pub fn get_layout(&self) -> Rc<Layout> {
+    pub fn get_layout(&self) -> Arc<Layout> {
         self.layout.clone()
     }
 
@@ -289,33 +639,67 @@ impl TableManager {
         self.hash_indexes.contains_key(field) || self.btree_indexes.contains_key(field)
     }
 
+    /// The indexes on more than one column of the table, with their columns in key order
+    pub fn composite_indexes(&self) -> Vec<(IndexType, Vec<&str>)> {
+        let hash = self.hash_indexes.keys().map(|k| (IndexType::Hash, k));
+        let btree = self.btree_indexes.keys().map(|k| (IndexType::Btree, k));
+        let mut indexes = hash
+            .chain(btree)
+            .map(|(index_type, columns)| (index_type, columns.split(',').collect::<Vec<_>>()))
+            .filter(|(_, columns)| columns.len() > 1)
+            .collect::<Vec<_>>();
+        indexes.sort_by(|a, b| a.1.cmp(&b.1));
+        indexes
+    }
+
     /// Creates a TableIter instance that is an sequential iterator over ALL the tuples in a table
-    pub fn heapscan_iter(&self) -> TableIter {
+    /// visible to a snapshot, or over every tuple version without one
+    pub fn heapscan_iter(&self, snapshot: Option<Arc<Snapshot>>) -> TableIter {
         TableIter::new(
             self.table_blocks.clone(),
             self.storage_mgr.clone(),
             self.layout.clone(),
+            snapshot,
         )
     }
 
-    pub fn direct_accessor(&self) -> DirectAccessor {
+    pub fn direct_accessor(&self, snapshot: Option<Arc<Snapshot>>) -> DirectAccessor {
         DirectAccessor::new(
             self.table_blocks.clone(),
             self.storage_mgr.clone(),
             self.layout.clone(),
+            snapshot,
         )
     }
 
-    pub fn hashscan_iter(&self, index_field: &str) -> Option<HashIter> {
+    pub fn hashscan_iter(
+        &self,
+        index_field: &str,
+        snapshot: Option<Arc<Snapshot>>,
+    ) -> Option<HashIter> {
         if let Some(idx) = self.hash_indexes.get(index_field) {
-            return Some(HashIter::new(self.direct_accessor(), idx.clone()));
+            return Some(HashIter::new(
+                self.direct_accessor(snapshot),
+                idx.clone(),
+                index_field,
+            ));
         }
         None
     }
 
-    pub fn btree_iter(&self, index_field: &str, op: evalexpr::Operator) -> Option<BtreeIter> {
+    pub fn btree_iter(
+        &self,
+        index_field: &str,
+        op: evalexpr::Operator,
+        snapshot: Option<Arc<Snapshot>>,
+    ) -> Option<BtreeIter> {
         if let Some(idx) = self.btree_indexes.get(index_field) {
-            return Some(BtreeIter::new(self.direct_accessor(), idx.clone(), op));
+            return Some(BtreeIter::new(
+                self.direct_accessor(snapshot),
+                idx.clone(),
+                index_field,
+                op,
+            ));
         }
         None
     }
@@ -325,19 +709,316 @@ impl TableManager {
             self.layout.type_map(),
             self.btree_indexes.keys().cloned().collect::<HashSet<_>>(),
             self.hash_indexes.keys().cloned().collect::<HashSet<_>>(),
+            self.table_blocks.len(),
+            self.estimated_rows(),
         )
     }
 
+    /// Estimates the number of tuples from the space used in the heap blocks, the blocks filled
+    /// up are not in the free space map and count as fully used
+    fn estimated_rows(&self) -> usize {
+        let capacity = HeapPage::default_free_space(self.storage_mgr.read().unwrap().blk_size());
+        let free = self
+            .free_map
+            .btree()
+            .range(0..u16::MAX)
+            .map(|(space, blocks)| *space as usize * blocks.len())
+            .sum::<usize>();
+        let used = (capacity * self.table_blocks.len()).saturating_sub(free);
+        used / self.layout.estimated_tuple_size()
+    }
+
     pub fn add_index_block(&mut self, blk: &BlockId) {
         self.table_blocks.push(blk.clone());
     }
 
-    pub fn remove_index_block(&mut self, blk_num: u64) {
-        let pos = self
-            .table_blocks
+    /// Drops a block released by an index from the tracked blocks, the whole BlockId is matched
+    /// so a heap block sharing the same block number is left alone
+    pub fn remove_index_block(&mut self, blk: &BlockId) {
+        if let Some(pos) = self.table_blocks.iter().position(|block| block == blk) {
+            self.table_blocks.remove(pos);
+        }
+    }
+}
+
+/// The key of a tuple in the index on some columns, the columns joined by commas.
+///
+/// An index on a single column takes the field as is and skips the tuples where it is null, an
+/// index on several columns takes every tuple with its fields encoded by `composite_key`
+fn index_key(
+    columns: &str,
+    fields: &HashMap<&str, &Option<Vec<u8>>>,
+    layout: &Layout,
+) -> Option<Vec<u8>> {
+    let field = |column: &str| fields.get(column).and_then(|data| data.as_deref());
+    if !columns.contains(',') {
+        return field(columns).map(|data| data.to_vec());
+    }
+    let values = columns
+        .split(',')
+        .map(|column| (field(column), layout.get_type(column)))
+        .collect::<Vec<_>>();
+    Some(composite_key(&values))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::testing::{TempDir, TestSession};
+    use crate::database::transaction::Transaction;
+    use crate::database::txnmgr::TransactionManager;
+    use crate::index::{Index, IndexInfo};
+    use crate::interface::message::Status;
+    use crate::schema::schema::{Layout, Schema};
+    use crate::schema::types::{NumericType::Integer, Type};
+    use crate::sql::create_table::IndexType;
+    use crate::storage::blockid::BlockId;
+    use crate::storage::free_space::FreeMap;
+    use crate::storage::heap::HeapPage;
+    use crate::storage::overflow::CHUNK_SIZE;
+    use crate::storage::storagemgr::StorageManager;
+    use crate::table::tablemgr::TableManager;
+    use std::fs;
+    use std::sync::{Arc, RwLock};
+
+    fn doc_layout(fields: &[&str]) -> Arc<Layout> {
+        let mut schema = Schema::new();
+        schema.add_field_default_constraints("k", Type::Numeric(Integer), None);
+        schema.add_field_default_constraints("v", Type::Binary, None);
+        for field in fields {
+            schema.add_field_default_constraints(field, Type::Numeric(Integer), None);
+        }
+        Arc::new(schema.to_layout())
+    }
+
+    /// A table of a key and a binary value holding a value of more than three chunks
+    fn doc_table(dir: &TempDir, storage: &Arc<RwLock<StorageManager>>) -> (TableManager, Vec<u8>) {
+        let heap = dir.join("doc");
+        let freemap = dir.join("doc_freemap");
+        let blocks = storage
+            .write()
+            .unwrap()
+            .empty_heap_pages(heap.to_str().unwrap(), 1);
+        FreeMap::init(
+            freemap.clone(),
+            HeapPage::default_free_space(4096) as u16,
+            &blocks[0],
+        );
+        let mut table =
+            TableManager::from_file(storage.clone(), heap, doc_layout(&[]), vec![], freemap);
+        let value = (0..3 * CHUNK_SIZE + 123)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        table.try_insert_tuple(vec![
+            ("k".to_string(), Some(1_i32.to_ne_bytes().to_vec())),
+            ("v".to_string(), Some(value.clone())),
+        ]);
+        (table, value)
+    }
+
+    /// The free space the overflow FSM records for each block of the overflow file
+    fn overflow_space(table: &TableManager) -> Vec<u16> {
+        let free_map = &table.overflow.as_ref().unwrap().free_map;
+        free_map
+            .btree()
+            .range(0..u16::MAX)
+            .flat_map(|(space, blocks)| blocks.iter().map(|_| *space))
+            .collect()
+    }
+
+    // A value larger than a page is read back whole from the chunks it is kept in. Once it is
+    // deleted and vacuumed, the overflow FSM has the space of a chunk back in every block
+    #[test]
+    fn test_overflow_value_is_read_and_reclaimed() {
+        let dir = TempDir::new("overflow");
+        let storage = dir.storage(50);
+        let (mut table, value) = doc_table(&dir, &storage);
+        let mut rows = table.heapscan_iter(None);
+        assert_eq!(rows.next().unwrap()["v"], Some(value.clone()));
+        let rid = rows.current_rid().unwrap();
+        assert!(rows.next().is_none());
+        drop(rows);
+        let blk = BlockId::new(table.get_heapfile_name(), rid.block_num());
+        let slot = rid.slot_num() as usize;
+        assert_eq!(table.get_field(&blk, slot, "v"), Some(value.clone()));
+        assert_eq!(table.get_tuple(&blk, slot)["v"], Some(value.clone()));
+
+        let chunk = CHUNK_SIZE as u16;
+        let full = overflow_space(&table)
             .iter()
-            .position(|block| block.block_num == blk_num)
-            .unwrap();
-        self.table_blocks.remove(pos);
+            .filter(|space| **space < chunk)
+            .count();
+        assert!(full >= 3);
+        let manager = Arc::new(TransactionManager::new(dir.path()));
+        let deleter = Transaction::new(manager.clone());
+        table.delete_version(&rid, deleter.id());
+        deleter.commit();
+        assert_eq!(table.vacuum(&manager), 1);
+        assert!(overflow_space(&table).iter().all(|space| *space > chunk));
+    }
+
+    // Rewriting the table to a new layout, as ALTER TABLE does, keeps its values that are in the
+    // overflow file
+    #[test]
+    fn test_rewrite_keeps_overflow_values() {
+        let dir = TempDir::new("overflow_rewrite");
+        let storage = dir.storage(50);
+        let (mut table, value) = doc_table(&dir, &storage);
+        let rewritten = table.rewrite(doc_layout(&["n"]), |fields| {
+            let mut fields = fields.into_iter().collect::<Vec<_>>();
+            fields.push(("n".to_string(), None));
+            fields
+        });
+        assert_eq!(rewritten, 1);
+        let rows = table.heapscan_iter(None).collect::<Vec<_>>();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["v"], Some(value));
+        assert_eq!(rows[0]["n"], None);
+    }
+
+    // The FSM and the bucket directory of a hash index are only written at checkpoints, a crash
+    // after the rows commit leaves their files as they were created. Recovery writes them from the
+    // log, then the table loads without reading its blocks or rebuilding the index
+    #[test]
+    fn test_crash_recovers_free_space_and_hash_index() {
+        let dir = TempDir::new("hash_crash");
+        let (heap, freemap) = (dir.join("doc"), dir.join("doc_freemap"));
+        let index = IndexInfo {
+            index_name: "kidx".to_string(),
+            index_type: IndexType::Hash,
+            columns: vec!["k".to_string()],
+            index_file_path: dir.join("kidx_file"),
+            directory_file_path: dir.join("kidx_directory"),
+            key_type: Type::Numeric(Integer),
+        };
+        let depth = || fs::read(&index.directory_file_path).unwrap()[0];
+        let (blocks, created) = {
+            let storage = dir.storage(50);
+            let blocks = storage
+                .write()
+                .unwrap()
+                .empty_heap_pages(heap.to_str().unwrap(), 1);
+            FreeMap::init(
+                freemap.clone(),
+                HeapPage::default_free_space(4096) as u16,
+                &blocks[0],
+            );
+            let mut table = TableManager::from_file(
+                storage.clone(),
+                heap.clone(),
+                doc_layout(&[]),
+                vec![],
+                freemap.clone(),
+            );
+            Index::init_index(index.clone(), storage.clone());
+            table.add_index(index.clone());
+            let created = depth();
+            for k in 0..12000_i32 {
+                table.try_insert_tuple(vec![
+                    ("k".to_string(), Some(k.to_ne_bytes().to_vec())),
+                    ("v".to_string(), Some(vec![7; 8])),
+                ]);
+            }
+            // the rows commit, then the server dies before any checkpoint
+            storage.write().unwrap().flush_log();
+            assert_eq!(depth(), created);
+            assert_eq!(
+                FreeMap::new(freemap.clone())
+                    .btree()
+                    .range(0..u16::MAX)
+                    .count(),
+                1
+            );
+            (table.table_blocks.len(), created)
+        };
+        let storage = dir.storage(50);
+        assert!(depth() > created);
+        let pins = StorageManager::thread_pins();
+        let table =
+            TableManager::from_file(storage.clone(), heap, doc_layout(&[]), vec![index], freemap);
+        // only the last heap block is read, to find the blocks the crash left unformatted
+        assert_eq!(StorageManager::thread_pins() - pins, 1);
+        assert_eq!(table.table_blocks.len(), blocks);
+        let last = table.table_blocks.last().unwrap();
+        let space = table.free_map.btree().range(0..u16::MAX);
+        assert!(space
+            .flat_map(|(_, blocks)| blocks)
+            .any(|block| block == last));
+        for k in [0_i32, 4321, 11999] {
+            let mut rows = table.hashscan_iter("k", None).unwrap();
+            rows.load_key(&k.to_ne_bytes());
+            let keys = rows
+                .map(|row| row["k"].clone().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(keys, vec![k.to_ne_bytes().to_vec()]);
+        }
+    }
+
+    // DELETE only marks the rows deleted, VACUUM then removes them from the blocks and from the
+    // hash and btree indexes of the table and gives their space back to the FSM. Inserting as
+    // many rows again fills the freed space instead of adding blocks
+    #[test]
+    fn test_deleted_rows_leave_the_indexes_and_free_their_space() {
+        let mut session = TestSession::open();
+        session.status(
+            "create table acc (id int, owner varchar, \
+             create index hash oidx on (owner), create index btree idx on (id))",
+        );
+        let owner = |id: i32| format!("owner {id:0>40}");
+        let insert = |id: i32| {
+            let owner = owner(id);
+            format!("insert into acc (id, owner) values ({id}, \"{owner}\")")
+        };
+        for id in 0..200 {
+            session.status(&insert(id));
+        }
+        let status = session.status("delete from acc where acc.id < 100");
+        assert!(matches!(status, Status::RecordsDeleted(100)));
+        let status = session.status("vacuum acc");
+        assert!(matches!(status, Status::Vacuumed(100)));
+        let mut ids = session.rows("select acc.id from acc");
+        ids.sort_by_key(|row| row[0].parse::<i32>().unwrap());
+        let expected = (100..200)
+            .map(|id| vec![id.to_string()])
+            .collect::<Vec<_>>();
+        assert_eq!(ids, expected);
+
+        let tables = session.instance().tables().clone();
+        let guard = tables.lock().unwrap();
+        let table = &guard["acc"];
+        let entries = |table: &TableManager, id: i32| {
+            let hash = table.hash_indexes["owner"].read().unwrap();
+            let storage = table.storage_mgr.write().unwrap();
+            let hashed = hash.get_rids(owner(id).as_bytes(), storage).len();
+            let btree = table.btree_indexes["id"].read().unwrap();
+            let keyed = btree.search(id.to_ne_bytes().to_vec()).unwrap_or_default();
+            (hashed, keyed.len())
+        };
+        for id in [0, 42, 99] {
+            assert_eq!(entries(table, id), (0, 0));
+        }
+        for id in [100, 142, 199] {
+            assert_eq!(entries(table, id), (1, 1));
+        }
+        let blocks = table.table_blocks.len();
+        // the blocks at least half empty
+        let freed = |table: &TableManager| {
+            let free_space = table.free_map.btree();
+            free_space
+                .range(2000..u16::MAX)
+                .map(|(_, blocks)| blocks.len())
+                .sum::<usize>()
+        };
+        assert!(blocks > 2 && freed(table) >= 2);
+        drop(guard);
+
+        for id in 200..300 {
+            let status = session.status(&insert(id));
+            assert!(matches!(status, Status::RecordInserted));
+        }
+        let guard = tables.lock().unwrap();
+        let table = &guard["acc"];
+        assert_eq!(table.table_blocks.len(), blocks);
+        assert_eq!(freed(table), 0);
+        assert_eq!(entries(table, 250), (1, 1));
     }
 }
@@ -1,5 +1,6 @@
+use aqua::common::temporal::{self, MICROS_PER_DAY};
 use aqua::schema::schema::Schema;
-use aqua::schema::types::{CharType, NumericType, Type};
+use aqua::schema::types::{CharType, NumericType, TemporalType, Type};
 use names::{Generator, Name};
 use rand::distributions::Alphanumeric;
 use rand::rngs::{StdRng, ThreadRng};
@@ -21,6 +22,11 @@ impl RandomTypeBytes for NumericType {
             NumericType::BigInt => rng.gen_range(i64::MIN..i64::MAX).to_ne_bytes().to_vec(),
             NumericType::Single => rng.gen_range(f32::MIN..f32::MAX).to_ne_bytes().to_vec(),
             NumericType::Double => rng.gen_range(f64::MIN..f64::MAX).to_ne_bytes().to_vec(),
+            NumericType::Decimal(precision, _) => {
+                // the unscaled value, with no more digits than the precision
+                let limit = 10_i128.pow(precision as u32);
+                rng.gen_range(1 - limit..limit).to_ne_bytes().to_vec()
+            }
         }
     }
 }
@@ -32,18 +38,38 @@ impl RandomTypeBytes for CharType {
     }
 }
 
+impl RandomTypeBytes for TemporalType {
+    fn random(self) -> Vec<u8> {
+        let mut rng = thread_rng();
+        // a few centuries around the epoch, dates fall on midnight and times within a day
+        let span = 100_000 * MICROS_PER_DAY;
+        let micros = match self {
+            TemporalType::Date => rng.gen_range(-100_000..100_000) * MICROS_PER_DAY,
+            TemporalType::Time => rng.gen_range(0..MICROS_PER_DAY),
+            TemporalType::Timestamp | TemporalType::Interval => rng.gen_range(-span..span),
+        };
+        temporal::to_bytes(self, micros).unwrap()
+    }
+}
+
 impl RandomTypeBytes for Type {
     fn random(self) -> Vec<u8> {
         match self {
             Type::Numeric(n) => n.random(),
             Type::Character(c) => c.random(),
-            Type::Boolean(_) => {
+            Type::Boolean => {
                 let mut rng = thread_rng();
                 match rng.gen_bool(0.5) {
                     true => 1_u8.to_ne_bytes().to_vec(),
                     false => 0_u8.to_ne_bytes().to_vec(),
                 }
             }
+            Type::Temporal(t) => t.random(),
+            Type::Binary => {
+                let mut rng = thread_rng();
+                let len = rng.gen_range(1..64);
+                (0..len).map(|_| rng.gen::<u8>()).collect()
+            }
         }
     }
 }
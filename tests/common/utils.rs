@@ -8,7 +8,7 @@ use aqua::storage::storagemgr::StorageManager;
 use std::fs;
 use std::fs::create_dir_all;
 use std::path::Path;
-use std::rc::Rc;
+use std::sync::Arc;
 
 pub fn create_blockids(blk_cnt: u64, filename: &str) -> Vec<BlockId> {
     (0..blk_cnt)
@@ -24,9 +24,9 @@ pub fn some_schema() -> Schema {
     let mut schema = Schema::new();
     let schema_vec = vec![
         ("id", Type::Numeric(SmallInt), false, None),
-        ("name", Type::Character(VarChar), false, None),
+        ("name", Type::Character(VarChar(None)), false, None),
         ("salary", Type::Numeric(Integer), false, None),
-        ("job", Type::Character(VarChar), false, None),
+        ("job", Type::Character(VarChar(None)), false, None),
     ];
     for attr in schema_vec {
         schema.add_field_default_constraints(attr.0, attr.1, attr.3);
@@ -44,7 +44,7 @@ pub fn empty_heapfile(
     filename: &str,
     blk_size: usize,
     blk_cnt: u32,
-    layout: Rc<Layout>,
+    layout: Arc<Layout>,
 ) -> Vec<BlockId> {
     let mut storagemgr = StorageManager::new(db_dir, blk_size, blk_cnt);
     let blks = storagemgr.extend_file_many(filename, blk_cnt);
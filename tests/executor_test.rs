@@ -2,10 +2,10 @@ use aqua::query::executor::Executor;
 use aqua::query::seq_scan::SeqScan;
 use aqua::storage::storagemgr::StorageManager;
 use aqua::table::tablemgr::TableManager;
-use aqua::RcRefCell;
-use std::cell::RefCell;
+use aqua::ArcRwLock;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::RwLock;
 mod common;
 use crate::common::random::distill_schema;
 use common::{random, utils};
@@ -19,9 +19,9 @@ const db_dir: &str = "tests/db";
 fn create_table(table_name: &str) -> TableManager {
     let test_file = table_name;
     let BLK_SIZE = 4096;
-    let layout = Rc::new(utils::some_layout());
+    let layout = Arc::new(utils::some_layout());
     let file_blocks = utils::empty_heapfile(db_dir, test_file, BLK_SIZE, 10, layout.clone());
-    let storagemgr = RcRefCell!(StorageManager::new(db_dir, BLK_SIZE, 100));
+    let storagemgr = ArcRwLock!(StorageManager::new(db_dir, BLK_SIZE, 100));
     let mut tblmgr = TableManager::new(
         file_blocks.clone(),
         storagemgr.clone(),
@@ -6,7 +6,7 @@ use aqua::storage::blockid::BlockId;
 use aqua::storage::heap::{HeapPage, PageHeader};
 use aqua::storage::storagemgr::StorageManager;
 use aqua::storage::tuple::Tuple;
-use std::rc::Rc;
+use std::sync::Arc;
 
 mod common;
 use crate::common::random;
@@ -30,16 +30,16 @@ fn empty_page() {
     let mut schema = Schema::new();
     let schema_vec = vec![
         ("id", Type::Numeric(SmallInt), false, None),
-        ("name", Type::Character(VarChar), false, None),
+        ("name", Type::Character(VarChar(None)), false, None),
         ("salary", Type::Numeric(Integer), false, None),
-        ("job", Type::Character(VarChar), false, None),
+        ("job", Type::Character(VarChar(None)), false, None),
     ];
     for attr in schema_vec {
         schema.add_field_default_constraints(attr.0, attr.1, attr.3);
     }
     let layout = schema.to_layout();
     let frame = storagemgr.pin(blk.clone()).unwrap();
-    let heap_page = HeapPage::new_from_empty(frame, &blk, Rc::new(layout));
+    let heap_page = HeapPage::new_from_empty(frame, &blk, Arc::new(layout));
     println!("{:?}", heap_page);
     let page_header = PageHeader {
         space_start: 4,
@@ -60,15 +60,15 @@ fn filling_page() {
     let mut schema = Schema::new();
     let schema_vec = vec![
         ("id", Type::Numeric(SmallInt), false, None),
-        ("name", Type::Character(VarChar), false, None),
+        ("name", Type::Character(VarChar(None)), false, None),
         ("salary", Type::Numeric(Integer), false, None),
-        ("job", Type::Character(VarChar), false, None),
+        ("job", Type::Character(VarChar(None)), false, None),
     ];
     for attr in schema_vec {
         schema.add_field_default_constraints(attr.0, attr.1, attr.3);
     }
     let layout = schema.to_layout();
-    let layout = Rc::new(layout);
+    let layout = Arc::new(layout);
     let frame = storagemgr.pin(blk.clone()).unwrap();
     let mut heap_page = HeapPage::new_from_empty(frame, &blk, layout.clone());
     let schema = random::distill_schema(schema);
@@ -93,15 +93,15 @@ fn write_read_tuples() {
     let mut schema = Schema::new();
     let schema_vec = vec![
         ("id", Type::Numeric(SmallInt), false, None),
-        ("name", Type::Character(VarChar), false, None),
+        ("name", Type::Character(VarChar(None)), false, None),
         ("salary", Type::Numeric(Integer), false, None),
-        ("job", Type::Character(VarChar), false, None),
+        ("job", Type::Character(VarChar(None)), false, None),
     ];
     for attr in schema_vec {
         schema.add_field_default_constraints(attr.0, attr.1, attr.3);
     }
     let layout = schema.to_layout();
-    let layout = Rc::new(layout);
+    let layout = Arc::new(layout);
     let frame = storagemgr.pin(blk.clone()).unwrap();
     let mut heap_page = HeapPage::new_from_empty(frame.clone(), &blk, layout.clone());
     let tuples = vec![
@@ -24,9 +24,9 @@ fn layout_test() {
     let mut schema = Schema::new();
     let schema_vec = vec![
         ("id", Type::Numeric(SmallInt), false, None),
-        ("name", Type::Character(VarChar), false, None),
+        ("name", Type::Character(VarChar(None)), false, None),
         ("salary", Type::Numeric(Integer), false, None),
-        ("job", Type::Character(VarChar), false, None),
+        ("job", Type::Character(VarChar(None)), false, None),
     ];
     for attr in schema_vec {
         schema.add_field_default_constraints(attr.0, attr.1, attr.3);
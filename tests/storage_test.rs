@@ -32,7 +32,7 @@ fn write() {
         block_num: 0,
     };
     let frame = storagemgr.pin(blk).unwrap();
-    (*frame).borrow_mut().write(b"test");
+    (*frame).write().unwrap().write(b"test");
     storagemgr.flush_frame(frame);
     let written = utils::readfile(format!("{}{}", db_dir, test_file).as_str());
     assert_eq!(b"test", written[0..b"test".len()].as_ref())
@@ -8,10 +8,10 @@ use aqua::schema::types::Type;
 use aqua::storage::blockid::BlockId;
 use aqua::storage::storagemgr::StorageManager;
 use aqua::table::tablemgr::TableManager;
-use aqua::RcRefCell;
+use aqua::ArcRwLock;
 use common::utils;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::RwLock;
 
 #[cfg(windows)]
 const db_dir: &str = "tests\\db\\";
@@ -23,9 +23,9 @@ const db_dir: &str = "tests/db/";
 fn freemap() {
     let test_file = "freemap_test_blks";
     let BLK_SIZE = 4096;
-    let layout = Rc::new(utils::some_layout());
+    let layout = Arc::new(utils::some_layout());
     let file_blocks = utils::empty_heapfile(db_dir, test_file, BLK_SIZE, 10, layout.clone());
-    let storagemgr = RcRefCell!(StorageManager::new(db_dir, BLK_SIZE, 100));
+    let storagemgr = ArcRwLock!(StorageManager::new(db_dir, BLK_SIZE, 100));
     let tblmgr = TableManager::new(
         file_blocks.clone(),
         storagemgr.clone(),
@@ -39,9 +39,9 @@ fn freemap() {
 fn insert_tuple_update_freemap() {
     let test_file = "insert_tuple_update_freemap";
     let BLK_SIZE = 4096;
-    let layout = Rc::new(utils::some_layout());
+    let layout = Arc::new(utils::some_layout());
     let file_blocks = utils::empty_heapfile(db_dir, test_file, BLK_SIZE, 10, layout.clone());
-    let storagemgr = RcRefCell!(StorageManager::new(db_dir, BLK_SIZE, 100));
+    let storagemgr = ArcRwLock!(StorageManager::new(db_dir, BLK_SIZE, 100));
     let mut tblmgr = TableManager::new(
         file_blocks.clone(),
         storagemgr.clone(),
@@ -75,17 +75,17 @@ fn insert_tuples_then_scan() {
     let mut schema = Schema::new();
     let schema_vec = vec![
         ("id", Type::Numeric(SmallInt), false, None),
-        ("name", Type::Character(VarChar), false, None),
+        ("name", Type::Character(VarChar(None)), false, None),
         ("salary", Type::Numeric(Integer), false, None),
-        ("job", Type::Character(VarChar), false, None),
+        ("job", Type::Character(VarChar(None)), false, None),
     ];
     for attr in schema_vec {
         schema.add_field_default_constraints(attr.0, attr.1, attr.3);
     }
     let layout = schema.to_layout();
-    let layout = Rc::new(layout);
+    let layout = Arc::new(layout);
     let file_blocks = utils::empty_heapfile(db_dir, test_file, BLK_SIZE, 1, layout.clone());
-    let storagemgr = RcRefCell!(StorageManager::new(db_dir, BLK_SIZE, 100));
+    let storagemgr = ArcRwLock!(StorageManager::new(db_dir, BLK_SIZE, 100));
     let mut tblmgr = TableManager::new(
         file_blocks.clone(),
         storagemgr.clone(),
@@ -117,17 +117,17 @@ fn mark_delete() {
     let mut schema = Schema::new();
     let schema_vec = vec![
         ("id", Type::Numeric(SmallInt), false, None),
-        ("name", Type::Character(VarChar), false, None),
+        ("name", Type::Character(VarChar(None)), false, None),
         ("salary", Type::Numeric(Integer), false, None),
-        ("job", Type::Character(VarChar), false, None),
+        ("job", Type::Character(VarChar(None)), false, None),
     ];
     for attr in schema_vec {
         schema.add_field_default_constraints(attr.0, attr.1, attr.3);
     }
     let layout = schema.to_layout();
-    let layout = Rc::new(layout);
+    let layout = Arc::new(layout);
     let file_blocks = utils::empty_heapfile(db_dir, test_file, BLK_SIZE, 1, layout.clone());
-    let storagemgr = RcRefCell!(StorageManager::new(db_dir, BLK_SIZE, 100));
+    let storagemgr = ArcRwLock!(StorageManager::new(db_dir, BLK_SIZE, 100));
     let mut tblmgr = TableManager::new(
         file_blocks.clone(),
         storagemgr.clone(),
@@ -152,17 +152,17 @@ fn delete_vacuum_test() {
     let mut schema = Schema::new();
     let schema_vec = vec![
         ("id", Type::Numeric(SmallInt), false, None),
-        ("name", Type::Character(VarChar), false, None),
+        ("name", Type::Character(VarChar(None)), false, None),
         ("salary", Type::Numeric(Integer), false, None),
-        ("job", Type::Character(VarChar), false, None),
+        ("job", Type::Character(VarChar(None)), false, None),
     ];
     for attr in schema_vec {
         schema.add_field_default_constraints(attr.0, attr.1, attr.3);
     }
     let layout = schema.to_layout();
-    let layout = Rc::new(layout);
+    let layout = Arc::new(layout);
     let file_blocks = utils::empty_heapfile(db_dir, test_file, BLK_SIZE, 1, layout.clone());
-    let storagemgr = RcRefCell!(StorageManager::new(db_dir, BLK_SIZE, 100));
+    let storagemgr = ArcRwLock!(StorageManager::new(db_dir, BLK_SIZE, 100));
     let mut tblmgr = TableManager::new(
         file_blocks.clone(),
         storagemgr.clone(),
@@ -0,0 +1,255 @@
+use aqua::common::numerical::ByteMagic;
+use aqua::meta::catalogmgr::CatalogManager;
+use aqua::meta::upgrade::upgrade_data_dir;
+use aqua::schema::schema::{Layout, Schema};
+use aqua::schema::types::CharType::VarChar;
+use aqua::schema::types::NumericType::{Double, Integer};
+use aqua::schema::types::Type;
+use aqua::storage::storagemgr::StorageManager;
+use aqua::storage::tuple::{Tuple, TUPLE_HEADER_SIZE};
+use aqua::{ArcRwLock, AQUA_HOME_VAR};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+const BLK_SIZE: usize = 4096;
+
+type Row = Vec<(String, Option<Vec<u8>>)>;
+
+fn layout(fields: &[(&str, Type)]) -> Arc<Layout> {
+    let mut schema = Schema::new();
+    for (name, field_type) in fields {
+        schema.add_field_default_constraints(name, *field_type, None);
+    }
+    Arc::new(schema.to_layout())
+}
+
+fn text(value: &str) -> Option<Vec<u8>> {
+    Some(value.as_bytes().to_vec())
+}
+
+fn row(fields: &[(&str, Option<Vec<u8>>)]) -> Row {
+    fields
+        .iter()
+        .map(|(name, data)| (name.to_string(), data.clone()))
+        .collect()
+}
+
+/// Writes a heap file as format version 1 wrote it, the tuples had the deleted byte alone for a
+/// header. The rows paired with true are written deleted
+fn write_v1_heap(path: &Path, layout: &Arc<Layout>, rows: Vec<(Row, bool)>) {
+    let mut file = vec![];
+    let mut page = vec![0_u8; BLK_SIZE];
+    let (mut space_start, mut space_end) = (6, BLK_SIZE - 1);
+    let mut rows = rows.into_iter().peekable();
+    while let Some((row, deleted)) = rows.next() {
+        let mut tuple = Tuple::new(row, layout.clone()).to_bytes();
+        tuple.drain(1..TUPLE_HEADER_SIZE);
+        tuple[0] = deleted as u8;
+        space_end -= tuple.len();
+        page[space_end..space_end + tuple.len()].copy_from_slice(&tuple);
+        page[space_start..space_start + 2].copy_from_slice(&(space_end as u16).to_ne_bytes());
+        page[space_start + 2..space_start + 4].copy_from_slice(&(tuple.len() as u16).to_ne_bytes());
+        space_start += 4;
+        // the next row is put on a new page once this one has no room left for a row that size
+        if rows.peek().is_none() || space_end - space_start < tuple.len() + 4 + 16 {
+            page[0..2].copy_from_slice(&(space_start as u16).to_ne_bytes());
+            page[2..4].copy_from_slice(&(space_end as u16).to_ne_bytes());
+            page[4..6].copy_from_slice(&((BLK_SIZE - 1) as u16).to_ne_bytes());
+            file.extend(page);
+            page = vec![0_u8; BLK_SIZE];
+            (space_start, space_end) = (6, BLK_SIZE - 1);
+        }
+    }
+    fs::write(path, file).unwrap();
+}
+
+fn varchar() -> Type {
+    Type::Character(VarChar(None))
+}
+
+/// Writes a data directory of format version 1 holding the database shop, its table items has a
+/// CHAR field and a hash and a btree index
+fn write_v1_data_dir(home: &Path) {
+    fs::create_dir_all(home.join("base").join("tmp")).unwrap();
+    fs::create_dir_all(home.join("base").join("shop")).unwrap();
+    fs::create_dir_all(home.join("global")).unwrap();
+    let databases = layout(&[("database_name", varchar())]);
+    write_v1_heap(
+        &home.join("global").join("aqua_database"),
+        &databases,
+        vec![(row(&[("database_name", text("shop"))]), false)],
+    );
+    let db_dir = home.join("base").join("shop");
+    let schemas = layout(&[
+        ("tablename", varchar()),
+        ("fieldname", varchar()),
+        ("fieldtype", varchar()),
+        ("pkey_piece", Type::Boolean),
+        ("nullable", Type::Boolean),
+        ("unique", Type::Boolean),
+        ("foreign_table", varchar()),
+        ("foreign_field", varchar()),
+    ]);
+    let field = |name: &str, field_type: &str, pkey: bool, nullable: bool| {
+        let row = row(&[
+            ("tablename", text("items")),
+            ("fieldname", text(name)),
+            ("fieldtype", text(field_type)),
+            ("pkey_piece", Some(vec![pkey as u8])),
+            ("nullable", Some(vec![nullable as u8])),
+            ("unique", Some(vec![pkey as u8])),
+            ("foreign_table", None),
+            ("foreign_field", None),
+        ]);
+        (row, false)
+    };
+    write_v1_heap(
+        &db_dir.join("shop_schemas"),
+        &schemas,
+        vec![
+            field("id", "int", true, false),
+            field("name", "varchar", false, false),
+            field("code", "char", false, true),
+            field("price", "double", false, false),
+        ],
+    );
+    let indexes = layout(&[
+        ("tablename", varchar()),
+        ("index_name", varchar()),
+        ("fieldname", varchar()),
+        ("directory_file", varchar()),
+        ("index_file", varchar()),
+        ("index_type", varchar()),
+    ]);
+    let index = |name: &str, field: &str, index_type: &str| {
+        let row = row(&[
+            ("tablename", text("items")),
+            ("index_name", text(name)),
+            ("fieldname", text(field)),
+            ("directory_file", text(&format!("{}_idx_directory", name))),
+            ("index_file", text(&format!("{}_idx_file", name))),
+            ("index_type", text(index_type)),
+        ]);
+        (row, false)
+    };
+    write_v1_heap(
+        &db_dir.join("shop_indexes"),
+        &indexes,
+        vec![
+            index("items_id", "id", "btree"),
+            index("items_name", "name", "hash"),
+        ],
+    );
+    let tables_files = layout(&[
+        ("tablename", varchar()),
+        ("filepath", varchar()),
+        ("freemap", varchar()),
+    ]);
+    write_v1_heap(
+        &db_dir.join("shop_tables_files"),
+        &tables_files,
+        vec![(
+            row(&[
+                ("tablename", text("items")),
+                ("filepath", text("items_heap0")),
+                ("freemap", text("items_freemap")),
+            ]),
+            false,
+        )],
+    );
+    let items = layout(&[
+        ("id", Type::Numeric(Integer)),
+        ("name", varchar()),
+        ("code", varchar()),
+        ("price", Type::Numeric(Double)),
+    ]);
+    let rows = (0..500)
+        .map(|i: i32| {
+            let code = (i % 3 != 0).then(|| format!("c{}", i % 7).into_bytes());
+            let row = row(&[
+                ("id", Some(i.to_ne_bytes().to_vec())),
+                ("name", text(&format!("item {}", i))),
+                ("code", code),
+                ("price", Some((i as f64 / 4.0).to_ne_bytes().to_vec())),
+            ]);
+            // the rows a DELETE left behind are not rows of the table
+            (row, i == 7)
+        })
+        .collect();
+    write_v1_heap(&db_dir.join("items_heap0"), &items, rows);
+}
+
+#[test]
+fn test_upgrade_format_version_1() {
+    let home = env::temp_dir().join(format!("aqua_upgrade_{}_test", std::process::id()));
+    env::set_var(AQUA_HOME_VAR, home.to_str().unwrap());
+    write_v1_data_dir(&home);
+    assert_eq!(
+        StorageManager::format_version(home.to_str().unwrap()),
+        Ok(1)
+    );
+
+    upgrade_data_dir().unwrap();
+    assert!(StorageManager::check_format_version(home.to_str().unwrap()).is_ok());
+    assert!(!home.join("upgrade_v1").exists());
+    // once upgraded the directory is left as it is
+    upgrade_data_dir().unwrap();
+
+    let storage = ArcRwLock!(StorageManager::new(home.to_str().unwrap(), BLK_SIZE, 100));
+    let mut catalog = CatalogManager::startup(storage.clone());
+    assert!(catalog.has_db("shop"));
+    let schema = catalog.get_schema("shop", "items").unwrap();
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|f| (f.name().to_string(), f.field_type()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fields,
+        vec![
+            ("id".to_string(), Type::Numeric(Integer)),
+            ("name".to_string(), varchar()),
+            ("code".to_string(), varchar()),
+            ("price".to_string(), Type::Numeric(Double)),
+        ]
+    );
+    assert_eq!(schema.primary_key(), &vec!["id".to_string()]);
+    assert_eq!(schema.indexes().len(), 2);
+
+    let tables = catalog.get_db_tables("shop");
+    let tables = tables.lock().unwrap();
+    let items = tables.get("items").unwrap();
+    let rows = items.heapscan_iter(None).collect::<Vec<_>>();
+    assert_eq!(rows.len(), 499);
+    assert!(rows
+        .iter()
+        .all(|row| row["id"].as_ref().unwrap().to_i32() != 7));
+    let row = rows
+        .iter()
+        .find(|row| row["id"].as_ref().unwrap().to_i32() == 12)
+        .unwrap();
+    assert_eq!(row["name"], text("item 12"));
+    assert_eq!(row["code"], None);
+    assert_eq!(row["price"], Some(3.0_f64.to_ne_bytes().to_vec()));
+
+    // the indexes were built again from the rows
+    let mut hash = items.hashscan_iter("name", None).unwrap();
+    hash.load_key(b"item 250");
+    let found = hash.collect::<Vec<_>>();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0]["id"].as_ref().unwrap().to_i32(), 250);
+    assert_eq!(found[0]["code"], text("c5"));
+    let mut btree = items
+        .btree_iter("id", evalexpr::Operator::Eq, None)
+        .unwrap();
+    btree.load_key(&499_i32.to_ne_bytes());
+    let found = btree.collect::<Vec<_>>();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0]["name"], text("item 499"));
+    drop(tables);
+    drop(catalog);
+    drop(storage);
+    fs::remove_dir_all(&home).unwrap();
+}